@@ -0,0 +1,180 @@
+//! POSIX access control lists of files and directories, the main reason to use the data lake API over Blob
+use std::fmt;
+use std::str::FromStr;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+
+/// Whom an [`AclEntry`] applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclTag {
+    User,
+    Group,
+    /// Upper bound of the permissions of named users, named groups and the owning group
+    Mask,
+    Other,
+}
+
+impl AclTag {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Group => "group",
+            Self::Mask => "mask",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Read, write and execute, `rwx` with `-` for the ones not granted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AclPermissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl FromStr for AclPermissions {
+    type Err = AzureStorageError;
+
+    fn from_str(permissions: &str) -> Result<Self, Self::Err> {
+        let invalid = || AzureStorageError::InvalidAcl(format!("invalid permissions {:?}", permissions));
+        let [read, write, execute]: [char; 3] = permissions.chars().collect::<Vec<_>>().try_into().map_err(|_| invalid())?;
+        let granted = |actual: char, expected: char| match actual {
+            _ if actual == expected => Ok(true),
+            '-' => Ok(false),
+            _ => Err(invalid()),
+        };
+        Ok(Self {
+            read: granted(read, 'r')?,
+            write: granted(write, 'w')?,
+            execute: granted(execute, 'x')?,
+        })
+    }
+}
+
+impl fmt::Display for AclPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |granted: bool, character: char| if granted { character } else { '-' };
+        write!(f, "{}{}{}", flag(self.read, 'r'), flag(self.write, 'w'), flag(self.execute, 'x'))
+    }
+}
+
+/// One entry of an ACL, e.g. `user:<object id>:r-x` or `default:group::rwx`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclEntry {
+    /// Whether this is a default entry of a directory, which new children inherit instead of it applying to the
+    /// directory itself
+    pub default: bool,
+    pub tag: AclTag,
+    /// Object id or principal name of a named user or group, `None` for the owner, the owning group, the mask
+    /// and others
+    pub qualifier: Option<String>,
+    pub permissions: AclPermissions,
+}
+
+impl FromStr for AclEntry {
+    type Err = AzureStorageError;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let invalid = || AzureStorageError::InvalidAcl(format!("invalid entry {:?}", entry));
+        let (default, rest) = match entry.strip_prefix("default:") {
+            Some(rest) => (true, rest),
+            None => (false, entry),
+        };
+        let [tag, qualifier, permissions]: [&str; 3] = rest.split(':').collect::<Vec<_>>().try_into().map_err(|_| invalid())?;
+        let tag = match tag {
+            "user" => AclTag::User,
+            "group" => AclTag::Group,
+            "mask" => AclTag::Mask,
+            "other" => AclTag::Other,
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            default,
+            tag,
+            qualifier: (!qualifier.is_empty()).then(|| qualifier.to_string()),
+            permissions: permissions.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for AclEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.default {
+            f.write_str("default:")?;
+        }
+        write!(f, "{}:{}:{}", self.tag.as_str(), self.qualifier.as_deref().unwrap_or_default(), self.permissions)
+    }
+}
+
+/// The entries of a path's ACL in the service's order, written as the comma separated `x-ms-acl` form
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessControlList {
+    pub entries: Vec<AclEntry>,
+}
+
+impl FromStr for AccessControlList {
+    type Err = AzureStorageError;
+
+    fn from_str(acl: &str) -> Result<Self, Self::Err> {
+        let entries = acl.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(AclEntry::from_str);
+        Ok(Self {
+            entries: entries.collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl fmt::Display for AccessControlList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self.entries.iter().map(AclEntry::to_string).collect();
+        f.write_str(&entries.join(","))
+    }
+}
+
+impl AzureStorageBackend {
+    /// The ACL of the file or directory at `path`, including the default entries of a directory
+    pub async fn get_acl(&self, container_name: &str, path: &str) -> Result<AccessControlList, miette::Error> {
+        let response = self
+            .file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .get_access_control_list()
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(response.acl.unwrap_or_default().parse()?)
+    }
+
+    /// Replaces the ACL of the file or directory at `path` with `acl`. It must hold the owner, owning group and
+    /// other entries, the service rejects it otherwise
+    pub async fn set_acl(&self, container_name: &str, path: &str, acl: &AccessControlList) -> Result<(), miette::Error> {
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .set_access_control_list(acl.to_string())
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acls_round_trip_through_their_text_form() {
+        let text = "user::rwx,user:4a9028cf-6d0e-4c5b-9a1c-2a3b4c5d6e7f:r-x,group::r--,mask::r-x,other::---,default:user::rwx";
+        let acl: AccessControlList = text.parse().unwrap();
+        assert_eq!(acl.entries.len(), 6);
+        assert_eq!(acl.entries[1].qualifier.as_deref(), Some("4a9028cf-6d0e-4c5b-9a1c-2a3b4c5d6e7f"));
+        assert_eq!(acl.entries[1].permissions, AclPermissions { read: true, write: false, execute: true });
+        assert!(acl.entries[5].default);
+        assert_eq!(acl.to_string(), text);
+
+        assert!("user::rwz".parse::<AccessControlList>().is_err());
+        assert!("owner::rwx".parse::<AccessControlList>().is_err());
+        assert_eq!("".parse::<AccessControlList>().unwrap(), AccessControlList::default());
+    }
+}
@@ -0,0 +1,389 @@
+//! Appending to files that other writers may be appending to at the same time
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use azure_core::prelude::IfMatchCondition;
+use azure_core::StatusCode;
+use bytes::Bytes;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::lease::{lease_context, Lease};
+use crate::logging::{LogLevel, LogSettings};
+use crate::sdk::datalake::*;
+use crate::upload::append_split;
+use crate::writer::{spawn_on_drop, DropBehavior};
+
+/// What an appender does when another writer committed data to the file first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppendConflictStrategy {
+    /// Return [`AzureStorageError::AppendConflict`]
+    #[default]
+    Fail,
+    /// Re-read the file length and append after the other writer's data, up to `max_attempts` times
+    RefetchAndRetry { max_attempts: u32 },
+    /// Leave the contended file to the other writer and continue in `<name>.<n>.<ext>` next to it
+    SwitchToNewFile,
+}
+
+/// Appends and commits data at the end of a file. Every append is flushed conditionally on the etag seen by the
+/// previous one, so a concurrent writer shows up as a conflict instead of interleaved or lost data. An append
+/// cancelled between sending and committing its data is settled on drop according to its [`DropBehavior`].
+#[derive(Debug)]
+pub struct FileAppender {
+    file_system_client: FileSystemClient,
+    base_path: String,
+    path: String,
+    file_client: FileClient,
+    /// Sent with every write while the file is leased
+    lease_id: Option<String>,
+    log_settings: Arc<LogSettings>,
+    position: i64,
+    etag: String,
+    strategy: AppendConflictStrategy,
+    rollovers: u32,
+    on_drop: DropBehavior,
+    /// Length of data sent after `position` whose commit has not completed
+    uncommitted: Option<i64>,
+}
+
+impl AzureStorageBackend {
+    /// Opens `path` for appending, creating it if it does not exist yet
+    pub async fn appender(&self, container_name: &str, path: &str, strategy: AppendConflictStrategy) -> Result<FileAppender, miette::Error> {
+        let file_system_client = self.file_system_client(container_name).await;
+        let file_client = file_system_client.get_file_client(path);
+        let (position, etag) = open_for_append(&file_client).await?;
+
+        Ok(FileAppender {
+            file_system_client,
+            base_path: path.to_string(),
+            path: path.to_string(),
+            file_client,
+            lease_id: None,
+            log_settings: Arc::clone(&self.log_settings),
+            position,
+            etag,
+            strategy,
+            rollovers: 0,
+            on_drop: DropBehavior::default(),
+            uncommitted: None,
+        })
+    }
+}
+
+/// Where [`AzureStorageBackend::append`] continues in each file, shared by every handle of a cached client
+#[derive(Debug, Default)]
+pub(crate) struct AppendPositions {
+    files: Mutex<HashMap<(String, String), SharedAppendPosition>>,
+}
+
+/// `None` until the position was read from the service
+type SharedAppendPosition = Arc<tokio::sync::Mutex<Option<AppendPosition>>>;
+
+#[derive(Debug)]
+struct AppendPosition {
+    committed: i64,
+    uncommitted: i64,
+}
+
+impl AppendPositions {
+    fn file(&self, container_name: &str, path: &str) -> SharedAppendPosition {
+        let mut files = self.files.lock().unwrap();
+        Arc::clone(files.entry((container_name.to_string(), path.to_string())).or_default())
+    }
+
+    /// Drops the tracked position of a file replaced or moved by other means, the next append reads it again
+    pub(crate) fn forget(&self, container_name: &str, path: &str) {
+        self.files.lock().unwrap().remove(&(container_name.to_string(), path.to_string()));
+    }
+
+    /// [`forget`](Self::forget) for every file below the directory `directory`
+    pub(crate) fn forget_below(&self, container_name: &str, directory: &str) {
+        let directory = format!("{}/", directory.trim_end_matches('/'));
+        self.files
+            .lock()
+            .unwrap()
+            .retain(|(container, path), _| container != container_name || !path.starts_with(&directory));
+    }
+}
+
+impl AzureStorageBackend {
+    /// Appends `bytes` to the end of `path` without committing them, creating the file if needed. The position
+    /// is tracked by the backend, appends to the same file are applied in call order. Readers only see the data
+    /// after [`flush`](Self::flush). A failed append makes the next one read the committed length again,
+    /// dropping everything appended since the last flush
+    pub async fn append(&self, container_name: &str, path: &str, bytes: impl Into<Bytes>) -> Result<(), miette::Error> {
+        let bytes = bytes.into();
+        let file = self.append_positions.file(container_name, path);
+        let mut position = file.lock().await;
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+
+        let current = match position.take() {
+            Some(current) => current,
+            None => {
+                let (committed, _) = open_for_append(&file_client).await?;
+                AppendPosition { committed, uncommitted: 0 }
+            }
+        };
+        let length = bytes.len() as i64;
+        append_split(&file_client, current.committed + current.uncommitted, bytes, None)
+            .await
+            .map_err(AzureStorageError::Request)?;
+        *position = Some(AppendPosition {
+            uncommitted: current.uncommitted + length,
+            ..current
+        });
+        Ok(())
+    }
+
+    /// Commits the data appended to `path` since the last flush
+    pub async fn flush(&self, container_name: &str, path: &str) -> Result<(), miette::Error> {
+        let file = self.append_positions.file(container_name, path);
+        let mut position = file.lock().await;
+        let Some(current) = position.as_mut() else {
+            return Ok(());
+        };
+        if current.uncommitted == 0 {
+            return Ok(());
+        }
+
+        let length = current.committed + current.uncommitted;
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        if let Err(error) = file_client.flush(length).await {
+            *position = None;
+            return Err(AzureStorageError::Request(error).into());
+        }
+        current.committed = length;
+        current.uncommitted = 0;
+        Ok(())
+    }
+}
+
+impl FileAppender {
+    /// What happens to an append that was cancelled after sending its data, see [`DropBehavior`]
+    pub fn on_drop(mut self, on_drop: DropBehavior) -> Self {
+        self.on_drop = on_drop;
+        self
+    }
+
+    /// Appends under `lease`, held on the file the appender was opened for. Files it switches to with
+    /// [`AppendConflictStrategy::SwitchToNewFile`] are new and written without it
+    pub fn lease(mut self, lease: &Lease) -> Self {
+        self.lease_id = Some(lease.id.clone());
+        self
+    }
+
+    /// Path currently appended to, which changes when [`AppendConflictStrategy::SwitchToNewFile`] kicks in
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Committed length of the file as of the last append
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    /// Appends `bytes` and commits them
+    pub async fn append(&mut self, bytes: impl Into<Bytes>) -> Result<(), miette::Error> {
+        let bytes = bytes.into();
+        let mut attempts = 0;
+
+        loop {
+            let error = match self.try_append(bytes.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(error) if is_position_conflict(&error) => error,
+                Err(error) => return Err(AzureStorageError::Request(error).into()),
+            };
+
+            attempts += 1;
+            match self.strategy {
+                AppendConflictStrategy::RefetchAndRetry { max_attempts } if attempts <= max_attempts => {
+                    self.log_settings.event(
+                        LogLevel::Info,
+                        format_args!("Append to {} conflicted at position {}, refetching length", self.path, self.position),
+                    );
+                    let (position, etag) = open_for_append(&self.file_client).await?;
+                    self.position = position;
+                    self.etag = etag;
+                }
+                AppendConflictStrategy::SwitchToNewFile => {
+                    self.log_settings.event(LogLevel::Info, format_args!("Append to {} conflicted, switching to a new file", self.path));
+                    self.switch_to_new_file().await?;
+                }
+                _ => {
+                    self.log_settings.event(LogLevel::Error, format_args!("Append to {} conflicted: {}", self.path, error));
+                    return Err(AzureStorageError::AppendConflict {
+                        path: self.path.clone(),
+                        position: self.position,
+                    }
+                    .into());
+                }
+            }
+        }
+    }
+
+    async fn try_append(&mut self, bytes: Bytes) -> azure_core::Result<()> {
+        let length = bytes.len() as i64;
+        append_split(&self.file_client, self.position, bytes, self.lease_id.as_deref()).await?;
+        self.uncommitted = Some(length);
+        let response = self
+            .file_client
+            .flush(self.position + length)
+            .if_match_condition(IfMatchCondition::Match(self.etag.clone()))
+            .context(lease_context(self.lease_id.as_deref()))
+            .await
+            .inspect_err(|error| {
+                // the other writer's commit already discarded our data
+                if is_position_conflict(error) {
+                    self.uncommitted = None;
+                }
+            })?;
+
+        self.uncommitted = None;
+        self.position += length;
+        if let Some(etag) = response.etag {
+            self.etag = etag;
+        }
+        Ok(())
+    }
+
+    /// Moves on to the next rolled over path nobody has created yet
+    async fn switch_to_new_file(&mut self) -> Result<(), miette::Error> {
+        loop {
+            self.rollovers += 1;
+            let path = rolled_over_path(&self.base_path, self.rollovers);
+            let file_client = self.file_system_client.get_file_client(&path);
+
+            match file_client.create_if_not_exists().await {
+                Ok(response) => {
+                    self.path = path;
+                    self.file_client = file_client;
+                    self.lease_id = None;
+                    self.position = 0;
+                    self.etag = response.etag;
+                    return Ok(());
+                }
+                Err(error) if matches!(http_status(&error), Some((StatusCode::Conflict, _))) => continue,
+                Err(error) => return Err(AzureStorageError::Request(error).into()),
+            }
+        }
+    }
+}
+
+impl Drop for FileAppender {
+    fn drop(&mut self) {
+        let Some(length) = self.uncommitted.take() else {
+            return;
+        };
+        let file_client = self.file_client.clone();
+        let lease_id = self.lease_id.clone();
+        let position = self.position;
+        let etag = self.etag.clone();
+        match self.on_drop {
+            // flushing the committed length again discards the data appended after it
+            DropBehavior::AbortAndCleanup => spawn_on_drop(&self.log_settings, format!("discard uncommitted data of {}", self.path), async move {
+                file_client
+                    .flush(position)
+                    .if_match_condition(IfMatchCondition::Match(etag))
+                    .context(lease_context(lease_id.as_deref()))
+                    .await
+                    .map(|_| ())
+            }),
+            DropBehavior::DetachAndFinish => spawn_on_drop(&self.log_settings, format!("commit pending append to {}", self.path), async move {
+                file_client
+                    .flush(position + length)
+                    .if_match_condition(IfMatchCondition::Match(etag))
+                    .context(lease_context(lease_id.as_deref()))
+                    .await
+                    .map(|_| ())
+            }),
+        }
+    }
+}
+
+/// Current committed length and etag of the file, creating it empty when missing
+async fn open_for_append(file_client: &FileClient) -> Result<(i64, String), AzureStorageError> {
+    loop {
+        match file_client.get_properties().await {
+            Ok(properties) => return Ok((properties.content_length.unwrap_or_default(), properties.etag)),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => {},
+            Err(error) => return Err(error.into()),
+        }
+
+        match file_client.create_if_not_exists().await {
+            Ok(response) => return Ok((0, response.etag)),
+            // lost the race to create it, read what the winner created
+            Err(error) if matches!(http_status(&error), Some((StatusCode::Conflict, _))) => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Whether the service rejected a commit because the file changed underneath the appender
+fn is_position_conflict(error: &azure_core::Error) -> bool {
+    match http_status(error) {
+        Some((StatusCode::PreconditionFailed, _)) => true,
+        Some((StatusCode::BadRequest, Some(code))) => code == "InvalidFlushPosition",
+        _ => false,
+    }
+}
+
+/// `logs/app.log` rolled over once becomes `logs/app.1.log`
+fn rolled_over_path(path: &str, rollover: u32) -> String {
+    let (directory, file_name) = match path.rfind('/') {
+        Some(index) => path.split_at(index + 1),
+        None => ("", path),
+    };
+    match file_name.rfind('.') {
+        Some(index) if index > 0 => {
+            let (stem, extension) = file_name.split_at(index);
+            format!("{}{}.{}{}", directory, stem, rollover, extension)
+        }
+        _ => format!("{}{}.{}", directory, file_name, rollover),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use azure_core::error::ErrorKind;
+
+    fn http_error(status: StatusCode, code: Option<&str>) -> azure_core::Error {
+        ErrorKind::http_response(status, code.map(str::to_string)).into_error()
+    }
+
+    #[test]
+    fn test_rolled_over_path() {
+        assert_eq!(rolled_over_path("logs/app.log", 1), "logs/app.1.log");
+        assert_eq!(rolled_over_path("logs/app", 2), "logs/app.2");
+        assert_eq!(rolled_over_path("app.tar.gz", 3), "app.tar.3.gz");
+        assert_eq!(rolled_over_path("logs.d/.hidden", 1), "logs.d/.hidden.1");
+    }
+
+    #[test]
+    fn test_position_conflicts_are_recognised() {
+        assert!(is_position_conflict(&http_error(StatusCode::PreconditionFailed, Some("ConditionNotMet"))));
+        assert!(is_position_conflict(&http_error(StatusCode::BadRequest, Some("InvalidFlushPosition"))));
+        assert!(!is_position_conflict(&http_error(StatusCode::BadRequest, Some("InvalidQueryParameterValue"))));
+        assert!(!is_position_conflict(&http_error(StatusCode::Forbidden, None)));
+    }
+
+    #[test]
+    fn test_append_positions_are_tracked_per_file() {
+        let positions = AppendPositions::default();
+        assert!(Arc::ptr_eq(&positions.file("logs", "app.log"), &positions.file("logs", "app.log")));
+        assert!(!Arc::ptr_eq(&positions.file("logs", "app.log"), &positions.file("other", "app.log")));
+
+        let tracked = positions.file("logs", "app.log");
+        positions.forget("logs", "app.log");
+        assert!(!Arc::ptr_eq(&tracked, &positions.file("logs", "app.log")));
+
+        let nested = positions.file("logs", "2024/app.log");
+        let sibling = positions.file("logs", "2024-archive/app.log");
+        positions.forget_below("logs", "2024");
+        assert!(!Arc::ptr_eq(&nested, &positions.file("logs", "2024/app.log")));
+        assert!(Arc::ptr_eq(&sibling, &positions.file("logs", "2024-archive/app.log")));
+    }
+}
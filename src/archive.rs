@@ -0,0 +1,223 @@
+//! Expanding local tar and zip archives into a container, for deploying bundles of many small files
+use std::io::{Read, Seek};
+use std::path::{Component, Path as LocalPath};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use futures::TryStreamExt;
+use tokio::sync::mpsc;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::upload::UploadOptions;
+
+const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+const ZIP_MAGIC: [u8; 2] = *b"PK";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// How an archive is expanded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    pub(crate) max_concurrency: usize,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+        }
+    }
+}
+
+impl ArchiveOptions {
+    /// Entries uploaded at once, 8 by default. As many entries again are read ahead into memory
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+}
+
+/// What expanding an archive uploaded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveReceipt {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Format of an archive starting with `magic`, tar when it is neither zip nor gzip as tar has no magic at
+    /// the start
+    fn detect(magic: &[u8]) -> Self {
+        match magic {
+            _ if magic.starts_with(&ZIP_MAGIC) => Self::Zip,
+            _ if magic.starts_with(&GZIP_MAGIC) => Self::TarGz,
+            _ => Self::Tar,
+        }
+    }
+}
+
+/// A file of the archive, read into memory to be uploaded
+struct ArchiveEntry {
+    path: String,
+    data: Bytes,
+}
+
+fn invalid(local_path: &LocalPath, description: impl ToString) -> AzureStorageError {
+    AzureStorageError::InvalidArchive {
+        path: local_path.to_path_buf(),
+        description: description.to_string(),
+    }
+}
+
+/// The `/` separated relative path of an entry, `None` for absolute paths and paths leaving the archive root
+fn entry_path(path: &LocalPath) -> Option<String> {
+    let mut segments = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(segment) => segments.push(segment.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!segments.is_empty()).then(|| segments.join("/"))
+}
+
+/// Reads the files of the archive at `local_path` in order, handing each to `emit` until it returns false
+fn read_archive(local_path: &LocalPath, mut emit: impl FnMut(ArchiveEntry) -> bool) -> Result<(), AzureStorageError> {
+    let local_io = |source| AzureStorageError::LocalIo { path: local_path.to_path_buf(), source };
+    let mut file = std::fs::File::open(local_path).map_err(local_io)?;
+    let mut magic = Vec::with_capacity(2);
+    (&mut file).take(2).read_to_end(&mut magic).map_err(local_io)?;
+    file.rewind().map_err(local_io)?;
+
+    match ArchiveFormat::detect(&magic) {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(|error| invalid(local_path, error))?;
+            for index in 0..archive.len() {
+                let mut file = archive.by_index(index).map_err(|error| invalid(local_path, error))?;
+                if !file.is_file() {
+                    continue;
+                }
+                let path = entry_path(LocalPath::new(file.name()))
+                    .ok_or_else(|| invalid(local_path, format!("entry {} lies outside the archive", file.name())))?;
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data).map_err(|error| invalid(local_path, error))?;
+                if !emit(ArchiveEntry { path, data: data.into() }) {
+                    return Ok(());
+                }
+            }
+            Ok(())
+        }
+        ArchiveFormat::Tar => read_tar(local_path, file, emit),
+        ArchiveFormat::TarGz => read_tar(local_path, GzDecoder::new(file), emit),
+    }
+}
+
+fn read_tar(local_path: &LocalPath, reader: impl Read, mut emit: impl FnMut(ArchiveEntry) -> bool) -> Result<(), AzureStorageError> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|error| invalid(local_path, error))? {
+        let mut entry = entry.map_err(|error| invalid(local_path, error))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path().map_err(|error| invalid(local_path, error))?.into_owned();
+        let path = entry_path(&name).ok_or_else(|| invalid(local_path, format!("entry {} lies outside the archive", name.display())))?;
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data).map_err(|error| invalid(local_path, error))?;
+        if !emit(ArchiveEntry { path, data: data.into() }) {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+impl AzureStorageBackend {
+    /// Uploads every file of the local zip, tar or gzip compressed tar archive at `local_path` below `prefix`,
+    /// at its path within the archive. The archive is read on a blocking thread while earlier entries upload,
+    /// each entry in memory as a whole, and the format is told from the content rather than the file name.
+    /// Entries with absolute paths or `..` fail the upload before anything outside `prefix` is written
+    pub async fn upload_archive(
+        &self,
+        container_name: &str,
+        prefix: &str,
+        local_path: impl AsRef<LocalPath>,
+        options: ArchiveOptions,
+    ) -> Result<ArchiveReceipt, miette::Error> {
+        let local_path = local_path.as_ref().to_path_buf();
+        let (sender, receiver) = mpsc::channel(options.max_concurrency);
+        let reader = {
+            let local_path = local_path.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(error) = read_archive(&local_path, |entry| sender.blocking_send(Ok(entry)).is_ok()) {
+                    let _ = sender.blocking_send(Err(error));
+                }
+            })
+        };
+
+        let prefix = prefix.trim_matches('/');
+        let entries = futures::stream::unfold(receiver, |mut receiver| async move { receiver.recv().await.map(|entry| (entry, receiver)) });
+        let receipt = entries
+            .map_err(miette::Error::from)
+            .map_ok(|entry| async move {
+                let path = match prefix.is_empty() {
+                    true => entry.path,
+                    false => format!("{}/{}", prefix, entry.path),
+                };
+                let size = entry.data.len() as u64;
+                self.upload_bytes(container_name, &path, entry.data, UploadOptions::default()).await.map(|_| size)
+            })
+            .try_buffer_unordered(options.max_concurrency)
+            .try_fold(ArchiveReceipt::default(), |mut receipt, size| {
+                receipt.files += 1;
+                receipt.bytes += size;
+                futures::future::ready(Ok(receipt))
+            })
+            .await?;
+        // an entry stream that ended because the reader panicked must not pass for a complete archive
+        reader.await.map_err(|error| invalid(&local_path, error))?;
+        Ok(receipt)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_paths_stay_within_the_archive() {
+        assert_eq!(entry_path(LocalPath::new("./site/index.html")).as_deref(), Some("site/index.html"));
+        assert_eq!(entry_path(LocalPath::new("site/../../etc/passwd")), None);
+        assert_eq!(entry_path(LocalPath::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn test_tar_archives_are_read_in_order() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in [("site/index.html", &b"<html>"[..]), ("site/app.js", &b"js"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content).unwrap();
+        }
+        let archive = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(archive.path(), builder.into_inner().unwrap()).unwrap();
+
+        let mut entries = Vec::new();
+        read_archive(archive.path(), |entry| {
+            entries.push((entry.path, entry.data));
+            true
+        })
+        .unwrap();
+        assert_eq!(entries, [("site/index.html".to_string(), Bytes::from("<html>")), ("site/app.js".to_string(), Bytes::from("js"))]);
+        assert_eq!(ArchiveFormat::detect(b"PK\x03\x04"), ArchiveFormat::Zip);
+    }
+}
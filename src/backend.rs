@@ -0,0 +1,780 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::auth::TokenCredential;
+use azure_core::ClientOptions;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::appender::AppendPositions;
+use crate::context_headers::ContextHeadersPolicy;
+use crate::credential::{
+    resource_for_scope, BackgroundRefreshCredential, CredentialDiagnostics, CredentialKind, CredentialReport, CredentialSource,
+    KeyVaultAccountKey, ManagedIdentityEndpoint, PersistentTokenCache, RetryingCredential, RotatingAccountKey, ScopedCredential,
+    SharedKeyPolicy, SignInOptions, TenantFallbackCredential, TokenCacheOptions, TokenRetryOptions, STORAGE_TOKEN_RESOURCE,
+};
+use crate::error::AzureStorageError;
+use crate::events::{emit, BackendEvent, RetryEventsPolicy, TokenEventCredential};
+use crate::handle::{ClientParts, HandleOptions};
+use crate::logging::{LogLevel, LogSettings, LoggingPolicy};
+use crate::prefix_limit::{PrefixLimit, PrefixLimitPolicy};
+use crate::provenance::{ProvenancePolicy, ProvenanceSettings};
+use crate::sas::UserDelegationKeys;
+use crate::sdk::datalake::*;
+use crate::sdk::identity::TokenCredentialOptions;
+use crate::sdk::rest::{self, Pipeline, ServiceType};
+use crate::sdk::storage::*;
+use crate::throttle::{ThrottleConfig, ThrottleGovernor, ThrottlePolicy};
+
+/// DNS suffix of the endpoints of accounts in the public cloud
+const DEFAULT_ENDPOINT_SUFFIX: &str = "core.windows.net";
+
+/// Caches and refreshes the tokens of a client. Test builds put the fault injection hooks in its place
+#[cfg(not(any(test, feature = "testing")))]
+pub(crate) type ClientTokenCredential = crate::sdk::identity::AutoRefreshingTokenCredential;
+#[cfg(any(test, feature = "testing"))]
+pub(crate) type ClientTokenCredential = crate::credential::FaultInjectingCredential;
+
+lazy_static! {
+    static ref AZ_STORAGE_BACKEND_CACHE: Arc<Mutex<HashMap<String, AzureStorageBackend>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Cloud backend for Azure ADLS Gen 2 storage. Creates an authenticated client for the supplied storage account with can be reused async
+#[derive(Clone, Debug)]
+pub struct AzureStorageBackend {
+    pub(crate) client: Arc<RwLock<DataLakeClient>>,
+    pub(crate) token_credential: Arc<ClientTokenCredential>,
+    pub(crate) account_key: Option<Arc<RotatingAccountKey>>,
+    pub(crate) credential_diagnostics: Arc<CredentialDiagnostics>,
+    pub(crate) config: AzureStorageBackendBuilder,
+    pub(crate) governor: Option<Arc<ThrottleGovernor>>,
+    pub(crate) log_settings: Arc<LogSettings>,
+    pub(crate) append_positions: Arc<AppendPositions>,
+    pub(crate) delegation_keys: Arc<UserDelegationKeys>,
+    pub(crate) provenance: Arc<ProvenanceSettings>,
+    /// Sends the requests built by hand, with the same policies as `client`
+    pub(crate) pipeline: Pipeline,
+    pub(crate) parts: Arc<ClientParts>,
+    pub(crate) options: HandleOptions,
+}
+
+
+impl AzureStorageBackend {
+    /// Creates a backend authenticated with `DefaultAzureCredential`. Use [`AzureStorageBackend::builder`] to pick another credential
+    pub fn new<'o, T: AsRef<str> + Send + Sync + 'o>(auth_parameter: T) ->  Pin<Box<dyn Future<Output = Result<Self, miette::Error>> + Send + Sync + 'o>>
+        where Self: Sized
+    {
+        Self::builder(auth_parameter.as_ref()).build()
+    }
+
+    pub fn builder(storage_account_url: impl Into<String>) -> AzureStorageBackendBuilder {
+        AzureStorageBackendBuilder::new(storage_account_url)
+    }
+
+    /// A clone of the cached data lake client, for operations not wrapped by the backend
+    pub async fn data_lake_client(&self) -> DataLakeClient {
+        self.client.read().await.clone()
+    }
+
+    pub(crate) async fn file_system_client(&self, container_name: &str) -> FileSystemClient {
+        self.client.read().await.file_system_client(container_name)
+    }
+
+    /// Current spacing the throttle governor enforces between requests to this account, `None` when throttling is disabled
+    pub fn throttle_delay(&self) -> Option<Duration> {
+        self.governor.as_ref().map(|governor| governor.current_delay())
+    }
+
+    /// Which credential source served the latest token request of this backend's client and why the sources
+    /// tried before it failed. `None` before the first request and for the interactive browser credential,
+    /// which is not a chain
+    pub fn credential_report(&self) -> Option<CredentialReport> {
+        self.credential_diagnostics.last_report()
+    }
+
+    /// Acquires a storage token, or the account key from Key Vault, now so the first request does not pay for the
+    /// auth handshake
+    pub async fn warm_up(&self) -> Result<(), miette::Error> {
+        if let Some(account_key) = &self.account_key {
+            account_key.warm_up().await.map_err(AzureStorageError::Credential)?;
+            return Ok(());
+        }
+        self.token_credential
+            .get_token(STORAGE_TOKEN_RESOURCE)
+            .await
+            .map_err(AzureStorageError::Credential)?;
+        Ok(())
+    }
+
+    /// Removes this backend's client from the cache, so the next build for the account creates a fresh one.
+    /// Handles to the evicted client keep working
+    pub async fn evict(&self) {
+        let removed = AZ_STORAGE_BACKEND_CACHE.lock().await.remove(&self.config.cache_key());
+        if removed.is_some() {
+            self.log(LogLevel::Info, format_args!("Evicted client for {}", self.config.storage_account_url));
+            emit(BackendEvent::ClientEvicted {
+                account: self.config.storage_account_url.clone(),
+            });
+        }
+    }
+}
+
+
+/// The configuration of every backend currently cached in this process
+pub(crate) async fn cached_backend_configs() -> Vec<AzureStorageBackendBuilder> {
+    let cache_guard = AZ_STORAGE_BACKEND_CACHE.lock().await;
+    cache_guard
+        .values()
+        .map(|backend| backend.config.clone())
+        .collect()
+}
+
+
+/// Configures how an [`AzureStorageBackend`] authenticates before it is created or fetched from the cache
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AzureStorageBackendBuilder {
+    pub(crate) storage_account_url: String,
+    pub(crate) credential: CredentialKind,
+    #[serde(default = "default_throttle")]
+    pub(crate) throttle: Option<ThrottleConfig>,
+    #[serde(default)]
+    pub(crate) prefix_limits: Vec<(String, PrefixLimit)>,
+    #[serde(default)]
+    pub(crate) token_cache: Option<TokenCacheOptions>,
+    #[serde(default)]
+    pub(crate) account_key: Option<KeyVaultAccountKey>,
+    #[serde(default)]
+    pub(crate) token_retry: Option<TokenRetryOptions>,
+    #[serde(default)]
+    pub(crate) background_token_refresh: Option<Duration>,
+    #[serde(default)]
+    pub(crate) token_scope: Option<String>,
+    #[serde(default)]
+    pub(crate) tenant_id: Option<String>,
+    #[serde(default)]
+    pub(crate) additionally_allowed_tenants: Vec<String>,
+    #[serde(default)]
+    pub(crate) authority_host: Option<String>,
+    #[serde(default)]
+    pub(crate) endpoint_suffix: Option<String>,
+    #[serde(default)]
+    pub(crate) managed_identity_endpoint: Option<ManagedIdentityEndpoint>,
+    #[serde(default)]
+    pub(crate) log_level: LogLevel,
+    #[serde(default)]
+    pub(crate) log_payloads: bool,
+}
+
+fn default_throttle() -> Option<ThrottleConfig> {
+    Some(ThrottleConfig::default())
+}
+
+impl AzureStorageBackendBuilder {
+    pub fn new(storage_account_url: impl Into<String>) -> Self {
+        Self {
+            storage_account_url: storage_account_url.into(),
+            credential: CredentialKind::default(),
+            throttle: default_throttle(),
+            prefix_limits: Vec::new(),
+            token_cache: None,
+            account_key: None,
+            token_retry: None,
+            background_token_refresh: None,
+            token_scope: None,
+            tenant_id: None,
+            additionally_allowed_tenants: Vec::new(),
+            authority_host: None,
+            endpoint_suffix: None,
+            managed_identity_endpoint: None,
+            log_level: LogLevel::default(),
+            log_payloads: false,
+        }
+    }
+
+    /// Selects the credential used if a new client has to be created
+    pub fn credential(mut self, credential: CredentialKind) -> Self {
+        self.credential = credential;
+        self
+    }
+
+    /// Uses `DefaultAzureCredential` with only the given sources, in order. For local development
+    /// `[CredentialSource::AzureCli]` avoids the managed identity probe timing out on every token request
+    pub fn credential_chain(self, sources: impl IntoIterator<Item = CredentialSource>) -> Self {
+        self.credential(CredentialKind::Chain(sources.into_iter().collect()))
+    }
+
+    /// Requests storage tokens for `scope` instead of `https://storage.azure.com/.default`, e.g.
+    /// `https://storage.usgovcloudapi.net/.default` for a sovereign cloud account, together with its
+    /// [`endpoint_suffix`](Self::endpoint_suffix), or a custom audience
+    pub fn token_scope(mut self, scope: impl Into<String>) -> Self {
+        self.token_scope = Some(scope.into());
+        self
+    }
+
+    /// Tenant to request tokens from, for accounts outside the home tenant of the identity (e.g. a guest user).
+    /// Takes precedence over `AZURE_TENANT_ID`, the CLI's default tenant and the browser options' tenant
+    pub fn tenant_id(mut self, tenant_id: impl Into<String>) -> Self {
+        self.tenant_id = Some(tenant_id.into());
+        self
+    }
+
+    /// Tenants tried in order when the [`tenant_id`](Self::tenant_id) does not issue a token, e.g. for
+    /// identities that are guests in several tenants
+    pub fn additionally_allowed_tenants<T: Into<String>>(mut self, tenant_ids: impl IntoIterator<Item = T>) -> Self {
+        self.additionally_allowed_tenants = tenant_ids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Azure AD endpoint to sign in through for national clouds, e.g. `https://login.microsoftonline.us` or
+    /// `https://login.chinacloudapi.cn`. Applies to the environment service principal and the browser sign-in,
+    /// the Azure CLI follows `az cloud set`. Usually combined with [`token_scope`](Self::token_scope) and
+    /// [`endpoint_suffix`](Self::endpoint_suffix)
+    pub fn authority_host(mut self, authority_host: impl Into<String>) -> Self {
+        self.authority_host = Some(authority_host.into());
+        self
+    }
+
+    /// DNS suffix of the account's endpoints instead of `core.windows.net`, e.g. `core.usgovcloudapi.net` for an
+    /// account in Azure Government or `core.chinacloudapi.cn` in Azure China. Every request goes to
+    /// `<account>.dfs.<suffix>` or `<account>.blob.<suffix>`
+    pub fn endpoint_suffix(mut self, endpoint_suffix: impl Into<String>) -> Self {
+        self.endpoint_suffix = Some(endpoint_suffix.into().trim_matches('.').to_string());
+        self
+    }
+
+    /// The `service` endpoint of the account, e.g. `https://account.dfs.core.windows.net`
+    pub(crate) fn endpoint(&self, service: ServiceType) -> String {
+        let endpoint_suffix = self.endpoint_suffix.as_deref().unwrap_or(DEFAULT_ENDPOINT_SUFFIX);
+        format!("https://{}.{}.{}", self.storage_account_url, service.subdomain(), endpoint_suffix)
+    }
+
+    /// A client of the account's data lake endpoint sending its requests through `client_options`
+    pub(crate) fn data_lake_client(&self, storage_credentials: StorageCredentials, client_options: ClientOptions) -> DataLakeClient {
+        let cloud_location = CloudLocation::Custom {
+            uri: self.endpoint(ServiceType::DataLake),
+            credentials: storage_credentials,
+        };
+        DataLakeClient::builder(self.storage_account_url.clone(), StorageCredentials::anonymous())
+            .cloud_location(cloud_location)
+            .client_options(client_options)
+            .build()
+    }
+
+    /// Managed identity endpoint of the hosting environment, for hosts other than Azure VMs such as App Service
+    /// or Azure Arc enabled servers
+    pub fn managed_identity_endpoint(mut self, endpoint: ManagedIdentityEndpoint) -> Self {
+        self.managed_identity_endpoint = Some(endpoint);
+        self
+    }
+
+    /// Persists acquired tokens to an encrypted store so short lived processes (CLI invocations, test runs) for the
+    /// same account and identity skip the auth handshake
+    pub fn persistent_token_cache(mut self, token_cache: TokenCacheOptions) -> Self {
+        self.token_cache = Some(token_cache);
+        self
+    }
+
+    /// Signs requests with the account access key kept in a Key Vault secret instead of a storage token. The
+    /// configured credential signs in to the vault. When the service rejects the key, e.g. after a rotation,
+    /// the secret is read again and the request retried, so rotating the key needs no restart
+    pub fn account_key_from_key_vault(mut self, account_key: KeyVaultAccountKey) -> Self {
+        self.account_key = Some(account_key);
+        self
+    }
+
+    /// Cuts off and retries token requests that fail or take too long. Applies to the sign in itself, tokens
+    /// served from the persistent cache are not affected
+    pub fn token_retry(mut self, token_retry: TokenRetryOptions) -> Self {
+        self.token_retry = Some(token_retry);
+        self
+    }
+
+    /// Renews tokens from a background task `margin` before they expire, so a request after a long idle period
+    /// does not stall on token acquisition. The storage token is acquired as soon as the client is created
+    pub fn background_token_refresh(mut self, margin: Duration) -> Self {
+        self.background_token_refresh = Some(margin);
+        self
+    }
+
+    /// Tunes the governor that slows all requests to the account down when it is throttled. Only applies when
+    /// a new client is created, backends fetched from the cache share the governor of the cached client
+    pub fn throttle(mut self, throttle: ThrottleConfig) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Limits the requests to paths below `prefix`, given as `<container>/<path>` such as `data/hot/serving/`.
+    /// The limit with the longest matching prefix applies, paths no prefix matches are not limited. Setting a
+    /// prefix again replaces its limit. Only applies when a new client is created, like [`throttle`](Self::throttle)
+    pub fn prefix_limit(mut self, prefix: impl Into<String>, limit: PrefixLimit) -> Self {
+        let prefix = prefix.into();
+        self.prefix_limits.retain(|(existing, _)| *existing != prefix);
+        self.prefix_limits.push((prefix, limit));
+        self
+    }
+
+    /// Initial log level of the client, see [`AzureStorageBackend::set_log_level`] to change it later
+    pub fn log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    /// Initial payload logging of the client, see [`AzureStorageBackend::set_payload_logging`]
+    pub fn log_payloads(mut self, log_payloads: bool) -> Self {
+        self.log_payloads = log_payloads;
+        self
+    }
+
+    /// Leaves throttling entirely to the SDK's per-request retry policy
+    pub fn disable_throttle(mut self) -> Self {
+        self.throttle = None;
+        self
+    }
+
+    fn validate_tenants(&self) -> Result<(), AzureStorageError> {
+        if self.tenant_id.is_none() && !self.additionally_allowed_tenants.is_empty() {
+            return Err(AzureStorageError::InvalidCredentialConfig(
+                "additionally allowed tenants need a tenant id to try first".to_string(),
+            ));
+        }
+        let empty = self.tenant_id.iter().chain(&self.additionally_allowed_tenants).any(|tenant_id| tenant_id.trim().is_empty());
+        if empty {
+            return Err(AzureStorageError::InvalidCredentialConfig("tenant id is empty".to_string()));
+        }
+        Ok(())
+    }
+
+    fn sign_in_options(&self) -> Result<SignInOptions, AzureStorageError> {
+        let token_options = match &self.authority_host {
+            None => TokenCredentialOptions::default(),
+            Some(authority_host) => match url::Url::parse(authority_host) {
+                Ok(url) if url.scheme() == "https" && url.has_host() => {
+                    TokenCredentialOptions::new(authority_host.trim_end_matches('/').to_string())
+                }
+                _ => {
+                    return Err(AzureStorageError::InvalidCredentialConfig(format!(
+                        "authority host {:?} is not an https URL such as https://login.microsoftonline.us",
+                        authority_host
+                    )))
+                }
+            },
+        };
+        if let Some(ManagedIdentityEndpoint::Custom(endpoint)) = &self.managed_identity_endpoint {
+            if url::Url::parse(endpoint).is_err() {
+                return Err(AzureStorageError::InvalidCredentialConfig(format!(
+                    "managed identity endpoint {:?} is not a URL",
+                    endpoint
+                )));
+            }
+        }
+
+        let log_settings = Arc::new(LogSettings::new(self.storage_account_url.clone(), self.log_level, self.log_payloads));
+        Ok(SignInOptions {
+            token_options,
+            managed_identity: self.managed_identity_endpoint.clone(),
+            diagnostics: Arc::new(CredentialDiagnostics::new(Arc::clone(&log_settings))),
+            log_settings,
+        })
+    }
+
+    fn tenant_token_credential(&self, sign_in: &SignInOptions) -> Arc<dyn TokenCredential> {
+        let Some(tenant_id) = &self.tenant_id else {
+            return self.credential.token_credential(sign_in);
+        };
+        if self.additionally_allowed_tenants.is_empty() {
+            return self.credential.tenant_credential(tenant_id, sign_in);
+        }
+        Arc::new(TenantFallbackCredential::new(
+            std::iter::once(tenant_id)
+                .chain(&self.additionally_allowed_tenants)
+                .map(|tenant_id| (tenant_id.clone(), self.credential.tenant_credential(tenant_id, sign_in)))
+                .collect(),
+        ))
+    }
+
+    /// Identifies the cached client this configuration builds, settings that do not change how it authenticates
+    /// share the client
+    fn cache_key(&self) -> String {
+        let mut cache_key = format!("{}|{}", self.storage_account_url, self.credential.cache_key());
+        if let Some(scope) = &self.token_scope {
+            cache_key = format!("{}|{}", cache_key, scope);
+        }
+        if let Some(authority_host) = &self.authority_host {
+            cache_key = format!("{}|authority:{}", cache_key, authority_host);
+        }
+        if let Some(endpoint_suffix) = &self.endpoint_suffix {
+            cache_key = format!("{}|endpoint:{}", cache_key, endpoint_suffix);
+        }
+        if let Some(account_key) = &self.account_key {
+            cache_key = format!("{}|key_vault:{}", cache_key, account_key.cache_key());
+        }
+        if let Some(endpoint) = &self.managed_identity_endpoint {
+            cache_key = format!("{}|managed_identity:{}", cache_key, endpoint.cache_key());
+        }
+        if let Some(tenant_id) = &self.tenant_id {
+            cache_key = format!("{}|tenant:{}", cache_key, tenant_id);
+            for allowed_tenant in &self.additionally_allowed_tenants {
+                cache_key = format!("{},{}", cache_key, allowed_tenant);
+            }
+        }
+        cache_key
+    }
+
+    pub fn build<'o>(self) -> Pin<Box<dyn Future<Output = Result<AzureStorageBackend, miette::Error>> + Send + Sync + 'o>> {
+        let cache_key = self.cache_key();
+        let cache_clone = Arc::clone(&AZ_STORAGE_BACKEND_CACHE);
+
+        Box::pin(async move {
+            self.credential.validate()?;
+            self.validate_tenants()?;
+            if let Some(account_key) = &self.account_key {
+                account_key.validate()?;
+            }
+            let sign_in = self.sign_in_options()?;
+            let token_resource = self.token_scope.as_deref().map(resource_for_scope).transpose()?;
+
+            let mut cache_guard = cache_clone.lock().await;
+
+            let backend = match cache_guard.get_mut(&cache_key) {
+                Some(existing_backend) => {
+                    println!("Found existing client");
+                    existing_backend.clone()
+                },
+                None => {
+                    println!("Creating new client");
+                    let log_settings = Arc::clone(&sign_in.log_settings);
+                    let mut token_credential = self.tenant_token_credential(&sign_in);
+                    if let Some(resource) = token_resource {
+                        token_credential = Arc::new(ScopedCredential::new(token_credential, resource));
+                    }
+                    if let Some(token_retry) = &self.token_retry {
+                        token_credential = Arc::new(RetryingCredential::new(token_credential, token_retry.clone(), Arc::clone(&log_settings)));
+                    }
+                    if let Some(token_cache) = &self.token_cache {
+                        let identity = cache_key.clone();
+                        token_credential =
+                            Arc::new(PersistentTokenCache::new(token_credential, token_cache.clone(), identity, Arc::clone(&log_settings)));
+                    }
+                    if let Some(margin) = self.background_token_refresh {
+                        token_credential = BackgroundRefreshCredential::spawn(token_credential, margin, Arc::clone(&log_settings));
+                    }
+                    token_credential = Arc::new(TokenEventCredential::new(token_credential, self.storage_account_url.clone()));
+                    let refresh_token = Arc::new(ClientTokenCredential::new(token_credential));
+                    let account_key = self
+                        .account_key
+                        .clone()
+                        .map(|account_key| Arc::new(RotatingAccountKey::new(refresh_token.clone(), account_key)));
+                    let storage_credentials = match account_key {
+                        Some(_) => StorageCredentials::anonymous(),
+                        None => StorageCredentials::token_credential(refresh_token.clone()),
+                    };
+                    let governor = self
+                        .throttle
+                        .clone()
+                        .map(|config| Arc::new(ThrottleGovernor::new(self.storage_account_url.clone(), config, Arc::clone(&log_settings))));
+                    let mut client_options = ClientOptions::default();
+                    client_options
+                        .per_call_policies_mut()
+                        .push(Arc::new(RetryEventsPolicy::new(self.storage_account_url.clone())));
+                    if let Some(governor) = &governor {
+                        client_options.per_retry_policies_mut().push(Arc::new(ThrottlePolicy::new(Arc::clone(governor))));
+                    }
+                    if !self.prefix_limits.is_empty() {
+                        client_options.per_retry_policies_mut().push(Arc::new(PrefixLimitPolicy::new(&self.prefix_limits)));
+                    }
+                    let provenance = Arc::new(ProvenanceSettings::default());
+                    client_options.per_retry_policies_mut().push(Arc::new(ProvenancePolicy::new(Arc::clone(&provenance))));
+                    client_options.per_retry_policies_mut().push(Arc::new(ContextHeadersPolicy));
+                    client_options.per_retry_policies_mut().push(Arc::new(LoggingPolicy::new(Arc::clone(&log_settings))));
+                    if let Some(account_key) = &account_key {
+                        // last, so the signature covers every header the other policies set
+                        let policy = SharedKeyPolicy::new(self.storage_account_url.clone(), Arc::clone(account_key), Arc::clone(&log_settings));
+                        client_options.per_retry_policies_mut().push(Arc::new(policy));
+                    }
+                    #[cfg(any(test, feature = "testing"))]
+                    client_options
+                        .per_retry_policies_mut()
+                        .push(Arc::new(crate::failover_drill::FailoverDrillPolicy::new(self.storage_account_url.clone())));
+                    let data_lake_client = self.data_lake_client(storage_credentials.clone(), client_options.clone());
+                    let client = Arc::new(RwLock::new(data_lake_client));
+                    let pipeline = rest::pipeline(client_options.clone(), storage_credentials.clone());
+
+                    let backend = AzureStorageBackend {
+                        client: Arc::clone(&client),
+                        token_credential: refresh_token,
+                        account_key,
+                        credential_diagnostics: sign_in.diagnostics.clone(),
+                        config: self,
+                        governor,
+                        log_settings,
+                        append_positions: Arc::default(),
+                        delegation_keys: Arc::default(),
+                        provenance,
+                        pipeline: pipeline.clone(),
+                        parts: Arc::new(ClientParts {
+                            client,
+                            pipeline,
+                            storage_credentials,
+                            client_options,
+                        }),
+                        options: HandleOptions::default(),
+                    };
+                    cache_guard.insert(cache_key, backend.clone());
+                    emit(BackendEvent::ClientCreated {
+                        account: backend.config.storage_account_url.clone(),
+                    });
+                    backend
+                }
+            };
+
+            Ok(backend)
+        }
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use uuid::Uuid;
+
+    const STORAGE_ACCOUNT: &str = "metastoredevazio";
+
+    fn generate_unique_names() -> (String, String) {
+        let container_name = format!("testcontainer-{}", Uuid::new_v4());
+        let file_name = format!("testfile-{}", Uuid::new_v4());
+        (container_name, file_name)
+    }
+
+    async fn create_container(backend: &AzureStorageBackend, container_name: &String) -> Result <(), Box<dyn std::error::Error>> {
+
+        let read_lock = backend.client.read().await;
+        let file_system_client = read_lock
+            .file_system_client(container_name);
+        file_system_client.create().await?;
+
+        drop(read_lock);
+        Ok(())
+    }
+
+    async fn create_file(backend: &AzureStorageBackend, container_name: &String, file_name: &String) -> Result <(), Box<dyn std::error::Error>> {
+
+        let read_lock = backend.client.read().await;
+        let file_client = read_lock
+            .file_system_client(container_name)
+            .into_file_client(file_name);
+        file_client.create().await?;
+
+        drop(read_lock);
+        Ok(())
+    }
+
+    async fn delete_file(backend: &AzureStorageBackend, container_name: &String, file_name: &String) -> Result <(), Box<dyn std::error::Error>> {
+
+        let read_lock = backend.client.read().await;
+        let file_client = read_lock
+            .file_system_client(container_name)
+            .into_file_client(file_name);
+        file_client.delete().await?;
+
+        drop(read_lock);
+        Ok(())
+    }
+
+    async fn delete_container(backend: &AzureStorageBackend, container_name: &String) -> Result <(), Box<dyn std::error::Error>> {
+
+        let read_lock = backend.client.read().await;
+        let file_system_client = read_lock
+            .file_system_client(container_name);
+        file_system_client.delete().await?;
+
+        drop(read_lock);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_1() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_2() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_3() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_4() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_5() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_6() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_7() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_8() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_9() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_10() -> Result <(), Box<dyn std::error::Error>> {
+        let (container_name, file_name) = generate_unique_names();
+
+        let azure_storage_backend = AzureStorageBackend::new(STORAGE_ACCOUNT).await?;
+        println!("Created backend: {:?}", azure_storage_backend);
+        println!("Creating container: {}", container_name);
+        create_container(&azure_storage_backend, &container_name).await?;
+        println!("Creating file: {}", file_name);
+        create_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting file: {}", file_name);
+        delete_file(&azure_storage_backend, &container_name, &file_name).await?;
+        println!("Deleting container: {}", container_name);
+        delete_container(&azure_storage_backend, &container_name).await?;
+
+        Ok(())
+    }
+}
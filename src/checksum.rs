@@ -0,0 +1,122 @@
+//! Hashing uploaded content next to the network sends instead of in front of them
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How an upload computes the checksum of its content
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// No checksum, for when CPU is scarcer than trust in the network
+    Off,
+    /// Hash each chunk on the uploading task before sending it. Costs no extra thread, but sends wait for the hash
+    Inline,
+    /// Hash on a blocking worker thread while the chunks are sent. The worker may fall behind by up to
+    /// `max_pending_chunks` chunks before sends wait for it, more lets bursts through at the cost of memory
+    Background { max_pending_chunks: usize },
+}
+
+impl Default for ChecksumMode {
+    fn default() -> Self {
+        ChecksumMode::Background { max_pending_chunks: 4 }
+    }
+}
+
+/// SHA-256 of everything an upload sent, independent of how it was chunked
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ContentChecksum {
+    pub sha256: [u8; 32],
+}
+
+impl ContentChecksum {
+    pub fn to_hex(&self) -> String {
+        self.sha256.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Accepts the chunks of one upload in order and hashes them according to a [`ChecksumMode`]
+pub(crate) enum ChecksumPipeline {
+    Off,
+    Inline(Sha256),
+    Background {
+        sender: mpsc::Sender<Bytes>,
+        worker: JoinHandle<[u8; 32]>,
+    },
+}
+
+impl ChecksumPipeline {
+    pub(crate) fn new(mode: ChecksumMode) -> Self {
+        match mode {
+            ChecksumMode::Off => ChecksumPipeline::Off,
+            ChecksumMode::Inline => ChecksumPipeline::Inline(Sha256::new()),
+            ChecksumMode::Background { max_pending_chunks } => {
+                let (sender, mut receiver) = mpsc::channel::<Bytes>(max_pending_chunks.max(1));
+                let worker = tokio::task::spawn_blocking(move || {
+                    let mut hasher = Sha256::new();
+                    while let Some(chunk) = receiver.blocking_recv() {
+                        hasher.update(&chunk);
+                    }
+                    hasher.finalize().into()
+                });
+                ChecksumPipeline::Background { sender, worker }
+            }
+        }
+    }
+
+    /// Adds the next chunk. In background mode this only waits when the worker is too far behind
+    pub(crate) async fn feed(&mut self, chunk: &Bytes) {
+        match self {
+            ChecksumPipeline::Off => {}
+            ChecksumPipeline::Inline(hasher) => hasher.update(chunk),
+            // a worker that went away is reported by `finish`
+            ChecksumPipeline::Background { sender, .. } => {
+                let _ = sender.send(chunk.clone()).await;
+            }
+        }
+    }
+
+    /// Checksum of every chunk fed so far, once the worker has caught up
+    pub(crate) async fn finish(self) -> Option<ContentChecksum> {
+        match self {
+            ChecksumPipeline::Off => None,
+            ChecksumPipeline::Inline(hasher) => Some(ContentChecksum { sha256: hasher.finalize().into() }),
+            ChecksumPipeline::Background { sender, worker } => {
+                drop(sender);
+                let sha256 = worker.await.expect("checksum worker panicked");
+                Some(ContentChecksum { sha256 })
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn checksum(mode: ChecksumMode, chunks: &[&'static [u8]]) -> Option<ContentChecksum> {
+        let mut pipeline = ChecksumPipeline::new(mode);
+        for chunk in chunks {
+            pipeline.feed(&Bytes::from_static(chunk)).await;
+        }
+        pipeline.finish().await
+    }
+
+    #[tokio::test]
+    async fn test_modes_agree_regardless_of_chunking() {
+        let expected = ContentChecksum { sha256: Sha256::digest(b"hello world").into() };
+        assert_eq!(checksum(ChecksumMode::Inline, &[b"hello world"]).await, Some(expected));
+        assert_eq!(checksum(ChecksumMode::Inline, &[b"hello", b" ", b"world"]).await, Some(expected));
+        assert_eq!(
+            checksum(ChecksumMode::Background { max_pending_chunks: 1 }, &[b"hel", b"lo wor", b"ld"]).await,
+            Some(expected)
+        );
+        assert_eq!(checksum(ChecksumMode::Off, &[b"hello world"]).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_hex_encoding() {
+        let empty = checksum(ChecksumMode::Inline, &[]).await.unwrap();
+        assert_eq!(empty.to_hex(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+}
@@ -0,0 +1,115 @@
+//! Etag conditions on reads, overwrites and deletes, so concurrent writers notice each other
+use azure_core::prelude::IfMatchCondition;
+use azure_core::StatusCode;
+use bytes::Bytes;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::upload::UploadOptions;
+
+/// Precondition on the current version of a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EtagCondition {
+    /// Only if the file still has this etag
+    IfMatch(String),
+    /// Only if the file does not have this etag, or does not exist at all for `*`
+    IfNoneMatch(String),
+}
+
+impl EtagCondition {
+    /// Only if the file does not exist yet
+    pub fn absent() -> Self {
+        Self::IfNoneMatch("*".to_string())
+    }
+
+    pub(crate) fn if_match_condition(&self) -> IfMatchCondition {
+        match self {
+            Self::IfMatch(etag) => IfMatchCondition::Match(etag.clone()),
+            Self::IfNoneMatch(etag) => IfMatchCondition::NotMatch(etag.clone()),
+        }
+    }
+}
+
+/// Content of a file with the etag of the version read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionedContent {
+    pub data: Bytes,
+    pub etag: String,
+}
+
+/// [`AzureStorageError::ConditionNotMet`] for a request rejected because of its condition. Creating a file
+/// that exists with `If-None-Match: *` is rejected with a conflict rather than a failed precondition
+pub(crate) fn condition_error(path: &str, error: azure_core::Error) -> AzureStorageError {
+    match http_status(&error) {
+        Some((StatusCode::PreconditionFailed, _)) | Some((StatusCode::Conflict, Some("PathAlreadyExists"))) => {
+            AzureStorageError::ConditionNotMet { path: path.to_string() }
+        }
+        _ => AzureStorageError::Request(error),
+    }
+}
+
+impl AzureStorageBackend {
+    /// The content of the file at `path` if `condition` holds. An `IfNoneMatch` on the etag of a version read
+    /// before returns `None` while the file is unchanged, so cached content is only fetched again when needed
+    pub async fn download_bytes_if(
+        &self,
+        container_name: &str,
+        path: &str,
+        condition: EtagCondition,
+    ) -> Result<Option<VersionedContent>, miette::Error> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        match file_client.read().if_match_condition(condition.if_match_condition()).await {
+            Ok(response) => Ok(Some(VersionedContent {
+                data: response.data,
+                etag: response.etag,
+            })),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::NotModified, _))) => Ok(None),
+            Err(error) => Err(condition_error(path, error).into()),
+        }
+    }
+
+    /// Creates `path` with `bytes` as its content unless it exists. Of several writers racing for the same path
+    /// exactly one gets `true`, which makes marker files usable for leader election and run-once guards. The
+    /// winner's file only appears once its content is committed
+    pub async fn create_exclusive(&self, container_name: &str, path: &str, bytes: impl Into<Bytes>) -> Result<bool, miette::Error> {
+        let options = UploadOptions::default().condition(EtagCondition::absent());
+        let chunks = futures::stream::iter([Ok(bytes.into())]);
+        match self.upload_stream(container_name, path, chunks, options).await {
+            Ok(_) => Ok(true),
+            Err(AzureStorageError::ConditionNotMet { .. }) => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Deletes the file at `path` if `condition` holds, e.g. only the version this caller last read
+    pub async fn delete_if(&self, container_name: &str, path: &str, condition: EtagCondition) -> Result<(), miette::Error> {
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .delete()
+            .if_match_condition(condition.if_match_condition())
+            .await
+            .map_err(|error| condition_error(path, error))?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use azure_core::error::ErrorKind;
+
+    #[test]
+    fn test_rejected_conditions_are_reported_as_such() {
+        let failed = ErrorKind::http_response(StatusCode::PreconditionFailed, Some("ConditionNotMet".to_string())).into_error();
+        assert!(matches!(condition_error("a.csv", failed), AzureStorageError::ConditionNotMet { path } if path == "a.csv"));
+
+        let exists = ErrorKind::http_response(StatusCode::Conflict, Some("PathAlreadyExists".to_string())).into_error();
+        assert!(matches!(condition_error("a.csv", exists), AzureStorageError::ConditionNotMet { .. }));
+
+        let leased = ErrorKind::http_response(StatusCode::Conflict, Some("LeaseIdMissing".to_string())).into_error();
+        assert!(matches!(condition_error("a.csv", leased), AzureStorageError::Request(_)));
+    }
+}
@@ -0,0 +1,325 @@
+//! Containers of the account, the data lake API's file systems
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::{Method, StatusCode};
+use futures::StreamExt;
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+use crate::metadata::{from_properties, to_properties, Metadata};
+use crate::rest::RestRequest;
+use crate::sdk::datalake::*;
+
+const PUBLIC_ACCESS: HeaderName = HeaderName::from_static("x-ms-blob-public-access");
+const DELETED_CONTAINER_NAME: HeaderName = HeaderName::from_static("x-ms-deleted-container-name");
+const DELETED_CONTAINER_VERSION: HeaderName = HeaderName::from_static("x-ms-deleted-container-version");
+const DEFAULT_ENCRYPTION_SCOPE: HeaderName = HeaderName::from_static("x-ms-default-encryption-scope");
+const DENY_ENCRYPTION_SCOPE_OVERRIDE: HeaderName = HeaderName::from_static("x-ms-deny-encryption-scope-override");
+
+/// A container found by [`AzureStorageBackend::list_containers`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub name: String,
+    pub last_modified: OffsetDateTime,
+    pub etag: String,
+}
+
+impl From<FileSystem> for ContainerInfo {
+    fn from(file_system: FileSystem) -> Self {
+        Self {
+            etag: file_system.etag.to_string(),
+            name: file_system.name,
+            last_modified: file_system.last_modified,
+        }
+    }
+}
+
+/// A container deleted within the retention period of container soft delete, restorable with
+/// [`AzureStorageBackend::restore_container`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeletedContainer {
+    pub name: String,
+    /// Tells apart several deleted containers of the same name
+    pub version: String,
+    pub deleted_on: Option<OffsetDateTime>,
+    /// Days until the service removes the container for good
+    pub remaining_retention_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainersPage {
+    #[serde(default)]
+    containers: ListedContainers,
+    #[serde(default)]
+    next_marker: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct ListedContainers {
+    #[serde(rename = "Container", default)]
+    containers: Vec<ListedContainer>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedContainer {
+    name: String,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    version: String,
+    properties: ListedContainerProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedContainerProperties {
+    #[serde(default, with = "azure_core::date::rfc1123::option")]
+    deleted_time: Option<OffsetDateTime>,
+    #[serde(default)]
+    remaining_retention_days: Option<u32>,
+}
+
+/// The deleted containers of a page of List Containers and the marker of the next page
+fn parse_deleted_containers(body: &[u8]) -> Result<(Vec<DeletedContainer>, Option<String>), azure_core::Error> {
+    let page: ContainersPage = azure_core::xml::read_xml(body)?;
+    let deleted = page.containers.containers.into_iter().filter(|container| container.deleted).map(|container| DeletedContainer {
+        name: container.name,
+        version: container.version,
+        deleted_on: container.properties.deleted_time,
+        remaining_retention_days: container.properties.remaining_retention_days,
+    });
+    Ok((deleted.collect(), page.next_marker.filter(|marker| !marker.is_empty())))
+}
+
+/// Who may read a container without credentials
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublicAccess {
+    /// Only authorized requests, the default
+    Private,
+    /// Anyone may read files whose URL they know
+    Blob,
+    /// Anyone may list the container and read its files
+    Container,
+}
+
+fn public_access(headers: &Headers) -> PublicAccess {
+    match headers.get_optional_str(&PUBLIC_ACCESS) {
+        Some("container") => PublicAccess::Container,
+        Some("blob") => PublicAccess::Blob,
+        _ => PublicAccess::Private,
+    }
+}
+
+/// Properties of a container at the time they were read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainerProperties {
+    pub last_modified: OffsetDateTime,
+    pub etag: String,
+    /// Whether the account has a hierarchical namespace, which makes directory renames atomic
+    pub namespace_enabled: bool,
+    pub public_access: PublicAccess,
+    pub metadata: Metadata,
+}
+
+/// Whether creating a container failed only because it exists, reported as `FilesystemAlreadyExists` by the data
+/// lake endpoint and `ContainerAlreadyExists` by the blob endpoint
+fn is_already_existing(error: &azure_core::Error) -> bool {
+    matches!(
+        http_status(error),
+        Some((StatusCode::Conflict, Some("FilesystemAlreadyExists" | "ContainerAlreadyExists")))
+    )
+}
+
+/// The headers of Create Container making `encryption_scope` the default of the container
+fn encryption_scope_headers(encryption_scope: &str, deny_override: bool) -> Vec<(HeaderName, String)> {
+    vec![
+        (DEFAULT_ENCRYPTION_SCOPE, encryption_scope.to_string()),
+        (DENY_ENCRYPTION_SCOPE_OVERRIDE, deny_override.to_string()),
+    ]
+}
+
+impl AzureStorageBackend {
+    /// Every container of the account in name order, for tooling that discovers containers instead of being
+    /// configured with them
+    pub async fn list_containers(&self) -> Result<Vec<ContainerInfo>, miette::Error> {
+        let mut containers = Vec::new();
+        let mut pages = self.data_lake_client().await.list_file_systems().into_stream();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(AzureStorageError::Request)?;
+            containers.extend(page.file_systems.into_iter().map(ContainerInfo::from));
+        }
+        Ok(containers)
+    }
+
+    /// Creates `container_name` unless it exists already, so bootstrap code can run on every deployment.
+    /// Returns whether the container was created by this call
+    pub async fn create_container_if_not_exists(&self, container_name: &str) -> Result<bool, miette::Error> {
+        match self.file_system_client(container_name).await.create().await {
+            Ok(_) => Ok(true),
+            Err(error) if is_already_existing(&error) => Ok(false),
+            Err(error) => Err(AzureStorageError::Request(error).into()),
+        }
+    }
+
+    /// Creates `container_name` like [`AzureStorageBackend::create_container_if_not_exists`] with
+    /// `encryption_scope` as the encryption scope of everything written to it, and with `deny_override` uploads
+    /// cannot pick another one. A container that exists already keeps its scope
+    pub async fn create_container_with_encryption_scope(
+        &self,
+        container_name: &str,
+        encryption_scope: &str,
+        deny_override: bool,
+    ) -> Result<bool, miette::Error> {
+        // Create Container takes the encryption scope headers, Create File System does not
+        let request = RestRequest::blob(Method::Put, container_name, "")
+            .query("restype", "container")
+            .headers(encryption_scope_headers(encryption_scope, deny_override));
+        match self.send_rest(request).await {
+            Ok(_) => Ok(true),
+            Err(AzureStorageError::Request(error)) if is_already_existing(&error) => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Last modification, etag, public access level and metadata of `container_name`. Public access is only
+    /// reported by the Blob REST API, so this takes a request to each endpoint
+    pub async fn get_container_properties(&self, container_name: &str) -> Result<ContainerProperties, miette::Error> {
+        let file_system_client = self.file_system_client(container_name).await;
+        let response = file_system_client.get_properties().await.map_err(AzureStorageError::Request)?;
+        let request = RestRequest::blob(Method::Head, container_name, "").query("restype", "container");
+        let headers = self.send_rest(request).await?.headers;
+
+        Ok(ContainerProperties {
+            last_modified: response.last_modified,
+            etag: response.etag.to_string(),
+            namespace_enabled: response.namespace_enabled,
+            public_access: public_access(&headers),
+            metadata: from_properties(&response.properties),
+        })
+    }
+
+    /// The soft-deleted containers of the account in name order, empty without container soft delete
+    pub async fn list_deleted_containers(&self) -> Result<Vec<DeletedContainer>, miette::Error> {
+        let mut deleted = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut request = RestRequest::blob(Method::Get, "", "").query("comp", "list").query("include", "deleted");
+            if let Some(marker) = marker.take() {
+                request = request.query("marker", marker);
+            }
+            let body = self.send_rest(request).await?.body;
+            let (containers, next_marker) = parse_deleted_containers(&body).map_err(AzureStorageError::Request)?;
+            deleted.extend(containers);
+            match next_marker {
+                Some(next_marker) => marker = Some(next_marker),
+                None => return Ok(deleted),
+            }
+        }
+    }
+
+    /// Restores the soft-deleted container `container_name` deleted as `version`, with its content and
+    /// metadata. Fails with a 409 while a container of the same name exists
+    pub async fn restore_container(&self, container_name: &str, version: &str) -> Result<(), miette::Error> {
+        let request = RestRequest::blob(Method::Put, container_name, "")
+            .query("restype", "container")
+            .query("comp", "undelete")
+            .headers([
+                (DELETED_CONTAINER_NAME, container_name.to_string()),
+                (DELETED_CONTAINER_VERSION, version.to_string()),
+            ]);
+        self.send_rest(request).await?;
+        self.log(LogLevel::Info, format_args!("Restored container {} from version {}", container_name, version));
+        Ok(())
+    }
+
+    /// Replaces all metadata of `container_name` with `metadata`
+    pub async fn set_container_metadata(&self, container_name: &str, metadata: &Metadata) -> Result<(), miette::Error> {
+        self.file_system_client(container_name)
+            .await
+            .set_properties(to_properties(metadata))
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(())
+    }
+
+    /// The metadata of `container_name`, empty when it has none
+    pub async fn get_container_metadata(&self, container_name: &str) -> Result<Metadata, miette::Error> {
+        let response = self
+            .file_system_client(container_name)
+            .await
+            .get_properties()
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(from_properties(&response.properties))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encryption_scope_headers() {
+        assert_eq!(
+            encryption_scope_headers("tenant-a", true),
+            [
+                (DEFAULT_ENCRYPTION_SCOPE, "tenant-a".to_string()),
+                (DENY_ENCRYPTION_SCOPE_OVERRIDE, "true".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listed_file_systems_become_containers() {
+        let file_system: FileSystem =
+            serde_json::from_str(r#"{"name": "raw", "lastModified": "Tue, 14 Oct 2025 12:00:00 GMT", "etag": "0x8D"}"#).unwrap();
+        let container = ContainerInfo::from(file_system);
+        assert_eq!((container.name.as_str(), container.etag.as_str()), ("raw", "0x8D"));
+    }
+
+    #[test]
+    fn test_public_access_defaults_to_private() {
+        let mut headers = Headers::new();
+        assert_eq!(public_access(&headers), PublicAccess::Private);
+        headers.insert(PUBLIC_ACCESS, "container");
+        assert_eq!(public_access(&headers), PublicAccess::Container);
+    }
+
+    #[test]
+    fn test_only_deleted_containers_are_restorable() {
+        let body = br#"<?xml version="1.0" encoding="utf-8"?>
+            <EnumerationResults ServiceEndpoint="https://account.blob.core.windows.net/">
+              <Containers>
+                <Container><Name>raw</Name><Properties><Last-Modified>Tue, 14 Oct 2025 12:00:00 GMT</Last-Modified></Properties></Container>
+                <Container><Name>raw</Name><Deleted>true</Deleted><Version>01D60F8BB59A4652</Version>
+                  <Properties><DeletedTime>Tue, 14 Oct 2025 13:00:00 GMT</DeletedTime><RemainingRetentionDays>6</RemainingRetentionDays></Properties>
+                </Container>
+              </Containers>
+              <NextMarker />
+            </EnumerationResults>"#;
+        let (deleted, marker) = parse_deleted_containers(body).unwrap();
+        assert_eq!(marker, None);
+        assert_eq!(deleted.len(), 1);
+        assert_eq!((deleted[0].name.as_str(), deleted[0].version.as_str()), ("raw", "01D60F8BB59A4652"));
+        assert_eq!(deleted[0].remaining_retention_days, Some(6));
+    }
+
+    #[test]
+    fn test_only_conflicts_on_the_container_count_as_existing() {
+        let conflict = |error_code: &str| {
+            let kind = azure_core::error::ErrorKind::HttpResponse {
+                status: StatusCode::Conflict,
+                error_code: Some(error_code.to_string()),
+            };
+            azure_core::Error::new(kind, "conflict")
+        };
+        assert!(is_already_existing(&conflict("FilesystemAlreadyExists")));
+        assert!(is_already_existing(&conflict("ContainerAlreadyExists")));
+        assert!(!is_already_existing(&conflict("ContainerBeingDeleted")));
+    }
+}
@@ -0,0 +1,118 @@
+//! Headers of single requests and responses exchanged through the request context, for what the SDK's
+//! operation builders and response types do not cover
+use std::sync::{Arc, Mutex};
+
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::{Context, Policy, PolicyResult, Request};
+
+/// Put into the context of a request to send it with these additional headers
+#[derive(Debug, Default)]
+pub(crate) struct RequestHeaders(pub(crate) Vec<(HeaderName, String)>);
+
+impl RequestHeaders {
+    /// A context sending these headers, `None` when there are none
+    pub(crate) fn into_context(self) -> Option<Context> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let mut context = Context::new();
+        context.insert(self);
+        Some(context)
+    }
+}
+
+/// Put into the context of a request to send it without these headers, for operations whose builder always
+/// sets a header the request must not carry
+#[derive(Debug, Default)]
+pub(crate) struct OmitHeaders(pub(crate) Vec<HeaderName>);
+
+/// Put into the context of a request to get the raw headers of its last response
+#[derive(Debug, Default)]
+pub(crate) struct ResponseHeaders(Mutex<Option<Headers>>);
+
+impl ResponseHeaders {
+    pub(crate) fn take(&self) -> Option<Headers> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Applies the [`RequestHeaders`] and fills the [`ResponseHeaders`] of requests that carry them, leaves every
+/// other request alone. Placed before the signing policy so the signature covers the added headers
+#[derive(Debug)]
+pub(crate) struct ContextHeadersPolicy;
+
+#[async_trait::async_trait]
+impl Policy for ContextHeadersPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        if let Some(OmitHeaders(omitted)) = ctx.get::<OmitHeaders>() {
+            omit_headers(request, omitted);
+        }
+        if let Some(RequestHeaders(headers)) = ctx.get::<RequestHeaders>() {
+            for (name, value) in headers {
+                request.insert_header(name.clone(), value.clone());
+            }
+        }
+        let response = next[0].send(ctx, request, &next[1..]).await?;
+        if let Some(capture) = ctx.get::<ResponseHeaders>() {
+            *capture.0.lock().unwrap() = Some(response.headers().clone());
+        }
+        Ok(response)
+    }
+}
+
+/// Drops the `omitted` headers of `request`. Requests cannot remove headers, so this rebuilds it without them
+fn omit_headers(request: &mut Request, omitted: &[HeaderName]) {
+    let mut rebuilt = Request::new(request.url().clone(), *request.method());
+    for (name, value) in request.headers().iter().filter(|(name, _)| !omitted.contains(name)) {
+        rebuilt.insert_header(name.clone(), value.clone());
+    }
+    rebuilt.set_body(request.body().clone());
+    *request = rebuilt;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl Policy for Echo {
+        async fn send(&self, _ctx: &Context, request: &mut Request, _next: &[Arc<dyn Policy>]) -> PolicyResult {
+            Ok(azure_core::Response::new(
+                azure_core::StatusCode::Ok,
+                request.headers().clone(),
+                Box::pin(azure_core::BytesStream::new_empty()),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_headers_travel_through_the_context() {
+        const CACHE_CONTROL: HeaderName = HeaderName::from_static("x-ms-cache-control");
+        let mut context = Context::new();
+        context.insert(RequestHeaders(vec![(CACHE_CONTROL, "max-age=60".to_string())]));
+        context.insert(ResponseHeaders::default());
+
+        let url = url::Url::parse("https://account.dfs.core.windows.net/data/file.csv").unwrap();
+        let mut request = Request::new(url, azure_core::Method::Put);
+        let next: Vec<Arc<dyn Policy>> = vec![Arc::new(Echo)];
+        ContextHeadersPolicy.send(&context, &mut request, &next).await.unwrap();
+
+        let headers = context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).unwrap();
+        assert_eq!(headers.get_optional_str(&CACHE_CONTROL), Some("max-age=60"));
+    }
+
+    #[test]
+    fn test_omitted_headers_are_dropped() {
+        let url = url::Url::parse("https://account.dfs.core.windows.net/data/file.csv").unwrap();
+        let mut request = Request::new(url, azure_core::Method::Patch);
+        request.insert_header(azure_core::headers::ACL, "user::rwx");
+        request.insert_header(azure_core::headers::CONTENT_LENGTH, "0");
+        omit_headers(&mut request, &[azure_core::headers::ACL]);
+        assert_eq!(request.headers().get_optional_str(&azure_core::headers::ACL), None);
+        assert_eq!(request.headers().get_optional_str(&azure_core::headers::CONTENT_LENGTH), Some("0"));
+    }
+}
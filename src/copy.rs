@@ -0,0 +1,177 @@
+//! Server side copies of files within the account, through the Blob REST API's Copy Blob
+use std::time::Duration;
+
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::{Context, Method};
+use url::Url;
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::ResponseHeaders;
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+use crate::rest::{endpoint_url, RestRequest};
+use crate::sdk::rest::ServiceType;
+
+const COPY_SOURCE: HeaderName = HeaderName::from_static("x-ms-copy-source");
+const COPY_ID: HeaderName = HeaderName::from_static("x-ms-copy-id");
+const COPY_STATUS: HeaderName = HeaderName::from_static("x-ms-copy-status");
+const COPY_STATUS_DESCRIPTION: HeaderName = HeaderName::from_static("x-ms-copy-status-description");
+
+/// How a copy is waited for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyOptions {
+    pub(crate) wait: bool,
+    pub(crate) poll_interval: Duration,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            wait: true,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Polls the destination until the copy finished, on by default. Copies within an account usually finish
+    /// before the request returns, large ones can stay pending for a while
+    pub fn wait(mut self, wait: bool) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyStatus {
+    Pending,
+    Success,
+    Aborted,
+    Failed,
+}
+
+impl CopyStatus {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "success" => Some(Self::Success),
+            "aborted" => Some(Self::Aborted),
+            "failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// State of a copy when [`AzureStorageBackend::copy`] returned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyReceipt {
+    pub copy_id: Option<String>,
+    pub status: CopyStatus,
+}
+
+/// Status of the copy that last wrote to a file, from its response headers
+fn copy_status(headers: &Headers) -> Option<CopyStatus> {
+    headers.get_optional_str(&COPY_STATUS).and_then(CopyStatus::parse)
+}
+
+impl AzureStorageBackend {
+    /// Copies the file at `source` to `destination`, which may be in another container of the account, without
+    /// the content passing through this process. Any file at the destination is replaced. A copy that ends
+    /// aborted or failed is an error
+    pub async fn copy(
+        &self,
+        source_container: &str,
+        source: &str,
+        destination_container: &str,
+        destination: &str,
+        options: CopyOptions,
+    ) -> Result<CopyReceipt, miette::Error> {
+        let source_url = self.blob_url(source_container, source);
+        let receipt = self.copy_from_url(source_url, destination_container, destination, options).await?;
+        self.log(
+            LogLevel::Info,
+            format_args!("Copied {}/{} to {}/{}: {:?}", source_container, source, destination_container, destination, receipt.status),
+        );
+        Ok(receipt)
+    }
+
+    /// [`AzureStorageBackend::copy`] from the blob endpoint URL `source_url`, which may select a version or
+    /// snapshot of the source
+    pub(crate) async fn copy_from_url(
+        &self,
+        source_url: Url,
+        destination_container: &str,
+        destination: &str,
+        options: CopyOptions,
+    ) -> Result<CopyReceipt, AzureStorageError> {
+        let request = RestRequest::blob(Method::Put, destination_container, destination).headers([(COPY_SOURCE, source_url.to_string())]);
+        let headers = self.send_rest(request).await?.headers;
+        self.append_positions.forget(destination_container, destination);
+        let copy_id = headers.get_optional_string(&COPY_ID);
+        let mut status = copy_status(&headers).unwrap_or(CopyStatus::Pending);
+        let mut description = None;
+
+        let destination_client = self.file_system_client(destination_container).await.get_file_client(destination);
+        while options.wait && status == CopyStatus::Pending {
+            tokio::time::sleep(options.poll_interval).await;
+            let mut context = Context::new();
+            context.insert(ResponseHeaders::default());
+            destination_client
+                .get_properties()
+                .context(context.clone())
+                .await
+                .map_err(AzureStorageError::Request)?;
+            let headers = context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).unwrap_or_default();
+            status = copy_status(&headers).unwrap_or(CopyStatus::Pending);
+            description = headers.get_optional_string(&COPY_STATUS_DESCRIPTION);
+        }
+
+        if matches!(status, CopyStatus::Aborted | CopyStatus::Failed) {
+            return Err(AzureStorageError::CopyFailed {
+                path: destination.to_string(),
+                description: description.unwrap_or_else(|| format!("{:?}", status)),
+            });
+        }
+        Ok(CopyReceipt { copy_id, status })
+    }
+
+    /// The file at `path` on the blob endpoint, the container itself when `path` is empty
+    pub(crate) fn blob_url(&self, container_name: &str, path: &str) -> Url {
+        endpoint_url(&self.config, ServiceType::Blob, container_name, path)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    #[test]
+    fn test_copy_status_is_read_from_the_headers() {
+        let mut headers = Headers::new();
+        assert_eq!(copy_status(&headers), None);
+        headers.insert(COPY_STATUS, "pending");
+        assert_eq!(copy_status(&headers), Some(CopyStatus::Pending));
+        headers.insert(COPY_STATUS, "failed");
+        assert_eq!(copy_status(&headers), Some(CopyStatus::Failed));
+    }
+
+    #[tokio::test]
+    async fn test_copies_are_sent_to_the_blob_endpoint() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "content");
+        let backend = service.backend();
+        let receipt = backend.copy("raw", "data/a.csv", "curated", "b.csv", CopyOptions::default()).await.unwrap();
+
+        assert_eq!(receipt.status, CopyStatus::Success);
+        assert_eq!(service.file("curated/b.csv").as_deref(), Some(&b"content"[..]));
+        assert_eq!(service.requests(), ["PUT blob:/curated/b.csv?"]);
+    }
+}
@@ -0,0 +1,293 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use azure_core::auth::TokenCredential;
+use azure_core::error::{ErrorKind, ResultExt};
+use azure_core::headers::{HeaderName, Headers, AUTHORIZATION};
+use azure_core::{Context, HttpClient, Method, Policy, PolicyResult, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use url::Url;
+
+use crate::error::AzureStorageError;
+use crate::logging::{LogLevel, LogSettings};
+
+const KEY_VAULT_RESOURCE: &str = "https://vault.azure.net";
+const KEY_VAULT_API_VERSION: &str = "7.4";
+const ERROR_CODE: HeaderName = HeaderName::from_static("x-ms-error-code");
+
+/// Headers that are part of the shared key signature in this order, before the canonicalized `x-ms-` headers
+const SIGNED_HEADERS: [&str; 11] = [
+    "content-encoding",
+    "content-language",
+    "content-length",
+    "content-md5",
+    "content-type",
+    "date",
+    "if-modified-since",
+    "if-match",
+    "if-none-match",
+    "if-unmodified-since",
+    "range",
+];
+
+/// A Key Vault secret holding the access key of the storage account
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyVaultAccountKey {
+    pub(crate) vault_url: String,
+    pub(crate) secret_name: String,
+}
+
+impl KeyVaultAccountKey {
+    /// The latest version of secret `secret_name` in the vault at `vault_url`, e.g. `https://contoso.vault.azure.net`
+    pub fn new(vault_url: impl Into<String>, secret_name: impl Into<String>) -> Self {
+        Self {
+            vault_url: vault_url.into(),
+            secret_name: secret_name.into(),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), AzureStorageError> {
+        let https = Url::parse(&self.vault_url).is_ok_and(|url| url.scheme() == "https" && url.has_host());
+        if !https {
+            return Err(AzureStorageError::InvalidCredentialConfig(format!(
+                "Key Vault URL {:?} is not an https URL such as https://contoso.vault.azure.net",
+                self.vault_url
+            )));
+        }
+        if self.secret_name.trim().is_empty() || self.secret_name.contains('/') {
+            return Err(AzureStorageError::InvalidCredentialConfig(format!(
+                "invalid Key Vault secret name {:?}",
+                self.secret_name
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cache_key(&self) -> String {
+        format!("{}/{}", self.vault_url.trim_end_matches('/'), self.secret_name)
+    }
+
+    pub(crate) fn secret_url(&self) -> Result<Url, url::ParseError> {
+        let mut url = Url::parse(&self.vault_url)?.join(&format!("secrets/{}", self.secret_name))?;
+        url.query_pairs_mut().append_pair("api-version", KEY_VAULT_API_VERSION);
+        Ok(url)
+    }
+}
+
+#[derive(Deserialize)]
+struct SecretBundle {
+    value: String,
+}
+
+#[derive(Clone)]
+struct FetchedKey {
+    key: Arc<String>,
+    generation: u64,
+}
+
+/// The account key as last fetched from Key Vault, fetched again when the service stops accepting it
+pub(crate) struct RotatingAccountKey {
+    http_client: Arc<dyn HttpClient>,
+    credential: Arc<dyn TokenCredential>,
+    secret: KeyVaultAccountKey,
+    current: RwLock<Option<FetchedKey>>,
+    fetching: Mutex<()>,
+}
+
+impl std::fmt::Debug for RotatingAccountKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RotatingAccountKey").field("secret", &self.secret).finish()
+    }
+}
+
+impl RotatingAccountKey {
+    /// Reads `secret` with tokens from `credential`, which must be allowed to get secrets from the vault
+    pub(crate) fn new(credential: Arc<dyn TokenCredential>, secret: KeyVaultAccountKey) -> Self {
+        Self {
+            http_client: azure_core::new_http_client(),
+            credential,
+            secret,
+            current: RwLock::new(None),
+            fetching: Mutex::new(()),
+        }
+    }
+
+    async fn current(&self) -> azure_core::Result<FetchedKey> {
+        if let Some(key) = self.current.read().await.clone() {
+            return Ok(key);
+        }
+        self.refetch(0).await
+    }
+
+    /// Fetches the key again unless another request already replaced `generation` in the meantime
+    async fn refetch(&self, generation: u64) -> azure_core::Result<FetchedKey> {
+        let _fetching = self.fetching.lock().await;
+        if let Some(key) = self.current.read().await.clone() {
+            if key.generation > generation {
+                return Ok(key);
+            }
+        }
+
+        let key = FetchedKey {
+            key: Arc::new(self.fetch().await?),
+            generation: generation + 1,
+        };
+        *self.current.write().await = Some(key.clone());
+        Ok(key)
+    }
+
+    async fn fetch(&self) -> azure_core::Result<String> {
+        let url = self
+            .secret
+            .secret_url()
+            .with_context(ErrorKind::Credential, || format!("invalid Key Vault secret {}", self.secret.cache_key()))?;
+        let token = self.credential.get_token(KEY_VAULT_RESOURCE).await?;
+        let mut request = Request::new(url, Method::Get);
+        request.insert_header(AUTHORIZATION, format!("Bearer {}", token.token.secret()));
+
+        let (status, _headers, body) = self.http_client.execute_request(&request).await?.deconstruct();
+        let body = body.collect().await?;
+        if !status.is_success() {
+            return Err(ErrorKind::http_response_from_body(status, &body).into_error()).with_context(ErrorKind::Credential, || {
+                format!("failed to read account key from Key Vault secret {}", self.secret.cache_key())
+            });
+        }
+        Ok(serde_json::from_slice::<SecretBundle>(&body)?.value)
+    }
+
+    /// Fetches the key now, so the first request does not wait for Key Vault
+    pub(crate) async fn warm_up(&self) -> azure_core::Result<()> {
+        self.current().await.map(|_| ())
+    }
+}
+
+/// Signs requests with the shared key from Key Vault. A request rejected with `AuthenticationFailed` is taken
+/// as a sign of a rotated key: the key is fetched again and the request resent once with the new signature.
+/// The SDK's authorization policy must be anonymous for this to be the only signature. Requests that already
+/// carry a bearer token, for operations only Azure AD may call, are sent as they are.
+#[derive(Debug)]
+pub(crate) struct SharedKeyPolicy {
+    account: String,
+    key: Arc<RotatingAccountKey>,
+    log_settings: Arc<LogSettings>,
+}
+
+impl SharedKeyPolicy {
+    pub(crate) fn new(account: impl Into<String>, key: Arc<RotatingAccountKey>, log_settings: Arc<LogSettings>) -> Self {
+        Self {
+            account: account.into(),
+            key,
+            log_settings,
+        }
+    }
+
+    fn sign(&self, request: &mut Request, key: &str) -> azure_core::Result<()> {
+        let string_to_sign = string_to_sign(request.headers(), request.url(), request.method(), &self.account);
+        let signature = crate::sdk::storage::sign(&string_to_sign, key)?;
+        request.insert_header(AUTHORIZATION, format!("SharedKey {}:{}", self.account, signature));
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for SharedKeyPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        if request.headers().get_optional_str(&AUTHORIZATION).is_some_and(|authorization| authorization.starts_with("Bearer ")) {
+            return next[0].send(ctx, request, &next[1..]).await;
+        }
+        let key = self.key.current().await?;
+        self.sign(request, &key.key)?;
+        let response = next[0].send(ctx, request, &next[1..]).await?;
+
+        let rejected = response.status() == StatusCode::Forbidden
+            && response.headers().get_optional_str(&ERROR_CODE) == Some("AuthenticationFailed");
+        if !rejected {
+            return Ok(response);
+        }
+
+        self.log_settings.event(
+            LogLevel::Info,
+            format_args!("Account key of {} was rejected, fetching it from Key Vault again", self.account),
+        );
+        let key = self.key.refetch(key.generation).await?;
+        self.sign(request, &key.key)?;
+        next[0].send(ctx, request, &next[1..]).await
+    }
+}
+
+/// The shared key string to sign of the blob and data lake services
+fn string_to_sign(headers: &Headers, url: &Url, method: &Method, account: &str) -> String {
+    let mut lines = vec![method.as_ref().to_string()];
+    for name in SIGNED_HEADERS {
+        let value = headers.get_optional_str(&HeaderName::from_static(name)).unwrap_or_default();
+        // a zero content length is signed as empty since version 2015-02-21
+        lines.push(if name == "content-length" && value == "0" { String::new() } else { value.to_string() });
+    }
+
+    let mut ms_headers: Vec<(&str, &str)> = headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-ms-"))
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    ms_headers.sort_unstable();
+    let mut canonicalized = String::new();
+    for (name, value) in ms_headers {
+        canonicalized.push_str(&format!("{}:{}\n", name, value));
+    }
+
+    format!("{}\n{}{}", lines.join("\n"), canonicalized, canonicalized_resource(account, url))
+}
+
+fn canonicalized_resource(account: &str, url: &Url) -> String {
+    let mut parameters: Vec<(String, Cow<'_, str>)> = url.query_pairs().map(|(name, value)| (name.to_lowercase(), value)).collect();
+    parameters.sort();
+
+    let mut resource = format!("/{}{}", account, url.path());
+    let mut previous: Option<&str> = None;
+    for (name, value) in &parameters {
+        if previous == Some(name.as_str()) {
+            resource.push_str(&format!(",{}", value));
+        } else {
+            resource.push_str(&format!("\n{}:{}", name, value));
+        }
+        previous = Some(name);
+    }
+    resource
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_url() {
+        let secret = KeyVaultAccountKey::new("https://contoso.vault.azure.net/", "storage-key");
+        assert_eq!(
+            secret.secret_url().unwrap().as_str(),
+            "https://contoso.vault.azure.net/secrets/storage-key?api-version=7.4"
+        );
+        assert_eq!(secret.cache_key(), "https://contoso.vault.azure.net/storage-key");
+        assert!(secret.validate().is_ok());
+
+        assert!(KeyVaultAccountKey::new("http://contoso.vault.azure.net", "storage-key").validate().is_err());
+        assert!(KeyVaultAccountKey::new("https://contoso.vault.azure.net", "").validate().is_err());
+    }
+
+    #[test]
+    fn test_string_to_sign() {
+        let mut headers = Headers::new();
+        headers.insert("content-length", "0");
+        headers.insert("x-ms-version", "2019-12-12");
+        headers.insert("x-ms-date", "Mon, 01 Jan 2024 00:00:00 GMT");
+        let url = Url::parse("https://account.dfs.core.windows.net/container/dir/file?resource=file&Timeout=30").unwrap();
+
+        assert_eq!(
+            string_to_sign(&headers, &url, &Method::Put, "account"),
+            "PUT\n\n\n\n\n\n\n\n\n\n\n\n\
+             x-ms-date:Mon, 01 Jan 2024 00:00:00 GMT\nx-ms-version:2019-12-12\n\
+             /account/container/dir/file\nresource:file\ntimeout:30"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+use super::STORAGE_TOKEN_RESOURCE;
+use crate::logging::{LogLevel, LogSettings};
+
+/// Shortest pause between two refresh rounds, also used to retry a failed refresh
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Keeps the tokens it handed out fresh from a background task, renewing each one `margin` before it expires
+pub(crate) struct BackgroundRefreshCredential {
+    credential: Arc<dyn TokenCredential>,
+    margin: Duration,
+    tokens: Mutex<HashMap<String, TokenResponse>>,
+    log_settings: Arc<LogSettings>,
+}
+
+impl BackgroundRefreshCredential {
+    /// Wraps `credential` and starts the refresh task, which stops once the returned credential is dropped. The
+    /// storage token is acquired straight away so not even the first request waits for it.
+    pub(crate) fn spawn(credential: Arc<dyn TokenCredential>, margin: Duration, log_settings: Arc<LogSettings>) -> Arc<Self> {
+        let refreshing = Arc::new(Self::new(credential, margin, log_settings));
+        let weak = Arc::downgrade(&refreshing);
+        tokio::spawn(async move {
+            if let Some(refreshing) = weak.upgrade() {
+                if let Err(error) = refreshing.get_token(STORAGE_TOKEN_RESOURCE).await {
+                    refreshing.log_settings.event(
+                        LogLevel::Error,
+                        format_args!("Failed to acquire storage token in background: {}", error),
+                    );
+                }
+            }
+            run(weak).await
+        });
+        refreshing
+    }
+
+    fn new(credential: Arc<dyn TokenCredential>, margin: Duration, log_settings: Arc<LogSettings>) -> Self {
+        Self {
+            credential,
+            margin,
+            tokens: Mutex::new(HashMap::new()),
+            log_settings,
+        }
+    }
+
+    fn is_due(&self, token: &TokenResponse, now: OffsetDateTime) -> bool {
+        token.expires_on <= now + self.margin
+    }
+
+    /// Renews every token that is due and returns how long to wait until the next one is
+    async fn refresh_due(&self) -> Duration {
+        let now = OffsetDateTime::now_utc();
+        let due: Vec<String> = {
+            let tokens = self.tokens.lock().await;
+            tokens
+                .iter()
+                .filter(|(_, token)| self.is_due(token, now))
+                .map(|(resource, _)| resource.clone())
+                .collect()
+        };
+
+        // fetched without holding the lock, requests keep using the old token meanwhile
+        for resource in due {
+            match self.credential.get_token(&resource).await {
+                Ok(token) => {
+                    self.tokens.lock().await.insert(resource, token);
+                }
+                Err(error) => {
+                    self.log_settings.event(
+                        LogLevel::Error,
+                        format_args!("Background refresh of token for {} failed: {}", resource, error),
+                    );
+                }
+            }
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let tokens = self.tokens.lock().await;
+        tokens
+            .values()
+            .map(|token| (token.expires_on - self.margin - now).try_into().unwrap_or(Duration::ZERO))
+            .min()
+            .unwrap_or(MIN_REFRESH_INTERVAL)
+            .max(MIN_REFRESH_INTERVAL)
+    }
+}
+
+async fn run(weak: Weak<BackgroundRefreshCredential>) {
+    loop {
+        let wait = match weak.upgrade() {
+            Some(refreshing) => refreshing.refresh_due().await,
+            None => return,
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for BackgroundRefreshCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut tokens = self.tokens.lock().await;
+        if let Some(token) = tokens.get(resource) {
+            if token.expires_on > OffsetDateTime::now_utc() + MIN_REFRESH_INTERVAL {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = self.credential.get_token(resource).await?;
+        tokens.insert(resource.to_string(), token.clone());
+        Ok(token)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use azure_core::auth::AccessToken;
+
+    struct CountingCredential {
+        calls: AtomicUsize,
+        lifetime: Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl TokenCredential for CountingCredential {
+        async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(TokenResponse::new(
+                AccessToken::new(format!("token-{}", call)),
+                OffsetDateTime::now_utc() + self.lifetime,
+            ))
+        }
+    }
+
+    fn refreshing(lifetime: Duration, margin: Duration) -> (Arc<CountingCredential>, BackgroundRefreshCredential) {
+        let credential = Arc::new(CountingCredential { calls: AtomicUsize::new(0), lifetime });
+        (credential.clone(), BackgroundRefreshCredential::new(credential, margin, Arc::default()))
+    }
+
+    #[tokio::test]
+    async fn test_due_tokens_are_renewed_ahead_of_use() -> azure_core::Result<()> {
+        let (credential, refreshing) = refreshing(Duration::from_secs(10 * 60), Duration::from_secs(15 * 60));
+        refreshing.get_token(STORAGE_TOKEN_RESOURCE).await?;
+
+        assert_eq!(refreshing.refresh_due().await, MIN_REFRESH_INTERVAL);
+        assert_eq!(credential.calls.load(Ordering::SeqCst), 2);
+
+        // requests are served the renewed token without going to the credential
+        let token = refreshing.get_token(STORAGE_TOKEN_RESOURCE).await?;
+        assert_eq!(token.token.secret(), "token-2");
+        assert_eq!(credential.calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_next_refresh_is_scheduled_before_expiry() -> azure_core::Result<()> {
+        let (credential, refreshing) = refreshing(Duration::from_secs(60 * 60), Duration::from_secs(5 * 60));
+        refreshing.get_token(STORAGE_TOKEN_RESOURCE).await?;
+
+        let wait = refreshing.refresh_due().await;
+        assert_eq!(credential.calls.load(Ordering::SeqCst), 1);
+        assert!(wait > Duration::from_secs(54 * 60) && wait <= Duration::from_secs(55 * 60), "{:?}", wait);
+        Ok(())
+    }
+}
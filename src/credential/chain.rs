@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind};
+
+use super::CredentialSource;
+use crate::logging::{LogLevel, LogSettings};
+
+/// Which source of a credential chain served the last token request, and why the sources before it failed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CredentialReport {
+    pub resource: String,
+    /// `None` when every source failed
+    pub source: Option<CredentialSource>,
+    /// The sources tried before `source`, with their error messages
+    pub failures: Vec<(CredentialSource, String)>,
+}
+
+/// The latest [`CredentialReport`] of the chains of one client
+#[derive(Debug, Default)]
+pub(crate) struct CredentialDiagnostics {
+    last: Mutex<Option<CredentialReport>>,
+    log_settings: Arc<LogSettings>,
+}
+
+impl CredentialDiagnostics {
+    pub(crate) fn new(log_settings: Arc<LogSettings>) -> Self {
+        Self {
+            last: Mutex::default(),
+            log_settings,
+        }
+    }
+
+    pub(crate) fn last_report(&self) -> Option<CredentialReport> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Stores `report`, logging it when a different source than before answered
+    fn record(&self, report: CredentialReport) {
+        let mut last = self.last.lock().unwrap();
+        let changed = last.as_ref().map(|last| last.source) != Some(report.source);
+        if changed {
+            match report.source {
+                Some(source) => self.log_settings.event(
+                    LogLevel::Info,
+                    format_args!("Acquired token for {} from {} credential", report.resource, source.name()),
+                ),
+                None => self
+                    .log_settings
+                    .event(LogLevel::Error, format_args!("No credential source could acquire a token for {}", report.resource)),
+            }
+        }
+        *last = Some(report);
+    }
+}
+
+/// The first credential of a list that returns a token, mirroring `DefaultAzureCredential` for credentials
+/// it has no variant for
+pub(crate) struct ChainCredential {
+    credentials: Vec<(CredentialSource, Arc<dyn TokenCredential>)>,
+    diagnostics: Arc<CredentialDiagnostics>,
+}
+
+impl ChainCredential {
+    pub(crate) fn new(credentials: Vec<(CredentialSource, Arc<dyn TokenCredential>)>, diagnostics: Arc<CredentialDiagnostics>) -> Self {
+        Self { credentials, diagnostics }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for ChainCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut report = CredentialReport {
+            resource: resource.to_string(),
+            source: None,
+            failures: Vec::with_capacity(self.credentials.len()),
+        };
+        for (source, credential) in &self.credentials {
+            match credential.get_token(resource).await {
+                Ok(token) => {
+                    report.source = Some(*source);
+                    self.diagnostics.record(report);
+                    return Ok(token);
+                }
+                Err(error) => report.failures.push((*source, error.to_string())),
+            }
+        }
+
+        let errors: Vec<String> = report.failures.iter().map(|(source, error)| format!("{}: {}", source.name(), error)).collect();
+        self.diagnostics.record(report);
+        Err(Error::with_message(ErrorKind::Credential, || {
+            format!("no credential in the chain returned a token: {}", errors.join("; "))
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use azure_core::auth::AccessToken;
+    use time::OffsetDateTime;
+
+    struct FixedCredential(bool);
+
+    #[async_trait::async_trait]
+    impl TokenCredential for FixedCredential {
+        async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+            match self.0 {
+                true => Ok(TokenResponse::new(AccessToken::new("token"), OffsetDateTime::now_utc())),
+                false => Err(Error::message(ErrorKind::Credential, "not configured")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_names_the_source_used() -> azure_core::Result<()> {
+        let diagnostics = Arc::new(CredentialDiagnostics::default());
+        let chain = ChainCredential::new(
+            vec![
+                (CredentialSource::Environment, Arc::new(FixedCredential(false))),
+                (CredentialSource::AzureCli, Arc::new(FixedCredential(true))),
+            ],
+            diagnostics.clone(),
+        );
+        chain.get_token("https://storage.azure.com/").await?;
+
+        let report = diagnostics.last_report().unwrap();
+        assert_eq!(report.source, Some(CredentialSource::AzureCli));
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, CredentialSource::Environment);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failure_is_reported_per_source() {
+        let diagnostics = Arc::new(CredentialDiagnostics::default());
+        let chain = ChainCredential::new(vec![(CredentialSource::ManagedIdentity, Arc::new(FixedCredential(false)))], diagnostics.clone());
+
+        let error = chain.get_token("https://storage.azure.com/").await.unwrap_err();
+        assert!(error.to_string().contains("managed_identity: "), "{}", error);
+        assert_eq!(diagnostics.last_report().unwrap().source, None);
+    }
+}
@@ -0,0 +1,134 @@
+//! Hooks for consumers' tests to break token refresh on purpose, enabled by the `testing` feature
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind};
+
+use crate::backend::AzureStorageBackend;
+use crate::sdk::identity::AutoRefreshingTokenCredential;
+
+/// Fails a configured number of token acquisitions before passing them on
+struct FailingCredential {
+    credential: Arc<dyn TokenCredential>,
+    failures: AtomicU32,
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for FailingCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let injected = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| failures.checked_sub(1))
+            .is_ok();
+        if injected {
+            return Err(Error::message(ErrorKind::Credential, "injected token refresh failure"));
+        }
+        self.credential.get_token(resource).await
+    }
+}
+
+/// Stands in for the `AutoRefreshingTokenCredential` of a client. Expiring the tokens swaps in an empty
+/// refreshing credential, so the next request has to acquire a token again exactly as after a real expiry.
+pub(crate) struct FaultInjectingCredential {
+    failing: Arc<FailingCredential>,
+    refreshing: RwLock<Arc<AutoRefreshingTokenCredential>>,
+}
+
+impl std::fmt::Debug for FaultInjectingCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FaultInjectingCredential")
+            .field("failures", &self.failing.failures.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl FaultInjectingCredential {
+    pub(crate) fn new(credential: Arc<dyn TokenCredential>) -> Self {
+        let failing = Arc::new(FailingCredential {
+            credential,
+            failures: AtomicU32::new(0),
+        });
+        Self {
+            refreshing: RwLock::new(Arc::new(AutoRefreshingTokenCredential::new(failing.clone()))),
+            failing,
+        }
+    }
+
+    fn expire(&self) {
+        *self.refreshing.write().unwrap() = Arc::new(AutoRefreshingTokenCredential::new(self.failing.clone()));
+    }
+
+    fn fail_next(&self, count: u32) {
+        self.failing.failures.store(count, Ordering::SeqCst);
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for FaultInjectingCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let refreshing = self.refreshing.read().unwrap().clone();
+        refreshing.get_token(resource).await
+    }
+}
+
+impl AzureStorageBackend {
+    /// Drops the cached tokens of this backend's client, so its next request refreshes the token mid-operation.
+    /// Tokens kept by a persistent cache or background refresh survive and are served to the refresh.
+    pub fn expire_cached_tokens(&self) {
+        self.token_credential.expire();
+    }
+
+    /// Makes the next `count` token refreshes of this backend's client fail with a credential error
+    pub fn fail_next_token_refreshes(&self, count: u32) {
+        self.token_credential.fail_next(count);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    use azure_core::auth::AccessToken;
+    use time::OffsetDateTime;
+
+    struct CountingCredential {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl TokenCredential for CountingCredential {
+        async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(TokenResponse::new(
+                AccessToken::new(format!("token-{}", call)),
+                OffsetDateTime::now_utc() + std::time::Duration::from_secs(3600),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expiry_forces_refresh() -> azure_core::Result<()> {
+        let credential = FaultInjectingCredential::new(Arc::new(CountingCredential { calls: AtomicUsize::new(0) }));
+        assert_eq!(credential.get_token("https://storage.azure.com/").await?.token.secret(), "token-1");
+        assert_eq!(credential.get_token("https://storage.azure.com/").await?.token.secret(), "token-1");
+
+        credential.expire();
+        assert_eq!(credential.get_token("https://storage.azure.com/").await?.token.secret(), "token-2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures_are_used_up() -> azure_core::Result<()> {
+        let credential = FaultInjectingCredential::new(Arc::new(CountingCredential { calls: AtomicUsize::new(0) }));
+        credential.fail_next(2);
+
+        assert!(credential.get_token("https://storage.azure.com/").await.is_err());
+        assert!(credential.get_token("https://storage.azure.com/").await.is_err());
+        assert_eq!(credential.get_token("https://storage.azure.com/").await?.token.secret(), "token-1");
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::auth::{AccessToken, TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind, ResultExt};
+use azure_core::{HttpClient, Method, Request};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use oauth2::{CsrfToken, PkceCodeChallenge};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::error::AzureStorageError;
+use crate::logging::{LogLevel, LogSettings};
+
+/// Public client id of the Azure CLI, which is pre-authorised for Azure Storage in every tenant
+const DEFAULT_CLIENT_ID: &str = "04b07795-8ddb-461a-bbee-02f9e1bf7b46";
+const DEFAULT_TENANT_ID: &str = "organizations";
+/// How long the user has to complete the sign-in before the browser's redirect is no longer waited for
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Options for signing in through the system browser
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InteractiveBrowserOptions {
+    pub(crate) client_id: String,
+    pub(crate) tenant_id: String,
+    pub(crate) redirect_port: u16,
+    pub(crate) open_browser: bool,
+}
+
+impl Default for InteractiveBrowserOptions {
+    fn default() -> Self {
+        Self {
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            tenant_id: DEFAULT_TENANT_ID.to_string(),
+            redirect_port: 0,
+            open_browser: true,
+        }
+    }
+}
+
+impl InteractiveBrowserOptions {
+    /// Application (client) id of a public client app registration. Defaults to the Azure CLI's client id
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = client_id.into();
+        self
+    }
+
+    /// Tenant to sign in to. Defaults to `organizations`, i.e. any work or school account
+    pub fn tenant_id(mut self, tenant_id: impl Into<String>) -> Self {
+        self.tenant_id = tenant_id.into();
+        self
+    }
+
+    /// Local port the `http://localhost` redirect is received on. `0` picks a free port
+    pub fn redirect_port(mut self, redirect_port: u16) -> Self {
+        self.redirect_port = redirect_port;
+        self
+    }
+
+    /// Whether to launch the system browser. When disabled the sign-in URL is only printed
+    pub fn open_browser(mut self, open_browser: bool) -> Self {
+        self.open_browser = open_browser;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), AzureStorageError> {
+        if self.client_id.trim().is_empty() {
+            return Err(AzureStorageError::InvalidCredentialConfig("interactive browser client id is empty".to_string()));
+        }
+        if self.tenant_id.trim().is_empty() {
+            return Err(AzureStorageError::InvalidCredentialConfig("interactive browser tenant id is empty".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Token endpoint payload shared by the authorization code and refresh token grants
+#[derive(Deserialize)]
+struct TokenEndpointResponse {
+    access_token: String,
+    expires_in: u64,
+    refresh_token: Option<String>,
+}
+
+/// Authorization code flow with PKCE against a loopback redirect. After the first sign-in the refresh token is
+/// kept in memory so later refreshes do not reopen the browser.
+pub(crate) struct InteractiveBrowserCredential {
+    http_client: Arc<dyn HttpClient>,
+    options: InteractiveBrowserOptions,
+    authority_host: String,
+    refresh_token: Mutex<Option<String>>,
+    log_settings: Arc<LogSettings>,
+}
+
+impl InteractiveBrowserCredential {
+    /// `authority_host` is the sign-in endpoint of the cloud the tenant lives in, e.g. `https://login.microsoftonline.us`
+    pub(crate) fn new(options: InteractiveBrowserOptions, authority_host: &str, log_settings: Arc<LogSettings>) -> Self {
+        Self {
+            http_client: azure_core::new_http_client(),
+            options,
+            authority_host: authority_host.trim_end_matches('/').to_string(),
+            refresh_token: Mutex::new(None),
+            log_settings,
+        }
+    }
+
+    fn endpoint(&self, name: &str) -> azure_core::Result<Url> {
+        Url::parse(&format!("{}/{}/oauth2/v2.0/{}", self.authority_host, self.options.tenant_id, name))
+            .with_context(ErrorKind::Credential, || {
+                format!("failed to construct {} endpoint for tenant {}", name, self.options.tenant_id)
+            })
+    }
+
+    async fn redeem(&self, params: &[(&str, &str)]) -> azure_core::Result<TokenEndpointResponse> {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("client_id", &self.options.client_id)
+            .extend_pairs(params)
+            .finish();
+
+        let mut request = Request::new(self.endpoint("token")?, Method::Post);
+        request.insert_header("content-type", "application/x-www-form-urlencoded");
+        request.set_body(body);
+
+        let response = self.http_client.execute_request(&request).await?;
+        let (status, _headers, body) = response.deconstruct();
+        let body = body.collect().await?;
+        if !status.is_success() {
+            return Err(ErrorKind::http_response_from_body(status, &body).into_error())
+                .context(ErrorKind::Credential, "token endpoint rejected the request");
+        }
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn sign_in(&self, scope: &str) -> azure_core::Result<TokenEndpointResponse> {
+        let listener = TcpListener::bind(("127.0.0.1", self.options.redirect_port))
+            .await
+            .context(ErrorKind::Credential, "failed to listen for the sign-in redirect")?;
+        let port = listener
+            .local_addr()
+            .context(ErrorKind::Credential, "failed to listen for the sign-in redirect")?
+            .port();
+        let redirect_uri = format!("http://localhost:{}", port);
+
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let state = CsrfToken::new_random();
+
+        let mut authorize_url = self.endpoint("authorize")?;
+        authorize_url
+            .query_pairs_mut()
+            .append_pair("client_id", &self.options.client_id)
+            .append_pair("response_type", "code")
+            .append_pair("response_mode", "query")
+            .append_pair("redirect_uri", &redirect_uri)
+            .append_pair("scope", scope)
+            .append_pair("state", state.secret())
+            .append_pair("code_challenge", pkce_challenge.as_str())
+            .append_pair("code_challenge_method", "S256");
+
+        println!("Sign in to Azure at: {}", authorize_url);
+        if self.options.open_browser {
+            if let Err(error) = open_browser(authorize_url.as_str()) {
+                println!("Could not open a browser ({}), open the URL above manually", error);
+            }
+        }
+
+        let code = receive_authorization_code(&listener, state.secret(), REDIRECT_TIMEOUT).await?;
+
+        self.redeem(&[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", &redirect_uri),
+            ("scope", scope),
+            ("code_verifier", pkce_verifier.secret()),
+        ])
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for InteractiveBrowserCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let scope = scope_for(resource);
+
+        // held for the whole acquisition so concurrent callers don't each open a browser
+        let mut refresh_token = self.refresh_token.lock().await;
+
+        let mut response = None;
+        if let Some(token) = refresh_token.as_deref() {
+            match self.redeem(&[("grant_type", "refresh_token"), ("refresh_token", token), ("scope", &scope)]).await {
+                Ok(refreshed) => response = Some(refreshed),
+                Err(error) => {
+                    self.log_settings.event(
+                        LogLevel::Info,
+                        format_args!("Refresh token rejected, signing in again: {}", error),
+                    );
+                }
+            }
+        }
+        let response = match response {
+            Some(response) => response,
+            None => self.sign_in(&scope).await?,
+        };
+
+        if response.refresh_token.is_some() {
+            *refresh_token = response.refresh_token;
+        }
+
+        Ok(TokenResponse::new(
+            AccessToken::new(response.access_token),
+            OffsetDateTime::now_utc() + Duration::from_secs(response.expires_in),
+        ))
+    }
+}
+
+/// Converts the v1 style resource passed by the storage pipeline into a v2 endpoint scope
+fn scope_for(resource: &str) -> String {
+    format!("{}/.default offline_access", resource.trim_end_matches('/'))
+}
+
+/// Waits up to `timeout` for the browser to be redirected back to the loopback listener and extracts the
+/// authorization code
+async fn receive_authorization_code(listener: &TcpListener, expected_state: &str, timeout: Duration) -> azure_core::Result<String> {
+    let (stream, request_line) = tokio::time::timeout(timeout, accept_redirect(listener))
+        .await
+        .map_err(|_| Error::with_message(ErrorKind::Credential, || format!("no sign-in redirect received within {:?}", timeout)))??;
+    answer_redirect(stream, parse_redirect(&request_line, expected_state)).await
+}
+
+/// The connection carrying the redirect and its request line. Browsers may open connections they send nothing on,
+/// or ask for other paths such as a favicon, so every connection is read at the same time and those without the
+/// redirect are answered with a 404 and dropped
+async fn accept_redirect(listener: &TcpListener) -> azure_core::Result<(TcpStream, String)> {
+    let mut reads = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted.context(ErrorKind::Credential, "failed to accept the sign-in redirect")?;
+                reads.push(read_request_line(stream));
+            }
+            Some((mut stream, request_line)) = reads.next(), if !reads.is_empty() => match request_line {
+                Ok(request_line) if is_redirect(&request_line) => return Ok((stream, request_line)),
+                _ => {
+                    let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n").await;
+                }
+            },
+        }
+    }
+}
+
+async fn read_request_line(mut stream: TcpStream) -> (TcpStream, std::io::Result<String>) {
+    let mut request_line = String::new();
+    let read = BufReader::new(&mut stream).read_line(&mut request_line).await;
+    (stream, read.map(|_| request_line))
+}
+
+/// Whether `request_line` is the sign-in redirect, which carries the state and either a code or an error
+fn is_redirect(request_line: &str) -> bool {
+    let Some(target) = request_line.split_whitespace().nth(1) else {
+        return false;
+    };
+    Url::parse(&format!("http://localhost{}", target))
+        .is_ok_and(|url| url.query_pairs().any(|(key, _)| key == "state" || key == "code" || key == "error"))
+}
+
+/// Tells the browser how the sign-in went and returns `result`
+async fn answer_redirect(mut stream: TcpStream, result: azure_core::Result<String>) -> azure_core::Result<String> {
+
+    let message = match result {
+        Ok(_) => "Authentication complete. You can close this window now.",
+        Err(_) => "Authentication failed. Return to the application for details.",
+    };
+    let response = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}", message.len(), message);
+    // the browser may already have gone away, the code is what matters
+    let _ = stream.write_all(response.as_bytes()).await;
+
+    result
+}
+
+fn parse_redirect(request_line: &str, expected_state: &str) -> azure_core::Result<String> {
+    let target = request_line.split_whitespace().nth(1).ok_or_else(|| {
+        Error::with_message(ErrorKind::Credential, || format!("unexpected redirect request: {}", request_line))
+    })?;
+    let url = Url::parse(&format!("http://localhost{}", target))
+        .context(ErrorKind::Credential, "failed to parse the sign-in redirect")?;
+    let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned());
+
+    if let Some(error) = param("error") {
+        let description = param("error_description").unwrap_or_default();
+        return Err(Error::with_message(ErrorKind::Credential, || {
+            format!("sign-in failed: {} {}", error, description)
+        }));
+    }
+    if param("state").as_deref() != Some(expected_state) {
+        return Err(Error::message(ErrorKind::Credential, "sign-in redirect state does not match the request"));
+    }
+    param("code").ok_or_else(|| Error::message(ErrorKind::Credential, "sign-in redirect is missing the code"))
+}
+
+fn open_browser(url: &str) -> std::io::Result<()> {
+    // `cmd /C start` would split the URL at its `&`s, the URL handler takes it as a single argument
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut command = std::process::Command::new("rundll32");
+        command.args(["url.dll,FileProtocolHandler", url]);
+        command
+    };
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = std::process::Command::new("open");
+        command.arg(url);
+        command
+    };
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let mut command = {
+        let mut command = std::process::Command::new("xdg-open");
+        command.arg(url);
+        command
+    };
+
+    command.spawn().map(|_| ())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_for_storage_resource() {
+        assert_eq!(scope_for("https://storage.azure.com/"), "https://storage.azure.com/.default offline_access");
+    }
+
+    #[test]
+    fn test_parse_redirect() {
+        let code = parse_redirect("GET /?code=abc&state=xyz HTTP/1.1", "xyz").unwrap();
+        assert_eq!(code, "abc");
+
+        assert!(parse_redirect("GET /?code=abc&state=other HTTP/1.1", "xyz").is_err());
+        assert!(parse_redirect("GET /?error=access_denied&state=xyz HTTP/1.1", "xyz").is_err());
+        assert!(parse_redirect("garbage", "xyz").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_waiting_for_the_redirect_times_out() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let error = receive_authorization_code(&listener, "xyz", Duration::from_millis(20)).await.unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::Credential);
+    }
+
+    #[tokio::test]
+    async fn test_connections_without_the_redirect_are_skipped() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // a speculative preconnect that never sends a request
+        let _idle = TcpStream::connect(address).await.unwrap();
+        let mut favicon = TcpStream::connect(address).await.unwrap();
+        favicon.write_all(b"GET /favicon.ico HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut redirect = TcpStream::connect(address).await.unwrap();
+        redirect.write_all(b"GET /?code=abc&state=xyz HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let code = receive_authorization_code(&listener, "xyz", Duration::from_secs(10)).await.unwrap();
+        assert_eq!(code, "abc");
+    }
+
+    #[test]
+    fn test_validate_rejects_empty_ids() {
+        assert!(InteractiveBrowserOptions::default().validate().is_ok());
+        assert!(InteractiveBrowserOptions::default().client_id("").validate().is_err());
+        assert!(InteractiveBrowserOptions::default().tenant_id(" ").validate().is_err());
+    }
+
+    #[test]
+    fn test_endpoints_use_authority_host() {
+        let options = InteractiveBrowserOptions::default().tenant_id("contoso.onmicrosoft.us");
+        let credential = InteractiveBrowserCredential::new(options, "https://login.microsoftonline.us/", Arc::default());
+        assert_eq!(
+            credential.endpoint("token").unwrap().as_str(),
+            "https://login.microsoftonline.us/contoso.onmicrosoft.us/oauth2/v2.0/token"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use azure_core::auth::{AccessToken, TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind, ResultExt};
+use azure_core::headers::{AUTHORIZATION, WWW_AUTHENTICATE};
+use azure_core::{HttpClient, Method, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use url::Url;
+
+const IMDS_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const IMDS_API_VERSION: &str = "2018-02-01";
+const APP_SERVICE_API_VERSION: &str = "2019-08-01";
+const ARC_ENDPOINT: &str = "http://localhost:40342/metadata/identity/oauth2/token";
+const ARC_API_VERSION: &str = "2020-06-01";
+const IDENTITY_ENDPOINT_ENV_KEY: &str = "IDENTITY_ENDPOINT";
+const IDENTITY_HEADER_ENV_KEY: &str = "IDENTITY_HEADER";
+/// Largest challenge file the agent writes, as enforced by the official SDKs
+const ARC_MAX_KEY_SIZE: u64 = 4096;
+
+/// The managed identity endpoint of the hosting environment
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ManagedIdentityEndpoint {
+    /// The instance metadata service of Azure VMs and scale sets
+    Imds,
+    /// App Service and Azure Functions, from `IDENTITY_ENDPOINT` and `IDENTITY_HEADER`
+    AppService,
+    /// The local agent of Azure Arc enabled servers, at `IDENTITY_ENDPOINT` when set
+    AzureArc,
+    /// Another endpoint speaking the IMDS protocol, e.g. a proxy or a local emulator
+    Custom(String),
+}
+
+impl ManagedIdentityEndpoint {
+    pub(crate) fn cache_key(&self) -> String {
+        match self {
+            ManagedIdentityEndpoint::Imds => "imds".to_string(),
+            ManagedIdentityEndpoint::AppService => "app_service".to_string(),
+            ManagedIdentityEndpoint::AzureArc => "azure_arc".to_string(),
+            ManagedIdentityEndpoint::Custom(url) => format!("custom:{}", url),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExpiresOn {
+    Seconds(i64),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+struct ManagedIdentityTokenResponse {
+    access_token: String,
+    expires_on: ExpiresOn,
+}
+
+impl ManagedIdentityTokenResponse {
+    fn into_token(self) -> azure_core::Result<TokenResponse> {
+        let seconds = match self.expires_on {
+            ExpiresOn::Seconds(seconds) => seconds,
+            ExpiresOn::Text(text) => text
+                .parse()
+                .with_context(ErrorKind::DataConversion, || format!("invalid expires_on {}", text))?,
+        };
+        let expires_on = OffsetDateTime::from_unix_timestamp(seconds).context(ErrorKind::DataConversion, "invalid expires_on")?;
+        Ok(TokenResponse::new(AccessToken::new(self.access_token), expires_on))
+    }
+}
+
+/// Managed identity tokens from an explicitly selected endpoint instead of the SDK's guess from the environment
+pub(crate) struct ManagedIdentityCredential {
+    http_client: Arc<dyn HttpClient>,
+    endpoint: ManagedIdentityEndpoint,
+}
+
+impl ManagedIdentityCredential {
+    pub(crate) fn new(endpoint: ManagedIdentityEndpoint) -> Self {
+        Self {
+            http_client: azure_core::new_http_client(),
+            endpoint,
+        }
+    }
+
+    fn request(&self, resource: &str) -> azure_core::Result<Request> {
+        let environment_endpoint = || {
+            std::env::var(IDENTITY_ENDPOINT_ENV_KEY).with_context(ErrorKind::Credential, || {
+                format!("missing managed identity endpoint in {} environment variable", IDENTITY_ENDPOINT_ENV_KEY)
+            })
+        };
+        let (endpoint, api_version) = match &self.endpoint {
+            ManagedIdentityEndpoint::Imds => (IMDS_ENDPOINT.to_string(), IMDS_API_VERSION),
+            ManagedIdentityEndpoint::AppService => (environment_endpoint()?, APP_SERVICE_API_VERSION),
+            ManagedIdentityEndpoint::AzureArc => (environment_endpoint().unwrap_or_else(|_| ARC_ENDPOINT.to_string()), ARC_API_VERSION),
+            ManagedIdentityEndpoint::Custom(endpoint) => (endpoint.clone(), IMDS_API_VERSION),
+        };
+
+        let url = Url::parse_with_params(&endpoint, [("api-version", api_version), ("resource", resource)])
+            .with_context(ErrorKind::Credential, || format!("invalid managed identity endpoint {}", endpoint))?;
+        let mut request = Request::new(url, Method::Get);
+        match &self.endpoint {
+            ManagedIdentityEndpoint::AppService => {
+                let identity_header = std::env::var(IDENTITY_HEADER_ENV_KEY).with_context(ErrorKind::Credential, || {
+                    format!("missing managed identity secret in {} environment variable", IDENTITY_HEADER_ENV_KEY)
+                })?;
+                request.insert_header("x-identity-header", identity_header);
+            }
+            _ => request.insert_header("metadata", "true"),
+        }
+        Ok(request)
+    }
+}
+
+/// Arc answers the first request with a challenge naming a file only local administrators can read
+fn arc_challenge_path(www_authenticate: &str) -> Option<&str> {
+    www_authenticate
+        .strip_prefix("Basic realm=")
+        .map(|path| path.trim().trim_matches('"'))
+        .filter(|path| !path.is_empty())
+}
+
+/// The directory the agent writes its challenge files to
+fn arc_token_directory() -> azure_core::Result<PathBuf> {
+    if cfg!(windows) {
+        let program_data = std::env::var("ProgramData").with_context(ErrorKind::Credential, || "missing ProgramData environment variable")?;
+        Ok(Path::new(&program_data).join("AzureConnectedMachineAgent").join("Tokens"))
+    } else {
+        Ok(PathBuf::from("/var/opt/azcmagent/tokens"))
+    }
+}
+
+/// Reads the challenge file at `path`, which must be a `.key` file of at most 4096 bytes directly inside
+/// `token_directory`, so whatever answers on the identity endpoint cannot have other local files sent to it
+async fn read_arc_secret(path: &str, token_directory: &Path) -> azure_core::Result<String> {
+    let rejected = || Error::with_message(ErrorKind::Credential, || format!("refusing Azure Arc challenge file {}", path));
+    let token_directory = tokio::fs::canonicalize(token_directory).await.map_err(|_| rejected())?;
+    let canonical = tokio::fs::canonicalize(path).await.map_err(|_| rejected())?;
+    if canonical.parent() != Some(token_directory.as_path()) || canonical.extension().and_then(|extension| extension.to_str()) != Some("key") {
+        return Err(rejected());
+    }
+    let metadata = tokio::fs::metadata(&canonical).await.map_err(|_| rejected())?;
+    if !metadata.is_file() || metadata.len() > ARC_MAX_KEY_SIZE {
+        return Err(rejected());
+    }
+    tokio::fs::read_to_string(&canonical)
+        .await
+        .with_context(ErrorKind::Credential, || format!("failed to read Azure Arc challenge file {}", path))
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for ManagedIdentityCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut request = self.request(resource)?;
+        let mut response = self.http_client.execute_request(&request).await?;
+
+        if self.endpoint == ManagedIdentityEndpoint::AzureArc && response.status() == StatusCode::Unauthorized {
+            let challenge = response.headers().get_optional_str(&WWW_AUTHENTICATE).unwrap_or_default().to_string();
+            let path = arc_challenge_path(&challenge)
+                .ok_or_else(|| Error::message(ErrorKind::Credential, "Azure Arc did not send an authentication challenge"))?;
+            let secret = read_arc_secret(path, &arc_token_directory()?).await?;
+            request.insert_header(AUTHORIZATION, format!("Basic {}", secret.trim()));
+            response = self.http_client.execute_request(&request).await?;
+        }
+
+        let (status, _headers, body) = response.deconstruct();
+        let body = body.collect().await?;
+        if !status.is_success() {
+            return Err(ErrorKind::http_response_from_body(status, &body).into_error())
+                .with_context(ErrorKind::Credential, || format!("managed identity endpoint {} refused the token request", self.endpoint.cache_key()));
+        }
+
+        serde_json::from_slice::<ManagedIdentityTokenResponse>(&body)?.into_token()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imds_style_requests() -> azure_core::Result<()> {
+        let request = ManagedIdentityCredential::new(ManagedIdentityEndpoint::Imds).request("https://storage.azure.com/")?;
+        assert_eq!(
+            request.url().as_str(),
+            "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01&resource=https%3A%2F%2Fstorage.azure.com%2F"
+        );
+        assert_eq!(request.headers().get_optional_str(&"metadata".into()), Some("true"));
+
+        let custom = ManagedIdentityEndpoint::Custom("http://localhost:8080/token".to_string());
+        let request = ManagedIdentityCredential::new(custom).request("https://storage.azure.com/")?;
+        assert!(request.url().as_str().starts_with("http://localhost:8080/token?api-version=2018-02-01"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_arc_challenge_path() {
+        assert_eq!(arc_challenge_path("Basic realm=/var/opt/azcmagent/tokens/abc.key"), Some("/var/opt/azcmagent/tokens/abc.key"));
+        assert_eq!(arc_challenge_path("Bearer authorization_uri=x"), None);
+        assert_eq!(arc_challenge_path("Basic realm="), None);
+    }
+
+    #[tokio::test]
+    async fn test_only_small_key_files_in_the_token_directory_are_read() {
+        let root = std::env::temp_dir().join(format!("arc-{}", uuid::Uuid::new_v4()));
+        let token_directory = root.join("tokens");
+        std::fs::create_dir_all(&token_directory).unwrap();
+        std::fs::write(token_directory.join("abc.key"), "secret\n").unwrap();
+        std::fs::write(token_directory.join("abc.txt"), "secret").unwrap();
+        std::fs::write(token_directory.join("big.key"), vec![b'a'; 4097]).unwrap();
+        std::fs::write(root.join("outside.key"), "other").unwrap();
+        let read = |path: PathBuf| {
+            let token_directory = token_directory.clone();
+            async move { read_arc_secret(path.to_str().unwrap(), &token_directory).await }
+        };
+
+        assert_eq!(read(token_directory.join("abc.key")).await.unwrap(), "secret\n");
+        assert!(read(PathBuf::from("/etc/shadow")).await.is_err());
+        assert!(read(token_directory.join("../outside.key")).await.is_err());
+        assert!(read(token_directory.join("abc.txt")).await.is_err());
+        assert!(read(token_directory.join("big.key")).await.is_err());
+        assert!(read(token_directory.join("missing.key")).await.is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_expires_on_as_string_or_number() -> azure_core::Result<()> {
+        let text: ManagedIdentityTokenResponse = serde_json::from_str(r#"{"access_token": "t", "expires_on": "1586984735"}"#)?;
+        assert_eq!(text.into_token()?.expires_on.unix_timestamp(), 1586984735);
+
+        let number: ManagedIdentityTokenResponse = serde_json::from_str(r#"{"access_token": "t", "expires_on": 1586984735}"#)?;
+        assert_eq!(number.into_token()?.expires_on.unix_timestamp(), 1586984735);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Selection of the token credential used to authenticate the cached data lake clients
+mod account_key;
+mod background_refresh;
+mod chain;
+#[cfg(any(test, feature = "testing"))]
+mod fault_injection;
+mod interactive_browser;
+mod managed_identity;
+mod scope;
+mod tenant;
+mod token_cache;
+mod token_retry;
+
+use std::sync::Arc;
+
+use azure_core::auth::TokenCredential;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AzureStorageError;
+use crate::logging::LogSettings;
+use crate::sdk::identity::{AzureCliCredential, EnvironmentCredential, ImdsManagedIdentityCredential, TokenCredentialOptions};
+
+pub use account_key::KeyVaultAccountKey;
+pub(crate) use account_key::{RotatingAccountKey, SharedKeyPolicy};
+pub(crate) use background_refresh::BackgroundRefreshCredential;
+pub use chain::CredentialReport;
+pub(crate) use chain::CredentialDiagnostics;
+#[cfg(any(test, feature = "testing"))]
+pub(crate) use fault_injection::FaultInjectingCredential;
+pub use interactive_browser::InteractiveBrowserOptions;
+pub(crate) use interactive_browser::InteractiveBrowserCredential;
+pub use managed_identity::ManagedIdentityEndpoint;
+pub(crate) use scope::{resource_for_scope, ScopedCredential};
+pub(crate) use tenant::TenantFallbackCredential;
+pub use token_cache::TokenCacheOptions;
+pub(crate) use token_cache::PersistentTokenCache;
+pub use token_retry::TokenRetryOptions;
+pub(crate) use token_retry::RetryingCredential;
+
+/// Resource the storage pipeline requests tokens for
+pub(crate) const STORAGE_TOKEN_RESOURCE: &str = "https://storage.azure.com/";
+
+/// The sources of `DefaultAzureCredential`, in the order it tries them
+const DEFAULT_SOURCES: [CredentialSource; 3] =
+    [CredentialSource::Environment, CredentialSource::ManagedIdentity, CredentialSource::AzureCli];
+
+/// Where the credentials of a backend sign in, whichever source ends up issuing the token
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SignInOptions {
+    pub(crate) token_options: TokenCredentialOptions,
+    pub(crate) managed_identity: Option<ManagedIdentityEndpoint>,
+    /// Shared by every chain built from these options
+    pub(crate) diagnostics: Arc<CredentialDiagnostics>,
+    /// Of the client the credentials sign in for
+    pub(crate) log_settings: Arc<LogSettings>,
+}
+
+impl SignInOptions {
+    fn managed_identity_credential(&self) -> Arc<dyn TokenCredential> {
+        match &self.managed_identity {
+            Some(endpoint) => Arc::new(managed_identity::ManagedIdentityCredential::new(endpoint.clone())),
+            None => Arc::new(ImdsManagedIdentityCredential::default()),
+        }
+    }
+}
+
+/// The credential a backend authenticates with
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CredentialKind {
+    /// `DefaultAzureCredential`: environment, managed identity and then Azure CLI
+    #[default]
+    Default,
+    /// Sign in interactively through the system browser with the end user's own identity
+    InteractiveBrowser(InteractiveBrowserOptions),
+    /// The sources of `DefaultAzureCredential` restricted to the given ones, tried in the given order
+    Chain(Vec<CredentialSource>),
+}
+
+/// A source `DefaultAzureCredential` can take a token from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CredentialSource {
+    /// Service principal configured through `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET`
+    Environment,
+    /// Managed identity, from IMDS unless another endpoint is configured. Probing IMDS outside of Azure costs a
+    /// timeout on every token request
+    ManagedIdentity,
+    /// The signed in `az` CLI user
+    AzureCli,
+}
+
+impl CredentialSource {
+    fn name(&self) -> &'static str {
+        match self {
+            CredentialSource::Environment => "environment",
+            CredentialSource::ManagedIdentity => "managed_identity",
+            CredentialSource::AzureCli => "azure_cli",
+        }
+    }
+
+    fn credential(&self, sign_in: &SignInOptions) -> Arc<dyn TokenCredential> {
+        match self {
+            CredentialSource::Environment => {
+                Arc::new(EnvironmentCredential::new(azure_core::new_http_client(), sign_in.token_options.clone()))
+            }
+            CredentialSource::ManagedIdentity => sign_in.managed_identity_credential(),
+            CredentialSource::AzureCli => Arc::new(AzureCliCredential::new()),
+        }
+    }
+}
+
+impl CredentialKind {
+    /// Identifies the credential within the client cache. Two backends for the same account only share a
+    /// client when they would authenticate the same way, so this must never contain secrets.
+    pub(crate) fn cache_key(&self) -> String {
+        match self {
+            CredentialKind::Default => "default".to_string(),
+            CredentialKind::InteractiveBrowser(options) => {
+                format!("interactive_browser:{}:{}", options.tenant_id, options.client_id)
+            }
+            CredentialKind::Chain(sources) => {
+                let names: Vec<&str> = sources.iter().map(CredentialSource::name).collect();
+                format!("chain:{}", names.join(","))
+            }
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), AzureStorageError> {
+        match self {
+            CredentialKind::Default => Ok(()),
+            CredentialKind::InteractiveBrowser(options) => options.validate(),
+            CredentialKind::Chain(sources) if sources.is_empty() => {
+                Err(AzureStorageError::InvalidCredentialConfig("credential chain has no sources".to_string()))
+            }
+            CredentialKind::Chain(_) => Ok(()),
+        }
+    }
+
+    /// The credential signing in through the authority host of `sign_in`. The Azure CLI and managed
+    /// identities pick their cloud themselves (`az cloud set`, the IMDS of the VM)
+    pub(crate) fn token_credential(&self, sign_in: &SignInOptions) -> Arc<dyn TokenCredential> {
+        let chain = |sources: &[CredentialSource]| -> Arc<dyn TokenCredential> {
+            Arc::new(chain::ChainCredential::new(
+                sources.iter().map(|source| (*source, source.credential(sign_in))).collect(),
+                sign_in.diagnostics.clone(),
+            ))
+        };
+        match self {
+            CredentialKind::Default => chain(&DEFAULT_SOURCES),
+            CredentialKind::InteractiveBrowser(options) => {
+                let authority_host = sign_in.token_options.authority_host();
+                Arc::new(InteractiveBrowserCredential::new(options.clone(), authority_host, Arc::clone(&sign_in.log_settings)))
+            }
+            CredentialKind::Chain(sources) => chain(sources),
+        }
+    }
+
+    /// Like [`CredentialKind::token_credential`] but signing in to `tenant_id` instead of the tenant the
+    /// environment, CLI login or browser options default to
+    pub(crate) fn tenant_credential(&self, tenant_id: &str, sign_in: &SignInOptions) -> Arc<dyn TokenCredential> {
+        let chain = |sources: &[CredentialSource]| -> Arc<dyn TokenCredential> {
+            Arc::new(chain::ChainCredential::new(
+                sources.iter().map(|source| (*source, source.tenant_credential(tenant_id, sign_in))).collect(),
+                sign_in.diagnostics.clone(),
+            ))
+        };
+        match self {
+            CredentialKind::Default => chain(&DEFAULT_SOURCES),
+            CredentialKind::InteractiveBrowser(options) => Arc::new(InteractiveBrowserCredential::new(
+                options.clone().tenant_id(tenant_id),
+                sign_in.token_options.authority_host(),
+                Arc::clone(&sign_in.log_settings),
+            )),
+            CredentialKind::Chain(sources) => chain(sources),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_key_distinguishes_credentials() {
+        let default_key = CredentialKind::Default.cache_key();
+        let browser_key = CredentialKind::InteractiveBrowser(InteractiveBrowserOptions::default()).cache_key();
+        assert_ne!(default_key, browser_key);
+
+        let other_tenant = InteractiveBrowserOptions::default().tenant_id("contoso.onmicrosoft.com");
+        assert_ne!(browser_key, CredentialKind::InteractiveBrowser(other_tenant).cache_key());
+    }
+
+    #[test]
+    fn test_chain_order_is_part_of_cache_key() {
+        let cli_first = CredentialKind::Chain(vec![CredentialSource::AzureCli, CredentialSource::Environment]);
+        let env_first = CredentialKind::Chain(vec![CredentialSource::Environment, CredentialSource::AzureCli]);
+        assert_eq!(cli_first.cache_key(), "chain:azure_cli,environment");
+        assert_ne!(cli_first.cache_key(), env_first.cache_key());
+    }
+
+    #[test]
+    fn test_empty_chain_is_rejected() {
+        assert!(CredentialKind::Chain(Vec::new()).validate().is_err());
+        assert!(CredentialKind::Chain(vec![CredentialSource::AzureCli]).validate().is_ok());
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+
+use super::STORAGE_TOKEN_RESOURCE;
+use crate::error::AzureStorageError;
+
+/// Resource every credential turns into a `<resource>/.default` scope itself
+pub(crate) fn resource_for_scope(scope: &str) -> Result<String, AzureStorageError> {
+    let resource = scope.trim().trim_end_matches("/.default");
+    match url::Url::parse(resource) {
+        Ok(url) if url.scheme() == "https" && url.has_host() => Ok(resource.to_string()),
+        _ => Err(AzureStorageError::InvalidCredentialConfig(format!(
+            "token scope {:?} is not an https audience such as https://storage.azure.com/.default",
+            scope
+        ))),
+    }
+}
+
+/// Requests storage tokens for another audience than the public cloud one the storage pipeline asks for
+pub(crate) struct ScopedCredential {
+    credential: Arc<dyn TokenCredential>,
+    resource: String,
+}
+
+impl ScopedCredential {
+    pub(crate) fn new(credential: Arc<dyn TokenCredential>, resource: String) -> Self {
+        Self { credential, resource }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for ScopedCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        if resource == STORAGE_TOKEN_RESOURCE {
+            self.credential.get_token(&self.resource).await
+        } else {
+            self.credential.get_token(resource).await
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use azure_core::auth::AccessToken;
+    use time::OffsetDateTime;
+
+    #[derive(Default)]
+    struct RecordingCredential {
+        resources: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TokenCredential for RecordingCredential {
+        async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+            self.resources.lock().unwrap().push(resource.to_string());
+            Ok(TokenResponse::new(AccessToken::new("token"), OffsetDateTime::now_utc()))
+        }
+    }
+
+    #[test]
+    fn test_scopes_are_normalised_to_resources() {
+        assert_eq!(resource_for_scope("https://storage.usgovcloudapi.net/.default").unwrap(), "https://storage.usgovcloudapi.net");
+        assert_eq!(resource_for_scope("https://myaccount.blob.core.chinacloudapi.cn/").unwrap(), "https://myaccount.blob.core.chinacloudapi.cn/");
+        assert!(resource_for_scope("storage.azure.com").is_err());
+        assert!(resource_for_scope("http://storage.azure.com/.default").is_err());
+        assert!(resource_for_scope("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_only_storage_resource_is_replaced() -> azure_core::Result<()> {
+        let recording = Arc::new(RecordingCredential::default());
+        let scoped = ScopedCredential::new(recording.clone(), "https://storage.usgovcloudapi.net".to_string());
+
+        scoped.get_token(STORAGE_TOKEN_RESOURCE).await?;
+        scoped.get_token("https://vault.azure.net").await?;
+        assert_eq!(*recording.resources.lock().unwrap(), vec!["https://storage.usgovcloudapi.net", "https://vault.azure.net"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::process::Command;
+use std::sync::Arc;
+
+use azure_core::auth::{AccessToken, TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind, ResultExt};
+use serde::Deserialize;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use crate::sdk::identity::{ClientSecretCredential, TokenCredentialOptions};
+
+use super::{CredentialSource, SignInOptions};
+
+const AZURE_CLIENT_ID_ENV_KEY: &str = "AZURE_CLIENT_ID";
+const AZURE_CLIENT_SECRET_ENV_KEY: &str = "AZURE_CLIENT_SECRET";
+
+impl CredentialSource {
+    /// Like [`CredentialSource::credential`] but signing in to `tenant_id`. Managed identities always belong to
+    /// the tenant of the Azure resource they are assigned to, so the tenant does not apply to them
+    pub(crate) fn tenant_credential(&self, tenant_id: &str, sign_in: &SignInOptions) -> Arc<dyn TokenCredential> {
+        match self {
+            CredentialSource::Environment => Arc::new(EnvironmentTenantCredential {
+                tenant_id: tenant_id.to_string(),
+                token_options: sign_in.token_options.clone(),
+            }),
+            CredentialSource::ManagedIdentity => sign_in.managed_identity_credential(),
+            CredentialSource::AzureCli => Arc::new(AzureCliTenantCredential { tenant_id: tenant_id.to_string() }),
+        }
+    }
+}
+
+/// A service principal from `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET`, ignoring `AZURE_TENANT_ID` in favour of
+/// the configured tenant
+struct EnvironmentTenantCredential {
+    tenant_id: String,
+    token_options: TokenCredentialOptions,
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for EnvironmentTenantCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let client_id = std::env::var(AZURE_CLIENT_ID_ENV_KEY).with_context(ErrorKind::Credential, || {
+            format!("missing client id set in {} environment variable", AZURE_CLIENT_ID_ENV_KEY)
+        })?;
+        let client_secret = std::env::var(AZURE_CLIENT_SECRET_ENV_KEY).with_context(ErrorKind::Credential, || {
+            format!("missing client secret set in {} environment variable", AZURE_CLIENT_SECRET_ENV_KEY)
+        })?;
+
+        ClientSecretCredential::new(
+            azure_core::new_http_client(),
+            self.tenant_id.clone(),
+            client_id,
+            client_secret,
+            self.token_options.clone(),
+        )
+        .get_token(resource)
+        .await
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CliTokenResponse {
+    access_token: String,
+    /// Local time without offset, kept for CLI versions older than 2.54 which lack `expires_on`
+    expires_on: Option<String>,
+    #[serde(rename = "expires_on")]
+    expires_on_timestamp: Option<i64>,
+}
+
+impl CliTokenResponse {
+    fn expires_on(&self) -> azure_core::Result<OffsetDateTime> {
+        if let Some(timestamp) = self.expires_on_timestamp {
+            return OffsetDateTime::from_unix_timestamp(timestamp).context(ErrorKind::DataConversion, "invalid expires_on");
+        }
+        let expires_on = self
+            .expires_on
+            .as_deref()
+            .ok_or_else(|| Error::message(ErrorKind::DataConversion, "az token response has no expiry"))?;
+        let format = time::format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second].[subsecond]")
+            .context(ErrorKind::DataConversion, "invalid expiresOn format")?;
+        let local = PrimitiveDateTime::parse(expires_on, &format)
+            .with_context(ErrorKind::DataConversion, || format!("unable to parse expiresOn {}", expires_on))?;
+        Ok(azure_core::date::assume_local(&local))
+    }
+}
+
+/// `az account get-access-token --tenant`, which the SDK's CLI credential cannot pass
+struct AzureCliTenantCredential {
+    tenant_id: String,
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for AzureCliTenantCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut command = if cfg!(target_os = "windows") {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "az"]);
+            command
+        } else {
+            Command::new("az")
+        };
+        command.args(["account", "get-access-token", "--output", "json", "--resource", resource, "--tenant", &self.tenant_id]);
+
+        let output = command.output().context(ErrorKind::Credential, "failed to run the Azure CLI")?;
+        if !output.status.success() {
+            return Err(Error::with_message(ErrorKind::Credential, || {
+                format!(
+                    "'az account get-access-token --tenant {}' failed: {}",
+                    self.tenant_id,
+                    String::from_utf8_lossy(&output.stderr)
+                )
+            }));
+        }
+
+        let response: CliTokenResponse = serde_json::from_slice(&output.stdout)?;
+        let expires_on = response.expires_on()?;
+        Ok(TokenResponse::new(AccessToken::new(response.access_token), expires_on))
+    }
+}
+
+/// Tries the configured tenant first and then each additionally allowed tenant, so a guest identity can reach
+/// an account in a tenant other than its home tenant. Every tenant's failure is reported when none works.
+pub(crate) struct TenantFallbackCredential {
+    tenants: Vec<(String, Arc<dyn TokenCredential>)>,
+}
+
+impl TenantFallbackCredential {
+    pub(crate) fn new(tenants: Vec<(String, Arc<dyn TokenCredential>)>) -> Self {
+        Self { tenants }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for TenantFallbackCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut errors = Vec::with_capacity(self.tenants.len());
+        for (tenant_id, credential) in &self.tenants {
+            match credential.get_token(resource).await {
+                Ok(token) => return Ok(token),
+                Err(error) => errors.push(format!("tenant {}: {}", tenant_id, error)),
+            }
+        }
+        Err(Error::with_message(ErrorKind::Credential, || {
+            format!("no allowed tenant issued a token for {}: {}", resource, errors.join("; "))
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedCredential(Option<&'static str>);
+
+    #[async_trait::async_trait]
+    impl TokenCredential for FixedCredential {
+        async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+            match self.0 {
+                Some(token) => Ok(TokenResponse::new(AccessToken::new(token), OffsetDateTime::now_utc())),
+                None => Err(Error::message(ErrorKind::Credential, "AADSTS50020: user does not exist in tenant")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allowed_tenants_are_tried_in_order() -> azure_core::Result<()> {
+        let credential = TenantFallbackCredential::new(vec![
+            ("home".to_string(), Arc::new(FixedCredential(None))),
+            ("guest".to_string(), Arc::new(FixedCredential(Some("guest-token")))),
+            ("other".to_string(), Arc::new(FixedCredential(Some("other-token")))),
+        ]);
+        assert_eq!(credential.get_token("https://storage.azure.com/").await?.token.secret(), "guest-token");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failure_names_every_tenant() {
+        let credential = TenantFallbackCredential::new(vec![
+            ("home".to_string(), Arc::new(FixedCredential(None))),
+            ("guest".to_string(), Arc::new(FixedCredential(None))),
+        ]);
+        let error = credential.get_token("https://storage.azure.com/").await.unwrap_err().to_string();
+        assert!(error.contains("tenant home: ") && error.contains("tenant guest: "), "{}", error);
+    }
+
+    #[test]
+    fn test_cli_expiry_formats() -> azure_core::Result<()> {
+        let current: CliTokenResponse =
+            serde_json::from_str(r#"{"accessToken": "t", "expiresOn": "2024-05-01 10:00:00.000000", "expires_on": 1714557600}"#)?;
+        assert_eq!(current.expires_on()?.unix_timestamp(), 1714557600);
+
+        let legacy: CliTokenResponse = serde_json::from_str(r#"{"accessToken": "t", "expiresOn": "2024-05-01 10:00:00.123456"}"#)?;
+        assert!(legacy.expires_on().is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use azure_core::auth::{AccessToken, TokenCredential, TokenResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::logging::{LogLevel, LogSettings};
+
+const KEY_FILE_NAME: &str = "token-cache.key";
+const NONCE_LEN: usize = 12;
+
+/// Tokens this close to expiry are not served from disk
+const EXPIRY_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Where and how acquired tokens are persisted between processes
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenCacheOptions {
+    pub(crate) directory: PathBuf,
+    /// Never part of a snapshot, restored backends fall back to the key file
+    #[serde(skip)]
+    pub(crate) key: Option<[u8; 32]>,
+}
+
+impl std::fmt::Debug for TokenCacheOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCacheOptions")
+            .field("directory", &self.directory)
+            .field("key", &self.key.map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl TokenCacheOptions {
+    /// Caches tokens under `directory`. Unless a key is supplied the tokens are encrypted with a key generated
+    /// into `directory` on first use, readable by the current user only
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            key: None,
+        }
+    }
+
+    /// AES-256 key to encrypt the cache with, e.g. fetched from the OS keychain by the application
+    pub fn key(mut self, key: [u8; 32]) -> Self {
+        self.key = Some(key);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedToken {
+    token: String,
+    expires_on: i64,
+}
+
+/// Serves tokens from an encrypted file per account, identity and resource before asking the wrapped credential
+pub(crate) struct PersistentTokenCache {
+    credential: Arc<dyn TokenCredential>,
+    options: TokenCacheOptions,
+    identity: String,
+    log_settings: Arc<LogSettings>,
+}
+
+impl PersistentTokenCache {
+    /// `identity` must distinguish every account and credential combination sharing the directory
+    pub(crate) fn new(
+        credential: Arc<dyn TokenCredential>,
+        options: TokenCacheOptions,
+        identity: String,
+        log_settings: Arc<LogSettings>,
+    ) -> Self {
+        Self {
+            credential,
+            options,
+            identity,
+            log_settings,
+        }
+    }
+
+    fn entry_path(&self, resource: &str) -> PathBuf {
+        let digest = Sha256::digest(format!("{}|{}", self.identity, resource).as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.options.directory.join(format!("{}.token", name))
+    }
+
+    async fn cipher(&self) -> std::io::Result<Aes256Gcm> {
+        if let Some(key) = &self.options.key {
+            return Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)));
+        }
+
+        let key_path = self.options.directory.join(KEY_FILE_NAME);
+        match tokio::fs::read(&key_path).await {
+            Ok(key) if key.len() == 32 => Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))),
+            Ok(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "token cache key file is corrupt")),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let key = Aes256Gcm::generate_key(OsRng);
+                write_private(&key_path, &key).await?;
+                Ok(Aes256Gcm::new(&key))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    async fn load(&self, resource: &str) -> std::io::Result<Option<TokenResponse>> {
+        let contents = match tokio::fs::read(self.entry_path(resource)).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        if contents.len() < NONCE_LEN {
+            return Ok(None);
+        }
+
+        let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher()
+            .await?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "token cache entry failed to decrypt"))?;
+        let cached: CachedToken = serde_json::from_slice(&plaintext)?;
+
+        let expires_on = OffsetDateTime::from_unix_timestamp(cached.expires_on)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+        if expires_on < OffsetDateTime::now_utc() + EXPIRY_MARGIN {
+            return Ok(None);
+        }
+        Ok(Some(TokenResponse::new(AccessToken::new(cached.token), expires_on)))
+    }
+
+    async fn store(&self, resource: &str, token: &TokenResponse) -> std::io::Result<()> {
+        let plaintext = serde_json::to_vec(&CachedToken {
+            token: token.token.secret().to_string(),
+            expires_on: token.expires_on.unix_timestamp(),
+        })?;
+
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = self
+            .cipher()
+            .await?
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| std::io::Error::other("failed to encrypt token"))?;
+
+        let mut contents = nonce.to_vec();
+        contents.extend_from_slice(&ciphertext);
+        write_private(&self.entry_path(resource), &contents).await
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for PersistentTokenCache {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        match self.load(resource).await {
+            Ok(Some(token)) => return Ok(token),
+            Ok(None) => {},
+            Err(error) => self.log_settings.event(LogLevel::Error, format_args!("Ignoring unreadable token cache entry: {}", error)),
+        }
+
+        let token = self.credential.get_token(resource).await?;
+        if let Err(error) = self.store(resource, &token).await {
+            self.log_settings.event(LogLevel::Error, format_args!("Failed to persist token: {}", error));
+        }
+        Ok(token)
+    }
+}
+
+/// Writes via a temporary file and rename so concurrent processes never read a torn entry
+async fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let temp_path = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+    let mut open_options = tokio::fs::OpenOptions::new();
+    open_options.write(true).create_new(true);
+    #[cfg(unix)]
+    open_options.mode(0o600);
+
+    let mut file = open_options.open(&temp_path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, contents).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    tokio::fs::rename(&temp_path, path).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingCredential {
+        calls: AtomicUsize,
+        lifetime: Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl TokenCredential for CountingCredential {
+        async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(TokenResponse::new(
+                AccessToken::new(format!("token-{}", call)),
+                OffsetDateTime::now_utc() + self.lifetime,
+            ))
+        }
+    }
+
+    fn cache(directory: &Path, lifetime: Duration) -> (Arc<CountingCredential>, PersistentTokenCache) {
+        let credential = Arc::new(CountingCredential { calls: AtomicUsize::new(0), lifetime });
+        let cache = PersistentTokenCache::new(credential.clone(), TokenCacheOptions::new(directory), "account|default".to_string(), Arc::default());
+        (credential, cache)
+    }
+
+    fn temp_directory() -> PathBuf {
+        std::env::temp_dir().join(format!("token-cache-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn test_token_is_reused_across_instances() -> azure_core::Result<()> {
+        let directory = temp_directory();
+
+        let (first_credential, first_cache) = cache(&directory, Duration::from_secs(3600));
+        let token = first_cache.get_token("https://storage.azure.com/").await?;
+        assert_eq!(first_credential.calls.load(Ordering::SeqCst), 1);
+
+        // a new process with the same directory gets the token without authenticating
+        let (second_credential, second_cache) = cache(&directory, Duration::from_secs(3600));
+        let cached = second_cache.get_token("https://storage.azure.com/").await?;
+        assert_eq!(second_credential.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(cached.token.secret(), token.token.secret());
+
+        // the token is not readable without the key
+        let entry = tokio::fs::read(first_cache.entry_path("https://storage.azure.com/")).await.unwrap();
+        assert!(!String::from_utf8_lossy(&entry).contains(token.token.secret()));
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nearly_expired_token_is_refreshed() -> azure_core::Result<()> {
+        let directory = temp_directory();
+
+        let (credential, cache) = cache(&directory, Duration::from_secs(60));
+        cache.get_token("https://storage.azure.com/").await?;
+        cache.get_token("https://storage.azure.com/").await?;
+        assert_eq!(credential.calls.load(Ordering::SeqCst), 2);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrong_key_is_a_cache_miss() -> azure_core::Result<()> {
+        let directory = temp_directory();
+
+        let (_, cache) = cache(&directory, Duration::from_secs(3600));
+        cache.get_token("https://storage.azure.com/").await?;
+
+        let credential = Arc::new(CountingCredential { calls: AtomicUsize::new(0), lifetime: Duration::from_secs(3600) });
+        let other_key = PersistentTokenCache::new(
+            credential.clone(),
+            TokenCacheOptions::new(&directory).key([7; 32]),
+            "account|default".to_string(),
+            Arc::default(),
+        );
+        other_key.get_token("https://storage.azure.com/").await?;
+        assert_eq!(credential.calls.load(Ordering::SeqCst), 1);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+        Ok(())
+    }
+}
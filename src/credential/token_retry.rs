@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+
+use crate::logging::{LogLevel, LogSettings};
+
+/// Bounds on how long acquiring a single token may take. Every attempt is cut off after `timeout` and failed
+/// or timed out attempts are retried with exponential backoff, so an unresponsive endpoint such as a flaky IMDS
+/// fails the request instead of hanging it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenRetryOptions {
+    /// Limit for one attempt. Keep it above the time a user needs to finish an interactive sign in
+    pub timeout: Duration,
+    /// Attempts after the first one
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for TokenRetryOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl TokenRetryOptions {
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Applies [`TokenRetryOptions`] to every token request of the wrapped credential
+pub(crate) struct RetryingCredential {
+    credential: Arc<dyn TokenCredential>,
+    options: TokenRetryOptions,
+    log_settings: Arc<LogSettings>,
+}
+
+impl RetryingCredential {
+    pub(crate) fn new(credential: Arc<dyn TokenCredential>, options: TokenRetryOptions, log_settings: Arc<LogSettings>) -> Self {
+        Self {
+            credential,
+            options,
+            log_settings,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for RetryingCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut retry = 0;
+        loop {
+            let error = match tokio::time::timeout(self.options.timeout, self.credential.get_token(resource)).await {
+                Ok(Ok(token)) => return Ok(token),
+                Ok(Err(error)) => error,
+                Err(_) => Error::with_message(ErrorKind::Credential, || {
+                    format!("token request for {} timed out after {:?}", resource, self.options.timeout)
+                }),
+            };
+            if retry >= self.options.max_retries {
+                return Err(error);
+            }
+
+            retry += 1;
+            let backoff = self.options.backoff(retry);
+            self.log_settings.event(LogLevel::Info, format_args!("Token request failed, retrying in {:?}: {}", backoff, error));
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use azure_core::auth::AccessToken;
+    use time::OffsetDateTime;
+
+    /// Hangs on the first `hangs` requests, then hands out tokens
+    struct HangingCredential {
+        hangs: u32,
+        calls: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl TokenCredential for HangingCredential {
+        async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.hangs {
+                futures::future::pending::<()>().await;
+            }
+            Ok(TokenResponse::new(AccessToken::new("token"), OffsetDateTime::now_utc()))
+        }
+    }
+
+    fn options(max_retries: u32) -> TokenRetryOptions {
+        TokenRetryOptions {
+            timeout: Duration::from_millis(20),
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hanging_attempts_are_retried() -> azure_core::Result<()> {
+        let hanging = Arc::new(HangingCredential { hangs: 2, calls: AtomicU32::new(0) });
+        let credential = RetryingCredential::new(hanging.clone(), options(2), Arc::default());
+        assert_eq!(credential.get_token("https://storage.azure.com/").await?.token.secret(), "token");
+        assert_eq!(hanging.calls.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let credential = RetryingCredential::new(Arc::new(HangingCredential { hangs: 5, calls: AtomicU32::new(0) }), options(1), Arc::default());
+        let error = credential.get_token("https://storage.azure.com/").await.unwrap_err();
+        assert!(error.to_string().contains("timed out"), "{}", error);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let options = TokenRetryOptions::default();
+        assert_eq!(options.backoff(1), Duration::from_millis(500));
+        assert_eq!(options.backoff(3), Duration::from_secs(2));
+        assert_eq!(options.backoff(10), Duration::from_secs(8));
+    }
+}
@@ -0,0 +1,178 @@
+//! Undoing the encoding layers of downloaded files without knowing up front how they were written
+use std::io::{self, Read};
+
+use azure_core::headers::CONTENT_ENCODING;
+use azure_core::Context;
+use bytes::Bytes;
+use flate2::read::MultiGzDecoder;
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::ResponseHeaders;
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+
+/// Layers nested deeper than this are treated as data, guarding against decompression bombs built from layers
+const MAX_LAYERS: usize = 8;
+/// Largest content a layer may decode to, guarding against decompression bombs within a layer
+const MAX_DECODED_SIZE: u64 = 1024 * 1024 * 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// An encoding layer recognised on download
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentLayer {
+    Gzip,
+}
+
+impl ContentLayer {
+    /// Outermost layer of `data`, from its magic bytes
+    fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&GZIP_MAGIC) {
+            return Some(ContentLayer::Gzip);
+        }
+        None
+    }
+
+    /// Layers named by a `Content-Encoding`, outermost last as they were applied in the order listed. `None` when
+    /// it names an encoding this crate cannot remove, since the layers below that one cannot be reached either
+    fn declared(content_encoding: &str) -> Option<Vec<Self>> {
+        let mut layers = Vec::new();
+        for encoding in content_encoding.split(',').map(str::trim).filter(|encoding| !encoding.is_empty()) {
+            match encoding.to_ascii_lowercase().as_str() {
+                "identity" => {}
+                "gzip" | "x-gzip" => layers.push(ContentLayer::Gzip),
+                _ => return None,
+            }
+        }
+        Some(layers)
+    }
+
+    /// Fails once the decoded content grows beyond `limit` bytes
+    fn decode(&self, data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::with_capacity(data.len().saturating_mul(2).min(limit as usize));
+        match self {
+            ContentLayer::Gzip => MultiGzDecoder::new(data).take(limit.saturating_add(1)).read_to_end(&mut decoded)?,
+        };
+        if decoded.len() as u64 > limit {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("decoded content exceeds {} bytes", limit)));
+        }
+        Ok(decoded)
+    }
+}
+
+/// A download with every recognised layer removed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedContent {
+    pub data: Bytes,
+    /// The layers that were removed, outermost first
+    pub layers: Vec<ContentLayer>,
+}
+
+impl AzureStorageBackend {
+    /// Downloads `path` and strips the layers its `Content-Encoding` names, then those it recognises by their magic
+    /// bytes until plain content is left
+    pub async fn read_decoded(&self, container_name: &str, path: &str) -> Result<DecodedContent, miette::Error> {
+        let mut context = Context::new();
+        context.insert(ResponseHeaders::default());
+        let response = self
+            .file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .read()
+            .context(context.clone())
+            .await
+            .map_err(AzureStorageError::Request)?;
+        let headers = context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).unwrap_or_default();
+        let content_encoding = headers.get_optional_str(&CONTENT_ENCODING);
+        if let Some(content_encoding) = content_encoding.filter(|content_encoding| ContentLayer::declared(content_encoding).is_none()) {
+            self.log(LogLevel::Info, format_args!("Content encoding {} is not supported, reading the content as it is", content_encoding));
+        }
+
+        Ok(decode_layers(path, response.data, content_encoding, MAX_DECODED_SIZE)?)
+    }
+}
+
+fn decode_layers(path: &str, mut data: Bytes, content_encoding: Option<&str>, limit: u64) -> Result<DecodedContent, AzureStorageError> {
+    let mut declared = content_encoding.and_then(ContentLayer::declared).unwrap_or_default();
+    let mut layers = Vec::new();
+    while layers.len() < MAX_LAYERS {
+        let Some(layer) = declared.pop().or_else(|| ContentLayer::detect(&data)) else {
+            break;
+        };
+        data = layer
+            .decode(&data, limit)
+            .map_err(|source| AzureStorageError::Decode {
+                path: path.to_string(),
+                layer,
+                source,
+            })?
+            .into();
+        layers.push(layer);
+    }
+    Ok(DecodedContent { data, layers })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_plain_content_is_untouched() {
+        let decoded = decode_layers("plain.txt", Bytes::from_static(b"hello world"), None, MAX_DECODED_SIZE).unwrap();
+        assert_eq!(decoded.data, Bytes::from_static(b"hello world"));
+        assert!(decoded.layers.is_empty());
+    }
+
+    #[test]
+    fn test_nested_layers_are_removed() {
+        let decoded = decode_layers("twice.gz.gz", gzip(&gzip(b"hello world")).into(), None, MAX_DECODED_SIZE).unwrap();
+        assert_eq!(decoded.data, Bytes::from_static(b"hello world"));
+        assert_eq!(decoded.layers, vec![ContentLayer::Gzip, ContentLayer::Gzip]);
+    }
+
+    #[test]
+    fn test_truncated_layer_is_an_error() {
+        let mut data = gzip(b"hello world");
+        data.truncate(data.len() / 2);
+        assert!(matches!(
+            decode_layers("broken.gz", data.into(), None, MAX_DECODED_SIZE),
+            Err(AzureStorageError::Decode { layer: ContentLayer::Gzip, .. })
+        ));
+    }
+
+    #[test]
+    fn test_declared_encodings_are_removed() {
+        assert_eq!(ContentLayer::declared("gzip, identity, GZIP"), Some(vec![ContentLayer::Gzip, ContentLayer::Gzip]));
+        assert_eq!(ContentLayer::declared("gzip, br"), None);
+
+        let declared = decode_layers("a.csv", gzip(b"").into(), Some("gzip"), MAX_DECODED_SIZE).unwrap();
+        assert_eq!(declared.layers, vec![ContentLayer::Gzip]);
+        assert!(declared.data.is_empty());
+
+        // a declared layer is decoded without looking at the magic bytes, so content that is not gzip fails
+        let corrupt = decode_layers("a.csv", Bytes::from_static(b"plain"), Some("gzip"), MAX_DECODED_SIZE);
+        assert!(matches!(corrupt, Err(AzureStorageError::Decode { layer: ContentLayer::Gzip, .. })));
+    }
+
+    #[test]
+    fn test_decoding_beyond_the_limit_is_an_error() {
+        let bomb = gzip(&vec![0; 4096]);
+        assert!(decode_layers("bomb.gz", bomb.clone().into(), None, 4096).is_ok());
+        let Err(AzureStorageError::Decode { source, .. }) = decode_layers("bomb.gz", bomb.into(), None, 4095) else {
+            panic!("content beyond the limit is rejected");
+        };
+        assert_eq!(source.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,89 @@
+//! Creating and moving directories, which hierarchical namespace accounts store as paths of their own
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::{Context, StatusCode};
+
+use crate::backend::AzureStorageBackend;
+use crate::condition::condition_error;
+use crate::context_headers::ResponseHeaders;
+use crate::error::{http_status, AzureStorageError};
+
+const RESOURCE_TYPE: HeaderName = HeaderName::from_static("x-ms-resource-type");
+
+fn is_directory(headers: &Headers) -> bool {
+    headers.get_optional_str(&RESOURCE_TYPE) == Some("directory")
+}
+
+impl AzureStorageBackend {
+    /// Creates the directory `path` and any missing parents. Fails with [`AzureStorageError::ConditionNotMet`]
+    /// when a file or directory already exists at `path`
+    pub async fn create_directory(&self, container_name: &str, path: &str) -> Result<(), miette::Error> {
+        self.file_system_client(container_name)
+            .await
+            .get_directory_client(path)
+            .create_if_not_exists()
+            .await
+            .map_err(|error| condition_error(path, error))?;
+        Ok(())
+    }
+
+    /// Creates the directory `path` and any missing parents like `mkdir -p`, succeeding when it already exists.
+    /// A file at `path` is an error
+    pub async fn create_directory_all(&self, container_name: &str, path: &str) -> Result<(), miette::Error> {
+        self.create_directory_if_not_exists(container_name, path).await?;
+        Ok(())
+    }
+
+    /// Like [`AzureStorageBackend::create_directory_all`], returning whether the directory was created by this
+    /// call rather than found
+    pub async fn create_directory_if_not_exists(&self, container_name: &str, path: &str) -> Result<bool, miette::Error> {
+        let directory_client = self.file_system_client(container_name).await.get_directory_client(path);
+        let error = match directory_client.create_if_not_exists().await {
+            Ok(_) => return Ok(true),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::Conflict, Some("PathAlreadyExists")))) => error,
+            Err(error) => return Err(AzureStorageError::Request(error).into()),
+        };
+
+        let mut context = Context::new();
+        context.insert(ResponseHeaders::default());
+        directory_client
+            .get_properties()
+            .context(context.clone())
+            .await
+            .map_err(AzureStorageError::Request)?;
+        match context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).as_ref().is_some_and(is_directory) {
+            true => Ok(false),
+            false => Err(AzureStorageError::Request(error).into()),
+        }
+    }
+
+    /// Moves the directory `source` with everything below it to `destination` in one atomic step, e.g. to promote
+    /// a staging directory to its final location. Readers see either the old tree or the new one. Fails with
+    /// [`AzureStorageError::ConditionNotMet`] when something exists at `destination` already
+    pub async fn rename_directory(&self, container_name: &str, source: &str, destination: &str) -> Result<(), miette::Error> {
+        self.file_system_client(container_name)
+            .await
+            .get_directory_client(source)
+            .rename_if_not_exists(destination)
+            .await
+            .map_err(|error| condition_error(destination, error))?;
+        self.append_positions.forget_below(container_name, source);
+        self.append_positions.forget_below(container_name, destination);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directories_are_told_apart_by_resource_type() {
+        let mut headers = Headers::new();
+        assert!(!is_directory(&headers));
+        headers.insert(RESOURCE_TYPE, "file");
+        assert!(!is_directory(&headers));
+        headers.insert(RESOURCE_TYPE, "directory");
+        assert!(is_directory(&headers));
+    }
+}
@@ -0,0 +1,484 @@
+//! Reading files whole, in chunks or by byte range
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use azure_core::prelude::{IfMatchCondition, Range};
+use azure_core::StatusCode;
+use bytes::{Bytes, BytesMut};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWriteExt, ReadBuf};
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::ResponseHeaders;
+use crate::error::{http_status, AzureStorageError};
+use crate::integrity::{stored_md5, Md5Verifier};
+use crate::logging::{LogLevel, LogSettings};
+use crate::progress::{ProgressCallback, ProgressCounter, ProgressHook};
+use crate::sdk::datalake::*;
+
+pub(crate) const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+const DEFAULT_MEMORY_BUDGET: u64 = 64 * 1024 * 1024;
+
+/// How a download is carried out
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    pub(crate) chunk_size: u64,
+    pub(crate) resume: bool,
+    pub(crate) memory_budget: u64,
+    pub(crate) verify_md5: bool,
+    pub(crate) progress: ProgressHook,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            resume: false,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            verify_md5: false,
+            progress: ProgressHook::default(),
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Bytes requested per read, 8 MiB by default. At most one chunk is held in memory at a time
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Records progress in `<local_path>.checkpoint` after every chunk, so a download to disk that was
+    /// interrupted continues where it stopped. It starts over when the remote file changed in the meantime
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Largest file [`download`](AzureStorageBackend::download) keeps in memory, 64 MiB by default. Larger
+    /// files are spilled to an anonymous temporary file that is removed when the reader is dropped
+    pub fn memory_budget(mut self, memory_budget: u64) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Hashes the content as it arrives and compares it with the MD5 stored with the file, e.g. by an upload with
+    /// [`UploadOptions::content_md5`](crate::UploadOptions::content_md5), failing with
+    /// [`AzureStorageError::ChecksumMismatch`] when they differ. Off by default, files without a stored MD5 are
+    /// not checked
+    pub fn verify_md5(mut self, verify_md5: bool) -> Self {
+        self.verify_md5 = verify_md5;
+        self
+    }
+
+    /// Called after every chunk [`download`](AzureStorageBackend::download), [`download_stream`](AzureStorageBackend::download_stream)
+    /// or [`download_to_path`](AzureStorageBackend::download_to_path) received, with the bytes received so far and
+    /// the size of the file. A resumed download counts the bytes it still had to fetch
+    pub fn progress(mut self, callback: ProgressCallback) -> Self {
+        self.progress.callback = Some(callback);
+        self
+    }
+}
+
+/// Content of a downloaded file, read the same way whether it is held in memory or was spilled to disk
+#[derive(Debug)]
+pub struct DownloadedFile {
+    size: u64,
+    content: DownloadedContent,
+}
+
+#[derive(Debug)]
+enum DownloadedContent {
+    Memory(Cursor<Bytes>),
+    Spilled(tokio::fs::File),
+}
+
+impl DownloadedFile {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether the file was larger than the memory budget and is read from a temporary file
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.content, DownloadedContent::Spilled(_))
+    }
+}
+
+impl AsyncRead for DownloadedFile {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.get_mut().content {
+            DownloadedContent::Memory(cursor) => Pin::new(cursor).poll_read(cx, buf),
+            DownloadedContent::Spilled(file) => Pin::new(file).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncSeek for DownloadedFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        match &mut self.get_mut().content {
+            DownloadedContent::Memory(cursor) => Pin::new(cursor).start_seek(position),
+            DownloadedContent::Spilled(file) => Pin::new(file).start_seek(position),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        match &mut self.get_mut().content {
+            DownloadedContent::Memory(cursor) => Pin::new(cursor).poll_complete(cx),
+            DownloadedContent::Spilled(file) => Pin::new(file).poll_complete(cx),
+        }
+    }
+}
+
+/// Progress of an interrupted download, stored next to the local file
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct DownloadCheckpoint {
+    etag: String,
+    offset: u64,
+}
+
+impl DownloadCheckpoint {
+    fn path(local_path: &Path) -> PathBuf {
+        let mut path = local_path.as_os_str().to_owned();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+
+    /// The saved offset if it belongs to the remote version `etag` and the local file holds that much data
+    async fn load(local_path: &Path, etag: &str, log_settings: &LogSettings) -> Option<u64> {
+        let content = tokio::fs::read(Self::path(local_path)).await.ok()?;
+        let checkpoint: DownloadCheckpoint = serde_json::from_slice(&content).ok()?;
+        let local_size = tokio::fs::metadata(local_path).await.ok()?.len();
+        if checkpoint.etag != etag {
+            log_settings.event(LogLevel::Info, format_args!("{:?} changed remotely since the download was interrupted, starting over", local_path));
+            return None;
+        }
+        (local_size >= checkpoint.offset).then_some(checkpoint.offset)
+    }
+
+    /// Replaces the checkpoint file in one rename, so an interruption never leaves half a checkpoint behind
+    async fn save(&self, local_path: &Path) -> std::io::Result<()> {
+        let path = Self::path(local_path);
+        let mut partial = path.clone().into_os_string();
+        partial.push(".tmp");
+        tokio::fs::write(&partial, serde_json::to_vec(self).expect("checkpoints only hold strings and numbers")).await?;
+        tokio::fs::rename(&partial, &path).await
+    }
+}
+
+impl AzureStorageBackend {
+    /// The whole content of the file at `path`
+    pub async fn download_bytes(&self, container_name: &str, path: &str) -> Result<Bytes, miette::Error> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        match file_client.read().await {
+            Ok(response) => Ok(response.data),
+            // the SDK always asks for a range, which an empty file cannot satisfy
+            Err(error) if matches!(http_status(&error), Some((StatusCode::RequestedRangeNotSatisfiable, _))) => Ok(Bytes::new()),
+            Err(error) => Err(AzureStorageError::Request(error).into()),
+        }
+    }
+
+    /// `length` bytes of the file at `path` starting at `offset`, fewer when the file ends before that. Reading
+    /// at or past the end of the file is an error
+    pub async fn read_range(&self, container_name: &str, path: &str, offset: u64, length: u64) -> Result<Bytes, miette::Error> {
+        if length == 0 {
+            return Ok(Bytes::new());
+        }
+        let response = self
+            .file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .read()
+            .range(Range::new(offset, offset.saturating_add(length)))
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(response.data)
+    }
+
+    /// The file at `path`, held in memory up to [`DownloadOptions::memory_budget`] and spilled to a temporary
+    /// file beyond it. Chunks are read conditionally on the etag like [`download_to_path`](Self::download_to_path)
+    pub async fn download(&self, container_name: &str, path: &str, options: DownloadOptions) -> Result<DownloadedFile, miette::Error> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        let (etag, size, mut verifier) = file_version(&file_client, options.verify_md5).await?;
+        let mut progress = ProgressHook { total: Some(size), ..options.progress }.start();
+
+        if size <= options.memory_budget {
+            let mut content = BytesMut::with_capacity(size as usize);
+            for range in chunk_ranges(size, options.chunk_size) {
+                let chunk = read_chunk(&file_client, range, &etag).await?;
+                progress.advance(chunk.len() as u64);
+                verifier.iter_mut().for_each(|verifier| verifier.update(&chunk));
+                content.extend_from_slice(&chunk);
+            }
+            if let Some(verifier) = verifier {
+                verifier.verify(path)?;
+            }
+            return Ok(DownloadedFile {
+                size,
+                content: DownloadedContent::Memory(Cursor::new(content.freeze())),
+            });
+        }
+
+        self.log(LogLevel::Info, format_args!("{} is larger than the memory budget, spilling it to disk", path));
+        let spill_io = |source| AzureStorageError::LocalIo { path: std::env::temp_dir(), source };
+        let mut file = tokio::fs::File::from_std(tempfile::tempfile().map_err(spill_io)?);
+        for range in chunk_ranges(size, options.chunk_size) {
+            let chunk = read_chunk(&file_client, range, &etag).await?;
+            progress.advance(chunk.len() as u64);
+            verifier.iter_mut().for_each(|verifier| verifier.update(&chunk));
+            file.write_all(&chunk).await.map_err(spill_io)?;
+        }
+        if let Some(verifier) = verifier {
+            verifier.verify(path)?;
+        }
+        file.flush().await.map_err(spill_io)?;
+        file.rewind().await.map_err(spill_io)?;
+        Ok(DownloadedFile {
+            size,
+            content: DownloadedContent::Spilled(file),
+        })
+    }
+
+    /// The file at `path` as a stream of chunks of [`DownloadOptions::chunk_size`] bytes, each read when the
+    /// previous one was consumed, so it can be piped into a response or pipeline without holding the file in
+    /// memory. Chunks are read conditionally on the etag of the first request and the stream ends after an error
+    pub async fn download_stream(
+        &self,
+        container_name: &str,
+        path: &str,
+        options: DownloadOptions,
+    ) -> impl Stream<Item = Result<Bytes, miette::Error>> {
+        let state = StreamState {
+            file_client: self.file_system_client(container_name).await.get_file_client(path),
+            path: path.to_string(),
+            chunk_size: options.chunk_size,
+            verify_md5: options.verify_md5,
+            verifier: None,
+            progress: options.progress,
+            counter: None,
+            version: None,
+            offset: 0,
+            failed: false,
+        };
+
+        futures::stream::unfold(state, |mut state| async move {
+            if state.failed {
+                return None;
+            }
+            match state.next_chunk().await {
+                Ok(chunk) => chunk.map(|chunk| (Ok(chunk), state)),
+                Err(error) => {
+                    state.failed = true;
+                    Some((Err(error.into()), state))
+                }
+            }
+        })
+    }
+
+    /// Writes the file at `path` to `local_path` a chunk at a time, replacing any local file unless it continues
+    /// an interrupted download (see [`DownloadOptions::resume`]), and syncs it to disk
+    /// before returning its size. Every chunk is read conditionally on the etag of the first request, so a file
+    /// changed during the download fails it instead of producing a mix of both versions
+    pub async fn download_to_path(
+        &self,
+        container_name: &str,
+        path: &str,
+        local_path: impl AsRef<Path>,
+        options: DownloadOptions,
+    ) -> Result<u64, miette::Error> {
+        let local_path = local_path.as_ref();
+        let local_io = |source| AzureStorageError::LocalIo { path: local_path.to_path_buf(), source };
+
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        let (etag, size, mut verifier) = file_version(&file_client, options.verify_md5).await?;
+
+        let resumed = match options.resume {
+            true => DownloadCheckpoint::load(local_path, &etag, &self.log_settings).await,
+            false => None,
+        };
+        let mut file = match resumed {
+            Some(offset) => {
+                self.log(LogLevel::Info, format_args!("Resuming download of {} at byte {}", path, offset));
+                if let Some(verifier) = &mut verifier {
+                    hash_local_prefix(verifier, local_path, offset).await.map_err(local_io)?;
+                }
+                let mut file = tokio::fs::OpenOptions::new().write(true).open(local_path).await.map_err(local_io)?;
+                file.set_len(offset).await.map_err(local_io)?;
+                file.seek(std::io::SeekFrom::Start(offset)).await.map_err(local_io)?;
+                file
+            }
+            None => tokio::fs::File::create(local_path).await.map_err(local_io)?,
+        };
+
+        let start = resumed.unwrap_or(0);
+        let mut progress = ProgressHook { total: Some(size - start.min(size)), ..options.progress }.start();
+        for range in chunk_ranges(size, options.chunk_size).skip_while(|range| range.end <= start) {
+            let range = Range::new(range.start.max(start), range.end);
+            let chunk = read_chunk(&file_client, range, &etag).await?;
+            progress.advance(chunk.len() as u64);
+            verifier.iter_mut().for_each(|verifier| verifier.update(&chunk));
+            file.write_all(&chunk).await.map_err(local_io)?;
+            if options.resume {
+                // the data has to be on disk before the checkpoint claims it is
+                file.sync_data().await.map_err(local_io)?;
+                let checkpoint = DownloadCheckpoint {
+                    etag: etag.clone(),
+                    offset: range.end,
+                };
+                checkpoint.save(local_path).await.map_err(local_io)?;
+            }
+        }
+        file.sync_all().await.map_err(local_io)?;
+        if let Some(Err(error)) = verifier.map(|verifier| verifier.verify(path)) {
+            // resuming would only hash the same corrupted content again
+            let _ = tokio::fs::remove_file(DownloadCheckpoint::path(local_path)).await;
+            return Err(error.into());
+        }
+        if options.resume {
+            match tokio::fs::remove_file(DownloadCheckpoint::path(local_path)).await {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(local_io(error).into()),
+                _ => {}
+            }
+        }
+        Ok(size)
+    }
+}
+
+struct StreamState {
+    file_client: FileClient,
+    path: String,
+    chunk_size: u64,
+    verify_md5: bool,
+    /// Taken when the stream ends
+    verifier: Option<Md5Verifier>,
+    progress: ProgressHook,
+    /// Started once the size is known
+    counter: Option<ProgressCounter>,
+    /// Etag and size of the file, once they were fetched
+    version: Option<(String, u64)>,
+    offset: u64,
+    failed: bool,
+}
+
+impl StreamState {
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, AzureStorageError> {
+        if self.version.is_none() {
+            let (etag, size, verifier) = file_version(&self.file_client, self.verify_md5).await?;
+            self.version = Some((etag, size));
+            self.verifier = verifier;
+            self.counter = Some(ProgressHook { total: Some(size), ..self.progress.clone() }.start());
+        }
+        let (etag, size) = self.version.as_ref().expect("version was just fetched");
+        if self.offset >= *size {
+            if let Some(verifier) = self.verifier.take() {
+                verifier.verify(&self.path)?;
+            }
+            return Ok(None);
+        }
+        let range = Range::new(self.offset, (self.offset + self.chunk_size).min(*size));
+        let chunk = read_chunk(&self.file_client, range, etag).await?;
+        self.offset = range.end;
+        if let Some(counter) = &mut self.counter {
+            counter.advance(chunk.len() as u64);
+        }
+        if let Some(verifier) = &mut self.verifier {
+            verifier.update(&chunk);
+        }
+        Ok(Some(chunk))
+    }
+}
+
+/// Etag and size of the file, and with `verify_md5` a verifier for its stored MD5 if it has one
+async fn file_version(file_client: &FileClient, verify_md5: bool) -> Result<(String, u64, Option<Md5Verifier>), AzureStorageError> {
+    let mut context = azure_core::Context::new();
+    context.insert(ResponseHeaders::default());
+    let properties = file_client
+        .get_properties()
+        .context(context.clone())
+        .await
+        .map_err(AzureStorageError::Request)?;
+    let size = properties.content_length.unwrap_or_default().max(0) as u64;
+    let verifier = match verify_md5 {
+        true => context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).as_ref().and_then(stored_md5),
+        false => None,
+    };
+    Ok((properties.etag, size, verifier.map(Md5Verifier::new)))
+}
+
+/// Feeds the first `length` bytes of the local file to `verifier`, for content a resumed download does not fetch
+async fn hash_local_prefix(verifier: &mut Md5Verifier, local_path: &Path, length: u64) -> std::io::Result<()> {
+    let mut prefix = tokio::fs::File::open(local_path).await?.take(length);
+    let mut buffer = vec![0; DEFAULT_CHUNK_SIZE as usize];
+    loop {
+        match prefix.read(&mut buffer).await? {
+            0 => return Ok(()),
+            read => verifier.update(&buffer[..read]),
+        }
+    }
+}
+
+/// One range of the file, failing if the file is no longer the version `etag`
+pub(crate) async fn read_chunk(file_client: &FileClient, range: Range, etag: &str) -> Result<Bytes, AzureStorageError> {
+    let response = file_client
+        .read()
+        .range(range)
+        .if_match_condition(IfMatchCondition::Match(etag.to_string()))
+        .await
+        .map_err(AzureStorageError::Request)?;
+    Ok(response.data)
+}
+
+/// Consecutive ranges of at most `chunk_size` bytes covering `size` bytes
+pub(crate) fn chunk_ranges(size: u64, chunk_size: u64) -> impl Iterator<Item = Range> {
+    (0..size).step_by(chunk_size as usize).map(move |start| Range::new(start, (start + chunk_size).min(size)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_ranges_cover_the_file() {
+        let ranges: Vec<Range> = chunk_ranges(10, 4).collect();
+        assert_eq!(ranges, [Range::new(0, 4), Range::new(4, 8), Range::new(8, 10)]);
+        assert_eq!(chunk_ranges(0, 4).count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_downloaded_file_reads_the_same_from_memory_and_disk() {
+        let mut spilled = tokio::fs::File::from_std(tempfile::tempfile().unwrap());
+        spilled.write_all(b"0123456789").await.unwrap();
+        let files = [
+            DownloadedContent::Memory(Cursor::new(Bytes::from_static(b"0123456789"))),
+            DownloadedContent::Spilled(spilled),
+        ];
+        for content in files {
+            let mut file = DownloadedFile { size: 10, content };
+            file.seek(SeekFrom::Start(6)).await.unwrap();
+            let mut tail = String::new();
+            file.read_to_string(&mut tail).await.unwrap();
+            assert_eq!(tail, "6789");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_only_resumes_the_same_version() {
+        let local_path = std::env::temp_dir().join(format!("download-{}.bin", uuid::Uuid::new_v4()));
+        tokio::fs::write(&local_path, b"0123456789").await.unwrap();
+        let checkpoint = DownloadCheckpoint {
+            etag: "0x1".to_string(),
+            offset: 8,
+        };
+        checkpoint.save(&local_path).await.unwrap();
+
+        let log_settings = LogSettings::new("account", LogLevel::Off, false);
+        assert_eq!(DownloadCheckpoint::load(&local_path, "0x1", &log_settings).await, Some(8));
+        assert_eq!(DownloadCheckpoint::load(&local_path, "0x2", &log_settings).await, None);
+
+        tokio::fs::remove_file(DownloadCheckpoint::path(&local_path)).await.unwrap();
+        tokio::fs::remove_file(&local_path).await.unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use azure_core::error::ErrorKind;
+use azure_core::StatusCode;
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::decode::ContentLayer;
+
+/// Errors raised by the storage backend before or while talking to Azure. Every variant has a stable
+/// [`error_code`](Self::error_code), which also leads its message and is its diagnostic code
+#[derive(Error, Diagnostic, Debug)]
+pub enum AzureStorageError {
+    #[error("[AZB-AUTH-001] invalid credential configuration: {0}")]
+    #[diagnostic(
+        code("AZB-AUTH-001"),
+        help("check the options passed to `AzureStorageBackendBuilder::credential`")
+    )]
+    InvalidCredentialConfig(String),
+
+    #[error("[AZB-AUTH-002] failed to acquire a storage token")]
+    #[diagnostic(code("AZB-AUTH-002"))]
+    Credential(#[source] azure_core::Error),
+
+    #[error("[AZB-CONFIG-001] invalid backend snapshot")]
+    #[diagnostic(
+        code("AZB-CONFIG-001"),
+        help("snapshots must be produced by `BackendSnapshot::to_json` of a compatible version")
+    )]
+    InvalidSnapshot(#[from] serde_json::Error),
+
+    #[error("[AZB-REQUEST-001] storage request failed")]
+    #[diagnostic(code("AZB-REQUEST-001"))]
+    Request(#[from] azure_core::Error),
+
+    #[error("[AZB-WRITE-001] another writer appended to {path} before position {position} could be committed")]
+    #[diagnostic(
+        code("AZB-WRITE-001"),
+        help("use `AppendConflictStrategy::RefetchAndRetry` or `SwitchToNewFile` to reconcile automatically")
+    )]
+    AppendConflict { path: String, position: i64 },
+
+    #[error("[AZB-KV-001] invalid key {0:?}")]
+    #[diagnostic(
+        code("AZB-KV-001"),
+        help("keys are relative `/` separated paths without empty, `.` or `..` segments")
+    )]
+    InvalidKey(String),
+
+    #[error("[AZB-KV-002] the condition on key {key} did not hold")]
+    #[diagnostic(
+        code("AZB-KV-002"),
+        help("another writer changed the key, `get` it again and retry with the new etag")
+    )]
+    KeyConflict { key: String },
+
+    #[error("[AZB-CONDITION-001] the etag condition on {path} did not hold")]
+    #[diagnostic(
+        code("AZB-CONDITION-001"),
+        help("another writer changed the file, read it again for its current etag and retry")
+    )]
+    ConditionNotMet { path: String },
+
+    #[error("[AZB-INTEGRITY-001] content of {path} does not match its MD5 {expected}")]
+    #[diagnostic(
+        code("AZB-INTEGRITY-001"),
+        help("the content was corrupted in transit or the file changed without its stored hash being updated")
+    )]
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        /// `None` when the service rejected the content
+        actual: Option<String>,
+    },
+
+    #[error("[AZB-COPY-001] copy to {path} did not complete: {description}")]
+    #[diagnostic(code("AZB-COPY-001"))]
+    CopyFailed { path: String, description: String },
+
+    #[error("[AZB-PARTITION-001] invalid partition {0:?}")]
+    #[diagnostic(
+        code("AZB-PARTITION-001"),
+        help("partition keys and values must be non empty and must not contain `/` or `=`")
+    )]
+    InvalidPartition(String),
+
+    #[error("[AZB-DECODE-001] failed to decode the {layer:?} layer of {path}")]
+    #[diagnostic(code("AZB-DECODE-001"))]
+    Decode {
+        path: String,
+        layer: ContentLayer,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("[AZB-IO-001] failed to access local file {path:?}")]
+    #[diagnostic(code("AZB-IO-001"))]
+    LocalIo {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("[AZB-ARCHIVE-001] invalid archive {path:?}: {description}")]
+    #[diagnostic(
+        code("AZB-ARCHIVE-001"),
+        help("archives must be zip, tar or gzip compressed tar files whose entries have relative paths")
+    )]
+    InvalidArchive { path: std::path::PathBuf, description: String },
+
+    #[error("[AZB-ACL-001] invalid access control list: {0}")]
+    #[diagnostic(
+        code("AZB-ACL-001"),
+        help("entries look like `[default:]user|group|mask|other:[id]:rwx`, separated by commas")
+    )]
+    InvalidAcl(String),
+
+    #[error("[AZB-ACL-002] changing the ACLs below {path} failed")]
+    #[diagnostic(
+        code("AZB-ACL-002"),
+        help("pass the error's continuation to `RecursiveAclOptions::resume_from` to carry on where it stopped")
+    )]
+    AclBatchFailed {
+        path: String,
+        /// `None` when the first batch failed
+        continuation: Option<String>,
+        #[source]
+        source: azure_core::Error,
+    },
+
+    #[error("[AZB-ACL-003] invalid permissions {0:?}")]
+    #[diagnostic(
+        code("AZB-ACL-003"),
+        help("permissions are octal like `0750`, symbolic like `rwxr-x---` or clauses like `u=rwx,g=rx,o=`")
+    )]
+    InvalidPermissions(String),
+
+    #[error("[AZB-SAS-001] invalid shared access signature request: {0}")]
+    #[diagnostic(
+        code("AZB-SAS-001"),
+        help("permissions are letters of `racwdl` and user delegation signatures expire within 7 days")
+    )]
+    InvalidSas(String),
+
+    #[error("[AZB-LEASE-001] invalid lease request: {0}")]
+    #[diagnostic(
+        code("AZB-LEASE-001"),
+        help("leases last whole seconds from 15 to 60 or are infinite, break periods are whole seconds up to 60")
+    )]
+    InvalidLease(String),
+
+    #[error("[AZB-LEASE-002] lock {path} is still held by someone else after waiting {waited:?}")]
+    #[diagnostic(
+        code("AZB-LEASE-002"),
+        help("raise `LockOptions::timeout`, or set `LockOptions::break_stale_after` if holders may hang")
+    )]
+    LockTimeout { path: String, waited: std::time::Duration },
+
+    #[error("[AZB-POINTER-001] {path} did not resolve to a file within {hops} pointers")]
+    #[diagnostic(
+        code("AZB-POINTER-001"),
+        help("pointers may point at each other, check the chain with `AzureStorageBackend::resolve` on each hop")
+    )]
+    PointerLoop { path: String, hops: usize },
+
+    #[error("[AZB-MANIFEST-001] invalid transfer manifest: {0}")]
+    #[diagnostic(
+        code("AZB-MANIFEST-001"),
+        help("manifests are written by `TransferManifest::to_json` or `azcopy jobs show --output-type=json`")
+    )]
+    InvalidManifest(String),
+
+    #[error("[AZB-WATCH-001] watched file {path} is not valid JSON for the expected type")]
+    #[diagnostic(code("AZB-WATCH-001"))]
+    InvalidWatchedFile {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("[AZB-JSON-001] {path} is not valid JSON for the expected type")]
+    #[diagnostic(code("AZB-JSON-001"))]
+    InvalidJson {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+impl AzureStorageError {
+    /// Stable code of the variant, e.g. `AZB-AUTH-001`, for alerting and runbooks. Codes are never reused
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::InvalidCredentialConfig(_) => "AZB-AUTH-001",
+            Self::Credential(_) => "AZB-AUTH-002",
+            Self::InvalidSnapshot(_) => "AZB-CONFIG-001",
+            Self::Request(_) => "AZB-REQUEST-001",
+            Self::AppendConflict { .. } => "AZB-WRITE-001",
+            Self::InvalidKey(_) => "AZB-KV-001",
+            Self::KeyConflict { .. } => "AZB-KV-002",
+            Self::ConditionNotMet { .. } => "AZB-CONDITION-001",
+            Self::ChecksumMismatch { .. } => "AZB-INTEGRITY-001",
+            Self::CopyFailed { .. } => "AZB-COPY-001",
+            Self::InvalidPartition(_) => "AZB-PARTITION-001",
+            Self::Decode { .. } => "AZB-DECODE-001",
+            Self::LocalIo { .. } => "AZB-IO-001",
+            Self::InvalidArchive { .. } => "AZB-ARCHIVE-001",
+            Self::InvalidAcl(_) => "AZB-ACL-001",
+            Self::AclBatchFailed { .. } => "AZB-ACL-002",
+            Self::InvalidPermissions(_) => "AZB-ACL-003",
+            Self::InvalidSas(_) => "AZB-SAS-001",
+            Self::InvalidLease(_) => "AZB-LEASE-001",
+            Self::LockTimeout { .. } => "AZB-LEASE-002",
+            Self::PointerLoop { .. } => "AZB-POINTER-001",
+            Self::InvalidManifest(_) => "AZB-MANIFEST-001",
+            Self::InvalidWatchedFile { .. } => "AZB-WATCH-001",
+            Self::InvalidJson { .. } => "AZB-JSON-001",
+        }
+    }
+}
+
+/// Status and service error code of a failed request, if it got as far as a response
+pub(crate) fn http_status(error: &azure_core::Error) -> Option<(StatusCode, Option<&str>)> {
+    match error.kind() {
+        ErrorKind::HttpResponse { status, error_code } => Some((*status, error_code.as_deref())),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_and_diagnostics_carry_the_error_code() {
+        let request_error = || azure_core::Error::message(ErrorKind::Other, "failed");
+        let json_error = || serde_json::from_str::<u8>("x").unwrap_err();
+        let io_error = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let errors = [
+            AzureStorageError::InvalidCredentialConfig("empty".to_string()),
+            AzureStorageError::Credential(request_error()),
+            AzureStorageError::InvalidSnapshot(json_error()),
+            AzureStorageError::Request(request_error()),
+            AzureStorageError::AppendConflict { path: "a".to_string(), position: 1 },
+            AzureStorageError::InvalidKey("../key".to_string()),
+            AzureStorageError::KeyConflict { key: "key".to_string() },
+            AzureStorageError::ConditionNotMet { path: "a".to_string() },
+            AzureStorageError::ChecksumMismatch { path: "a".to_string(), expected: "x".to_string(), actual: None },
+            AzureStorageError::CopyFailed { path: "a".to_string(), description: "aborted".to_string() },
+            AzureStorageError::InvalidPartition("a/b".to_string()),
+            AzureStorageError::Decode { path: "a".to_string(), layer: ContentLayer::Gzip, source: io_error() },
+            AzureStorageError::LocalIo { path: "local".into(), source: io_error() },
+            AzureStorageError::InvalidArchive { path: "a.zip".into(), description: "truncated".to_string() },
+            AzureStorageError::InvalidAcl("user".to_string()),
+            AzureStorageError::AclBatchFailed { path: "a".to_string(), continuation: None, source: request_error() },
+            AzureStorageError::InvalidPermissions("rwz".to_string()),
+            AzureStorageError::InvalidSas("q".to_string()),
+            AzureStorageError::InvalidLease("5s".to_string()),
+            AzureStorageError::LockTimeout { path: "a".to_string(), waited: std::time::Duration::from_secs(1) },
+            AzureStorageError::PointerLoop { path: "a".to_string(), hops: 8 },
+            AzureStorageError::InvalidManifest("empty".to_string()),
+            AzureStorageError::InvalidWatchedFile { path: "a".to_string(), source: json_error() },
+            AzureStorageError::InvalidJson { path: "a".to_string(), source: json_error() },
+        ];
+        let mut codes = std::collections::HashSet::new();
+        for error in errors {
+            let code = error.error_code();
+            assert!(codes.insert(code), "{} is used twice", code);
+            assert!(error.to_string().starts_with(&format!("[{}] ", code)), "{}", error);
+            assert_eq!(error.code().map(|diagnostic_code| diagnostic_code.to_string()).as_deref(), Some(code));
+        }
+    }
+}
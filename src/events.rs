@@ -0,0 +1,168 @@
+//! Lifecycle events of every backend in the process, for custom alerting and admin UIs
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+use azure_core::error::ErrorKind;
+use azure_core::{Context, Policy, PolicyResult, Request, StatusCode};
+use lazy_static::lazy_static;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::http_status;
+
+/// Events a subscriber may fall behind by before it misses the oldest ones
+const EVENT_CAPACITY: usize = 256;
+
+/// Statuses the SDK's retry policy retries, so a request failing with one of them ran out of retries
+const RETRIED_STATUSES: [StatusCode; 6] = [
+    StatusCode::RequestTimeout,
+    StatusCode::TooManyRequests,
+    StatusCode::InternalServerError,
+    StatusCode::BadGateway,
+    StatusCode::ServiceUnavailable,
+    StatusCode::GatewayTimeout,
+];
+
+lazy_static! {
+    static ref BACKEND_EVENTS: broadcast::Sender<BackendEvent> = broadcast::channel(EVENT_CAPACITY).0;
+}
+
+/// Something that happened to a cached client, identified by its storage account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendEvent {
+    /// A new client was created and cached
+    ClientCreated { account: String },
+    /// A client was removed from the cache by [`AzureStorageBackend::evict`]
+    ClientEvicted { account: String },
+    /// The client acquired a new token for `resource`
+    TokenRefreshed {
+        account: String,
+        resource: String,
+        expires_on: OffsetDateTime,
+    },
+    /// The service throttled the account and the governor stopped every request to it for `pause`
+    CircuitOpened { account: String, pause: Duration },
+    /// A request still failed after the SDK's last retry. `status` is `None` for connection errors
+    RetriesExhausted {
+        account: String,
+        url: String,
+        status: Option<u16>,
+    },
+}
+
+/// Publishes `event` to the current subscribers, if there are any
+pub(crate) fn emit(event: BackendEvent) {
+    let _ = BACKEND_EVENTS.send(event);
+}
+
+impl AzureStorageBackend {
+    /// Receives the events of all backends in this process from now on. A receiver that falls more than
+    /// 256 events behind gets [`broadcast::error::RecvError::Lagged`] and continues with the oldest event kept
+    pub fn subscribe_events() -> broadcast::Receiver<BackendEvent> {
+        BACKEND_EVENTS.subscribe()
+    }
+}
+
+/// Reports every token the wrapped credential hands out. Placed under the client's refreshing cache, so it only
+/// sees the requests that actually refresh a token
+pub(crate) struct TokenEventCredential {
+    credential: Arc<dyn TokenCredential>,
+    account: String,
+}
+
+impl TokenEventCredential {
+    pub(crate) fn new(credential: Arc<dyn TokenCredential>, account: impl Into<String>) -> Self {
+        Self {
+            credential,
+            account: account.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenCredential for TokenEventCredential {
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let token = self.credential.get_token(resource).await?;
+        emit(BackendEvent::TokenRefreshed {
+            account: self.account.clone(),
+            resource: resource.to_string(),
+            expires_on: token.expires_on,
+        });
+        Ok(token)
+    }
+}
+
+/// Per call policy, so it sees the outcome after the SDK's retry policy gave up
+#[derive(Debug)]
+pub(crate) struct RetryEventsPolicy {
+    account: String,
+}
+
+impl RetryEventsPolicy {
+    pub(crate) fn new(account: impl Into<String>) -> Self {
+        Self { account: account.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for RetryEventsPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        let result = next[0].send(ctx, request, &next[1..]).await;
+        if let Err(error) = &result {
+            if let Some(status) = retried_status(error) {
+                emit(BackendEvent::RetriesExhausted {
+                    account: self.account.clone(),
+                    url: request.url().to_string(),
+                    status,
+                });
+            }
+        }
+        result
+    }
+}
+
+/// `Some` with the HTTP status, if any, when `error` is one the retry policy retries
+fn retried_status(error: &azure_core::Error) -> Option<Option<u16>> {
+    match http_status(error) {
+        Some((status, _)) if RETRIED_STATUSES.contains(&status) => Some(Some(status as u16)),
+        Some(_) => None,
+        None => matches!(error.kind(), ErrorKind::Io).then_some(None),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_retried_errors_count_as_exhausted() {
+        let throttled = ErrorKind::http_response(StatusCode::ServiceUnavailable, None).into_error();
+        assert_eq!(retried_status(&throttled), Some(Some(503)));
+
+        let missing = ErrorKind::http_response(StatusCode::NotFound, Some("PathNotFound".to_string())).into_error();
+        assert_eq!(retried_status(&missing), None);
+
+        let reset = azure_core::Error::message(ErrorKind::Io, "connection reset");
+        assert_eq!(retried_status(&reset), Some(None));
+        assert_eq!(retried_status(&azure_core::Error::message(ErrorKind::Credential, "no token")), None);
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_events() {
+        let mut events = AzureStorageBackend::subscribe_events();
+        let event = BackendEvent::ClientEvicted {
+            account: "events-test".to_string(),
+        };
+        emit(event.clone());
+
+        // other tests may emit at the same time, skip their events
+        loop {
+            if events.recv().await.unwrap() == event {
+                break;
+            }
+        }
+    }
+}
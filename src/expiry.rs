@@ -0,0 +1,83 @@
+//! Expiry times after which the service deletes files by itself, e.g. for staging files nothing cleans up
+use std::time::Duration;
+
+use azure_core::headers::HeaderName;
+use azure_core::Method;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::logging::LogLevel;
+use crate::rest::RestRequest;
+
+const EXPIRY_OPTION: HeaderName = HeaderName::from_static("x-ms-expiry-option");
+const EXPIRY_TIME: HeaderName = HeaderName::from_static("x-ms-expiry-time");
+
+/// When a file expires. Files only, directories cannot expire
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileExpiry {
+    /// This long from now
+    RelativeToNow(Duration),
+    /// This long after the file was created, already expired when that lies in the past
+    RelativeToCreation(Duration),
+    /// At this time, which must lie in the future
+    Absolute(OffsetDateTime),
+    /// Removes the expiry time the file had
+    Never,
+}
+
+impl FileExpiry {
+    /// The headers selecting the expiry, relative times in milliseconds
+    fn headers(&self) -> Vec<(HeaderName, String)> {
+        let (option, time) = match self {
+            Self::RelativeToNow(duration) => ("RelativeToNow", Some(duration.as_millis().to_string())),
+            Self::RelativeToCreation(duration) => ("RelativeToCreation", Some(duration.as_millis().to_string())),
+            Self::Absolute(time) => ("Absolute", Some(azure_core::date::to_rfc1123(time))),
+            Self::Never => ("NeverExpire", None),
+        };
+        let mut headers = vec![(EXPIRY_OPTION, option.to_string())];
+        headers.extend(time.map(|time| (EXPIRY_TIME, time)));
+        headers
+    }
+}
+
+impl AzureStorageBackend {
+    /// Sets when the service deletes the file at `path`, replacing any expiry time it had. Overwriting the file
+    /// keeps the expiry time, deleting it before then is fine
+    pub async fn set_expiry(&self, container_name: &str, path: &str, expiry: FileExpiry) -> Result<(), miette::Error> {
+        let request = RestRequest::blob(Method::Put, container_name, path).query("comp", "expiry").headers(expiry.headers());
+        self.send_rest(request).await?;
+        self.log(LogLevel::Info, format_args!("Set expiry of {}/{} to {:?}", container_name, path, expiry));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    #[test]
+    fn test_expiry_headers() {
+        let headers = FileExpiry::RelativeToNow(Duration::from_secs(90)).headers();
+        assert_eq!(headers, [(EXPIRY_OPTION, "RelativeToNow".to_string()), (EXPIRY_TIME, "90000".to_string())]);
+
+        let time = OffsetDateTime::from_unix_timestamp(1_760_443_200).unwrap();
+        let headers = FileExpiry::Absolute(time).headers();
+        assert_eq!(headers, [(EXPIRY_OPTION, "Absolute".to_string()), (EXPIRY_TIME, "Tue, 14 Oct 2025 12:00:00 GMT".to_string())]);
+
+        assert_eq!(FileExpiry::Never.headers(), [(EXPIRY_OPTION, "NeverExpire".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_setting_the_expiry_leaves_the_content_alone() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "content");
+        let expiry = FileExpiry::RelativeToNow(Duration::from_secs(60));
+        service.backend().set_expiry("raw", "data/a.csv", expiry).await.unwrap();
+
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"content"[..]));
+        assert_eq!(service.requests(), ["PUT blob:/raw/data/a.csv?comp=expiry"]);
+    }
+}
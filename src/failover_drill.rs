@@ -0,0 +1,98 @@
+//! Simulated outages of an account's primary endpoint for failover drills, enabled by the `testing` feature
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use azure_core::error::{Error, ErrorKind};
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::{BytesStream, Context, Policy, PolicyResult, Request, Response, StatusCode};
+use lazy_static::lazy_static;
+
+use crate::backend::AzureStorageBackend;
+use crate::logging::LogLevel;
+
+const ERROR_CODE: HeaderName = HeaderName::from_static("x-ms-error-code");
+
+lazy_static! {
+    static ref ACTIVE_DRILLS: RwLock<HashMap<String, DrillFailure>> = RwLock::new(HashMap::new());
+}
+
+/// How requests to the primary endpoint fail during a drill
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DrillFailure {
+    /// `503 ServerBusy` responses, which the SDK retries and the throttle governor reacts to
+    #[default]
+    ServiceUnavailable,
+    /// Connection errors, as when the region is unreachable
+    ConnectionError,
+}
+
+impl AzureStorageBackend {
+    /// Fails every request to this backend's primary endpoint client side until the drill is stopped, for every
+    /// client of the account. Requests to the `-secondary` endpoint are unaffected, so read fallbacks and circuit
+    /// breakers can be exercised without touching real infrastructure
+    pub fn start_failover_drill(&self, failure: DrillFailure) {
+        self.log(LogLevel::Info, format_args!("Starting failover drill for {}", self.config.storage_account_url));
+        ACTIVE_DRILLS.write().unwrap().insert(self.config.storage_account_url.clone(), failure);
+    }
+
+    pub fn stop_failover_drill(&self) {
+        if ACTIVE_DRILLS.write().unwrap().remove(&self.config.storage_account_url).is_some() {
+            self.log(LogLevel::Info, format_args!("Stopped failover drill for {}", self.config.storage_account_url));
+        }
+    }
+}
+
+/// Last policy before the transport, so everything above it sees the simulated failures like real ones
+#[derive(Debug)]
+pub(crate) struct FailoverDrillPolicy {
+    account: String,
+}
+
+impl FailoverDrillPolicy {
+    pub(crate) fn new(account: impl Into<String>) -> Self {
+        Self { account: account.into() }
+    }
+
+    /// The failure to simulate for a request to `host`, if a drill is running for its account
+    fn failure(&self, host: Option<&str>) -> Option<DrillFailure> {
+        // the secondary endpoint is `<account>-secondary.<suffix>`
+        let primary = host.and_then(|host| host.split('.').next()) == Some(self.account.as_str());
+        if !primary {
+            return None;
+        }
+        ACTIVE_DRILLS.read().unwrap().get(&self.account).copied()
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for FailoverDrillPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        match self.failure(request.url().host_str()) {
+            None => next[0].send(ctx, request, &next[1..]).await,
+            Some(DrillFailure::ConnectionError) => Err(Error::message(ErrorKind::Io, "failover drill: primary endpoint unreachable")),
+            Some(DrillFailure::ServiceUnavailable) => {
+                let mut headers = Headers::new();
+                headers.insert(ERROR_CODE, "ServerBusy");
+                Ok(Response::new(StatusCode::ServiceUnavailable, headers, Box::pin(BytesStream::new_empty())))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drill_only_fails_the_primary_endpoint() {
+        let policy = FailoverDrillPolicy::new("drillaccount");
+        assert_eq!(policy.failure(Some("drillaccount.dfs.core.windows.net")), None);
+
+        ACTIVE_DRILLS.write().unwrap().insert("drillaccount".to_string(), DrillFailure::ConnectionError);
+        assert_eq!(policy.failure(Some("drillaccount.dfs.core.windows.net")), Some(DrillFailure::ConnectionError));
+        assert_eq!(policy.failure(Some("drillaccount-secondary.dfs.core.windows.net")), None);
+        assert_eq!(FailoverDrillPolicy::new("otheraccount").failure(Some("otheraccount.dfs.core.windows.net")), None);
+        ACTIVE_DRILLS.write().unwrap().remove("drillaccount");
+    }
+}
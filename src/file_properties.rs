@@ -0,0 +1,83 @@
+//! Typed properties of files, including the headers the SDK's response types leave out
+use azure_core::headers::{Headers, LEASE_STATE};
+use azure_core::Context;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::ResponseHeaders;
+use crate::error::AzureStorageError;
+
+/// Whether a file is leased, as reported by `x-ms-lease-state`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseState {
+    Available,
+    Leased,
+    Expired,
+    Breaking,
+    Broken,
+}
+
+impl LeaseState {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "available" => Some(Self::Available),
+            "leased" => Some(Self::Leased),
+            "expired" => Some(Self::Expired),
+            "breaking" => Some(Self::Breaking),
+            "broken" => Some(Self::Broken),
+            _ => None,
+        }
+    }
+}
+
+/// Properties of a file at the time they were read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileProperties {
+    pub size: u64,
+    pub last_modified: OffsetDateTime,
+    pub etag: String,
+    pub content_type: Option<String>,
+    /// `None` when the service did not report it
+    pub lease_state: Option<LeaseState>,
+}
+
+impl AzureStorageBackend {
+    /// Size, last modification, etag, content type and lease state of the file at `path`, in one request
+    pub async fn get_properties(&self, container_name: &str, path: &str) -> Result<FileProperties, miette::Error> {
+        let mut context = Context::new();
+        context.insert(ResponseHeaders::default());
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        let response = file_client
+            .get_properties()
+            .context(context.clone())
+            .await
+            .map_err(AzureStorageError::Request)?;
+        let headers = context.get::<ResponseHeaders>().and_then(ResponseHeaders::take);
+
+        Ok(FileProperties {
+            size: response.content_length.unwrap_or_default().max(0) as u64,
+            last_modified: response.last_modified,
+            etag: response.etag,
+            content_type: response.content_type,
+            lease_state: headers.as_ref().and_then(lease_state),
+        })
+    }
+}
+
+fn lease_state(headers: &Headers) -> Option<LeaseState> {
+    headers.get_optional_str(&LEASE_STATE).and_then(LeaseState::parse)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_state_is_read_from_the_headers() {
+        let mut headers = Headers::new();
+        assert_eq!(lease_state(&headers), None);
+        headers.insert(LEASE_STATE, "leased");
+        assert_eq!(lease_state(&headers), Some(LeaseState::Leased));
+    }
+}
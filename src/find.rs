@@ -0,0 +1,130 @@
+//! Searching listings by name, size and age, the building block of retention and cleanup jobs
+use futures::{Stream, TryStreamExt};
+use regex::Regex;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::listing::PathEntry;
+
+/// Predicates an entry must all satisfy to be found. Without any, every file below the prefix is found
+#[derive(Clone, Debug)]
+pub struct FindOptions {
+    pub(crate) name: Option<Regex>,
+    pub(crate) min_size: Option<u64>,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) modified_after: Option<OffsetDateTime>,
+    pub(crate) modified_before: Option<OffsetDateTime>,
+    pub(crate) recursive: bool,
+    pub(crate) include_directories: bool,
+}
+
+impl Default for FindOptions {
+    fn default() -> Self {
+        Self {
+            name: None,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            recursive: true,
+            include_directories: false,
+        }
+    }
+}
+
+impl FindOptions {
+    /// Only entries whose last path segment matches `name`, e.g. `^part-\d+\.parquet$`
+    pub fn name(mut self, name: Regex) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Only files of at least `min_size` bytes
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = Some(min_size);
+        self
+    }
+
+    /// Only files of at most `max_size` bytes
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Only entries last modified after `modified_after`
+    pub fn modified_after(mut self, modified_after: OffsetDateTime) -> Self {
+        self.modified_after = Some(modified_after);
+        self
+    }
+
+    /// Only entries last modified before `modified_before`, e.g. now minus the retention period
+    pub fn modified_before(mut self, modified_before: OffsetDateTime) -> Self {
+        self.modified_before = Some(modified_before);
+        self
+    }
+
+    /// Whether to search below the direct children of the prefix, true by default
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Whether directories can be found too, false by default. Size predicates only ever match files
+    pub fn include_directories(mut self, include_directories: bool) -> Self {
+        self.include_directories = include_directories;
+        self
+    }
+
+    fn matches(&self, entry: &PathEntry) -> bool {
+        let name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+        let sized = self.min_size.is_some() || self.max_size.is_some();
+        (!entry.is_directory || (self.include_directories && !sized))
+            && self.name.as_ref().is_none_or(|pattern| pattern.is_match(name))
+            && self.min_size.is_none_or(|min_size| entry.size >= min_size)
+            && self.max_size.is_none_or(|max_size| entry.size <= max_size)
+            && self.modified_after.is_none_or(|after| entry.last_modified > after)
+            && self.modified_before.is_none_or(|before| entry.last_modified < before)
+    }
+}
+
+impl AzureStorageBackend {
+    /// The entries below `prefix` that satisfy every predicate of `options`, like `find`. The listing streams
+    /// in page by page and is filtered as it arrives, so matches can be acted on before the search completes
+    pub async fn find(&self, container_name: &str, prefix: &str, options: FindOptions) -> impl Stream<Item = Result<PathEntry, miette::Error>> {
+        self.list(container_name, prefix, options.recursive)
+            .await
+            .try_filter(move |entry| futures::future::ready(options.matches(entry)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    fn entry(path: &str, is_directory: bool, size: u64, age: Duration) -> PathEntry {
+        PathEntry {
+            path: path.to_string(),
+            is_directory,
+            size,
+            last_modified: OffsetDateTime::UNIX_EPOCH + Duration::days(100) - age,
+            etag: "0x8D".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_entries_must_satisfy_every_predicate() {
+        let cutoff = OffsetDateTime::UNIX_EPOCH + Duration::days(70);
+        let options = FindOptions::default().name(Regex::new(r"\.log$").unwrap()).min_size(10).modified_before(cutoff);
+
+        assert!(options.matches(&entry("logs/app.log", false, 10, Duration::days(40))));
+        assert!(!options.matches(&entry("logs/app.log", false, 9, Duration::days(40))));
+        assert!(!options.matches(&entry("logs/app.log", false, 10, Duration::days(20))));
+        assert!(!options.matches(&entry("logs.log/app.csv", false, 10, Duration::days(40))));
+        assert!(!options.matches(&entry("logs/old.log", true, 0, Duration::days(40))));
+
+        let directories = FindOptions::default().include_directories(true);
+        assert!(directories.matches(&entry("logs", true, 0, Duration::ZERO)));
+    }
+}
@@ -0,0 +1,122 @@
+//! Selecting paths by glob pattern, on top of a listing of the pattern's literal prefix
+use futures::{Stream, TryStreamExt};
+
+use crate::backend::AzureStorageBackend;
+use crate::listing::PathEntry;
+
+/// A pattern over `/` separated paths. `*` matches any characters within a segment, `?` a single one, and a
+/// `**` segment any number of segments, including none
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Glob {
+    segments: Vec<Vec<char>>,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Self {
+        let segments = pattern.trim_matches('/').split('/').filter(|segment| !segment.is_empty());
+        Self {
+            segments: segments.map(|segment| segment.chars().collect()).collect(),
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let path: Vec<Vec<char>> = path.trim_matches('/').split('/').map(|segment| segment.chars().collect()).collect();
+        match_segments(&self.segments, &path)
+    }
+
+    /// The literal leading segments, the deepest directory every match lies below
+    fn prefix(&self) -> String {
+        let literal = &self.segments[..self.prefix_length()];
+        literal.iter().map(|segment| segment.iter().collect::<String>()).collect::<Vec<_>>().join("/")
+    }
+
+    /// Whether matches can lie deeper than the direct children of the prefix
+    fn is_recursive(&self) -> bool {
+        let below_prefix = &self.segments[self.prefix_length()..];
+        below_prefix.len() > 1 || below_prefix.iter().any(|segment| segment == &['*', '*'])
+    }
+
+    /// Leading segments without wildcards, never the last one as it may name a file
+    fn prefix_length(&self) -> usize {
+        self.segments.iter().take(self.segments.len().saturating_sub(1)).take_while(|segment| !is_wildcard(segment)).count()
+    }
+}
+
+fn is_wildcard(segment: &[char]) -> bool {
+    segment.iter().any(|character| matches!(character, '*' | '?'))
+}
+
+fn match_segments(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == &['*', '*'] => (0..=path.len()).any(|skipped| match_segments(rest, &path[skipped..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => match_segment(first, segment) && match_segments(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Wildcard match of one segment, backtracking to the last `*` on a mismatch
+fn match_segment(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&character) if character == '?' || character == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&character| character == '*')
+}
+
+impl AzureStorageBackend {
+    /// Files and directories whose path matches `pattern`, e.g. `data/2024-*/**/*.parquet`. Only the literal
+    /// leading directories of the pattern are listed by the service, the rest is matched as the listing streams in,
+    /// so a pattern starting with a wildcard lists the whole container
+    pub async fn glob(&self, container_name: &str, pattern: &str) -> impl Stream<Item = Result<PathEntry, miette::Error>> {
+        let glob = Glob::new(pattern);
+        self.list(container_name, &glob.prefix(), glob.is_recursive())
+            .await
+            .try_filter(move |entry| futures::future::ready(glob.matches(&entry.path)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns_match_within_and_across_segments() {
+        let glob = Glob::new("data/2024-*/**/*.parquet");
+        assert!(glob.matches("data/2024-01/part-0.parquet"));
+        assert!(glob.matches("data/2024-01/region=eu/day=3/part-0.parquet"));
+        assert!(!glob.matches("data/2023-12/part-0.parquet"));
+        assert!(!glob.matches("data/2024-01/part-0.csv"));
+        assert!(!glob.matches("data/part-0.parquet"));
+        assert_eq!((glob.prefix().as_str(), glob.is_recursive()), ("data", true));
+
+        let glob = Glob::new("logs/app-?.log");
+        assert!(glob.matches("logs/app-1.log"));
+        assert!(!glob.matches("logs/app-10.log"));
+        assert_eq!((glob.prefix().as_str(), glob.is_recursive()), ("logs", false));
+
+        assert!(match_segment(&['*', 'a', '*', 'b'], &['x', 'a', 'y', 'a', 'b']));
+        assert_eq!(Glob::new("*.csv").prefix(), "");
+    }
+}
@@ -0,0 +1,225 @@
+//! Handles on a cached client with their own retry, timeout, priority and dry-run settings
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::error::{Error, ErrorKind};
+use azure_core::{ClientOptions, Context, ExponentialRetryOptions, Method, Policy, PolicyResult, Request, RetryOptions};
+use tokio::sync::RwLock;
+
+use crate::backend::AzureStorageBackend;
+use crate::logging::{LogLevel, LogSettings};
+use crate::sdk::datalake::*;
+use crate::sdk::rest::{self, Pipeline};
+use crate::sdk::storage::StorageCredentials;
+use crate::throttle::ThrottleGovernor;
+
+/// How many further governor delays a background request waits while the account is throttled
+const BACKGROUND_DELAY_FACTOR: u32 = 3;
+
+/// A cached client and what it was built from, so handles with overrides can build their own on the same
+/// credential, policies and connection pool
+#[derive(Debug)]
+pub(crate) struct ClientParts {
+    pub(crate) client: Arc<RwLock<DataLakeClient>>,
+    pub(crate) pipeline: Pipeline,
+    pub(crate) storage_credentials: StorageCredentials,
+    pub(crate) client_options: ClientOptions,
+}
+
+/// How requests compete for the account when it is throttled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RequestPriority {
+    #[default]
+    Normal,
+    /// Backs off several times longer than normal requests while the throttle governor spaces requests out,
+    /// leaving the capacity to latency sensitive call sites. Without throttling it makes no difference
+    Background,
+}
+
+/// Overrides for the requests sent through one handle, see [`AzureStorageBackend::with_options`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandleOptions {
+    pub(crate) max_retries: Option<u32>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) priority: RequestPriority,
+    pub(crate) dry_run: bool,
+}
+
+impl HandleOptions {
+    /// Retries of a failed request, 8 by default as in the SDK
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Time each attempt may take until the response headers arrive, before it fails and is retried. Reading
+    /// the response body is not timed, a large download may take longer
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn priority(mut self, priority: RequestPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Logs requests that would change anything instead of sending them, and fails them. Reads are sent as
+    /// usual, so a dry run shows the first change a call site would make
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl AzureStorageBackend {
+    /// A handle on the same cached client whose requests use `options`, replacing any overrides of this handle.
+    /// Tokens, throttling, limits, logging and connections stay shared with every other handle of the client
+    pub fn with_options(&self, options: HandleOptions) -> Self {
+        let mut backend = self.clone();
+        let mut client_options = self.parts.client_options.clone();
+        if let Some(max_retries) = options.max_retries {
+            client_options = client_options.retry(RetryOptions::exponential(ExponentialRetryOptions::default().max_retries(max_retries)));
+        }
+        if options.dry_run {
+            client_options.per_call_policies_mut().insert(0, Arc::new(DryRunPolicy::new(Arc::clone(&self.log_settings))));
+        }
+        if let (RequestPriority::Background, Some(governor)) = (options.priority, &self.governor) {
+            client_options
+                .per_retry_policies_mut()
+                .insert(0, Arc::new(BackgroundPriorityPolicy::new(Arc::clone(governor))));
+        }
+        if let Some(timeout) = options.timeout {
+            client_options.per_retry_policies_mut().push(Arc::new(AttemptTimeoutPolicy::new(timeout)));
+        }
+
+        (backend.client, backend.pipeline) = match options.is_default() {
+            true => (Arc::clone(&self.parts.client), self.parts.pipeline.clone()),
+            false => {
+                let data_lake_client = self.config.data_lake_client(self.parts.storage_credentials.clone(), client_options.clone());
+                let pipeline = rest::pipeline(client_options, self.parts.storage_credentials.clone());
+                (Arc::new(RwLock::new(data_lake_client)), pipeline)
+            }
+        };
+        backend.options = options;
+        backend
+    }
+
+    /// The overrides of this handle
+    pub fn options(&self) -> &HandleOptions {
+        &self.options
+    }
+}
+
+#[derive(Debug)]
+struct DryRunPolicy {
+    log_settings: Arc<LogSettings>,
+}
+
+impl DryRunPolicy {
+    fn new(log_settings: Arc<LogSettings>) -> Self {
+        Self { log_settings }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for DryRunPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        if matches!(*request.method(), Method::Get | Method::Head) {
+            return next[0].send(ctx, request, &next[1..]).await;
+        }
+        self.log_settings.event(LogLevel::Info, format_args!("Dry run, not sending {} {}", request.method(), request.url()));
+        Err(Error::message(ErrorKind::Other, format!("dry run: {} {} was not sent", request.method(), request.url())))
+    }
+}
+
+/// Placed before the throttle policy, so a throttled account serves normal requests first
+#[derive(Debug)]
+struct BackgroundPriorityPolicy {
+    governor: Arc<ThrottleGovernor>,
+}
+
+impl BackgroundPriorityPolicy {
+    fn new(governor: Arc<ThrottleGovernor>) -> Self {
+        Self { governor }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for BackgroundPriorityPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        let delay = self.governor.current_delay();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay * BACKGROUND_DELAY_FACTOR).await;
+        }
+        next[0].send(ctx, request, &next[1..]).await
+    }
+}
+
+/// Last before the transport, so each attempt is timed on its own and the retry policy sees the timeout. The
+/// transport returns once the headers arrived, the body is read after the policies
+#[derive(Debug)]
+struct AttemptTimeoutPolicy {
+    timeout: Duration,
+}
+
+impl AttemptTimeoutPolicy {
+    fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for AttemptTimeoutPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        match tokio::time::timeout(self.timeout, next[0].send(ctx, request, &next[1..])).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::message(ErrorKind::Io, format!("request timed out after {:?}", self.timeout))),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Respond(Duration);
+
+    #[async_trait::async_trait]
+    impl Policy for Respond {
+        async fn send(&self, _ctx: &Context, _request: &mut Request, _next: &[Arc<dyn Policy>]) -> PolicyResult {
+            tokio::time::sleep(self.0).await;
+            Ok(azure_core::Response::new(
+                azure_core::StatusCode::Ok,
+                azure_core::headers::Headers::new(),
+                Box::pin(azure_core::BytesStream::new_empty()),
+            ))
+        }
+    }
+
+    fn request(method: Method) -> Request {
+        Request::new(url::Url::parse("https://account.dfs.core.windows.net/data/file.csv").unwrap(), method)
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_only_sends_reads() {
+        let next: Vec<Arc<dyn Policy>> = vec![Arc::new(Respond(Duration::ZERO))];
+        let policy = DryRunPolicy::new(Arc::default());
+        assert!(policy.send(&Context::new(), &mut request(Method::Head), &next).await.is_ok());
+        assert!(policy.send(&Context::new(), &mut request(Method::Delete), &next).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slow_attempts_time_out() {
+        let policy = AttemptTimeoutPolicy::new(Duration::from_millis(10));
+        let slow: Vec<Arc<dyn Policy>> = vec![Arc::new(Respond(Duration::from_secs(5)))];
+        let error = policy.send(&Context::new(), &mut request(Method::Get), &slow).await.unwrap_err();
+        assert_eq!(*error.kind(), ErrorKind::Io);
+    }
+}
@@ -0,0 +1,86 @@
+//! Handing cached backends over to a restarted or forked worker process
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{cached_backend_configs, AzureStorageBackend, AzureStorageBackendBuilder};
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+
+/// Configuration of every backend cached in a process, without any secrets or tokens. Capture it before a
+/// restart, pass the JSON to the new process (environment, file, pipe...) and restore it there.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendSnapshot {
+    pub backends: Vec<AzureStorageBackendBuilder>,
+}
+
+impl BackendSnapshot {
+    /// Snapshot of the backends currently in the client cache
+    pub async fn capture() -> Self {
+        Self {
+            backends: cached_backend_configs().await,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, miette::Error> {
+        Ok(serde_json::to_string(self).map_err(AzureStorageError::InvalidSnapshot)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, miette::Error> {
+        Ok(serde_json::from_str(json).map_err(AzureStorageError::InvalidSnapshot)?)
+    }
+
+    /// Recreates every backend in the snapshot and concurrently re-acquires their storage tokens. A failed warm
+    /// up is only reported, the token will be requested again on first use.
+    pub async fn restore(&self) -> Result<Vec<AzureStorageBackend>, miette::Error> {
+        let mut backends = Vec::with_capacity(self.backends.len());
+        for config in &self.backends {
+            backends.push(config.clone().build().await?);
+        }
+
+        let warm_ups = backends.iter().map(|backend| async move { (backend, backend.warm_up().await) });
+        for (backend, warm_up) in futures::future::join_all(warm_ups).await {
+            if let Err(error) = warm_up {
+                let account = &backend.config.storage_account_url;
+                backend.log(LogLevel::Error, format_args!("Failed to re-warm token for {}: {:?}", account, error));
+            }
+        }
+
+        Ok(backends)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::credential::{CredentialKind, InteractiveBrowserOptions};
+
+    #[test]
+    fn test_snapshot_json_round_trip() -> Result<(), miette::Error> {
+        let snapshot = BackendSnapshot {
+            backends: vec![
+                AzureStorageBackendBuilder::new("account1"),
+                AzureStorageBackendBuilder::new("account2")
+                    .credential(CredentialKind::InteractiveBrowser(InteractiveBrowserOptions::default().tenant_id("tenant"))),
+            ],
+        };
+
+        let restored = BackendSnapshot::from_json(&snapshot.to_json()?)?;
+        assert_eq!(snapshot, restored);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_snapshot_is_rejected() {
+        assert!(BackendSnapshot::from_json("{\"backends\": 3}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capture_includes_cached_backends() -> Result<(), miette::Error> {
+        let config = AzureStorageBackendBuilder::new("snapshotcaptureaccount");
+        config.clone().build().await?;
+
+        assert!(BackendSnapshot::capture().await.backends.contains(&config));
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Content-MD5 of transferred content, checked by the service on upload and against the stored hash on download
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::StatusCode;
+use md5::{Digest, Md5};
+
+use crate::error::{http_status, AzureStorageError};
+
+/// Hash of the content of one request, verified by the service before it accepts the request
+pub(crate) const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+/// Hash of the whole file, stored with the file when it is committed and returned as its `Content-MD5`
+pub(crate) const FILE_CONTENT_MD5: HeaderName = HeaderName::from_static("x-ms-content-md5");
+
+pub(crate) fn md5_base64(bytes: &[u8]) -> String {
+    azure_core::base64::encode(Md5::digest(bytes))
+}
+
+/// The hash stored with the file, from the headers of a properties request. `None` for files committed without
+/// one, which cannot be verified
+pub(crate) fn stored_md5(headers: &Headers) -> Option<String> {
+    headers.get_optional_string(&CONTENT_MD5)
+}
+
+/// [`AzureStorageError::ChecksumMismatch`] for an append the service rejected because its content did not
+/// match its `Content-MD5`, the error as it is otherwise
+pub(crate) fn append_error(path: &str, expected: Option<String>, error: azure_core::Error) -> AzureStorageError {
+    match (expected, http_status(&error)) {
+        (Some(expected), Some((StatusCode::BadRequest, Some("Md5Mismatch")))) => AzureStorageError::ChecksumMismatch {
+            path: path.to_string(),
+            expected,
+            actual: None,
+        },
+        _ => AzureStorageError::Request(error),
+    }
+}
+
+/// Hashes content in order as it arrives and compares the result with the hash stored with the file
+pub(crate) struct Md5Verifier {
+    expected: String,
+    hasher: Md5,
+}
+
+impl Md5Verifier {
+    pub(crate) fn new(expected: String) -> Self {
+        Self { expected, hasher: Md5::new() }
+    }
+
+    pub(crate) fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    pub(crate) fn verify(self, path: &str) -> Result<(), AzureStorageError> {
+        let actual = azure_core::base64::encode(self.hasher.finalize());
+        match actual == self.expected {
+            true => Ok(()),
+            false => Err(AzureStorageError::ChecksumMismatch {
+                path: path.to_string(),
+                expected: self.expected,
+                actual: Some(actual),
+            }),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_is_verified_against_the_stored_hash() {
+        // RFC 1321 test suite
+        assert_eq!(md5_base64(b"abc"), "kAFQmDzST7DWlj99KOF/cg==");
+
+        let mut verifier = Md5Verifier::new(md5_base64(b"abc"));
+        verifier.update(b"a");
+        verifier.update(b"bc");
+        assert!(verifier.verify("a.csv").is_ok());
+
+        let mut corrupted = Md5Verifier::new(md5_base64(b"abc"));
+        corrupted.update(b"abd");
+        assert!(matches!(corrupted.verify("a.csv"), Err(AzureStorageError::ChecksumMismatch { actual: Some(_), .. })));
+    }
+}
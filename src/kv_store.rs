@@ -0,0 +1,251 @@
+//! A small durable key-value store kept as files under a prefix
+use azure_core::prelude::IfMatchCondition;
+use azure_core::StatusCode;
+use bytes::Bytes;
+use futures::StreamExt;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+
+/// Directory under the store prefix values are staged in before being renamed into place
+const STAGING_DIRECTORY: &str = ".kvstaging";
+
+/// A value together with the etag to update or delete it conditionally
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KvEntry {
+    pub value: Bytes,
+    pub etag: String,
+}
+
+/// Precondition for a write or delete
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum KvCondition {
+    /// Unconditional, last writer wins
+    #[default]
+    Any,
+    /// Only if the key does not exist yet
+    Absent,
+    /// Only if the key still has the given etag
+    Match(String),
+}
+
+impl KvCondition {
+    fn if_match_condition(&self) -> Option<IfMatchCondition> {
+        match self {
+            KvCondition::Any => None,
+            KvCondition::Absent => Some(IfMatchCondition::NotMatch("*".to_string())),
+            KvCondition::Match(etag) => Some(IfMatchCondition::Match(etag.clone())),
+        }
+    }
+}
+
+/// Keys map to files `<prefix>/<key>` in a container. Writes are staged and renamed into place, so readers see
+/// either the old or the new value, and accept a [`KvCondition`] for optimistic concurrency.
+#[derive(Clone, Debug)]
+pub struct KvStore {
+    backend: AzureStorageBackend,
+    container_name: String,
+    prefix: String,
+}
+
+impl KvStore {
+    pub fn new(backend: &AzureStorageBackend, container_name: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self {
+            backend: backend.clone(),
+            container_name: container_name.into(),
+            prefix: prefix.into().trim_matches('/').to_string(),
+        }
+    }
+
+    fn key_path(&self, key: &str) -> Result<String, AzureStorageError> {
+        validate_key(key)?;
+        Ok(join_path(&self.prefix, key))
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<KvEntry>, miette::Error> {
+        let path = self.key_path(key)?;
+        let file_client = self.backend.file_system_client(&self.container_name).await.into_file_client(path);
+
+        match file_client.read().await {
+            Ok(response) => Ok(Some(KvEntry {
+                value: response.data,
+                etag: response.etag,
+            })),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => Ok(None),
+            Err(error) => Err(AzureStorageError::Request(error).into()),
+        }
+    }
+
+    /// Stores `value` under `key` if `condition` holds and returns the new etag
+    pub async fn put(&self, key: &str, value: impl Into<Bytes>, condition: KvCondition) -> Result<String, miette::Error> {
+        let path = self.key_path(key)?;
+        let value = value.into();
+        let file_system_client = self.backend.file_system_client(&self.container_name).await;
+        // a rename does not create the directories above its destination, those of nested keys are made here.
+        // The prefix itself is created along with the staged file
+        if let Some((parent, _)) = key.rsplit_once('/') {
+            self.backend.create_directory_all(&self.container_name, &join_path(&self.prefix, parent)).await?;
+        }
+
+        let staging_path = join_path(&self.prefix, &format!("{}/{}", STAGING_DIRECTORY, uuid::Uuid::new_v4()));
+        let staging_client = file_system_client.get_file_client(&staging_path);
+        staging_client.create().await.map_err(AzureStorageError::Request)?;
+        let length = value.len() as i64;
+        if length > 0 {
+            staging_client.append(0, value).await.map_err(AzureStorageError::Request)?;
+        }
+        // the etag of the staged file is carried over by the rename
+        let etag = staging_client
+            .flush(length)
+            .await
+            .map_err(AzureStorageError::Request)?
+            .etag
+            .unwrap_or_default();
+
+        let mut rename = staging_client.rename(path);
+        if let Some(if_match_condition) = condition.if_match_condition() {
+            rename = rename.if_match_condition(if_match_condition);
+        }
+        if let Err(error) = rename.await {
+            if let Err(cleanup_error) = staging_client.delete().await {
+                self.backend.log(LogLevel::Error, format_args!("Failed to remove staged value {}: {}", staging_path, cleanup_error));
+            }
+            return Err(self.condition_error(key, error).into());
+        }
+
+        Ok(etag)
+    }
+
+    /// Removes `key` if `condition` holds. Returns whether there was anything to remove
+    pub async fn delete(&self, key: &str, condition: KvCondition) -> Result<bool, miette::Error> {
+        let path = self.key_path(key)?;
+        let file_client = self.backend.file_system_client(&self.container_name).await.into_file_client(path);
+
+        let mut delete = file_client.delete();
+        if let Some(if_match_condition) = condition.if_match_condition() {
+            delete = delete.if_match_condition(if_match_condition);
+        }
+        match delete.await {
+            Ok(_) => Ok(true),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => Ok(false),
+            Err(error) => Err(self.condition_error(key, error).into()),
+        }
+    }
+
+    /// All keys in the store, nested ones like `jobs/42/state` included, in listing order
+    pub async fn list(&self) -> Result<Vec<String>, miette::Error> {
+        let file_system_client = self.backend.file_system_client(&self.container_name).await;
+        let mut list_paths = file_system_client.list_paths().recursive(true);
+        if !self.prefix.is_empty() {
+            list_paths = list_paths.directory(self.prefix.clone());
+        }
+
+        let mut keys = Vec::new();
+        let mut pages = list_paths.into_stream();
+        while let Some(page) = pages.next().await {
+            let page = match page {
+                Ok(page) => page,
+                Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => break,
+                Err(error) => return Err(AzureStorageError::Request(error).into()),
+            };
+            keys.extend(
+                page.paths
+                    .into_iter()
+                    .filter(|path| !path.is_directory)
+                    .filter_map(|path| key_from_path(&self.prefix, &path.name)),
+            );
+        }
+        Ok(keys)
+    }
+
+    fn condition_error(&self, key: &str, error: azure_core::Error) -> AzureStorageError {
+        match http_status(&error) {
+            Some((StatusCode::PreconditionFailed, _)) | Some((StatusCode::Conflict, _)) => {
+                AzureStorageError::KeyConflict { key: key.to_string() }
+            }
+            _ => AzureStorageError::Request(error),
+        }
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), AzureStorageError> {
+    let invalid = key.is_empty()
+        || key.starts_with('/')
+        || key.ends_with('/')
+        || key.split('/').any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        || key.split('/').next() == Some(STAGING_DIRECTORY);
+    if invalid {
+        return Err(AzureStorageError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}/{}", prefix, key)
+    }
+}
+
+/// Inverse of [`join_path`] for listed paths, skipping anything that is not a stored key
+fn key_from_path(prefix: &str, path: &str) -> Option<String> {
+    let key = if prefix.is_empty() {
+        path
+    } else {
+        path.strip_prefix(prefix)?.strip_prefix('/')?
+    };
+    validate_key(key).ok()?;
+    Some(key.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("config").is_ok());
+        assert!(validate_key("jobs/42/state").is_ok());
+
+        for key in ["", "/abs", "trailing/", "a//b", "../escape", "a/./b", ".kvstaging/x"] {
+            assert!(validate_key(key).is_err(), "{} should be rejected", key);
+        }
+    }
+
+    #[test]
+    fn test_key_paths_round_trip() {
+        assert_eq!(join_path("state", "jobs/42"), "state/jobs/42");
+        assert_eq!(join_path("", "jobs/42"), "jobs/42");
+
+        assert_eq!(key_from_path("state", "state/jobs/42").as_deref(), Some("jobs/42"));
+        assert_eq!(key_from_path("", "jobs/42").as_deref(), Some("jobs/42"));
+        assert_eq!(key_from_path("state", "statefile"), None);
+        assert_eq!(key_from_path("state", "state/.kvstaging/1234"), None);
+    }
+
+    #[test]
+    fn test_conditions_map_to_headers() {
+        assert!(KvCondition::Any.if_match_condition().is_none());
+        assert!(matches!(KvCondition::Absent.if_match_condition(), Some(IfMatchCondition::NotMatch(etag)) if etag == "*"));
+        assert!(matches!(KvCondition::Match("0x1".to_string()).if_match_condition(), Some(IfMatchCondition::Match(etag)) if etag == "0x1"));
+    }
+
+    #[tokio::test]
+    async fn test_nested_keys_are_listed() {
+        let service = FakeDataLake::new();
+        let store = KvStore::new(&service.backend(), "raw", "state");
+        store.put("config", Bytes::from("a"), KvCondition::Any).await.unwrap();
+        store.put("jobs/42/state", Bytes::from("b"), KvCondition::Any).await.unwrap();
+
+        assert!(service.paths().contains(&"raw/state/jobs/42".to_string()));
+
+        let mut keys = store.list().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["config", "jobs/42/state"]);
+    }
+}
@@ -0,0 +1,174 @@
+//! Leases on files, exclusive write locks the service enforces on every writer
+use std::time::Duration;
+
+use azure_core::headers::{HeaderName, Headers, LEASE_ACTION, LEASE_BREAK_PERIOD, LEASE_DURATION, LEASE_ID, LEASE_TIME, PROPOSED_LEASE_ID};
+use azure_core::{Context, Method};
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::RequestHeaders;
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+use crate::rest::RestRequest;
+
+/// Shortest and longest finite lease the service grants
+const MIN_LEASE_DURATION: Duration = Duration::from_secs(15);
+const MAX_LEASE_DURATION: Duration = Duration::from_secs(60);
+
+/// A lease held on a file. Writes to the file by anyone not sending the lease id fail until it is released, broken
+/// or, for finite leases, expires without being renewed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub container_name: String,
+    pub path: String,
+    pub id: String,
+    /// `None` for an infinite lease
+    pub duration: Option<Duration>,
+}
+
+/// The header a write to a leased file sends `lease_id` in, none for a file written without a lease
+pub(crate) fn lease_header(lease_id: Option<&str>) -> Option<(HeaderName, String)> {
+    lease_id.map(|lease_id| (LEASE_ID, lease_id.to_string()))
+}
+
+/// A context sending [`lease_header`] with a request, an empty one without a lease
+pub(crate) fn lease_context(lease_id: Option<&str>) -> Context {
+    RequestHeaders(lease_header(lease_id).into_iter().collect()).into_context().unwrap_or_default()
+}
+
+/// The `x-ms-lease-duration` of a lease lasting `duration`, `-1` for an infinite one
+fn lease_duration(duration: Option<Duration>) -> Result<String, AzureStorageError> {
+    match duration {
+        None => Ok("-1".to_string()),
+        Some(duration) if (MIN_LEASE_DURATION..=MAX_LEASE_DURATION).contains(&duration) && duration.subsec_nanos() == 0 => {
+            Ok(duration.as_secs().to_string())
+        }
+        Some(duration) => Err(AzureStorageError::InvalidLease(format!("lease duration {:?} is not whole seconds from 15 to 60", duration))),
+    }
+}
+
+impl AzureStorageBackend {
+    /// Sends the Path Lease operation, a POST to the path, and returns the response headers
+    async fn lease_request(&self, container_name: &str, path: &str, headers: Vec<(HeaderName, String)>) -> Result<Headers, AzureStorageError> {
+        let request = RestRequest::data_lake(Method::Post, container_name, path).headers(headers);
+        Ok(self.send_rest(request).await?.headers)
+    }
+
+    /// Takes a lease on the file at `path` for `duration`, 15 to 60 seconds, or until released when `None`. The lease
+    /// id is generated here and proposed to the service, so an acquire the retry policy sends again finds the lease
+    /// already its own. Fails with a 409 `LeaseAlreadyPresent` while someone else holds a lease
+    pub async fn acquire_lease(&self, container_name: &str, path: &str, duration: Option<Duration>) -> Result<Lease, miette::Error> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let headers = vec![
+            (LEASE_ACTION, "acquire".to_string()),
+            (LEASE_DURATION, lease_duration(duration)?),
+            (PROPOSED_LEASE_ID, id.clone()),
+        ];
+        self.lease_request(container_name, path, headers).await?;
+        self.log(LogLevel::Info, format_args!("Acquired lease {} on {}/{}", id, container_name, path));
+        Ok(Lease {
+            container_name: container_name.to_string(),
+            path: path.to_string(),
+            id,
+            duration,
+        })
+    }
+
+    /// Restarts the duration of `lease`, which must not have been released or broken. An expired lease can be
+    /// renewed as long as nobody else leased the file in the meantime
+    pub async fn renew_lease(&self, lease: &Lease) -> Result<(), miette::Error> {
+        let headers = vec![(LEASE_ACTION, "renew".to_string()), (LEASE_ID, lease.id.clone())];
+        self.lease_request(&lease.container_name, &lease.path, headers).await?;
+        Ok(())
+    }
+
+    /// Gives up `lease`, so others can write to or lease the file right away
+    pub async fn release_lease(&self, lease: Lease) -> Result<(), miette::Error> {
+        let headers = vec![(LEASE_ACTION, "release".to_string()), (LEASE_ID, lease.id.clone())];
+        self.lease_request(&lease.container_name, &lease.path, headers).await?;
+        self.log(LogLevel::Info, format_args!("Released lease {} on {}/{}", lease.id, lease.container_name, lease.path));
+        Ok(())
+    }
+
+    /// Ends whatever lease is held on the file at `path` without knowing its id, e.g. one left behind by a crashed
+    /// writer. The lease ends after `break_period` of whole seconds up to 60, or immediately when `None`, and once
+    /// broken it cannot be renewed. Returns how long until the file can be leased again
+    pub async fn break_lease(&self, container_name: &str, path: &str, break_period: Option<Duration>) -> Result<Duration, miette::Error> {
+        let mut headers = vec![(LEASE_ACTION, "break".to_string())];
+        if let Some(break_period) = break_period {
+            if break_period > MAX_LEASE_DURATION || break_period.subsec_nanos() != 0 {
+                return Err(AzureStorageError::InvalidLease(format!("break period {:?} is not whole seconds up to 60", break_period)).into());
+            }
+            headers.push((LEASE_BREAK_PERIOD, break_period.as_secs().to_string()));
+        }
+        let response = self.lease_request(container_name, path, headers).await?;
+        self.log(LogLevel::Info, format_args!("Broke the lease on {}/{}", container_name, path));
+        let remaining = response.get_optional_as::<u64, _>(&LEASE_TIME).ok().flatten().unwrap_or_default();
+        Ok(Duration::from_secs(remaining))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+
+    use crate::appender::AppendConflictStrategy;
+    use crate::condition::EtagCondition;
+    use crate::handle::HandleOptions;
+    use crate::metadata::Metadata;
+    use crate::test_service::FakeDataLake;
+    use crate::upload::UploadOptions;
+
+    #[tokio::test]
+    async fn test_dry_runs_take_no_leases() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "content");
+        let backend = service.backend().with_options(HandleOptions::default().dry_run(true));
+        assert!(backend.acquire_lease("raw", "data/a.csv", None).await.is_err());
+        assert!(service.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_the_holder_writes_under_its_lease() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "old");
+        let backend = service.backend();
+        let lease = backend.acquire_lease("raw", "data/a.csv", None).await.unwrap();
+        assert!(backend.upload_bytes("raw", "data/a.csv", "other", UploadOptions::default()).await.is_err());
+
+        backend.upload_bytes("raw", "data/a.csv", "new", UploadOptions::default().lease(&lease)).await.unwrap();
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"new"[..]));
+        let staged = UploadOptions::default().lease(&lease).condition(EtagCondition::IfMatch(service.etag("raw/data/a.csv").unwrap()));
+        backend.upload_bytes("raw", "data/a.csv", "staged", staged).await.unwrap();
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"staged"[..]));
+
+        let mut writer = backend.file_writer_under_lease(&lease).await.unwrap();
+        writer.write_all(b"written").await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"written"[..]));
+
+        let mut appender = backend.appender("raw", "data/a.csv", AppendConflictStrategy::Fail).await.unwrap().lease(&lease);
+        appender.append(" and appended").await.unwrap();
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"written and appended"[..]));
+
+        let metadata = Metadata::from([("owner".to_string(), "ingest".to_string())]);
+        assert!(backend.set_metadata("raw", "data/a.csv", &metadata).await.is_err());
+        backend.set_metadata_under_lease(&lease, &metadata).await.unwrap();
+        assert_eq!(backend.get_metadata("raw", "data/a.csv").await.unwrap(), metadata);
+
+        backend.release_lease(lease).await.unwrap();
+        backend.set_metadata("raw", "data/a.csv", &Metadata::new()).await.unwrap();
+    }
+
+    #[test]
+    fn test_lease_durations_are_whole_seconds_from_15_to_60() {
+        assert_eq!(lease_duration(None).unwrap(), "-1");
+        assert_eq!(lease_duration(Some(Duration::from_secs(15))).unwrap(), "15");
+        assert_eq!(lease_duration(Some(Duration::from_secs(60))).unwrap(), "60");
+        assert!(lease_duration(Some(Duration::from_secs(10))).is_err());
+        assert!(lease_duration(Some(Duration::from_secs(61))).is_err());
+        assert!(lease_duration(Some(Duration::from_millis(30_500))).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Reusable, cached clients for Azure ADLS Gen 2 storage accounts
+mod acl;
+mod appender;
+mod archive;
+mod backend;
+mod checksum;
+mod condition;
+mod containers;
+mod context_headers;
+mod copy;
+mod credential;
+mod decode;
+mod directory;
+mod download;
+mod error;
+mod events;
+mod expiry;
+#[cfg(any(test, feature = "testing"))]
+mod failover_drill;
+mod file_properties;
+mod find;
+mod glob;
+mod handle;
+mod handoff;
+mod immutability;
+mod integrity;
+mod inventory;
+mod kv_store;
+mod lease;
+mod listing;
+mod lock;
+mod logging;
+mod manifest;
+mod metadata;
+mod ownership;
+mod pack;
+mod partitioned_writer;
+mod path_ops;
+mod permissions;
+mod pointer;
+mod prefix_limit;
+mod progress;
+mod provenance;
+mod read_modify_write;
+mod reader;
+mod recursive_acl;
+mod rest;
+mod sas;
+mod sdk;
+mod snapshot;
+mod soft_delete;
+mod sync;
+mod tags;
+mod tail;
+#[cfg(test)]
+mod test_service;
+mod throttle;
+mod tree;
+mod upload;
+mod usage;
+mod versions;
+mod watch;
+mod writer;
+mod zip_stream;
+
+pub use acl::{AccessControlList, AclEntry, AclPermissions, AclTag};
+pub use appender::{AppendConflictStrategy, FileAppender};
+pub use archive::{ArchiveOptions, ArchiveReceipt};
+pub use backend::{AzureStorageBackend, AzureStorageBackendBuilder};
+pub use checksum::{ChecksumMode, ContentChecksum};
+pub use condition::{EtagCondition, VersionedContent};
+pub use containers::{ContainerInfo, ContainerProperties, DeletedContainer, PublicAccess};
+pub use copy::{CopyOptions, CopyReceipt, CopyStatus};
+pub use credential::{
+    CredentialKind, CredentialReport, CredentialSource, InteractiveBrowserOptions, KeyVaultAccountKey, ManagedIdentityEndpoint, TokenCacheOptions,
+    TokenRetryOptions,
+};
+pub use decode::{ContentLayer, DecodedContent};
+pub use download::{DownloadOptions, DownloadedFile};
+pub use error::AzureStorageError;
+pub use events::BackendEvent;
+pub use expiry::FileExpiry;
+#[cfg(any(test, feature = "testing"))]
+pub use failover_drill::DrillFailure;
+pub use file_properties::{FileProperties, LeaseState};
+pub use find::FindOptions;
+pub use handle::{HandleOptions, RequestPriority};
+pub use handoff::BackendSnapshot;
+pub use immutability::ImmutabilityPolicyMode;
+pub use inventory::{InventoryFormat, InventoryOptions};
+pub use kv_store::{KvCondition, KvEntry, KvStore};
+pub use lease::Lease;
+pub use listing::{DirectoryListing, ListPage, PathEntry};
+pub use lock::{DistributedLock, LockGuard, LockOptions};
+pub use logging::LogLevel;
+pub use manifest::{TransferEntry, TransferManifest, TransferStatus};
+pub use metadata::Metadata;
+pub use ownership::PathOwnership;
+pub use pack::{PackIndex, PackOptions, PackWriter, PackedFile};
+pub use partitioned_writer::{ManifestFile, PartitionManifest, PartitionedWriter, PartitionedWriterOptions};
+pub use permissions::PosixPermissions;
+pub use pointer::PointerTarget;
+pub use prefix_limit::PrefixLimit;
+pub use progress::{ProgressCallback, TransferProgress};
+pub use provenance::ProvenanceCallback;
+pub use read_modify_write::UpdateOptions;
+pub use reader::DataLakeFileReader;
+pub use recursive_acl::{AclChange, AclFailure, AclProgress, AclProgressCallback, RecursiveAclOptions};
+pub use sas::SasPermissions;
+pub use soft_delete::{DeletedPath, ListedPath};
+pub use sync::{ConflictCallback, ConflictResolution, ConflictStrategy, FileVersion, SyncAction, SyncConflict, SyncOptions, SyncPlan};
+pub use tags::{TaggedFile, Tags};
+pub use tail::TailOptions;
+pub use throttle::ThrottleConfig;
+pub use tree::TreeNode;
+pub use upload::{UploadOptions, UploadReceipt};
+pub use usage::DiskUsage;
+pub use versions::PathVersion;
+pub use writer::{DataLakeFileWriter, DropBehavior};
@@ -0,0 +1,219 @@
+//! Listing the paths below a prefix, lazily across as many pages as the service splits them into
+use std::num::NonZeroU32;
+
+use azure_core::headers::VERSION;
+use azure_core::prelude::{MaxResults, NextMarker};
+use azure_core::{Method, StatusCode};
+use bytes::Bytes;
+use futures::{Stream, StreamExt, TryStreamExt};
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::rest::RestRequest;
+use crate::sdk::datalake::*;
+
+/// A file or directory found by a listing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathEntry {
+    /// Full path within the container
+    pub path: String,
+    pub is_directory: bool,
+    /// 0 for directories
+    pub size: u64,
+    pub last_modified: OffsetDateTime,
+    pub etag: String,
+}
+
+impl From<Path> for PathEntry {
+    fn from(path: Path) -> Self {
+        Self {
+            size: path.content_length.max(0) as u64,
+            etag: path.etag.to_string(),
+            path: path.name,
+            is_directory: path.is_directory,
+            last_modified: path.last_modified,
+        }
+    }
+}
+
+/// One page of a listing, with the token [`AzureStorageBackend::list_page`] continues it from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListPage {
+    pub entries: Vec<PathEntry>,
+    /// Opaque token of the next page, `None` on the last one. Only valid for the same container, prefix and
+    /// recursion
+    pub continuation: Option<String>,
+}
+
+/// One level of a directory with its subdirectories and files apart, e.g. for a file browser
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DirectoryListing {
+    pub directories: Vec<PathEntry>,
+    pub files: Vec<PathEntry>,
+    /// Token of the rest of the level, `None` once it was listed completely
+    pub continuation: Option<String>,
+}
+
+impl From<ListPage> for DirectoryListing {
+    fn from(page: ListPage) -> Self {
+        let (directories, files) = page.entries.into_iter().partition(|entry| entry.is_directory);
+        Self {
+            directories,
+            files,
+            continuation: page.continuation,
+        }
+    }
+}
+
+/// A listing of `prefix`, the whole container when it is empty
+pub(crate) fn list_paths(file_system_client: &FileSystemClient, prefix: &str, recursive: bool) -> ListPathsBuilder {
+    let prefix = prefix.trim_matches('/');
+    let list_paths = file_system_client.list_paths().recursive(recursive);
+    match prefix.is_empty() {
+        true => list_paths,
+        false => list_paths.directory(prefix.to_string()),
+    }
+}
+
+/// Whether a listing failed because the directory listed does not exist, which lists as empty
+pub(crate) fn is_missing_directory(error: &azure_core::Error) -> bool {
+    matches!(http_status(error), Some((StatusCode::NotFound, _)))
+}
+
+/// The stream of [`AzureStorageBackend::list`]
+pub(crate) fn list_entries(
+    file_system_client: &FileSystemClient,
+    prefix: &str,
+    recursive: bool,
+) -> impl Stream<Item = Result<PathEntry, AzureStorageError>> {
+    list_paths(file_system_client, prefix, recursive)
+        .into_stream()
+        .map_ok(|page| futures::stream::iter(page.paths.into_iter().map(Ok)))
+        .try_flatten()
+        .scan(false, |failed, entry| {
+            let item = match (*failed, entry) {
+                (true, _) => None,
+                (false, Ok(path)) => Some(Ok(PathEntry::from(path))),
+                (false, Err(error)) if is_missing_directory(&error) => None,
+                (false, Err(error)) => {
+                    *failed = true;
+                    Some(Err(AzureStorageError::Request(error)))
+                }
+            };
+            futures::future::ready(item)
+        })
+}
+
+impl AzureStorageBackend {
+    /// The XML body of one page of the Blob REST API's List Blobs in `container_name`, for what the data lake
+    /// listing does not report. `query` selects what is listed, e.g. `include=versions`, and adds the prefix and
+    /// marker, `version` overrides the service version of the request
+    pub(crate) async fn list_blobs_page(
+        &self,
+        container_name: &str,
+        query: Vec<(&'static str, String)>,
+        version: Option<&'static str>,
+    ) -> Result<Bytes, AzureStorageError> {
+        let request = RestRequest::blob(Method::Get, container_name, "").query("restype", "container").query("comp", "list");
+        let request = query.into_iter().fold(request, |request, (name, value)| request.query(name, value));
+        let request = request.headers(version.map(|version| (VERSION, version.to_string())));
+        Ok(self.send_rest(request).await?.body)
+    }
+
+    /// The files and directories below `prefix`, only its direct children unless `recursive`. Pages are fetched
+    /// as the stream is consumed, so huge directories are never held in memory as a whole. A prefix that does
+    /// not exist lists as empty, and the stream ends after an error
+    pub async fn list(&self, container_name: &str, prefix: &str, recursive: bool) -> impl Stream<Item = Result<PathEntry, miette::Error>> {
+        let file_system_client = self.file_system_client(container_name).await;
+        list_entries(&file_system_client, prefix, recursive).map_err(miette::Error::from)
+    }
+
+    /// Every file and directory below `prefix` at any depth, parents before their children. Size, last
+    /// modification, etag and kind all come from the listing itself, so walking a tree takes one request per
+    /// page of entries rather than one per entry
+    pub async fn walk(&self, container_name: &str, prefix: &str) -> impl Stream<Item = Result<PathEntry, miette::Error>> {
+        self.list(container_name, prefix, true).await
+    }
+
+    /// One page of up to `page_size` entries below `prefix`, starting where the page that returned
+    /// `continuation` ended, or at the beginning without one. The service may return fewer entries than asked for
+    /// even when more follow, only a missing continuation marks the end
+    pub async fn list_page(
+        &self,
+        container_name: &str,
+        prefix: &str,
+        recursive: bool,
+        page_size: u32,
+        continuation: Option<&str>,
+    ) -> Result<ListPage, miette::Error> {
+        let file_system_client = self.file_system_client(container_name).await;
+        let mut list_paths = list_paths(&file_system_client, prefix, recursive);
+        if let Some(page_size) = NonZeroU32::new(page_size) {
+            list_paths = list_paths.max_results(MaxResults::new(page_size));
+        }
+        if let Some(continuation) = continuation {
+            list_paths = list_paths.continuation(NextMarker::new(continuation.to_string()));
+        }
+        let page = match list_paths.into_stream().next().await {
+            Some(Ok(page)) => page,
+            Some(Err(error)) if is_missing_directory(&error) => return Ok(ListPage { entries: Vec::new(), continuation: None }),
+            Some(Err(error)) => return Err(AzureStorageError::Request(error).into()),
+            None => return Ok(ListPage { entries: Vec::new(), continuation: None }),
+        };
+        Ok(ListPage {
+            entries: page.paths.into_iter().map(PathEntry::from).collect(),
+            continuation: page.continuation.map(|continuation| continuation.as_str().to_string()),
+        })
+    }
+
+    /// The direct children of `directory`, subdirectories apart from files, so a browser can render one level
+    /// at a time without listing what lies below it. Takes up to `page_size` entries per call like
+    /// [`AzureStorageBackend::list_page`], continued from the `continuation` of the previous call
+    pub async fn list_directory(
+        &self,
+        container_name: &str,
+        directory: &str,
+        page_size: u32,
+        continuation: Option<&str>,
+    ) -> Result<DirectoryListing, miette::Error> {
+        let page = self.list_page(container_name, directory, false, page_size, continuation).await?;
+        Ok(DirectoryListing::from(page))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listed_paths_become_entries() {
+        let path: Path = serde_json::from_str(
+            r#"{"contentLength": "10", "etag": "0x8D", "group": "g", "lastModified": "Tue, 14 Oct 2025 12:00:00 GMT",
+                "name": "data/a.csv", "owner": "o", "permissions": "rw-r-----"}"#,
+        )
+        .unwrap();
+        let entry = PathEntry::from(path);
+        assert_eq!((entry.path.as_str(), entry.is_directory, entry.size, entry.etag.as_str()), ("data/a.csv", false, 10, "0x8D"));
+    }
+
+    #[test]
+    fn test_directory_listings_keep_directories_apart() {
+        let entry = |path: &str, is_directory| PathEntry {
+            path: path.to_string(),
+            is_directory,
+            size: 0,
+            last_modified: OffsetDateTime::UNIX_EPOCH,
+            etag: "0x8D".to_string(),
+        };
+        let page = ListPage {
+            entries: vec![entry("data/a.csv", false), entry("data/logs", true), entry("data/b.csv", false)],
+            continuation: Some("next".to_string()),
+        };
+        let listing = DirectoryListing::from(page);
+        assert_eq!(listing.directories, [entry("data/logs", true)]);
+        assert_eq!(listing.files, [entry("data/a.csv", false), entry("data/b.csv", false)]);
+        assert_eq!(listing.continuation.as_deref(), Some("next"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Locks across processes and hosts, held as leases on a lock file so a crashed holder's lock expires by itself
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::StatusCode;
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::lease::{lease_context, Lease};
+use crate::logging::LogLevel;
+use crate::metadata::{to_properties, Metadata};
+
+/// Metadata key of the lock file holding the Unix time in seconds the current holder acquired the lock at
+const ACQUIRED_AT: &str = "acquired_at";
+
+/// How a [`DistributedLock`] leases its file and waits for it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockOptions {
+    pub(crate) lease_duration: Duration,
+    pub(crate) poll_interval: Duration,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) break_stale_after: Option<Duration>,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        Self {
+            lease_duration: Duration::from_secs(30),
+            poll_interval: Duration::from_secs(5),
+            timeout: None,
+            break_stale_after: None,
+        }
+    }
+}
+
+impl LockOptions {
+    /// Duration of the lease, 15 to 60 whole seconds and 30s by default. The lock of a holder that stops renewing
+    /// it, e.g. because its process died, is free again after at most this long. Renewed every third of it
+    pub fn lease_duration(mut self, lease_duration: Duration) -> Self {
+        self.lease_duration = lease_duration;
+        self
+    }
+
+    /// How often [`DistributedLock::acquire`] tries again while the lock is held, 5s by default
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long [`DistributedLock::acquire`] waits for the lock before failing with
+    /// [`AzureStorageError::LockTimeout`], forever by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Breaks the lock of a holder that has held it for longer than `break_stale_after`, for jobs that never run
+    /// that long unless they hang. A holder whose lock was broken sees it in [`LockGuard::is_held`]. Holders are
+    /// never broken by default
+    pub fn break_stale_after(mut self, break_stale_after: Duration) -> Self {
+        self.break_stale_after = Some(break_stale_after);
+        self
+    }
+}
+
+/// A lock on the file `path` of a container, for jobs that must run on one instance at a time. The file is
+/// created empty when missing and only its metadata is written after, recording when the holder took the lock,
+/// so any path no other code uses will do
+#[derive(Clone, Debug)]
+pub struct DistributedLock {
+    backend: AzureStorageBackend,
+    container_name: String,
+    path: String,
+    options: LockOptions,
+}
+
+/// Whether the holder recorded in the lock file's `metadata` acquired the lock more than `break_stale_after`
+/// before `now`
+fn is_stale(metadata: &Metadata, now: OffsetDateTime, break_stale_after: Duration) -> bool {
+    let acquired_at = metadata.get(ACQUIRED_AT).and_then(|acquired_at| acquired_at.parse::<i64>().ok());
+    match acquired_at.and_then(|acquired_at| OffsetDateTime::from_unix_timestamp(acquired_at).ok()) {
+        Some(acquired_at) => now - acquired_at > break_stale_after,
+        // held by a holder that died between leasing the file and recording when
+        None => false,
+    }
+}
+
+impl DistributedLock {
+    pub fn new(backend: &AzureStorageBackend, container_name: impl Into<String>, path: impl Into<String>, options: LockOptions) -> Self {
+        Self {
+            backend: backend.clone(),
+            container_name: container_name.into(),
+            path: path.into().trim_matches('/').to_string(),
+            options,
+        }
+    }
+
+    /// Creates the lock file unless it exists
+    async fn create_file(&self) -> Result<(), AzureStorageError> {
+        let file_client = self.backend.file_system_client(&self.container_name).await.get_file_client(&self.path);
+        match file_client.create_if_not_exists().await {
+            Ok(_) => Ok(()),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::Conflict, _))) => Ok(()),
+            Err(error) => Err(AzureStorageError::Request(error)),
+        }
+    }
+
+    /// Takes the lock if nobody holds it, `None` if someone does. A holder found stale per
+    /// [`LockOptions::break_stale_after`] is broken first
+    pub async fn try_acquire(&self) -> Result<Option<LockGuard>, miette::Error> {
+        self.create_file().await?;
+        let lease = match self.lease().await? {
+            Some(lease) => lease,
+            None if self.break_if_stale().await? => match self.lease().await? {
+                Some(lease) => lease,
+                // another instance took the lock between the break and the lease
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        let metadata = Metadata::from([(ACQUIRED_AT.to_string(), OffsetDateTime::now_utc().unix_timestamp().to_string())]);
+        let recorded = self
+            .backend
+            .file_system_client(&self.container_name)
+            .await
+            .get_file_client(&self.path)
+            .set_properties(to_properties(&metadata))
+            .context(lease_context(Some(&lease.id)))
+            .await;
+        if let Err(error) = recorded {
+            self.backend.release_lease(lease).await?;
+            return Err(AzureStorageError::Request(error).into());
+        }
+        Ok(Some(LockGuard::new(&self.backend, lease, self.options.lease_duration)))
+    }
+
+    /// Takes the lock, trying again every [`LockOptions::poll_interval`] while someone holds it, up to
+    /// [`LockOptions::timeout`]
+    pub async fn acquire(&self) -> Result<LockGuard, miette::Error> {
+        let started = Instant::now();
+        loop {
+            if let Some(guard) = self.try_acquire().await? {
+                return Ok(guard);
+            }
+            if let Some(timeout) = self.options.timeout {
+                if started.elapsed() + self.options.poll_interval > timeout {
+                    return Err(AzureStorageError::LockTimeout {
+                        path: self.path.clone(),
+                        waited: started.elapsed(),
+                    }
+                    .into());
+                }
+            }
+            tokio::time::sleep(self.options.poll_interval).await;
+        }
+    }
+
+    /// Leases the lock file, `None` when someone else holds it
+    async fn lease(&self) -> Result<Option<Lease>, miette::Error> {
+        match self.backend.acquire_lease(&self.container_name, &self.path, Some(self.options.lease_duration)).await {
+            Ok(lease) => Ok(Some(lease)),
+            Err(error) if is_lease_conflict(&error) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Breaks the current holder's lease when it is stale, returning whether it did
+    async fn break_if_stale(&self) -> Result<bool, miette::Error> {
+        let Some(break_stale_after) = self.options.break_stale_after else {
+            return Ok(false);
+        };
+        let metadata = self.backend.get_metadata(&self.container_name, &self.path).await?;
+        if !is_stale(&metadata, OffsetDateTime::now_utc(), break_stale_after) {
+            return Ok(false);
+        }
+        self.backend.log(
+            LogLevel::Info,
+            format_args!("Lock {}/{} is held for longer than {:?}, breaking it", self.container_name, self.path, break_stale_after),
+        );
+        self.backend.break_lease(&self.container_name, &self.path, None).await?;
+        Ok(true)
+    }
+}
+
+/// Whether leasing failed because someone else holds the lease
+fn is_lease_conflict(error: &miette::Error) -> bool {
+    match error.downcast_ref::<AzureStorageError>() {
+        Some(AzureStorageError::Request(error)) => matches!(http_status(error), Some((StatusCode::Conflict, Some("LeaseAlreadyPresent")))),
+        _ => false,
+    }
+}
+
+/// A held [`DistributedLock`]. Its lease is renewed in the background until the guard is released or dropped,
+/// dropping releases it in the background
+#[derive(Debug)]
+pub struct LockGuard {
+    backend: AzureStorageBackend,
+    /// `None` once released
+    lease: Option<Lease>,
+    held: Arc<AtomicBool>,
+    renewal: JoinHandle<()>,
+}
+
+impl LockGuard {
+    fn new(backend: &AzureStorageBackend, lease: Lease, lease_duration: Duration) -> Self {
+        let held = Arc::new(AtomicBool::new(true));
+        let renewal = tokio::spawn(renew(backend.clone(), lease.clone(), lease_duration, Arc::clone(&held)));
+        Self {
+            backend: backend.clone(),
+            lease: Some(lease),
+            held,
+            renewal,
+        }
+    }
+
+    /// Whether the lock is still held. Turns false when the lease could not be renewed before it expired or was
+    /// broken by someone else, after which the work it guards should stop
+    pub fn is_held(&self) -> bool {
+        self.held.load(Ordering::SeqCst)
+    }
+
+    /// The lease the lock is held with
+    pub fn lease(&self) -> &Lease {
+        self.lease.as_ref().expect("the lease is only taken by release and drop")
+    }
+
+    /// Stops renewing and releases the lock, so the next instance can take it right away
+    pub async fn release(mut self) -> Result<(), miette::Error> {
+        self.renewal.abort();
+        let lease = self.lease.take().expect("the lease is only taken by release and drop");
+        match self.held.swap(false, Ordering::SeqCst) {
+            true => self.backend.release_lease(lease).await,
+            false => Ok(()),
+        }
+    }
+}
+
+/// Renews `lease` every third of its duration until the renewals fail for as long as it lasts or someone else
+/// took it
+async fn renew(backend: AzureStorageBackend, lease: Lease, lease_duration: Duration, held: Arc<AtomicBool>) {
+    let mut ticker = tokio::time::interval(lease_duration / 3);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let mut renewed = Instant::now();
+    loop {
+        ticker.tick().await;
+        match backend.renew_lease(&lease).await {
+            Ok(()) => renewed = Instant::now(),
+            Err(error) => {
+                let lost = error.downcast_ref::<AzureStorageError>().is_some_and(|error| match error {
+                    AzureStorageError::Request(error) => matches!(http_status(error), Some((StatusCode::Conflict, _))),
+                    _ => false,
+                });
+                if lost || renewed.elapsed() >= lease_duration {
+                    backend.log(LogLevel::Error, format_args!("Lost lock {}/{}: {}", lease.container_name, lease.path, error));
+                    held.store(false, Ordering::SeqCst);
+                    return;
+                }
+                backend.log(LogLevel::Info, format_args!("Failed to renew lock {}/{}, trying again: {}", lease.container_name, lease.path, error));
+            }
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.renewal.abort();
+        let Some(lease) = self.lease.take() else {
+            return;
+        };
+        if !self.held.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let backend = self.backend.clone();
+                runtime.spawn(async move {
+                    let description = format!("{}/{}", lease.container_name, lease.path);
+                    if let Err(error) = backend.release_lease(lease).await {
+                        backend.log(LogLevel::Error, format_args!("Failed to release lock {} after its guard was dropped: {}", description, error));
+                    }
+                });
+            }
+            Err(_) => self.backend.log(
+                LogLevel::Error,
+                format_args!("Lock guard dropped outside of a tokio runtime, {}/{} is freed when its lease expires", lease.container_name, lease.path),
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_locks_held_for_too_long_are_stale() {
+        let now = OffsetDateTime::from_unix_timestamp(10_000).unwrap();
+        let held_since = |acquired_at: &str| Metadata::from([(ACQUIRED_AT.to_string(), acquired_at.to_string())]);
+        assert!(is_stale(&held_since("6000"), now, Duration::from_secs(3600)));
+        assert!(!is_stale(&held_since("9000"), now, Duration::from_secs(3600)));
+        assert!(!is_stale(&held_since("soon"), now, Duration::from_secs(3600)));
+        assert!(!is_stale(&Metadata::new(), now, Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_only_leases_held_by_others_are_conflicts() {
+        let error = |status, code: &str| -> miette::Error {
+            let error = azure_core::error::ErrorKind::http_response(status, Some(code.to_string())).into_error();
+            AzureStorageError::Request(error).into()
+        };
+        assert!(is_lease_conflict(&error(StatusCode::Conflict, "LeaseAlreadyPresent")));
+        assert!(!is_lease_conflict(&error(StatusCode::Conflict, "PathAlreadyExists")));
+        assert!(!is_lease_conflict(&error(StatusCode::Forbidden, "AuthorizationFailure")));
+    }
+}
@@ -0,0 +1,237 @@
+//! Request and operation diagnostics that can be turned up for a single account while the process is running
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use azure_core::headers::{HeaderName, Headers, AUTHORIZATION};
+use azure_core::{Body, Context, Policy, PolicyResult, Request, Response};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::backend::AzureStorageBackend;
+
+/// Payloads are cut off after this many bytes
+const MAX_LOGGED_PAYLOAD: usize = 4096;
+
+/// Headers whose values are never logged
+const REDACTED_HEADERS: [HeaderName; 2] = [AUTHORIZATION, HeaderName::from_static("x-ms-copy-source-authorization")];
+
+/// How much a backend logs about the requests it sends and what it does on its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LogLevel {
+    #[default]
+    Off,
+    /// Failed requests, and background work such as lease renewals or writer cleanup that failed
+    Error,
+    /// Every request with its status and duration, plus retries, skipped uploads and other decisions of the backend
+    Info,
+    /// Adds the service request id of every response, for support cases
+    Debug,
+    /// Adds request and response headers, with credentials redacted
+    Trace,
+}
+
+impl LogLevel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => LogLevel::Error,
+            2 => LogLevel::Info,
+            3 => LogLevel::Debug,
+            4 => LogLevel::Trace,
+            _ => LogLevel::Off,
+        }
+    }
+}
+
+/// The logging settings of one cached client, shared by every backend handle for it. The default logs nothing
+#[derive(Debug, Default)]
+pub(crate) struct LogSettings {
+    account: String,
+    level: AtomicU8,
+    payloads: AtomicBool,
+}
+
+impl LogSettings {
+    pub(crate) fn new(account: impl Into<String>, level: LogLevel, payloads: bool) -> Self {
+        Self {
+            account: account.into(),
+            level: AtomicU8::new(level as u8),
+            payloads: AtomicBool::new(payloads),
+        }
+    }
+
+    fn level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    fn enabled(&self, level: LogLevel) -> bool {
+        level != LogLevel::Off && level <= self.level()
+    }
+
+    fn log(&self, message: std::fmt::Arguments<'_>) {
+        println!("[{}] {}", self.account, message);
+    }
+
+    /// Logs a diagnostic when `level` is enabled for the account
+    pub(crate) fn event(&self, level: LogLevel, message: std::fmt::Arguments<'_>) {
+        if self.enabled(level) {
+            self.log(message);
+        }
+    }
+}
+
+impl AzureStorageBackend {
+    /// Changes the log level of this backend's account, effective for the next request or diagnostic
+    pub fn set_log_level(&self, level: LogLevel) {
+        self.log_settings.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    pub fn log_level(&self) -> LogLevel {
+        self.log_settings.level()
+    }
+
+    /// Logs a diagnostic of this backend's account when `level` is enabled, outside of any single request
+    pub(crate) fn log(&self, level: LogLevel, message: std::fmt::Arguments<'_>) {
+        self.log_settings.event(level, message);
+    }
+
+    /// Logs request and response bodies (truncated) of this backend's account at every enabled level. Bodies can
+    /// contain application data, only enable this while diagnosing
+    pub fn set_payload_logging(&self, enabled: bool) {
+        self.log_settings.payloads.store(enabled, Ordering::Relaxed);
+    }
+}
+
+fn format_headers(headers: &Headers) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if REDACTED_HEADERS.iter().any(|redacted| redacted.as_str() == name.as_str()) {
+                format!("{}: <redacted>", name.as_str())
+            } else {
+                format!("{}: {}", name.as_str(), value.as_str())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_payload(payload: &[u8]) -> String {
+    if payload.len() <= MAX_LOGGED_PAYLOAD {
+        String::from_utf8_lossy(payload).into_owned()
+    } else {
+        format!(
+            "{}... ({} more bytes)",
+            String::from_utf8_lossy(&payload[..MAX_LOGGED_PAYLOAD]),
+            payload.len() - MAX_LOGGED_PAYLOAD
+        )
+    }
+}
+
+/// Pipeline policy logging each attempt according to the live [`LogSettings`]
+#[derive(Debug)]
+pub(crate) struct LoggingPolicy {
+    settings: Arc<LogSettings>,
+}
+
+impl LoggingPolicy {
+    pub(crate) fn new(settings: Arc<LogSettings>) -> Self {
+        Self { settings }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for LoggingPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        let settings = &self.settings;
+        if !settings.enabled(LogLevel::Error) {
+            return next[0].send(ctx, request, &next[1..]).await;
+        }
+
+        let payloads = settings.payloads.load(Ordering::Relaxed);
+        let target = format!("{} {}", request.method(), request.path_and_query());
+        if settings.enabled(LogLevel::Trace) {
+            settings.log(format_args!("{} request headers: {}", target, format_headers(request.headers())));
+        }
+        if payloads && !request.body().is_empty() {
+            // only the logged prefix is formatted, the body itself is never copied
+            let body = match request.body() {
+                Body::Bytes(bytes) => format_payload(bytes),
+                Body::SeekableStream(_) => "(stream)".to_string(),
+            };
+            settings.log(format_args!("{} request body ({} bytes): {}", target, request.body().len(), body));
+        }
+
+        let started = Instant::now();
+        let response = match next[0].send(ctx, request, &next[1..]).await {
+            Ok(response) => response,
+            Err(error) => {
+                settings.log(format_args!("{} failed after {:?}: {}", target, started.elapsed(), error));
+                return Err(error);
+            }
+        };
+        let elapsed = started.elapsed();
+        let status = response.status();
+
+        if settings.enabled(LogLevel::Info) || !status.is_success() {
+            settings.log(format_args!("{} -> {} in {:?}", target, status as u16, elapsed));
+        }
+        if settings.enabled(LogLevel::Debug) {
+            if let Some(request_id) = response.headers().get_optional_str(&HeaderName::from_static("x-ms-request-id")) {
+                settings.log(format_args!("{} request id {}", target, request_id));
+            }
+        }
+        if settings.enabled(LogLevel::Trace) {
+            settings.log(format_args!("{} response headers: {}", target, format_headers(response.headers())));
+        }
+        if !payloads {
+            return Ok(response);
+        }
+
+        let (status, headers, body) = response.deconstruct();
+        let body = body.collect().await?;
+        if !body.is_empty() {
+            settings.log(format_args!("{} response body: {}", target, format_payload(&body)));
+        }
+        let stream = futures::stream::once(async move { Ok::<Bytes, azure_core::Error>(body) });
+        Ok(Response::new(status, headers, Box::pin(stream)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_are_cumulative() {
+        let settings = LogSettings::new("account", LogLevel::Info, false);
+        assert!(settings.enabled(LogLevel::Error));
+        assert!(settings.enabled(LogLevel::Info));
+        assert!(!settings.enabled(LogLevel::Debug));
+
+        settings.level.store(LogLevel::Off as u8, Ordering::Relaxed);
+        assert!(!settings.enabled(LogLevel::Error));
+        assert!(!settings.enabled(LogLevel::Off));
+        assert!(!LogSettings::default().enabled(LogLevel::Error));
+    }
+
+    #[test]
+    fn test_credentials_are_redacted() {
+        let mut headers = Headers::new();
+        headers.insert(AUTHORIZATION, "Bearer secret");
+        headers.insert("x-ms-version", "2019-12-12");
+
+        let formatted = format_headers(&headers);
+        assert!(!formatted.contains("secret"));
+        assert!(formatted.contains("authorization: <redacted>"));
+        assert!(formatted.contains("x-ms-version: 2019-12-12"));
+    }
+
+    #[test]
+    fn test_long_payloads_are_truncated() {
+        assert_eq!(format_payload(b"short"), "short");
+        let formatted = format_payload(&vec![b'a'; MAX_LOGGED_PAYLOAD + 10]);
+        assert!(formatted.ends_with("... (10 more bytes)"));
+    }
+}
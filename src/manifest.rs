@@ -0,0 +1,191 @@
+//! Transfer manifests in the format of azcopy's job transfer listings, for handing transfers over to and from azcopy
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+use crate::rest::endpoint_url;
+use crate::sdk::rest::ServiceType;
+use crate::sync::{SyncAction, SyncPlan};
+use crate::upload::UploadOptions;
+
+/// The transfers of one job, in the shape of azcopy's `ListJobTransfersResponse`, i.e. what
+/// `azcopy jobs show <job-id> --with-status=All --output-type=json` prints. The optional `Size` of each
+/// transfer is the only addition, azcopy ignores it on the way back
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TransferManifest {
+    #[serde(rename = "JobID", default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    #[serde(default)]
+    pub details: Vec<TransferEntry>,
+}
+
+/// One file of a job. Local sources are plain paths, remote ends are full `https://` URLs
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TransferEntry {
+    pub src: String,
+    pub dst: String,
+    pub transfer_status: TransferStatus,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_folder_properties: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// azcopy's transfer states, with every `Skipped...` reason read as [`Skipped`](Self::Skipped)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferStatus {
+    NotStarted,
+    Started,
+    Success,
+    Failed,
+    #[serde(
+        rename = "SkippedEntityAlreadyExists",
+        alias = "SkippedBlobHasSnapshots",
+        alias = "TierAvailabilityCheckFailure",
+        alias = "BlobTierFailure"
+    )]
+    Skipped,
+}
+
+impl TransferStatus {
+    /// Whether the transfer still has to be carried out
+    pub fn is_pending(self) -> bool {
+        matches!(self, Self::NotStarted | Self::Started | Self::Failed)
+    }
+}
+
+/// A line of azcopy's `--output-type json` output, which carries its payload as a JSON string
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AzcopyMessage {
+    message_content: String,
+}
+
+impl TransferManifest {
+    pub fn to_json(&self) -> Result<String, miette::Error> {
+        Ok(serde_json::to_string_pretty(self).map_err(|error| AzureStorageError::InvalidManifest(error.to_string()))?)
+    }
+
+    /// Reads a manifest written by [`to_json`](Self::to_json) or the raw output of `azcopy jobs show
+    /// --output-type=json`, where the listing is the first message that holds one
+    pub fn from_json(json: &str) -> Result<Self, miette::Error> {
+        if let Ok(manifest) = serde_json::from_str::<TransferManifest>(json) {
+            return Ok(manifest);
+        }
+        json.lines()
+            .filter_map(|line| serde_json::from_str::<AzcopyMessage>(line).ok())
+            .filter_map(|message| serde_json::from_str::<TransferManifest>(&message.message_content).ok())
+            .find(|manifest| !manifest.details.is_empty())
+            .ok_or_else(|| AzureStorageError::InvalidManifest("neither a manifest nor azcopy job output with transfers".to_string()).into())
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &TransferEntry> {
+        self.details.iter().filter(|entry| entry.transfer_status.is_pending())
+    }
+
+    /// The pending sources below `source_root`, relative to it and one per line, as azcopy's `--list-of-files`
+    /// expects, so `azcopy copy <source_root> <destination> --list-of-files` picks up where this left off
+    pub fn to_list_of_files(&self, source_root: &str) -> String {
+        let root = source_root.trim_end_matches(['/', '\\']);
+        self.pending()
+            .filter_map(|entry| entry.src.strip_prefix(root))
+            .map(|relative| format!("{}\n", relative.trim_start_matches(['/', '\\'])))
+            .collect()
+    }
+}
+
+impl AzureStorageBackend {
+    /// The uploads of `plan` as a manifest with every transfer not started, for azcopy or
+    /// [`apply_manifest`](Self::apply_manifest) to carry out. Deletions have no azcopy counterpart and are left out
+    pub fn sync_manifest(&self, container_name: &str, plan: &SyncPlan) -> TransferManifest {
+        let details = plan
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                SyncAction::Create { path, local_path, size } | SyncAction::Update { path, local_path, size } => Some(TransferEntry {
+                    src: local_path.to_string_lossy().into_owned(),
+                    dst: self.file_url(container_name, path).to_string(),
+                    transfer_status: TransferStatus::NotStarted,
+                    is_folder_properties: false,
+                    size: Some(*size),
+                }),
+                SyncAction::Delete { .. } => None,
+            })
+            .collect();
+        TransferManifest { job_id: None, details }
+    }
+
+    /// Uploads the pending transfers of `manifest` from local files into this backend's account, marking each as
+    /// it completes, e.g. to finish a job azcopy started. A failed transfer is marked and the rest carry on, the
+    /// first failure is returned once all were tried. Transfers to other accounts or from remote sources are errors
+    pub async fn apply_manifest(&self, manifest: &mut TransferManifest) -> Result<(), miette::Error> {
+        let mut first_failure = None;
+        for entry in manifest.details.iter_mut().filter(|entry| entry.transfer_status.is_pending() && !entry.is_folder_properties) {
+            let (container_name, path) = self.remote_path(&entry.dst)?;
+            if Url::parse(&entry.src).is_ok_and(|url| url.scheme().starts_with("http")) {
+                return Err(AzureStorageError::InvalidManifest(format!("{} is not a local file", entry.src)).into());
+            }
+            entry.transfer_status = TransferStatus::Started;
+            match self.upload_from_path(&container_name, &path, PathBuf::from(&entry.src), UploadOptions::default()).await {
+                Ok(_) => entry.transfer_status = TransferStatus::Success,
+                Err(error) => {
+                    self.log(LogLevel::Error, format_args!("Transfer of {} failed: {:?}", entry.src, error));
+                    entry.transfer_status = TransferStatus::Failed;
+                    first_failure.get_or_insert(error);
+                }
+            }
+        }
+        first_failure.map_or(Ok(()), Err)
+    }
+
+    fn file_url(&self, container_name: &str, path: &str) -> Url {
+        endpoint_url(&self.config, ServiceType::DataLake, container_name, path)
+    }
+
+    /// Container and path of a destination URL in this account, on either the blob or dfs endpoint
+    fn remote_path(&self, destination: &str) -> Result<(String, String), AzureStorageError> {
+        let invalid = || AzureStorageError::InvalidManifest(format!("{} is not a path in account {}", destination, self.config.storage_account_url));
+        let url = Url::parse(destination).map_err(|_| invalid())?;
+        if url.host_str().and_then(|host| host.split('.').next()) != Some(self.config.storage_account_url.as_str()) {
+            return Err(invalid());
+        }
+        let mut segments = url
+            .path_segments()
+            .ok_or_else(invalid)?
+            .map(|segment| percent_encoding::percent_decode_str(segment).decode_utf8_lossy().into_owned());
+        let container_name = segments.next().filter(|container| !container.is_empty()).ok_or_else(invalid)?;
+        let path = segments.collect::<Vec<_>>().join("/");
+        if path.is_empty() {
+            return Err(invalid());
+        }
+        Ok((container_name, path))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_azcopy_job_output() {
+        let listing = r#"{"JobID":"6e5c","Details":[{"Src":"/data/a.csv","Dst":"https://account.blob.core.windows.net/raw/a.csv","TransferStatus":"Success"},{"Src":"/data/sub/b.csv","Dst":"https://account.blob.core.windows.net/raw/sub/b.csv","TransferStatus":"Failed"},{"Src":"/data/c.csv","Dst":"https://account.blob.core.windows.net/raw/c.csv","TransferStatus":"SkippedBlobHasSnapshots"}]}"#;
+        let output = format!(
+            "{}\n{}\n",
+            r#"{"TimeStamp":"2023-06-01T10:00:00Z","MessageType":"Info","MessageContent":"Listing transfers"}"#,
+            serde_json::json!({"TimeStamp": "2023-06-01T10:00:01Z", "MessageType": "EndOfJob", "MessageContent": listing})
+        );
+
+        let manifest = TransferManifest::from_json(&output).unwrap();
+        assert_eq!(manifest.job_id.as_deref(), Some("6e5c"));
+        assert_eq!(manifest.details[2].transfer_status, TransferStatus::Skipped);
+        assert_eq!(manifest.to_list_of_files("/data/"), "sub/b.csv\n");
+        assert_eq!(TransferManifest::from_json(&manifest.to_json().unwrap()).unwrap(), manifest);
+    }
+}
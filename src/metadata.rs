@@ -0,0 +1,91 @@
+//! User defined key/value metadata of files
+use std::collections::BTreeMap;
+
+use azure_core::headers::Header;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::lease::{lease_context, Lease};
+use crate::sdk::datalake::*;
+
+/// Metadata of a file. Keys must be valid C# identifiers, the service compares them case insensitively
+pub type Metadata = BTreeMap<String, String>;
+
+pub(crate) fn to_properties(metadata: &Metadata) -> Properties {
+    let mut properties = Properties::new();
+    for (key, value) in metadata {
+        properties.insert(key.clone(), value.clone());
+    }
+    properties
+}
+
+/// The SDK's `Properties` cannot be iterated, so they are read back from their `x-ms-properties` form
+pub(crate) fn from_properties(properties: &Properties) -> Metadata {
+    metadata_from_header(properties.value().as_str())
+}
+
+/// Pairs of `key=base64(value)`, skipping any that do not decode
+fn metadata_from_header(value: &str) -> Metadata {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(key, value)| {
+            let value = String::from_utf8(azure_core::base64::decode(value).ok()?).ok()?;
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+impl AzureStorageBackend {
+    /// The metadata of the file at `path`, empty when it has none
+    pub async fn get_metadata(&self, container_name: &str, path: &str) -> Result<Metadata, miette::Error> {
+        let properties = self
+            .file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .get_properties()
+            .await
+            .map_err(AzureStorageError::Request)?
+            .properties;
+        Ok(properties.as_ref().map(from_properties).unwrap_or_default())
+    }
+
+    /// Replaces all metadata of the file at `path` with `metadata`. To set it as the file is created, so it is
+    /// never seen without, use [`UploadOptions::metadata`](crate::UploadOptions::metadata)
+    pub async fn set_metadata(&self, container_name: &str, path: &str, metadata: &Metadata) -> Result<(), miette::Error> {
+        Ok(self.replace_metadata(container_name, path, metadata, None).await?)
+    }
+
+    /// [`set_metadata`](Self::set_metadata) for the file `lease` is held on
+    pub async fn set_metadata_under_lease(&self, lease: &Lease, metadata: &Metadata) -> Result<(), miette::Error> {
+        Ok(self.replace_metadata(&lease.container_name, &lease.path, metadata, Some(&lease.id)).await?)
+    }
+
+    async fn replace_metadata(&self, container_name: &str, path: &str, metadata: &Metadata, lease_id: Option<&str>) -> Result<(), AzureStorageError> {
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .set_properties(to_properties(metadata))
+            .context(lease_context(lease_id))
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_round_trips_through_properties() {
+        let metadata = Metadata::from([
+            ("owner".to_string(), "data-platform".to_string()),
+            ("run".to_string(), "7".to_string()),
+        ]);
+        assert_eq!(from_properties(&to_properties(&metadata)), metadata);
+        assert_eq!(metadata_from_header(""), Metadata::new());
+        assert_eq!(metadata_from_header("broken,run=Nw=="), Metadata::from([("run".to_string(), "7".to_string())]));
+    }
+}
@@ -0,0 +1,62 @@
+//! Owners and owning groups of files and directories
+use azure_core::headers::{HeaderName, ACL};
+use azure_core::Context;
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::{OmitHeaders, RequestHeaders, ResponseHeaders};
+use crate::error::AzureStorageError;
+
+const OWNER: HeaderName = HeaderName::from_static("x-ms-owner");
+const GROUP: HeaderName = HeaderName::from_static("x-ms-group");
+
+/// Who owns a path, as object ids of Azure AD users, service principals or groups. `$superuser` stands for
+/// paths created with a shared key or SAS
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathOwnership {
+    pub owner: String,
+    pub group: String,
+}
+
+impl AzureStorageBackend {
+    pub async fn get_ownership(&self, container_name: &str, path: &str) -> Result<PathOwnership, miette::Error> {
+        let mut context = Context::new();
+        context.insert(ResponseHeaders::default());
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .get_access_control_list()
+            .context(context.clone())
+            .await
+            .map_err(AzureStorageError::Request)?;
+        let headers = context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).unwrap_or_default();
+        Ok(PathOwnership {
+            owner: headers.get_optional_string(&OWNER).unwrap_or_default(),
+            group: headers.get_optional_string(&GROUP).unwrap_or_default(),
+        })
+    }
+
+    /// Changes the owner and owning group of the file or directory at `path` to the given object ids, leaving
+    /// out either keeps it. Changing the owner takes superuser rights, i.e. the RBAC role Storage Blob Data
+    /// Owner, as the service has no `chown` for regular owners
+    pub async fn set_ownership(&self, container_name: &str, path: &str, owner: Option<&str>, group: Option<&str>) -> Result<(), miette::Error> {
+        let headers: Vec<(HeaderName, String)> = [(OWNER, owner), (GROUP, group)]
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value.to_string())))
+            .collect();
+        if headers.is_empty() {
+            return Ok(());
+        }
+        let mut context = Context::new();
+        // the operation always sends an ACL, which would replace the path's entries
+        context.insert(OmitHeaders(vec![ACL]));
+        context.insert(RequestHeaders(headers));
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .set_access_control_list(String::new())
+            .context(context)
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(())
+    }
+}
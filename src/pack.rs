@@ -0,0 +1,185 @@
+//! Packing many small files into a few large objects with an index, where a request per file would dominate
+//! the cost of writing them
+use std::collections::BTreeMap;
+
+use azure_core::prelude::Range;
+use azure_core::StatusCode;
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+use crate::read_modify_write::UpdateOptions;
+use crate::upload::UploadOptions;
+
+const INDEX_FILE_NAME: &str = "_pack_index.json";
+
+/// When a [`PackWriter`] seals a pack
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackOptions {
+    pub(crate) target_size: usize,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            target_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl PackOptions {
+    /// Uploads the buffered files as a pack once they hold at least this many bytes, 64 MiB by default
+    pub fn target_size(mut self, target_size: usize) -> Self {
+        self.target_size = target_size.max(1);
+        self
+    }
+}
+
+/// Where the content of a packed file lies
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackedFile {
+    /// Path of the pack within the container
+    pub pack: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Every packed file below a root by name, stored as `_pack_index.json` under the root
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackIndex {
+    pub files: BTreeMap<String, PackedFile>,
+}
+
+/// Gathers small files in memory and uploads them together as `<root>/pack-<uuid>.pack` objects. Files added
+/// are only listed in the index once [`finish`](Self::finish) writes it, content still buffered when the writer
+/// is dropped is lost
+#[derive(Debug)]
+pub struct PackWriter {
+    backend: AzureStorageBackend,
+    container_name: String,
+    root: String,
+    options: PackOptions,
+    buffer: BytesMut,
+    /// Files in the buffer, with their offsets in it
+    buffered: Vec<(String, u64, u64)>,
+    index: PackIndex,
+}
+
+impl PackWriter {
+    pub fn new(backend: &AzureStorageBackend, container_name: impl Into<String>, root: impl Into<String>, options: PackOptions) -> Self {
+        Self {
+            backend: backend.clone(),
+            container_name: container_name.into(),
+            root: root.into().trim_matches('/').to_string(),
+            options,
+            buffer: BytesMut::new(),
+            buffered: Vec::new(),
+            index: PackIndex::default(),
+        }
+    }
+
+    /// Adds the file `name` to the current pack, uploading the pack if it reached the target size. A name added
+    /// again replaces the earlier content
+    pub async fn add(&mut self, name: &str, content: impl AsRef<[u8]>) -> Result<(), miette::Error> {
+        let content = content.as_ref();
+        self.buffered.push((name.to_string(), self.buffer.len() as u64, content.len() as u64));
+        self.buffer.extend_from_slice(content);
+        if self.buffer.len() >= self.options.target_size {
+            self.seal().await?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the remaining files and merges everything this writer packed into the index, keeping the entries
+    /// of other writers. Returns the merged index
+    pub async fn finish(mut self) -> Result<PackIndex, miette::Error> {
+        self.seal().await?;
+        let packed = std::mem::take(&mut self.index.files);
+        self.backend
+            .update_json(&self.container_name, &index_path(&self.root), UpdateOptions::default(), |index: &mut PackIndex| {
+                index.files.extend(packed.clone());
+            })
+            .await
+    }
+
+    async fn seal(&mut self) -> Result<(), miette::Error> {
+        if self.buffered.is_empty() {
+            return Ok(());
+        }
+        let pack = join_path(&self.root, &format!("pack-{}.pack", uuid::Uuid::new_v4()));
+        let content = self.buffer.split().freeze();
+        self.backend
+            .upload_bytes(&self.container_name, &pack, content, UploadOptions::default())
+            .await?;
+        self.backend.log(LogLevel::Info, format_args!("Packed {} files into {}", self.buffered.len(), pack));
+        for (name, offset, length) in self.buffered.drain(..) {
+            self.index.files.insert(name, PackedFile { pack: pack.clone(), offset, length });
+        }
+        Ok(())
+    }
+}
+
+fn index_path(root: &str) -> String {
+    join_path(root, INDEX_FILE_NAME)
+}
+
+fn join_path(directory: &str, name: &str) -> String {
+    match directory.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", directory, name),
+    }
+}
+
+impl AzureStorageBackend {
+    /// The index of the packs under `root`, empty when nothing was packed there yet
+    pub async fn pack_index(&self, container_name: &str, root: &str) -> Result<PackIndex, miette::Error> {
+        let path = index_path(root.trim_matches('/'));
+        let file_client = self.file_system_client(container_name).await.get_file_client(&path);
+        match file_client.read().await {
+            Ok(response) => Ok(serde_json::from_slice(&response.data).map_err(|source| AzureStorageError::InvalidJson { path, source })?),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => Ok(PackIndex::default()),
+            Err(error) => Err(AzureStorageError::Request(error).into()),
+        }
+    }
+
+    /// The content of a packed file, with a single range read of its pack
+    pub async fn read_packed(&self, container_name: &str, file: &PackedFile) -> Result<Bytes, miette::Error> {
+        if file.length == 0 {
+            return Ok(Bytes::new());
+        }
+        let response = self
+            .file_system_client(container_name)
+            .await
+            .get_file_client(&file.pack)
+            .read()
+            .range(Range::new(file.offset, file.offset + file.length))
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(response.data)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexes_round_trip_through_json() {
+        let mut index = PackIndex::default();
+        index.files.insert(
+            "thumbs/1.png".to_string(),
+            PackedFile {
+                pack: "images/pack-1.pack".to_string(),
+                offset: 10,
+                length: 20,
+            },
+        );
+        let json = serde_json::to_string(&index).unwrap();
+        assert_eq!(json, r#"{"files":{"thumbs/1.png":{"pack":"images/pack-1.pack","offset":10,"length":20}}}"#);
+        assert_eq!(serde_json::from_str::<PackIndex>(&json).unwrap(), index);
+        assert_eq!(index_path(""), INDEX_FILE_NAME);
+    }
+}
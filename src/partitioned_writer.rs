@@ -0,0 +1,359 @@
+//! Writing records into a hive style partitioned directory layout
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::StatusCode;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::{LogLevel, LogSettings};
+use crate::sdk::datalake::*;
+use crate::upload::append_split;
+use crate::writer::{spawn_on_drop, DropBehavior};
+
+const MANIFEST_FILE_NAME: &str = "_manifest.json";
+
+/// When the files of a [`PartitionedWriter`] are rolled over and how they are named
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionedWriterOptions {
+    pub(crate) extension: String,
+    pub(crate) max_file_size: u64,
+    pub(crate) max_file_age: Option<Duration>,
+    pub(crate) on_drop: DropBehavior,
+}
+
+impl Default for PartitionedWriterOptions {
+    fn default() -> Self {
+        Self {
+            extension: "jsonl".to_string(),
+            max_file_size: 128 * 1024 * 1024,
+            max_file_age: None,
+            on_drop: DropBehavior::default(),
+        }
+    }
+}
+
+impl PartitionedWriterOptions {
+    /// Extension of the part files, without the leading dot
+    pub fn extension(mut self, extension: impl Into<String>) -> Self {
+        self.extension = extension.into().trim_start_matches('.').to_string();
+        self
+    }
+
+    /// Starts a new part file once the current one holds at least this many bytes
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Starts a new part file for records arriving this long after the current one was opened
+    pub fn max_file_age(mut self, max_file_age: Duration) -> Self {
+        self.max_file_age = Some(max_file_age);
+        self
+    }
+
+    /// What a writer dropped before [`PartitionedWriter::finish`] does with its open files. Aborting deletes the
+    /// open part files, finishing commits them and writes the manifest. Files committed earlier are kept either way
+    pub fn on_drop(mut self, on_drop: DropBehavior) -> Self {
+        self.on_drop = on_drop;
+        self
+    }
+}
+
+/// A committed part file listed in the manifest
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub partition: String,
+    pub size: u64,
+    pub records: u64,
+}
+
+/// Everything a [`PartitionedWriter`] wrote, stored as `_manifest.json` under its root
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionManifest {
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug)]
+struct OpenPart {
+    file_client: FileClient,
+    file: ManifestFile,
+    opened_at: Instant,
+}
+
+/// Routes records into `<root>/<key>=<value>/.../part-<n>.<ext>` files. Records are appended as they arrive
+/// and committed when a file is rolled over or the writer is finished.
+#[derive(Debug)]
+pub struct PartitionedWriter {
+    file_system_client: FileSystemClient,
+    root: String,
+    options: PartitionedWriterOptions,
+    open_parts: HashMap<String, OpenPart>,
+    next_part: HashMap<String, u32>,
+    manifest: PartitionManifest,
+    finished: bool,
+    log_settings: Arc<LogSettings>,
+}
+
+impl AzureStorageBackend {
+    /// Writer for a partitioned dataset under `root`
+    pub async fn partitioned_writer(&self, container_name: &str, root: &str, options: PartitionedWriterOptions) -> PartitionedWriter {
+        PartitionedWriter {
+            file_system_client: self.file_system_client(container_name).await,
+            root: root.trim_matches('/').to_string(),
+            options,
+            open_parts: HashMap::new(),
+            next_part: HashMap::new(),
+            manifest: PartitionManifest::default(),
+            finished: false,
+            log_settings: Arc::clone(&self.log_settings),
+        }
+    }
+}
+
+impl PartitionedWriter {
+    /// Appends `record` to the current file of the partition described by `partition`, e.g.
+    /// `&[("date", "2024-05-01")]`. Records are written as given, include any delimiter they need
+    pub async fn write(&mut self, partition: &[(&str, &str)], record: impl Into<Bytes>) -> Result<(), miette::Error> {
+        let partition = partition_path(partition)?;
+        let record = record.into();
+
+        let roll = match self.open_parts.get(&partition) {
+            Some(part) => should_roll(&self.options, part.file.size, part.opened_at.elapsed()),
+            None => false,
+        };
+        if roll {
+            self.commit(&partition).await?;
+        }
+        if !self.open_parts.contains_key(&partition) {
+            let part = self.open_part(&partition).await?;
+            self.open_parts.insert(partition.clone(), part);
+        }
+
+        let part = self.open_parts.get_mut(&partition).expect("part was just opened");
+        let length = record.len() as u64;
+        if length > 0 {
+            append_split(&part.file_client, part.file.size as i64, record, None)
+                .await
+                .map_err(AzureStorageError::Request)?;
+        }
+        part.file.size += length;
+        part.file.records += 1;
+        Ok(())
+    }
+
+    /// Commits every file older than the configured maximum age, for writers that can be idle for a while
+    pub async fn roll_expired(&mut self) -> Result<(), miette::Error> {
+        let Some(max_file_age) = self.options.max_file_age else {
+            return Ok(());
+        };
+        let expired: Vec<String> = self
+            .open_parts
+            .iter()
+            .filter(|(_, part)| part.opened_at.elapsed() >= max_file_age)
+            .map(|(partition, _)| partition.clone())
+            .collect();
+        for partition in expired {
+            self.commit(&partition).await?;
+        }
+        Ok(())
+    }
+
+    /// Commits all open files and writes the manifest
+    pub async fn finish(mut self) -> Result<PartitionManifest, miette::Error> {
+        let partitions: Vec<String> = self.open_parts.keys().cloned().collect();
+        for partition in partitions {
+            self.commit(&partition).await?;
+        }
+        self.manifest.files.sort_by(|left, right| left.path.cmp(&right.path));
+
+        let manifest = Bytes::from(serde_json::to_vec_pretty(&self.manifest).expect("manifest only holds strings and numbers"));
+        let manifest_client = self.file_system_client.get_file_client(join_path(&self.root, MANIFEST_FILE_NAME));
+        let length = manifest.len() as i64;
+        manifest_client.create().await.map_err(AzureStorageError::Request)?;
+        manifest_client.append(0, manifest).await.map_err(AzureStorageError::Request)?;
+        manifest_client.flush(length).await.map_err(AzureStorageError::Request)?;
+
+        self.finished = true;
+        Ok(std::mem::take(&mut self.manifest))
+    }
+
+    /// Commits the open file of `partition`, which stays open when that fails so a retry or the drop behavior
+    /// still settles it
+    async fn commit(&mut self, partition: &str) -> Result<(), miette::Error> {
+        let Some(part) = self.open_parts.get(partition) else {
+            return Ok(());
+        };
+        part.file_client
+            .flush(part.file.size as i64)
+            .await
+            .map_err(AzureStorageError::Request)?;
+        let part = self.open_parts.remove(partition).expect("the part is open");
+        self.log_settings.event(LogLevel::Info, format_args!("Committed {} with {} records", part.file.path, part.file.records));
+        self.manifest.files.push(part.file);
+        Ok(())
+    }
+
+    /// Creates the next part file of the partition nobody else has created yet
+    async fn open_part(&mut self, partition: &str) -> Result<OpenPart, miette::Error> {
+        loop {
+            let part_number = self.next_part.entry(partition.to_string()).or_insert(0);
+            *part_number += 1;
+            let path = join_path(&join_path(&self.root, partition), &part_file_name(*part_number, &self.options.extension));
+            let file_client = self.file_system_client.get_file_client(&path);
+
+            match file_client.create_if_not_exists().await {
+                Ok(_) => {
+                    return Ok(OpenPart {
+                        file_client,
+                        file: ManifestFile {
+                            path,
+                            partition: partition.to_string(),
+                            size: 0,
+                            records: 0,
+                        },
+                        opened_at: Instant::now(),
+                    })
+                }
+                Err(error) if matches!(http_status(&error), Some((StatusCode::Conflict, _))) => continue,
+                Err(error) => return Err(AzureStorageError::Request(error).into()),
+            }
+        }
+    }
+}
+
+impl Drop for PartitionedWriter {
+    fn drop(&mut self) {
+        if self.finished || (self.open_parts.is_empty() && self.manifest.files.is_empty()) {
+            return;
+        }
+        let open_parts = std::mem::take(&mut self.open_parts);
+        match self.options.on_drop {
+            DropBehavior::AbortAndCleanup => {
+                if open_parts.is_empty() {
+                    return;
+                }
+                spawn_on_drop(&self.log_settings, format!("delete {} open part files under {}", open_parts.len(), self.root), async move {
+                    for part in open_parts.into_values() {
+                        part.file_client.delete().await?;
+                    }
+                    Ok::<_, azure_core::Error>(())
+                })
+            }
+            DropBehavior::DetachAndFinish => {
+                let writer = PartitionedWriter {
+                    file_system_client: self.file_system_client.clone(),
+                    root: self.root.clone(),
+                    options: self.options.clone().on_drop(DropBehavior::AbortAndCleanup),
+                    open_parts,
+                    next_part: std::mem::take(&mut self.next_part),
+                    manifest: std::mem::take(&mut self.manifest),
+                    finished: false,
+                    log_settings: Arc::clone(&self.log_settings),
+                };
+                spawn_on_drop(&self.log_settings, format!("finish writing {}", self.root), async move { writer.finish().await.map(|_| ()) })
+            }
+        }
+    }
+}
+
+fn should_roll(options: &PartitionedWriterOptions, size: u64, age: Duration) -> bool {
+    size >= options.max_file_size || options.max_file_age.is_some_and(|max_file_age| age >= max_file_age)
+}
+
+/// `[("date", "2024-05-01"), ("region", "eu")]` becomes `date=2024-05-01/region=eu`
+fn partition_path(partition: &[(&str, &str)]) -> Result<String, AzureStorageError> {
+    let mut segments = Vec::with_capacity(partition.len());
+    for (key, value) in partition {
+        let invalid = |part: &str| part.is_empty() || part.contains(['/', '=']) || part == "." || part == "..";
+        if invalid(key) || invalid(value) {
+            return Err(AzureStorageError::InvalidPartition(format!("{}={}", key, value)));
+        }
+        segments.push(format!("{}={}", key, value));
+    }
+    Ok(segments.join("/"))
+}
+
+fn part_file_name(part_number: u32, extension: &str) -> String {
+    if extension.is_empty() {
+        format!("part-{:04}", part_number)
+    } else {
+        format!("part-{:04}.{}", part_number, extension)
+    }
+}
+
+fn join_path(directory: &str, name: &str) -> String {
+    match (directory.is_empty(), name.is_empty()) {
+        (true, _) => name.to_string(),
+        (_, true) => directory.to_string(),
+        _ => format!("{}/{}", directory, name),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::test_service::FakeDataLake;
+
+    #[test]
+    fn test_partition_path() {
+        assert_eq!(partition_path(&[("date", "2024-05-01")]).unwrap(), "date=2024-05-01");
+        assert_eq!(partition_path(&[("date", "2024-05-01"), ("region", "eu")]).unwrap(), "date=2024-05-01/region=eu");
+        assert_eq!(partition_path(&[]).unwrap(), "");
+
+        assert!(partition_path(&[("date", "2024/05/01")]).is_err());
+        assert!(partition_path(&[("", "eu")]).is_err());
+        assert!(partition_path(&[("region", "..")]).is_err());
+    }
+
+    #[test]
+    fn test_part_paths() {
+        assert_eq!(part_file_name(1, "parquet"), "part-0001.parquet");
+        assert_eq!(part_file_name(12345, ""), "part-12345");
+        assert_eq!(join_path(&join_path("events", "date=2024-05-01"), "part-0001.jsonl"), "events/date=2024-05-01/part-0001.jsonl");
+        assert_eq!(join_path(&join_path("", ""), "part-0001.jsonl"), "part-0001.jsonl");
+        assert_eq!(PartitionedWriterOptions::default().extension(".csv").extension, "csv");
+    }
+
+    #[test]
+    fn test_rolling_by_size_and_age() {
+        let options = PartitionedWriterOptions::default().max_file_size(100);
+        assert!(!should_roll(&options, 99, Duration::from_secs(3600)));
+        assert!(should_roll(&options, 100, Duration::ZERO));
+
+        let options = options.max_file_age(Duration::from_secs(60));
+        assert!(!should_roll(&options, 0, Duration::from_secs(59)));
+        assert!(should_roll(&options, 0, Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_commits_keep_the_part_open() {
+        let service = FakeDataLake::new();
+        let failed = AtomicBool::new(false);
+        service.fail(move |_, target| target.contains("action=flush") && !failed.swap(true, Ordering::SeqCst), StatusCode::InternalServerError);
+        let options = PartitionedWriterOptions::default().max_file_size(1);
+        let mut writer = service.backend().partitioned_writer("raw", "events", options).await;
+        let partition = [("date", "2024-05-01")];
+        writer.write(&partition, "a").await.unwrap();
+
+        // rolling over commits the full part first, which fails once
+        assert!(writer.write(&partition, "b").await.is_err());
+        assert_eq!(writer.open_parts.len(), 1);
+        assert!(writer.manifest.files.is_empty());
+
+        writer.write(&partition, "b").await.unwrap();
+        let manifest = writer.finish().await.unwrap();
+        let committed: Vec<(&str, u64)> = manifest.files.iter().map(|file| (file.path.as_str(), file.records)).collect();
+        assert_eq!(committed, [("events/date=2024-05-01/part-0001.jsonl", 1), ("events/date=2024-05-01/part-0002.jsonl", 1)]);
+        assert_eq!(service.file("raw/events/date=2024-05-01/part-0001.jsonl").as_deref(), Some(&b"a"[..]));
+    }
+}
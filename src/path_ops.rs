@@ -0,0 +1,85 @@
+//! Operations on single paths that need no content, such as renames and existence checks
+use azure_core::StatusCode;
+use futures::StreamExt;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+
+/// Deletes a batch keeps in flight at once
+const MAX_PARALLEL_DELETES: usize = 8;
+
+/// `Ok(false)` for a request that failed because its target does not exist
+fn found<T>(result: azure_core::Result<T>) -> Result<bool, AzureStorageError> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => Ok(false),
+        Err(error) => Err(AzureStorageError::Request(error)),
+    }
+}
+
+impl AzureStorageBackend {
+    /// Moves the file at `source` to `destination` in one atomic step, replacing any file there. Readers see
+    /// either the old destination or the complete new one, which makes writing to a temporary path and renaming
+    /// it into place a safe way to publish
+    pub async fn rename(&self, container_name: &str, source: &str, destination: &str) -> Result<(), miette::Error> {
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(source)
+            .rename(destination)
+            .await
+            .map_err(AzureStorageError::Request)?;
+        self.append_positions.forget(container_name, source);
+        self.append_positions.forget(container_name, destination);
+        Ok(())
+    }
+
+    /// Whether a file or directory exists at `path`, `false` as well when the container does not exist
+    pub async fn exists(&self, container_name: &str, path: &str) -> Result<bool, miette::Error> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        Ok(found(file_client.get_properties().await)?)
+    }
+
+    /// Deletes each of `paths`, files or empty directories, with up to eight deletes in flight. Returns every path
+    /// with its own outcome in the order given, `Ok(false)` for paths that did not exist, so one failure does not
+    /// hide which of the others went through
+    pub async fn delete_many<P: Into<String>>(
+        &self,
+        container_name: &str,
+        paths: impl IntoIterator<Item = P>,
+    ) -> Vec<(String, Result<bool, miette::Error>)> {
+        let file_system_client = self.file_system_client(container_name).await;
+        futures::stream::iter(paths.into_iter().map(Into::into))
+            .map(|path: String| {
+                let file_client = file_system_client.get_file_client(&path);
+                async move {
+                    let deleted = found(file_client.delete().await).map_err(miette::Error::from);
+                    self.append_positions.forget(container_name, &path);
+                    (path, deleted)
+                }
+            })
+            .buffered(MAX_PARALLEL_DELETES)
+            .collect()
+            .await
+    }
+
+    pub async fn container_exists(&self, container_name: &str) -> Result<bool, miette::Error> {
+        Ok(found(self.file_system_client(container_name).await.get_properties().await)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use azure_core::error::ErrorKind;
+
+    #[test]
+    fn test_only_not_found_means_absent() {
+        assert!(found(Ok(())).unwrap());
+        let missing = ErrorKind::http_response(StatusCode::NotFound, Some("PathNotFound".to_string())).into_error();
+        assert!(!found::<()>(Err(missing)).unwrap());
+        let denied = ErrorKind::http_response(StatusCode::Forbidden, None).into_error();
+        assert!(found::<()>(Err(denied)).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! chmod style permissions of files and directories, without spelling out their ACL entries
+use std::fmt;
+use std::str::FromStr;
+
+use azure_core::headers::{HeaderName, ACL};
+use azure_core::Context;
+
+use crate::backend::AzureStorageBackend;
+use crate::context_headers::{OmitHeaders, RequestHeaders, ResponseHeaders};
+use crate::error::AzureStorageError;
+
+const PERMISSIONS: HeaderName = HeaderName::from_static("x-ms-permissions");
+
+const STICKY: u16 = 0o1000;
+
+/// Read, write and execute bits of a path's owner, owning group and others, plus the sticky bit, e.g.
+/// `rwxr-x---` or `0750`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PosixPermissions(u16);
+
+impl PosixPermissions {
+    /// Permissions of the octal `mode`, e.g. `0o750`. Bits beyond the sticky bit are dropped
+    pub fn from_mode(mode: u16) -> Self {
+        Self(mode & 0o1777)
+    }
+
+    pub fn mode(self) -> u16 {
+        self.0
+    }
+
+    /// The four digit octal form the service accepts
+    fn octal(self) -> String {
+        format!("{:04o}", self.0)
+    }
+
+    /// `rwxr-x---`, with a trailing `+` the service adds to paths that have ACL entries beyond these ignored
+    fn parse_symbolic(symbolic: &str) -> Option<Self> {
+        let symbolic: Vec<char> = symbolic.strip_suffix('+').unwrap_or(symbolic).chars().collect();
+        if symbolic.len() != 9 {
+            return None;
+        }
+        let mut mode = 0;
+        for (index, &character) in symbolic.iter().enumerate() {
+            let bit = 1 << (8 - index);
+            let flag = ['r', 'w', 'x'][index % 3];
+            mode |= match character {
+                '-' => 0,
+                't' if index == 8 => bit | STICKY,
+                'T' if index == 8 => STICKY,
+                _ if character == flag => bit,
+                _ => return None,
+            };
+        }
+        Some(Self(mode))
+    }
+
+    /// `u=rwx,g=rx,o=`, classes that are not mentioned get no permissions
+    fn parse_clauses(clauses: &str) -> Option<Self> {
+        let mut mode = 0;
+        for clause in clauses.split(',') {
+            let (classes, flags) = clause.split_once('=')?;
+            if classes.is_empty() {
+                return None;
+            }
+            let mut bits = 0;
+            for flag in flags.chars() {
+                bits |= match flag {
+                    'r' => 0o4,
+                    'w' => 0o2,
+                    'x' => 0o1,
+                    't' => 0,
+                    _ => return None,
+                };
+            }
+            for class in classes.chars() {
+                let shifts: &[u16] = match class {
+                    'u' => &[6],
+                    'g' => &[3],
+                    'o' => &[0],
+                    'a' => &[6, 3, 0],
+                    _ => return None,
+                };
+                for shift in shifts {
+                    mode |= bits << shift;
+                }
+            }
+            if flags.contains('t') {
+                mode |= STICKY;
+            }
+        }
+        Some(Self(mode))
+    }
+}
+
+impl FromStr for PosixPermissions {
+    type Err = AzureStorageError;
+
+    /// Reads octal modes (`750`, `0750`, `1777`), symbolic ones (`rwxr-x---`) and chmod's absolute clauses
+    /// (`u=rwx,g=rx,o=`)
+    fn from_str(permissions: &str) -> Result<Self, Self::Err> {
+        let parsed = match permissions {
+            _ if (3..=4).contains(&permissions.len()) && permissions.chars().all(|digit| digit.is_digit(8)) => {
+                u16::from_str_radix(permissions, 8).ok().filter(|mode| *mode <= 0o1777).map(Self)
+            }
+            _ if permissions.contains('=') => Self::parse_clauses(permissions),
+            _ => Self::parse_symbolic(permissions),
+        };
+        parsed.ok_or_else(|| AzureStorageError::InvalidPermissions(permissions.to_string()))
+    }
+}
+
+impl fmt::Display for PosixPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbolic: String = (0..9)
+            .map(|index| {
+                let set = self.0 & (1 << (8 - index)) != 0;
+                match (index, set, self.0 & STICKY != 0) {
+                    (8, true, true) => 't',
+                    (8, false, true) => 'T',
+                    (_, true, _) => ['r', 'w', 'x'][index % 3],
+                    (_, false, _) => '-',
+                }
+            })
+            .collect();
+        f.write_str(&symbolic)
+    }
+}
+
+impl AzureStorageBackend {
+    /// The permissions of the file or directory at `path`
+    pub async fn get_permissions(&self, container_name: &str, path: &str) -> Result<PosixPermissions, miette::Error> {
+        let mut context = Context::new();
+        context.insert(ResponseHeaders::default());
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .get_access_control_list()
+            .context(context.clone())
+            .await
+            .map_err(AzureStorageError::Request)?;
+        let headers = context.get::<ResponseHeaders>().and_then(ResponseHeaders::take).unwrap_or_default();
+        Ok(headers.get_optional_str(&PERMISSIONS).unwrap_or_default().parse()?)
+    }
+
+    /// Sets the permissions of the file or directory at `path` like `chmod`, from any form
+    /// [`PosixPermissions`] reads, e.g. `rwxr-x---` or `0750`. Named ACL entries are kept, the mask is set to
+    /// the group bits when the path has one
+    pub async fn set_permissions(&self, container_name: &str, path: &str, permissions: &str) -> Result<(), miette::Error> {
+        let permissions: PosixPermissions = permissions.parse()?;
+        let mut context = Context::new();
+        // the operation always sends an ACL, which the service rejects alongside permissions
+        context.insert(OmitHeaders(vec![ACL]));
+        context.insert(RequestHeaders(vec![(PERMISSIONS, permissions.octal())]));
+        self.file_system_client(container_name)
+            .await
+            .get_file_client(path)
+            .set_access_control_list(String::new())
+            .context(context)
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_form_reads_the_same() {
+        let expected = PosixPermissions::from_mode(0o750);
+        for permissions in ["750", "0750", "rwxr-x---", "rwxr-x---+", "u=rwx,g=rx,o="] {
+            assert_eq!(permissions.parse::<PosixPermissions>().unwrap(), expected, "{}", permissions);
+        }
+        assert_eq!(expected.to_string(), "rwxr-x---");
+        assert_eq!(expected.octal(), "0750");
+
+        let sticky: PosixPermissions = "1777".parse().unwrap();
+        assert_eq!(sticky.to_string(), "rwxrwxrwt");
+        assert_eq!("rwxrwxrwt".parse::<PosixPermissions>().unwrap(), sticky);
+        assert_eq!("a=rwxt".parse::<PosixPermissions>().unwrap(), sticky);
+
+        for invalid in ["800", "rwxr-x--", "rwxr-x--z", "u+x", "=rw"] {
+            assert!(invalid.parse::<PosixPermissions>().is_err(), "{}", invalid);
+        }
+    }
+}
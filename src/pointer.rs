@@ -0,0 +1,162 @@
+//! Pointer files that alias a path in another directory, container or account, like a symlink
+use azure_core::StatusCode;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{AzureStorageBackend, AzureStorageBackendBuilder};
+use crate::checksum::ChecksumMode;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+use crate::upload::UploadOptions;
+
+/// Files larger than this are never read to check whether they are pointers
+const MAX_POINTER_SIZE: i64 = 4096;
+
+/// Pointers followed before resolution gives up, also catching pointers that point at each other
+const MAX_HOPS: usize = 8;
+
+/// Where a pointer file points
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointerTarget {
+    /// Storage account of the target, `None` for the account the pointer is in
+    pub account: Option<String>,
+    pub container: String,
+    /// A file, or a directory for aliasing a whole dataset
+    pub path: String,
+}
+
+impl PointerTarget {
+    /// `path` in `container` of the pointer's own account
+    pub fn new(container: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            account: None,
+            container: container.into(),
+            path: path.into(),
+        }
+    }
+
+    /// Moves the target to another storage account, which is signed in to with the credential settings of the
+    /// backend resolving the pointer
+    pub fn in_account(mut self, account: impl Into<String>) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+}
+
+/// Content of a pointer file
+#[derive(Debug, Serialize, Deserialize)]
+struct PointerFile {
+    pointer_version: u32,
+    target: PointerTarget,
+}
+
+impl AzureStorageBackend {
+    /// Writes a pointer file at `path` that [`resolve`](Self::resolve) and [`read_resolved`](Self::read_resolved)
+    /// follow to `target`, replacing any existing file. The target does not have to exist yet
+    pub async fn create_pointer(&self, container_name: &str, path: &str, target: PointerTarget) -> Result<(), miette::Error> {
+        let pointer = PointerFile { pointer_version: 1, target };
+        let content = serde_json::to_vec(&pointer).expect("pointers only hold strings");
+        self.upload_bytes(container_name, path, content, UploadOptions::default().checksum(ChecksumMode::Off))
+            .await?;
+        Ok(())
+    }
+
+    /// Follows `path` through any chain of pointer files to the file or directory it ends at. Paths that are not
+    /// pointers, including ones that do not exist, resolve to themselves
+    pub async fn resolve(&self, container_name: &str, path: &str) -> Result<PointerTarget, miette::Error> {
+        Ok(self.follow(container_name, path).await?.0)
+    }
+
+    /// Reads the file `path` resolves to
+    pub async fn read_resolved(&self, container_name: &str, path: &str) -> Result<Bytes, miette::Error> {
+        let (target, backend, content) = self.follow(container_name, path).await?;
+        if let Some(content) = content {
+            return Ok(content);
+        }
+        backend.download_bytes(&target.container, &target.path).await
+    }
+
+    /// The final target with the backend of its account, and its content if it had to be read to rule out
+    /// another pointer
+    async fn follow(&self, container_name: &str, path: &str) -> Result<(PointerTarget, AzureStorageBackend, Option<Bytes>), miette::Error> {
+        let mut backend = self.clone();
+        let mut target = PointerTarget::new(container_name, path);
+        for _ in 0..MAX_HOPS {
+            let file_client = backend.file_system_client(&target.container).await.get_file_client(&target.path);
+            let properties = match file_client.get_properties().await {
+                Ok(properties) => properties,
+                // a missing path holds no pointer, its target may still be created later
+                Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => return Ok((target, backend, None)),
+                Err(error) => return Err(AzureStorageError::Request(error).into()),
+            };
+            // directories have no content
+            let length = properties.content_length.unwrap_or_default();
+            if length == 0 || length > MAX_POINTER_SIZE {
+                return Ok((target, backend, None));
+            }
+
+            let content = file_client.read().await.map_err(AzureStorageError::Request)?.data;
+            let Some(next) = parse_pointer(&content) else {
+                return Ok((target, backend, Some(content)));
+            };
+            backend.log(LogLevel::Info, format_args!("Following pointer {}/{} to {}/{}", target.container, target.path, next.container, next.path));
+            if let Some(account) = &next.account {
+                backend = backend.for_account(account).await?;
+            }
+            target = PointerTarget {
+                account: next.account.or(target.account),
+                ..next
+            };
+        }
+        Err(AzureStorageError::PointerLoop {
+            path: format!("{}/{}", container_name, path),
+            hops: MAX_HOPS,
+        }
+        .into())
+    }
+
+    /// A backend for `account` with this backend's credential settings
+    async fn for_account(&self, account: &str) -> Result<AzureStorageBackend, miette::Error> {
+        if account == self.config.storage_account_url {
+            return Ok(self.clone());
+        }
+        let builder = AzureStorageBackendBuilder {
+            storage_account_url: account.to_string(),
+            // the key belongs to this account only
+            account_key: None,
+            ..self.config.clone()
+        };
+        builder.build().await
+    }
+}
+
+/// The target if `content` is a pointer file, anything else is regular content
+fn parse_pointer(content: &[u8]) -> Option<PointerTarget> {
+    serde_json::from_slice::<PointerFile>(content)
+        .ok()
+        .filter(|pointer| pointer.pointer_version == 1)
+        .map(|pointer| pointer.target)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointer_round_trip() {
+        let target = PointerTarget::new("datasets", "sales/2024").in_account("archiveaccount");
+        let content = serde_json::to_vec(&PointerFile { pointer_version: 1, target: target.clone() }).unwrap();
+        assert_eq!(parse_pointer(&content), Some(target));
+    }
+
+    #[test]
+    fn test_regular_content_is_not_a_pointer() {
+        assert_eq!(parse_pointer(b"plain text"), None);
+        assert_eq!(parse_pointer(br#"{"target": "elsewhere"}"#), None);
+        assert_eq!(
+            parse_pointer(br#"{"pointer_version": 2, "target": {"account": null, "container": "c", "path": "p"}}"#),
+            None
+        );
+    }
+}
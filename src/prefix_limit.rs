@@ -0,0 +1,138 @@
+//! Request limits for parts of an account, so traffic on one prefix cannot crowd out another
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use azure_core::{Context, Policy, PolicyResult, Request};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use url::Url;
+
+/// Limits for the requests to paths below one prefix. Unset limits do not apply
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixLimit {
+    /// Requests in flight at once
+    pub max_concurrency: Option<usize>,
+    /// Request starts per second, spaced out evenly
+    pub max_requests_per_second: Option<u32>,
+}
+
+#[derive(Debug)]
+struct PrefixLimiter {
+    /// URL path the limit applies to, percent encoded like the request URLs
+    path: String,
+    in_flight: Option<Semaphore>,
+    spacing: Option<Duration>,
+    next_start: Mutex<Instant>,
+}
+
+impl PrefixLimiter {
+    fn new(prefix: &str, limit: &PrefixLimit) -> Self {
+        let mut url = Url::parse("https://limit.invalid/").expect("static URL is valid");
+        url.set_path(prefix.trim_start_matches('/'));
+        Self {
+            path: url.path().to_string(),
+            in_flight: limit.max_concurrency.map(|max_concurrency| Semaphore::new(max_concurrency.max(1))),
+            spacing: limit
+                .max_requests_per_second
+                .map(|max_requests_per_second| Duration::from_secs(1) / max_requests_per_second.max(1)),
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves the next start slot and returns when the caller may send
+    fn reserve(&self, now: Instant) -> Instant {
+        let Some(spacing) = self.spacing else {
+            return now;
+        };
+        let mut next_start = self.next_start.lock().unwrap();
+        let start = now.max(*next_start);
+        *next_start = start + spacing;
+        start
+    }
+}
+
+/// Applies the [`PrefixLimit`] with the longest prefix matching each request's `<container>/<path>`. Sits in
+/// the per retry policies, so every attempt counts against the limits
+#[derive(Debug)]
+pub(crate) struct PrefixLimitPolicy {
+    /// Longest prefix first
+    limiters: Vec<PrefixLimiter>,
+}
+
+impl PrefixLimitPolicy {
+    pub(crate) fn new(limits: &[(String, PrefixLimit)]) -> Self {
+        let mut limiters: Vec<PrefixLimiter> = limits.iter().map(|(prefix, limit)| PrefixLimiter::new(prefix, limit)).collect();
+        limiters.sort_by_key(|limiter| std::cmp::Reverse(limiter.path.len()));
+        Self { limiters }
+    }
+
+    fn limiter(&self, path: &str) -> Option<&PrefixLimiter> {
+        self.limiters.iter().find(|limiter| is_below(path, &limiter.path))
+    }
+}
+
+/// Whether `path` is `prefix` or below it, so a limit on `data` covers `data/a.csv` but not `database/a.csv`
+fn is_below(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[async_trait::async_trait]
+impl Policy for PrefixLimitPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        let Some(limiter) = self.limiter(request.url().path()) else {
+            return next[0].send(ctx, request, &next[1..]).await;
+        };
+
+        let _permit = match &limiter.in_flight {
+            Some(in_flight) => Some(in_flight.acquire().await.expect("prefix semaphore is never closed")),
+            None => None,
+        };
+        tokio::time::sleep_until(limiter.reserve(Instant::now())).await;
+        next[0].send(ctx, request, &next[1..]).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let gentle = PrefixLimit { max_concurrency: Some(2), ..Default::default() };
+        let policy = PrefixLimitPolicy::new(&[
+            ("data".to_string(), PrefixLimit::default()),
+            ("data/hot serving/".to_string(), gentle),
+        ]);
+
+        assert_eq!(policy.limiter("/data/hot%20serving/index.json").unwrap().path, "/data/hot%20serving/");
+        assert_eq!(policy.limiter("/data/archive/2020.parquet").unwrap().path, "/data");
+        assert!(policy.limiter("/logs/app.log").is_none());
+    }
+
+    #[test]
+    fn test_siblings_sharing_the_prefix_are_not_limited() {
+        let policy = PrefixLimitPolicy::new(&[("data".to_string(), PrefixLimit::default())]);
+
+        assert!(policy.limiter("/data").is_some());
+        assert!(policy.limiter("/data/a.csv").is_some());
+        assert!(policy.limiter("/database/a.csv").is_none());
+        assert!(policy.limiter("/data-archive").is_none());
+    }
+
+    #[test]
+    fn test_rate_limit_spaces_starts() {
+        let limiter = PrefixLimiter::new(
+            "archive/",
+            &PrefixLimit {
+                max_requests_per_second: Some(4),
+                ..Default::default()
+            },
+        );
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(now), now);
+        assert_eq!(limiter.reserve(now), now + Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,118 @@
+//! Progress of long transfers, for progress bars and health reporting
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Called with the progress of a transfer each time more of it completed
+pub type ProgressCallback = Arc<dyn Fn(&TransferProgress) + Send + Sync>;
+
+/// How far a transfer got
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// Bytes the service acknowledged so far
+    pub bytes: u64,
+    /// `None` when the size is not known up front, e.g. for uploads of a stream
+    pub total: Option<u64>,
+    /// Time since the transfer started
+    pub elapsed: Duration,
+}
+
+impl TransferProgress {
+    /// Share of the transfer done, between 0 and 1, `None` without a known total
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| match total {
+            0 => 1.0,
+            total => self.bytes as f64 / total as f64,
+        })
+    }
+
+    /// Average bytes per second since the transfer started
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            elapsed if elapsed > 0.0 => self.bytes as f64 / elapsed,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The callback set on a transfer's options, together with the size when it is known
+#[derive(Clone, Default)]
+pub(crate) struct ProgressHook {
+    pub(crate) callback: Option<ProgressCallback>,
+    pub(crate) total: Option<u64>,
+}
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressHook")
+            .field("callback", &self.callback.as_ref().map(|_| ".."))
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+impl ProgressHook {
+    /// Starts counting a transfer, reported to the callback if there is one
+    pub(crate) fn start(&self) -> ProgressCounter {
+        ProgressCounter {
+            callback: self.callback.clone(),
+            total: self.total,
+            bytes: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+pub(crate) struct ProgressCounter {
+    callback: Option<ProgressCallback>,
+    total: Option<u64>,
+    bytes: u64,
+    started: Instant,
+}
+
+impl ProgressCounter {
+    /// Counts `bytes` more as done and reports the progress
+    pub(crate) fn advance(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        if let Some(callback) = &self.callback {
+            callback(&self.progress());
+        }
+    }
+
+    fn progress(&self) -> TransferProgress {
+        TransferProgress {
+            bytes: self.bytes,
+            total: self.total,
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_progress_is_reported_as_it_advances() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let hook = ProgressHook {
+            callback: Some({
+                let reported = reported.clone();
+                Arc::new(move |progress: &TransferProgress| reported.lock().unwrap().push(progress.bytes))
+            }),
+            total: Some(10),
+        };
+        let mut counter = hook.start();
+        counter.advance(4);
+        counter.advance(6);
+        assert_eq!(*reported.lock().unwrap(), [4, 10]);
+        assert_eq!(counter.progress().fraction(), Some(1.0));
+
+        let unknown = TransferProgress { bytes: 5, total: None, elapsed: Duration::from_secs(2) };
+        assert_eq!(unknown.fraction(), None);
+        assert_eq!(unknown.throughput(), 2.5);
+    }
+}
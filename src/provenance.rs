@@ -0,0 +1,108 @@
+//! Stamping every file created through a client with provenance metadata, whoever creates it
+use std::sync::{Arc, RwLock};
+
+use azure_core::headers::HeaderName;
+use azure_core::{Context, Method, Policy, PolicyResult, Request};
+
+use crate::backend::AzureStorageBackend;
+
+const PROPERTIES: HeaderName = HeaderName::from_static("x-ms-properties");
+
+/// Metadata to stamp on the file being created at `<container>/<path>`, percent encoded as in the request URL,
+/// e.g. the creator, pipeline run id and git SHA. Keys must be valid metadata names, values are stored as given
+pub type ProvenanceCallback = Arc<dyn Fn(&str) -> Vec<(String, String)> + Send + Sync>;
+
+/// The provenance callback of one cached client, shared by every backend handle for it
+#[derive(Default)]
+pub(crate) struct ProvenanceSettings {
+    callback: RwLock<Option<ProvenanceCallback>>,
+}
+
+impl std::fmt::Debug for ProvenanceSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let enabled = self.callback.read().unwrap().is_some();
+        f.debug_struct("ProvenanceSettings").field("enabled", &enabled).finish()
+    }
+}
+
+impl AzureStorageBackend {
+    /// Stamps every file created through this backend's client with the metadata `callback` returns, including
+    /// uploads, writers and direct use of [`data_lake_client`](Self::data_lake_client). Metadata the caller sets
+    /// itself takes precedence over the callback's for the same key
+    pub fn set_provenance(&self, callback: ProvenanceCallback) {
+        *self.provenance.callback.write().unwrap() = Some(callback);
+    }
+
+    pub fn clear_provenance(&self) {
+        *self.provenance.callback.write().unwrap() = None;
+    }
+}
+
+/// Adds the provenance metadata to file creations. Placed before the signing policy so the signature covers it
+#[derive(Debug)]
+pub(crate) struct ProvenancePolicy {
+    settings: Arc<ProvenanceSettings>,
+}
+
+impl ProvenancePolicy {
+    pub(crate) fn new(settings: Arc<ProvenanceSettings>) -> Self {
+        Self { settings }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for ProvenancePolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        let callback = self.settings.callback.read().unwrap().clone();
+        if let Some(callback) = callback {
+            if is_file_creation(request) {
+                let path = request.url().path().trim_start_matches('/').to_string();
+                let existing = request.headers().get_optional_str(&PROPERTIES).map(str::to_string);
+                if let Some(properties) = merge_properties(existing.as_deref(), callback(&path)) {
+                    request.insert_header(PROPERTIES, properties);
+                }
+            }
+        }
+        next[0].send(ctx, request, &next[1..]).await
+    }
+}
+
+fn is_file_creation(request: &Request) -> bool {
+    *request.method() == Method::Put && request.url().query_pairs().any(|(name, value)| name == "resource" && value == "file")
+}
+
+/// `existing` `x-ms-properties` with the `added` pairs it does not have yet, `None` when there is nothing to set
+fn merge_properties(existing: Option<&str>, added: Vec<(String, String)>) -> Option<String> {
+    let mut properties: Vec<String> = existing.into_iter().flat_map(|existing| existing.split(',')).map(str::to_string).collect();
+    let has_key = |properties: &[String], key: &str| properties.iter().any(|property| property.split('=').next() == Some(key));
+    for (key, value) in added {
+        if !has_key(&properties, &key) {
+            properties.push(format!("{}={}", key, azure_core::base64::encode(value)));
+        }
+    }
+    (!properties.is_empty()).then(|| properties.join(","))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caller_metadata_takes_precedence() {
+        let added = vec![("creator".to_string(), "etl".to_string()), ("run".to_string(), "42".to_string())];
+        assert_eq!(merge_properties(None, added.clone()).unwrap(), "creator=ZXRs,run=NDI=");
+        assert_eq!(merge_properties(Some("run=Nw=="), added).unwrap(), "run=Nw==,creator=ZXRs");
+        assert_eq!(merge_properties(None, Vec::new()), None);
+    }
+
+    #[test]
+    fn test_only_file_creations_are_stamped() {
+        let url = url::Url::parse("https://account.dfs.core.windows.net/data/file.csv?resource=file").unwrap();
+        assert!(is_file_creation(&Request::new(url.clone(), Method::Put)));
+        assert!(!is_file_creation(&Request::new(url, Method::Patch)));
+
+        let directory = url::Url::parse("https://account.dfs.core.windows.net/data/dir?resource=directory").unwrap();
+        assert!(!is_file_creation(&Request::new(directory, Method::Put)));
+    }
+}
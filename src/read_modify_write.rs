@@ -0,0 +1,164 @@
+//! Optimistic read-modify-write of small files, retried when another writer got there first
+use std::time::Duration;
+
+use azure_core::StatusCode;
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backend::AzureStorageBackend;
+use crate::condition::{EtagCondition, VersionedContent};
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+use crate::upload::UploadOptions;
+
+/// How often a conflicting update is tried again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateOptions {
+    pub(crate) max_attempts: u32,
+    pub(crate) backoff: Duration,
+}
+
+impl Default for UpdateOptions {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+impl UpdateOptions {
+    /// Attempts before a conflict is returned as [`AzureStorageError::ConditionNotMet`], 5 by default
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Wait before the second attempt, doubled for every further one. 50ms by default
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+impl AzureStorageBackend {
+    /// Reads the file at `path`, passes its content to `modify`, `None` when it does not exist, and writes the
+    /// result back only if nobody changed the file in the meantime. On a conflict the file is read again and
+    /// `modify` called again with the new content, so it must not have side effects. Returns what was written
+    pub async fn update<F>(&self, container_name: &str, path: &str, options: UpdateOptions, mut modify: F) -> Result<VersionedContent, miette::Error>
+    where
+        F: FnMut(Option<Bytes>) -> Result<Bytes, miette::Error>,
+    {
+        let UpdateOptions { max_attempts, mut backoff } = options;
+        for attempt in 1..=max_attempts {
+            let current = self.read_versioned(container_name, path).await?;
+            let condition = match &current {
+                Some(current) => EtagCondition::IfMatch(current.etag.clone()),
+                None => EtagCondition::absent(),
+            };
+            let data = modify(current.map(|current| current.data))?;
+
+            let chunks = futures::stream::iter([Ok(data.clone())]);
+            match self.upload_stream(container_name, path, chunks, UploadOptions::default().condition(condition)).await {
+                Ok(receipt) => {
+                    return Ok(VersionedContent {
+                        data,
+                        etag: receipt.etag.unwrap_or_default(),
+                    })
+                }
+                Err(AzureStorageError::ConditionNotMet { .. }) if attempt < max_attempts => {}
+                Err(error) => return Err(error.into()),
+            }
+            self.log(LogLevel::Info, format_args!("{} changed while it was updated, attempt {} of {}", path, attempt, max_attempts));
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        unreachable!("the last attempt returns")
+    }
+
+    /// [`update`](Self::update) for JSON files, `modify` gets the deserialized content or `T::default()`
+    pub async fn update_json<T, F>(&self, container_name: &str, path: &str, options: UpdateOptions, mut modify: F) -> Result<T, miette::Error>
+    where
+        T: Serialize + DeserializeOwned + Default,
+        F: FnMut(&mut T),
+    {
+        let json_error = |source| AzureStorageError::InvalidJson { path: path.to_string(), source };
+        let written = self
+            .update(container_name, path, options, |current| {
+                let mut value = match current {
+                    Some(current) if !current.is_empty() => serde_json::from_slice(&current).map_err(json_error)?,
+                    _ => T::default(),
+                };
+                modify(&mut value);
+                Ok(Bytes::from(serde_json::to_vec(&value).map_err(json_error)?))
+            })
+            .await?;
+        Ok(serde_json::from_slice(&written.data).map_err(json_error)?)
+    }
+
+    /// The content and etag of the file at `path`, `None` when it does not exist
+    async fn read_versioned(&self, container_name: &str, path: &str) -> Result<Option<VersionedContent>, AzureStorageError> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        match file_client.read().await {
+            Ok(response) => Ok(Some(VersionedContent {
+                data: response.data,
+                etag: response.etag,
+            })),
+            Err(error) => match http_status(&error) {
+                Some((StatusCode::NotFound, _)) => Ok(None),
+                // an empty file cannot satisfy the SDK's range, its etag comes from its properties
+                Some((StatusCode::RequestedRangeNotSatisfiable, _)) => {
+                    let properties = file_client.get_properties().await?;
+                    Ok(Some(VersionedContent {
+                        data: Bytes::new(),
+                        etag: properties.etag,
+                    }))
+                }
+                _ => Err(AzureStorageError::Request(error)),
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use azure_core::Method;
+
+    use crate::test_service::FakeDataLake;
+
+    #[test]
+    fn test_an_update_is_attempted_at_least_once() {
+        assert_eq!(UpdateOptions::default().max_attempts(0).max_attempts, 1);
+        assert_eq!(UpdateOptions::default().max_attempts(3).max_attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_writers_keep_each_others_updates() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/state/counter", "a");
+        let backend = service.backend();
+        let options = UpdateOptions::default().backoff(Duration::from_millis(1));
+        let append = |suffix: &'static str| {
+            move |current: Option<Bytes>| Ok(Bytes::from([current.unwrap_or_default(), Bytes::from(suffix)].concat()))
+        };
+
+        // the first writer stops right before committing, the second runs in between
+        let hold = service.hold_next(|method, target| *method == Method::Patch && target.contains("action=flush"));
+        let first = {
+            let (backend, options) = (backend.clone(), options.clone());
+            tokio::spawn(async move { backend.update("raw", "state/counter", options, append("b")).await })
+        };
+        hold.reached().await;
+        let second = backend.update("raw", "state/counter", options, append("c")).await.unwrap();
+        assert_eq!(second.data, "ac");
+        hold.release();
+
+        let first = first.await.unwrap().unwrap();
+        assert_eq!(first.data, "acb");
+        assert_eq!(service.file("raw/state/counter").as_deref(), Some(&b"acb"[..]));
+    }
+}
@@ -0,0 +1,185 @@
+//! Reading files through `tokio::io::AsyncRead` and `AsyncSeek`
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use azure_core::prelude::{IfMatchCondition, Range};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use crate::backend::AzureStorageBackend;
+use crate::download::DownloadOptions;
+use crate::error::AzureStorageError;
+use crate::sdk::datalake::*;
+
+/// Reads a file through [`AsyncRead`], fetching it a chunk at a time with ranged reads, and seeks through
+/// [`AsyncSeek`] without any request. Every read is conditional on the etag seen when the reader was opened,
+/// so a file replaced while it is read fails the read instead of mixing versions
+pub struct DataLakeFileReader {
+    file_client: FileClient,
+    path: String,
+    size: u64,
+    etag: String,
+    chunk_size: u64,
+    position: u64,
+    /// The chunk read last and its offset in the file
+    chunk: Bytes,
+    chunk_start: u64,
+    pending: Option<(u64, BoxFuture<'static, azure_core::Result<Bytes>>)>,
+}
+
+impl std::fmt::Debug for DataLakeFileReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataLakeFileReader")
+            .field("path", &self.path)
+            .field("size", &self.size)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl AzureStorageBackend {
+    /// Opens the file at `path` for reading, `options` sets the size of each ranged read
+    pub async fn file_reader(&self, container_name: &str, path: &str, options: DownloadOptions) -> Result<DataLakeFileReader, miette::Error> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        let properties = file_client.get_properties().await.map_err(AzureStorageError::Request)?;
+        Ok(DataLakeFileReader {
+            file_client,
+            path: path.to_string(),
+            size: properties.content_length.unwrap_or_default().max(0) as u64,
+            etag: properties.etag,
+            chunk_size: options.chunk_size,
+            position: 0,
+            chunk: Bytes::new(),
+            chunk_start: 0,
+            pending: None,
+        })
+    }
+}
+
+impl DataLakeFileReader {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Size of the file when it was opened
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The part of the current chunk at and after the read position
+    fn buffered(&self) -> &[u8] {
+        let chunk_end = self.chunk_start + self.chunk.len() as u64;
+        if self.position < self.chunk_start || self.position >= chunk_end {
+            return &[];
+        }
+        &self.chunk[(self.position - self.chunk_start) as usize..]
+    }
+
+    fn start_fetch(&mut self) {
+        let file_client = self.file_client.clone();
+        let range = Range::new(self.position, (self.position + self.chunk_size).min(self.size));
+        let etag = self.etag.clone();
+        let fetch = async move {
+            let response = file_client.read().range(range).if_match_condition(IfMatchCondition::Match(etag)).await?;
+            Ok(response.data)
+        };
+        self.pending = Some((self.position, fetch.boxed()));
+    }
+}
+
+impl AsyncRead for DataLakeFileReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.position >= this.size || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        if this.buffered().is_empty() {
+            if this.pending.is_none() {
+                this.start_fetch();
+            }
+            let (start, fetch) = this.pending.as_mut().expect("fetch was just started");
+            let start = *start;
+            let result = ready!(fetch.poll_unpin(cx));
+            this.pending = None;
+            this.chunk = result.map_err(io::Error::other)?;
+            this.chunk_start = start;
+            if this.chunk.is_empty() {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} ended early", this.path))));
+            }
+        }
+
+        let buffered = this.buffered();
+        let length = buffered.len().min(buf.remaining());
+        buf.put_slice(&buffered[..length]);
+        this.position += length as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for DataLakeFileReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        };
+        let Some(target) = target else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file"));
+        };
+        if this.pending.as_ref().is_some_and(|(start, _)| *start != target) {
+            this.pending = None;
+        }
+        this.position = target;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    fn reader(size: u64) -> DataLakeFileReader {
+        let file_client = DataLakeClient::new("account", crate::sdk::storage::StorageCredentials::anonymous())
+            .file_system_client("container")
+            .get_file_client("file.bin");
+        DataLakeFileReader {
+            file_client,
+            path: "file.bin".to_string(),
+            size,
+            etag: "0x1".to_string(),
+            chunk_size: 4,
+            position: 0,
+            chunk: Bytes::new(),
+            chunk_start: 0,
+            pending: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reads_are_served_from_the_current_chunk() {
+        let mut reader = reader(10);
+        reader.chunk = Bytes::from_static(b"4567");
+        reader.chunk_start = 4;
+
+        assert_eq!(reader.seek(SeekFrom::End(-5)).await.unwrap(), 5);
+        let mut buffer = [0; 8];
+        assert_eq!(reader.read(&mut buffer).await.unwrap(), 3);
+        assert_eq!(&buffer[..3], b"567");
+        assert!(reader.seek(SeekFrom::Current(-20)).await.is_err());
+
+        reader.seek(SeekFrom::Start(10)).await.unwrap();
+        assert_eq!(reader.read(&mut buffer).await.unwrap(), 0);
+    }
+}
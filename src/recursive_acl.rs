@@ -0,0 +1,293 @@
+//! Changing the ACLs of whole directory trees in batches the service applies one after the other
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::error::ErrorKind;
+use azure_core::headers::{ACL, CONTINUATION};
+use azure_core::Method;
+use serde::Deserialize;
+
+use crate::acl::{AccessControlList, AclEntry};
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+use crate::rest::RestRequest;
+
+/// Called after every batch with the totals so far
+pub type AclProgressCallback = Arc<dyn Fn(&AclProgress) + Send + Sync>;
+
+/// How the given entries change the ACL of every path of the tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclChange {
+    /// Replace the whole ACL
+    Set,
+    /// Add the entries, or update the permissions of entries for the same user or group
+    Modify,
+    /// Remove the entries for the same users and groups, their permissions are ignored
+    Remove,
+}
+
+impl AclChange {
+    fn mode(self) -> &'static str {
+        match self {
+            Self::Set => "set",
+            Self::Modify => "modify",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+/// A path whose ACL could not be changed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclFailure {
+    pub path: String,
+    pub is_directory: bool,
+    pub message: String,
+}
+
+/// What a recursive ACL change did so far
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AclProgress {
+    pub directories: u64,
+    pub files: u64,
+    pub failures: u64,
+    /// Paths that failed, as far as the service listed them
+    pub failed_entries: Vec<AclFailure>,
+    /// Where the next batch starts, `None` once the whole tree was processed. Pass it to
+    /// [`RecursiveAclOptions::resume_from`] to carry on with a change that stopped
+    pub continuation: Option<String>,
+}
+
+/// Batching, retries and progress reporting of a recursive ACL change
+#[derive(Clone)]
+pub struct RecursiveAclOptions {
+    pub(crate) batch_size: Option<u32>,
+    pub(crate) continue_on_failure: bool,
+    pub(crate) continuation: Option<String>,
+    pub(crate) max_attempts: u32,
+    pub(crate) backoff: Duration,
+    pub(crate) progress: Option<AclProgressCallback>,
+}
+
+impl Default for RecursiveAclOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: None,
+            continue_on_failure: false,
+            continuation: None,
+            max_attempts: 3,
+            backoff: Duration::from_secs(1),
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for RecursiveAclOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecursiveAclOptions")
+            .field("batch_size", &self.batch_size)
+            .field("continue_on_failure", &self.continue_on_failure)
+            .field("continuation", &self.continuation)
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl RecursiveAclOptions {
+    /// Paths changed per request, up to 2000 which is also the service's default
+    pub fn batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = Some(batch_size.clamp(1, 2000));
+        self
+    }
+
+    /// Whether paths the change fails on, e.g. for lack of permission, are reported and skipped instead of
+    /// stopping the change at the first of them. Off by default
+    pub fn continue_on_failure(mut self, continue_on_failure: bool) -> Self {
+        self.continue_on_failure = continue_on_failure;
+        self
+    }
+
+    /// Continues a change from the [`AclProgress::continuation`] it stopped at, instead of starting at the top
+    pub fn resume_from(mut self, continuation: impl Into<String>) -> Self {
+        self.continuation = Some(continuation.into());
+        self
+    }
+
+    /// Attempts per batch before the change fails, 3 by default, waiting `backoff` after the first failure and
+    /// twice as long after each further one
+    pub fn retries(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn progress(mut self, progress: AclProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+}
+
+/// The service's account of one batch
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchResult {
+    directories_successful: u64,
+    files_successful: u64,
+    failure_count: u64,
+    #[serde(default)]
+    failed_entries: Vec<FailedEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FailedEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    error_message: String,
+}
+
+impl AclProgress {
+    fn add(&mut self, batch: BatchResult) {
+        self.directories += batch.directories_successful;
+        self.files += batch.files_successful;
+        self.failures += batch.failure_count;
+        self.failed_entries.extend(batch.failed_entries.into_iter().map(|entry| AclFailure {
+            is_directory: entry.kind.eq_ignore_ascii_case("directory"),
+            path: entry.name,
+            message: entry.error_message,
+        }));
+    }
+}
+
+/// The `x-ms-acl` of a change. Removals name entries without permissions
+fn acl_text(change: AclChange, acl: &AccessControlList) -> String {
+    match change {
+        AclChange::Set | AclChange::Modify => acl.to_string(),
+        AclChange::Remove => {
+            let removal = |entry: &AclEntry| {
+                let entry = entry.to_string();
+                let without_permissions = entry.rsplit_once(':').map_or(entry.as_str(), |(scope, _)| scope);
+                without_permissions.trim_end_matches(':').to_string()
+            };
+            acl.entries.iter().map(removal).collect::<Vec<_>>().join(",")
+        }
+    }
+}
+
+impl AzureStorageBackend {
+    /// Sends one batch of Set Access Control Recursive, the one after `continuation` or the first, and returns what it
+    /// changed and the continuation of the next batch
+    async fn apply_batch(
+        &self,
+        container_name: &str,
+        path: &str,
+        change: AclChange,
+        acl: &str,
+        options: &RecursiveAclOptions,
+        continuation: Option<&str>,
+    ) -> azure_core::Result<(BatchResult, Option<String>)> {
+        let mut backoff = options.backoff;
+        for attempt in 1..=options.max_attempts {
+            let mut request = RestRequest::data_lake(Method::Patch, container_name, path)
+                .query("action", "setAccessControlRecursive")
+                .query("mode", change.mode())
+                .headers([(ACL, acl.to_string())]);
+            if let Some(batch_size) = options.batch_size {
+                request = request.query("maxRecords", batch_size.to_string());
+            }
+            if options.continue_on_failure {
+                request = request.query("forceFlag", "true");
+            }
+            if let Some(continuation) = continuation {
+                request = request.query("continuation", continuation);
+            }
+            match self.send_rest(request).await {
+                Ok(response) => {
+                    let result = match response.body.is_empty() {
+                        true => BatchResult::default(),
+                        false => serde_json::from_slice(&response.body).map_err(|error| azure_core::Error::new(ErrorKind::DataConversion, error))?,
+                    };
+                    return Ok((result, response.headers.get_optional_string(&CONTINUATION)));
+                }
+                Err(error) if attempt < options.max_attempts => {
+                    self.log(LogLevel::Info, format_args!("ACL batch failed on attempt {}, retrying in {:?}: {}", attempt, backoff, error));
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(AzureStorageError::Request(error)) => return Err(error),
+                Err(error) => return Err(azure_core::Error::new(ErrorKind::Other, error)),
+            }
+        }
+        unreachable!("the last attempt returns")
+    }
+
+    /// Applies `change` with the entries of `acl` to the directory `path` and everything below it, in batches
+    /// the service works through one after the other. A batch that keeps failing stops the change with
+    /// [`AzureStorageError::AclBatchFailed`], which carries the continuation to resume from. Paths failing on
+    /// their own stop it too unless [`RecursiveAclOptions::continue_on_failure`], either way they are reported
+    /// in the returned progress
+    pub async fn update_acl_recursive(
+        &self,
+        container_name: &str,
+        path: &str,
+        change: AclChange,
+        acl: &AccessControlList,
+        options: RecursiveAclOptions,
+    ) -> Result<AclProgress, miette::Error> {
+        let acl = acl_text(change, acl);
+        let mut progress = AclProgress {
+            continuation: options.continuation.clone(),
+            ..AclProgress::default()
+        };
+        loop {
+            let (batch, continuation) = self
+                .apply_batch(container_name, path, change, &acl, &options, progress.continuation.as_deref())
+                .await
+                .map_err(|source| AzureStorageError::AclBatchFailed {
+                    path: path.to_string(),
+                    continuation: progress.continuation.clone(),
+                    source,
+                })?;
+            let failed = batch.failure_count > 0;
+            progress.add(batch);
+            progress.continuation = continuation;
+            if let Some(callback) = &options.progress {
+                callback(&progress);
+            }
+            if progress.continuation.is_none() || (failed && !options.continue_on_failure) {
+                return Ok(progress);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removals_name_entries_without_permissions() {
+        let acl: AccessControlList = "user:alice:r-x,default:group:etl:rwx,mask::rwx".parse().unwrap();
+        assert_eq!(acl_text(AclChange::Remove, &acl), "user:alice,default:group:etl,mask");
+        assert_eq!(acl_text(AclChange::Modify, &acl), "user:alice:r-x,default:group:etl:rwx,mask::rwx");
+    }
+
+    #[test]
+    fn test_batches_add_up() {
+        let batch: BatchResult = serde_json::from_str(
+            r#"{"directoriesSuccessful": 2, "filesSuccessful": 10, "failureCount": 1,
+                "failedEntries": [{"errorMessage": "denied", "name": "raw/secret.csv", "type": "FILE"}]}"#,
+        )
+        .unwrap();
+        let mut progress = AclProgress { files: 5, ..AclProgress::default() };
+        progress.add(batch);
+        assert_eq!((progress.directories, progress.files, progress.failures), (2, 15, 1));
+        assert_eq!(progress.failed_entries[0].path, "raw/secret.csv");
+        assert!(!progress.failed_entries[0].is_directory);
+    }
+}
@@ -0,0 +1,130 @@
+//! Blob and data lake REST operations the SDK's clients have no builder for, sent as requests built here
+use azure_core::headers::{HeaderName, Headers};
+use azure_core::{Context, Method};
+use bytes::Bytes;
+use url::Url;
+
+use crate::backend::{AzureStorageBackend, AzureStorageBackendBuilder};
+use crate::error::AzureStorageError;
+use crate::sdk::rest::{self, ServiceType};
+
+/// A request on a container or a path in it
+#[derive(Debug)]
+pub(crate) struct RestRequest {
+    pub(crate) service: ServiceType,
+    pub(crate) method: Method,
+    pub(crate) container_name: String,
+    pub(crate) path: String,
+    pub(crate) query: Vec<(&'static str, String)>,
+    pub(crate) headers: Vec<(HeaderName, String)>,
+    pub(crate) body: Bytes,
+}
+
+impl RestRequest {
+    /// A Blob REST request on `path` in `container_name`, on the container itself when `path` is empty
+    pub(crate) fn blob(method: Method, container_name: &str, path: &str) -> Self {
+        Self::new(ServiceType::Blob, method, container_name, path)
+    }
+
+    /// A data lake REST request on `path` in `container_name`
+    pub(crate) fn data_lake(method: Method, container_name: &str, path: &str) -> Self {
+        Self::new(ServiceType::DataLake, method, container_name, path)
+    }
+
+    fn new(service: ServiceType, method: Method, container_name: &str, path: &str) -> Self {
+        Self {
+            service,
+            method,
+            container_name: container_name.to_string(),
+            path: path.to_string(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub(crate) fn query(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.query.push((name, value.into()));
+        self
+    }
+
+    /// Sent after the headers every request carries, so these replace them, e.g. `x-ms-version`
+    pub(crate) fn headers(mut self, headers: impl IntoIterator<Item = (HeaderName, String)>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub(crate) fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    fn url(&self, config: &AzureStorageBackendBuilder) -> Url {
+        let mut url = endpoint_url(config, self.service, &self.container_name, &self.path);
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(self.query.iter().map(|(name, value)| (*name, value.as_str())));
+        }
+        url
+    }
+}
+
+/// The headers and body of a successful response
+#[derive(Debug)]
+pub(crate) struct RestResponse {
+    pub(crate) headers: Headers,
+    pub(crate) body: Bytes,
+}
+
+/// `path` in `container_name` at the `service` endpoint of the account of `config`, the container itself when
+/// `path` is empty and the account when both are
+pub(crate) fn endpoint_url(config: &AzureStorageBackendBuilder, service: ServiceType, container_name: &str, path: &str) -> Url {
+    let mut url = Url::parse(&config.endpoint(service)).expect("account names and endpoint suffixes are valid hosts");
+    url.path_segments_mut()
+        .expect("https URLs have a path")
+        .extend(std::iter::once(container_name).chain(path.split('/')).filter(|segment| !segment.is_empty()));
+    url
+}
+
+impl AzureStorageBackend {
+    /// Sends `request` through the policies of the client, so it is retried, throttled, logged and signed like
+    /// the SDK's operations. Responses other than 2xx are errors with their status and error code
+    pub(crate) async fn send_rest(&self, request: RestRequest) -> Result<RestResponse, AzureStorageError> {
+        let url = request.url(&self.config);
+        let mut http_request = rest::request(url, request.method, request.body).map_err(AzureStorageError::Request)?;
+        for (name, value) in request.headers {
+            http_request.insert_header(name, value);
+        }
+        let mut context = Context::new();
+        context.insert(request.service);
+        let response = self.pipeline.send(&mut context, &mut http_request).await.map_err(AzureStorageError::Request)?;
+        let (_status, headers, body) = response.deconstruct();
+        let body = body.collect().await.map_err(AzureStorageError::Request)?;
+        Ok(RestResponse { headers, body })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_address_the_endpoint_of_their_service() {
+        let config = AzureStorageBackendBuilder::new("account");
+        let request = RestRequest::blob(Method::Put, "raw", "data/a b.csv").query("comp", "tags");
+        assert_eq!(request.url(&config).as_str(), "https://account.blob.core.windows.net/raw/data/a%20b.csv?comp=tags");
+
+        let request = RestRequest::data_lake(Method::Post, "raw", "/data//a.csv/");
+        assert_eq!(request.url(&config).as_str(), "https://account.dfs.core.windows.net/raw/data/a.csv");
+
+        let request = RestRequest::blob(Method::Put, "raw", "").query("restype", "container").query("comp", "undelete");
+        assert_eq!(request.url(&config).as_str(), "https://account.blob.core.windows.net/raw?restype=container&comp=undelete");
+
+        let request = RestRequest::blob(Method::Post, "", "").query("restype", "service");
+        assert_eq!(request.url(&config).as_str(), "https://account.blob.core.windows.net/?restype=service");
+
+        let config = config.endpoint_suffix("core.usgovcloudapi.net");
+        let request = RestRequest::data_lake(Method::Post, "raw", "a.csv");
+        assert_eq!(request.url(&config).as_str(), "https://account.dfs.core.usgovcloudapi.net/raw/a.csv");
+    }
+}
@@ -0,0 +1,344 @@
+//! Shared access signatures signed with a user delegation key, time-limited links without an account key
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use azure_core::auth::TokenCredential;
+use azure_core::headers::{AUTHORIZATION, CONTENT_TYPE, VERSION};
+use azure_core::Method;
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::backend::AzureStorageBackend;
+use crate::credential::STORAGE_TOKEN_RESOURCE;
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+use crate::rest::RestRequest;
+
+const SAS_VERSION: &str = "2020-12-06";
+/// Longest validity of a user delegation key, and so of the signatures made with it
+const MAX_KEY_VALIDITY: Duration = Duration::days(7);
+/// How far the key's validity reaches back, for clocks of other hosts running behind
+const CLOCK_SKEW: Duration = Duration::minutes(5);
+/// Margin kept to the longest validity when requesting a key, so the request is not rejected for asking too much
+const KEY_VALIDITY_MARGIN: Duration = Duration::minutes(1);
+
+/// What a SAS allows, written in the service's `racwdl` order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SasPermissions {
+    pub read: bool,
+    pub add: bool,
+    pub create: bool,
+    pub write: bool,
+    pub delete: bool,
+    pub list: bool,
+}
+
+impl FromStr for SasPermissions {
+    type Err = AzureStorageError;
+
+    fn from_str(permissions: &str) -> Result<Self, Self::Err> {
+        let mut parsed = Self::default();
+        for character in permissions.chars() {
+            let flag = match character {
+                'r' => &mut parsed.read,
+                'a' => &mut parsed.add,
+                'c' => &mut parsed.create,
+                'w' => &mut parsed.write,
+                'd' => &mut parsed.delete,
+                'l' => &mut parsed.list,
+                _ => return Err(AzureStorageError::InvalidSas(format!("unknown permission {:?} in {:?}", character, permissions))),
+            };
+            *flag = true;
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for SasPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.read, 'r'),
+            (self.add, 'a'),
+            (self.create, 'c'),
+            (self.write, 'w'),
+            (self.delete, 'd'),
+            (self.list, 'l'),
+        ];
+        flags.iter().filter(|(granted, _)| *granted).try_for_each(|(_, character)| write!(f, "{}", character))
+    }
+}
+
+/// A key from Get User Delegation Key, valid from `signed_start` to `signed_expiry` for signing on behalf of the
+/// principal `signed_oid`
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct UserDelegationKey {
+    pub(crate) signed_oid: String,
+    pub(crate) signed_tid: String,
+    pub(crate) signed_start: String,
+    pub(crate) signed_expiry: String,
+    pub(crate) signed_service: String,
+    pub(crate) signed_version: String,
+    /// Base64 signing key
+    pub(crate) value: String,
+}
+
+impl UserDelegationKey {
+    /// Whether signatures made with the key stay valid until `expiry`. An unreadable expiry never does
+    fn is_valid_until(&self, expiry: OffsetDateTime) -> bool {
+        OffsetDateTime::parse(&self.signed_expiry, &Rfc3339).is_ok_and(|key_expiry| key_expiry >= expiry)
+    }
+}
+
+/// The user delegation key last issued to a backend, shared by every handle of a cached client. Keys are
+/// requested for the longest validity the service allows and replaced once a signature needs to outlive them
+#[derive(Default)]
+pub(crate) struct UserDelegationKeys {
+    current: Mutex<Option<Arc<UserDelegationKey>>>,
+}
+
+impl fmt::Debug for UserDelegationKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserDelegationKeys").finish_non_exhaustive()
+    }
+}
+
+/// Seconds precision UTC time as the service expects in SAS fields, e.g. `2025-10-14T12:00:00Z`
+fn sas_time(time: OffsetDateTime) -> String {
+    let time = time.to_offset(time::UtcOffset::UTC).replace_nanosecond(0).expect("0 is a valid nanosecond");
+    time.format(&Rfc3339).expect("UTC times within years 0 to 9999 format as RFC 3339")
+}
+
+fn key_info(start: OffsetDateTime, expiry: OffsetDateTime) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><KeyInfo><Start>{}</Start><Expiry>{}</Expiry></KeyInfo>",
+        sas_time(start),
+        sas_time(expiry)
+    )
+}
+
+/// The fields of a user delegation SAS for a blob, or for the whole container when `path` is empty, in the
+/// order of the string to sign
+struct SasFields<'a> {
+    account: &'a str,
+    container_name: &'a str,
+    path: &'a str,
+    permissions: SasPermissions,
+    expiry: String,
+    key: &'a UserDelegationKey,
+}
+
+impl SasFields<'_> {
+    fn resource(&self) -> &'static str {
+        match self.path.is_empty() {
+            true => "c",
+            false => "b",
+        }
+    }
+
+    fn canonicalized_resource(&self) -> String {
+        match self.path.is_empty() {
+            true => format!("/blob/{}/{}", self.account, self.container_name),
+            false => format!("/blob/{}/{}/{}", self.account, self.container_name, self.path),
+        }
+    }
+
+    /// Permissions, start, expiry, resource, the key's fields, authorized and unauthorized object id, correlation
+    /// id, IP, protocol, version, resource type, snapshot time, encryption scope and the five response headers.
+    /// Fields this crate never sets stay empty lines
+    fn string_to_sign(&self) -> String {
+        let permissions = self.permissions.to_string();
+        let canonicalized_resource = self.canonicalized_resource();
+        let fields = [
+            permissions.as_str(),
+            "",
+            self.expiry.as_str(),
+            canonicalized_resource.as_str(),
+            self.key.signed_oid.as_str(),
+            self.key.signed_tid.as_str(),
+            self.key.signed_start.as_str(),
+            self.key.signed_expiry.as_str(),
+            self.key.signed_service.as_str(),
+            self.key.signed_version.as_str(),
+            "",
+            "",
+            "",
+            "",
+            "https",
+            SAS_VERSION,
+            self.resource(),
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ];
+        fields.join("\n")
+    }
+
+    fn query(&self, signature: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("sv", SAS_VERSION.to_string()),
+            ("sr", self.resource().to_string()),
+            ("sp", self.permissions.to_string()),
+            ("se", self.expiry.clone()),
+            ("skoid", self.key.signed_oid.clone()),
+            ("sktid", self.key.signed_tid.clone()),
+            ("skt", self.key.signed_start.clone()),
+            ("ske", self.key.signed_expiry.clone()),
+            ("sks", self.key.signed_service.clone()),
+            ("skv", self.key.signed_version.clone()),
+            ("spr", "https".to_string()),
+            ("sig", signature.to_string()),
+        ]
+    }
+}
+
+impl AzureStorageBackend {
+    /// A user delegation key valid until at least `expiry`, the cached one unless it expires before
+    pub(crate) async fn user_delegation_key(&self, expiry: OffsetDateTime) -> Result<Arc<UserDelegationKey>, AzureStorageError> {
+        let mut current = self.delegation_keys.current.lock().await;
+        if let Some(key) = current.as_ref().filter(|key| key.is_valid_until(expiry)) {
+            return Ok(Arc::clone(key));
+        }
+        let key_expiry = OffsetDateTime::now_utc() + MAX_KEY_VALIDITY - KEY_VALIDITY_MARGIN;
+        let key = Arc::new(self.request_user_delegation_key(key_expiry.max(expiry)).await?);
+        *current = Some(Arc::clone(&key));
+        Ok(key)
+    }
+
+    /// A new user delegation key valid until `expiry`, requested with the backend's Azure AD credential. Only
+    /// Azure AD may request keys, so a backend signing with an account key sends a bearer token of its own
+    async fn request_user_delegation_key(&self, expiry: OffsetDateTime) -> Result<UserDelegationKey, AzureStorageError> {
+        let mut headers = vec![(VERSION, SAS_VERSION.to_string()), (CONTENT_TYPE, "application/xml".to_string())];
+        if self.account_key.is_some() {
+            let token = self
+                .token_credential
+                .get_token(STORAGE_TOKEN_RESOURCE)
+                .await
+                .map_err(AzureStorageError::Credential)?;
+            headers.push((AUTHORIZATION, format!("Bearer {}", token.token.secret())));
+        }
+        let request = RestRequest::blob(Method::Post, "", "")
+            .query("restype", "service")
+            .query("comp", "userdelegationkey")
+            .headers(headers)
+            .body(key_info(OffsetDateTime::now_utc() - CLOCK_SKEW, expiry));
+        let body = self.send_rest(request).await?.body;
+        let key: UserDelegationKey = azure_core::xml::read_xml(&body).map_err(AzureStorageError::Request)?;
+        self.log(
+            LogLevel::Info,
+            format_args!("Issued a user delegation key for {} valid until {}", self.config.storage_account_url, key.signed_expiry),
+        );
+        Ok(key)
+    }
+
+    /// A URL of the file at `path` that grants `permissions` until `expiry` to whoever holds it, or of the whole
+    /// container when `path` is empty. Signed with a user delegation key of the backend's Azure AD credential, so
+    /// no account key is involved. The key is reused for every signature expiring within its validity of up to 7
+    /// days, so generating many links takes one key request. The principal needs a role with
+    /// `Microsoft.Storage/storageAccounts/blobServices/generateUserDelegationKey`. Expiries beyond 7 days are
+    /// rejected with [`AzureStorageError::InvalidSas`]
+    pub async fn generate_sas(
+        &self,
+        container_name: &str,
+        path: &str,
+        permissions: SasPermissions,
+        expiry: OffsetDateTime,
+    ) -> Result<Url, miette::Error> {
+        let now = OffsetDateTime::now_utc();
+        if expiry <= now || expiry - now > MAX_KEY_VALIDITY {
+            return Err(AzureStorageError::InvalidSas(format!("expiry {} is not within the next 7 days", sas_time(expiry))).into());
+        }
+        let key = self.user_delegation_key(expiry).await?;
+
+        let path = path.trim_matches('/');
+        let fields = SasFields {
+            account: &self.config.storage_account_url,
+            container_name,
+            path,
+            permissions,
+            expiry: sas_time(expiry),
+            key: &key,
+        };
+        let signature = crate::sdk::storage::sign(&fields.string_to_sign(), &key.value).map_err(AzureStorageError::Request)?;
+
+        let mut url = self.blob_url(container_name, path);
+        url.query_pairs_mut().extend_pairs(fields.query(&signature));
+        Ok(url)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    fn key() -> UserDelegationKey {
+        azure_core::xml::read_xml(
+            br#"<?xml version="1.0" encoding="utf-8"?><UserDelegationKey><SignedOid>oid</SignedOid><SignedTid>tid</SignedTid>
+                <SignedStart>2025-10-14T11:55:00Z</SignedStart><SignedExpiry>2025-10-15T12:00:00Z</SignedExpiry>
+                <SignedService>b</SignedService><SignedVersion>2020-12-06</SignedVersion><Value>a2V5</Value></UserDelegationKey>"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_keys_cover_signatures_expiring_before_them() {
+        let key = key();
+        let expiry = OffsetDateTime::parse("2025-10-15T12:00:00Z", &Rfc3339).unwrap();
+        assert!(key.is_valid_until(expiry - Duration::hours(1)));
+        assert!(key.is_valid_until(expiry));
+        assert!(!key.is_valid_until(expiry + Duration::seconds(1)));
+        assert!(!UserDelegationKey { signed_expiry: "soon".to_string(), ..key }.is_valid_until(expiry));
+    }
+
+    #[tokio::test]
+    async fn test_delegation_keys_are_requested_through_the_pipeline() {
+        let service = FakeDataLake::new();
+        let backend = service.backend();
+        let expiry = OffsetDateTime::now_utc() + Duration::hours(1);
+        let url = backend.generate_sas("raw", "data/a.csv", SasPermissions::from_str("r").unwrap(), expiry).await.unwrap();
+        backend.generate_sas("raw", "data/b.csv", SasPermissions::from_str("r").unwrap(), expiry).await.unwrap();
+
+        assert_eq!(url.origin().ascii_serialization(), backend.config.endpoint(crate::sdk::rest::ServiceType::Blob));
+        assert!(url.query_pairs().any(|(name, value)| name == "skoid" && value == "oid"));
+        // the second signature reuses the key
+        assert_eq!(service.requests(), ["POST blob:/?restype=service&comp=userdelegationkey"]);
+    }
+
+    #[test]
+    fn test_permissions_are_written_in_service_order() {
+        let permissions: SasPermissions = "lwr".parse().unwrap();
+        assert_eq!(permissions.to_string(), "rwl");
+        assert!("rx".parse::<SasPermissions>().is_err());
+    }
+
+    #[test]
+    fn test_string_to_sign_follows_the_user_delegation_layout() {
+        let key = key();
+        let fields = SasFields {
+            account: "account",
+            container_name: "raw",
+            path: "data/a.csv",
+            permissions: "r".parse().unwrap(),
+            expiry: sas_time(OffsetDateTime::UNIX_EPOCH + Duration::days(1)),
+            key: &key,
+        };
+        let expected = "r\n\n1970-01-02T00:00:00Z\n/blob/account/raw/data/a.csv\noid\ntid\n2025-10-14T11:55:00Z\n\
+                        2025-10-15T12:00:00Z\nb\n2020-12-06\n\n\n\n\nhttps\n2020-12-06\nb\n\n\n\n\n\n\n";
+        assert_eq!(fields.string_to_sign(), expected);
+
+        let container = SasFields { path: "", ..fields };
+        assert_eq!(container.resource(), "c");
+        assert_eq!(container.canonicalized_resource(), "/blob/account/raw");
+    }
+}
@@ -0,0 +1,11 @@
+//! The Azure SDK release the crate is built against. The rest of the crate takes storage and identity items
+//! from here instead of the SDK crates, so moving to a new, usually breaking, SDK release means adding an
+//! adapter next to `v0_12` behind its own `azure-sdk-*` feature rather than touching every module.
+#[cfg(feature = "azure-sdk-0-12")]
+mod v0_12;
+
+#[cfg(feature = "azure-sdk-0-12")]
+pub(crate) use v0_12::*;
+
+#[cfg(not(feature = "azure-sdk-0-12"))]
+compile_error!("enable exactly one `azure-sdk-*` feature to select the Azure SDK release");
@@ -0,0 +1,48 @@
+//! Adapter for the 0.12 releases of the Azure SDK crates
+
+/// Data lake clients, operations and listing types
+pub(crate) mod datalake {
+    pub(crate) use azure_storage_datalake::file_system::Path;
+    pub(crate) use azure_storage_datalake::prelude::*;
+}
+
+/// Storage account credentials and request signing
+pub(crate) mod storage {
+    pub(crate) use azure_storage::prelude::*;
+    pub(crate) use azure_storage::CloudLocation;
+
+    /// Base64 HMAC-SHA256 of `data` under the base64 account `key`
+    pub(crate) fn sign(data: &str, key: &str) -> azure_core::Result<String> {
+        azure_storage::hmac::sign(data, key)
+    }
+}
+
+/// Requests for the REST operations the clients have no builder for, sent through a pipeline with the clients'
+/// policies and credentials
+pub(crate) mod rest {
+    use azure_core::headers::Headers;
+    use azure_core::{ClientOptions, Method, Request};
+    use bytes::Bytes;
+    use url::Url;
+
+    pub(crate) use azure_core::Pipeline;
+    /// Selects how the pipeline signs a request, put into the context of every request sent through it
+    pub(crate) use azure_storage::clients::ServiceType;
+
+    pub(crate) fn pipeline(options: ClientOptions, credentials: super::storage::StorageCredentials) -> Pipeline {
+        azure_storage::clients::new_pipeline_from_options(options, credentials)
+    }
+
+    /// A request with the `Content-Length`, `x-ms-date` and `x-ms-version` the clients send too
+    pub(crate) fn request(url: Url, method: Method, body: Bytes) -> azure_core::Result<Request> {
+        azure_storage::clients::finalize_request(url, method, Headers::new(), Some(body.into()))
+    }
+}
+
+/// Azure AD credentials
+pub(crate) mod identity {
+    pub(crate) use azure_identity::{
+        AutoRefreshingTokenCredential, AzureCliCredential, ClientSecretCredential, EnvironmentCredential,
+        ImdsManagedIdentityCredential, TokenCredentialOptions,
+    };
+}
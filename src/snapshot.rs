@@ -0,0 +1,122 @@
+//! Read-only point in time copies of files, taken through the Blob REST API before changes that may need undoing
+use std::path::Path;
+
+use azure_core::headers::{HeaderName, CONTENT_RANGE};
+use azure_core::{Method, StatusCode};
+use bytes::{Bytes, BytesMut};
+use tokio::io::AsyncWriteExt;
+
+use crate::backend::AzureStorageBackend;
+use crate::download::DEFAULT_CHUNK_SIZE;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+use crate::rest::RestRequest;
+
+const SNAPSHOT: HeaderName = HeaderName::from_static("x-ms-snapshot");
+const RANGE: HeaderName = HeaderName::from_static("x-ms-range");
+
+/// The size of the whole blob from a `Content-Range` like `bytes 0-99/1234`
+fn total_length(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/')?.1.parse().ok()
+}
+
+impl AzureStorageBackend {
+    /// The chunk of the snapshot starting at `offset` and the size of the whole snapshot, empty past its end
+    async fn read_snapshot_chunk(&self, container_name: &str, path: &str, snapshot: &str, offset: u64) -> Result<(Bytes, u64), AzureStorageError> {
+        let request = RestRequest::blob(Method::Get, container_name, path)
+            .query("snapshot", snapshot)
+            .headers([(RANGE, format!("bytes={}-{}", offset, offset + DEFAULT_CHUNK_SIZE - 1))]);
+        match self.send_rest(request).await {
+            Ok(response) => {
+                let size = response.headers.get_optional_str(&CONTENT_RANGE).and_then(total_length);
+                let size = size.unwrap_or(offset + response.body.len() as u64);
+                Ok((response.body, size))
+            }
+            // an empty snapshot cannot satisfy any range
+            Err(AzureStorageError::Request(error))
+                if offset == 0 && matches!(http_status(&error), Some((StatusCode::RequestedRangeNotSatisfiable, _))) =>
+            {
+                Ok((Bytes::new(), 0))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Takes a snapshot of the file at `path` and returns its timestamp, which names it for
+    /// [`AzureStorageBackend::download_snapshot`]. The snapshot keeps the content and metadata the file has now
+    /// until it is deleted with the file
+    pub async fn create_snapshot(&self, container_name: &str, path: &str) -> Result<String, miette::Error> {
+        let request = RestRequest::blob(Method::Put, container_name, path).query("comp", "snapshot");
+        let headers = self.send_rest(request).await?.headers;
+        let snapshot = headers.get_str(&SNAPSHOT).map_err(AzureStorageError::Request)?.to_string();
+        self.log(LogLevel::Info, format_args!("Took snapshot {} of {}/{}", snapshot, container_name, path));
+        Ok(snapshot)
+    }
+
+    /// The content of the file at `path` as of the snapshot `snapshot`, read in chunks like
+    /// [`AzureStorageBackend::download`]
+    pub async fn download_snapshot(&self, container_name: &str, path: &str, snapshot: &str) -> Result<Bytes, miette::Error> {
+        let (first, size) = self.read_snapshot_chunk(container_name, path, snapshot, 0).await?;
+        let mut content = BytesMut::with_capacity(size as usize);
+        content.extend_from_slice(&first);
+        while (content.len() as u64) < size {
+            let (chunk, _) = self.read_snapshot_chunk(container_name, path, snapshot, content.len() as u64).await?;
+            if chunk.is_empty() {
+                break;
+            }
+            content.extend_from_slice(&chunk);
+        }
+        Ok(content.freeze())
+    }
+
+    /// Writes the content of the file at `path` as of the snapshot `snapshot` to `local_path` one chunk at a time,
+    /// replacing any file there, and returns the number of bytes written
+    pub async fn download_snapshot_to_path(
+        &self,
+        container_name: &str,
+        path: &str,
+        snapshot: &str,
+        local_path: impl AsRef<Path>,
+    ) -> Result<u64, miette::Error> {
+        let local_path = local_path.as_ref();
+        let local_io = |source| AzureStorageError::LocalIo { path: local_path.to_path_buf(), source };
+
+        let mut file = tokio::fs::File::create(local_path).await.map_err(local_io)?;
+        let mut written = 0;
+        loop {
+            let (chunk, size) = self.read_snapshot_chunk(container_name, path, snapshot, written).await?;
+            file.write_all(&chunk).await.map_err(local_io)?;
+            written += chunk.len() as u64;
+            if chunk.is_empty() || written >= size {
+                break;
+            }
+        }
+        file.sync_all().await.map_err(local_io)?;
+        Ok(written)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    #[tokio::test]
+    async fn test_snapshots_are_read_from_the_blob_endpoint() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "content");
+        let backend = service.backend();
+        let content = backend.download_snapshot("raw", "data/a.csv", "2025-10-14T12:00:00.0000000Z").await.unwrap();
+        assert_eq!(&content[..], b"content");
+        assert_eq!(service.requests(), ["GET blob:/raw/data/a.csv?snapshot=2025-10-14T12%3A00%3A00.0000000Z"]);
+    }
+
+    #[test]
+    fn test_the_size_is_read_from_the_content_range() {
+        assert_eq!(total_length("bytes 0-99/1234"), Some(1234));
+        assert_eq!(total_length("bytes */0"), Some(0));
+        assert_eq!(total_length("bytes 0-99"), None);
+    }
+}
@@ -0,0 +1,211 @@
+//! Recovering soft-deleted files and directories of accounts with blob soft delete enabled
+use azure_core::headers::{HeaderName, VERSION};
+use azure_core::Method;
+use futures::{Stream, StreamExt, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::listing::PathEntry;
+use crate::logging::LogLevel;
+use crate::rest::RestRequest;
+
+/// First service version with List Deleted Paths and Undelete Path on hierarchical namespace accounts
+const SOFT_DELETE_VERSION: &str = "2020-06-12";
+const UNDELETE_SOURCE: HeaderName = HeaderName::from_static("x-ms-undelete-source");
+
+/// A file or directory deleted within the retention period, restorable with
+/// [`AzureStorageBackend::undelete_path`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeletedPath {
+    pub path: String,
+    /// Tells apart several deleted paths of the same name
+    pub deletion_id: String,
+    pub is_directory: bool,
+    /// 0 for directories
+    pub size: u64,
+    pub deleted_on: Option<OffsetDateTime>,
+    /// Days until the service removes the path for good
+    pub remaining_retention_days: Option<u32>,
+}
+
+/// An entry of [`AzureStorageBackend::list_with_deleted`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListedPath {
+    Existing(PathEntry),
+    /// Deleted within the retention period, with when and for how long it can still be restored
+    Deleted(DeletedPath),
+}
+
+/// Whether `path` lies below the directory `prefix` and, unless `recursive`, directly in it
+fn is_listed_below(path: &str, prefix: &str, recursive: bool) -> bool {
+    let relative = match prefix.is_empty() {
+        true => Some(path),
+        false => path.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('/')),
+    };
+    relative.is_some_and(|relative| !relative.is_empty() && (recursive || !relative.contains('/')))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeletedPathsPage {
+    #[serde(default)]
+    blobs: DeletedBlobs,
+    #[serde(default)]
+    next_marker: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct DeletedBlobs {
+    #[serde(rename = "Blob", default)]
+    blobs: Vec<DeletedBlob>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeletedBlob {
+    name: String,
+    #[serde(default)]
+    deletion_id: String,
+    properties: DeletedBlobProperties,
+}
+
+#[derive(Deserialize)]
+struct DeletedBlobProperties {
+    #[serde(rename = "Content-Length", default)]
+    content_length: u64,
+    #[serde(rename = "ResourceType", default)]
+    resource_type: Option<String>,
+    #[serde(rename = "DeletedTime", default, with = "azure_core::date::rfc1123::option")]
+    deleted_time: Option<OffsetDateTime>,
+    #[serde(rename = "RemainingRetentionDays", default)]
+    remaining_retention_days: Option<u32>,
+}
+
+impl From<DeletedBlob> for DeletedPath {
+    fn from(blob: DeletedBlob) -> Self {
+        let is_directory = blob.properties.resource_type.as_deref() == Some("directory");
+        Self {
+            path: blob.name,
+            deletion_id: blob.deletion_id,
+            is_directory,
+            size: if is_directory { 0 } else { blob.properties.content_length },
+            deleted_on: blob.properties.deleted_time,
+            remaining_retention_days: blob.properties.remaining_retention_days,
+        }
+    }
+}
+
+fn parse_page(body: &[u8]) -> Result<(Vec<DeletedPath>, Option<String>), azure_core::Error> {
+    let page: DeletedPathsPage = azure_core::xml::read_xml(body)?;
+    let paths = page.blobs.blobs.into_iter().map(DeletedPath::from).collect();
+    Ok((paths, page.next_marker.filter(|marker| !marker.is_empty())))
+}
+
+impl AzureStorageBackend {
+    /// The soft-deleted files and directories below the directory `prefix` at any depth, the whole container when
+    /// it is empty. Lists as empty on accounts without soft delete
+    pub async fn list_deleted_paths(&self, container_name: &str, prefix: &str) -> Result<Vec<DeletedPath>, miette::Error> {
+        let prefix = prefix.trim_matches('/');
+        let mut deleted = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![("showonly", "deleted".to_string())];
+            if !prefix.is_empty() {
+                query.push(("prefix", format!("{}/", prefix)));
+            }
+            if let Some(marker) = marker.take() {
+                query.push(("marker", marker));
+            }
+            let body = self.list_blobs_page(container_name, query, Some(SOFT_DELETE_VERSION)).await?;
+            let (paths, next_marker) = parse_page(&body).map_err(AzureStorageError::Request)?;
+            deleted.extend(paths);
+            match next_marker {
+                Some(next_marker) => marker = Some(next_marker),
+                None => return Ok(deleted),
+            }
+        }
+    }
+
+    /// The files and directories below `prefix` like [`AzureStorageBackend::list`], followed by those deleted
+    /// below it that can still be restored, to audit what is recoverable. The deleted paths are listed once the
+    /// existing ones were consumed
+    pub async fn list_with_deleted(
+        &self,
+        container_name: &str,
+        prefix: &str,
+        recursive: bool,
+    ) -> impl Stream<Item = Result<ListedPath, miette::Error>> {
+        let existing = self.list(container_name, prefix, recursive).await.map_ok(ListedPath::Existing);
+        let backend = self.clone();
+        let container_name = container_name.to_string();
+        let prefix = prefix.trim_matches('/').to_string();
+        let deleted = futures::stream::once(async move {
+            let deleted = backend.list_deleted_paths(&container_name, &prefix).await?;
+            let listed = deleted.into_iter().filter(move |path| is_listed_below(&path.path, &prefix, recursive));
+            Ok::<_, miette::Error>(futures::stream::iter(listed.map(|path| Ok(ListedPath::Deleted(path)))))
+        })
+        .try_flatten();
+        existing.chain(deleted)
+    }
+
+    /// Restores the soft-deleted file or directory `path` deleted as `deletion_id`, a directory with everything
+    /// that was below it. Fails with a 409 when a path of the same name exists again
+    pub async fn undelete_path(&self, container_name: &str, path: &str, deletion_id: &str) -> Result<(), miette::Error> {
+        let path = path.trim_matches('/');
+        let encoded = path.split('/').map(|segment| utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string()).collect::<Vec<_>>();
+        let request = RestRequest::blob(Method::Put, container_name, path).query("comp", "undelete").headers([
+            (VERSION, SOFT_DELETE_VERSION.to_string()),
+            (UNDELETE_SOURCE, format!("{}?deletionid={}", encoded.join("/"), deletion_id)),
+        ]);
+        self.send_rest(request).await?;
+        self.append_positions.forget_below(container_name, path);
+        self.append_positions.forget(container_name, path);
+        self.log(LogLevel::Info, format_args!("Restored {}/{} from deletion {}", container_name, path, deletion_id));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deleted_paths_are_read_from_the_listing() {
+        let body = br#"<?xml version="1.0" encoding="utf-8"?>
+            <EnumerationResults ServiceEndpoint="https://account.blob.core.windows.net/" ContainerName="raw">
+              <Blobs>
+                <Blob><Name>data/a.csv</Name><DeletionId>132549163</DeletionId><Deleted>true</Deleted>
+                  <Properties><Content-Length>10</Content-Length><ResourceType>file</ResourceType>
+                    <DeletedTime>Tue, 14 Oct 2025 12:00:00 GMT</DeletedTime><RemainingRetentionDays>6</RemainingRetentionDays>
+                  </Properties></Blob>
+                <Blob><Name>data/logs</Name><DeletionId>132549164</DeletionId><Deleted>true</Deleted>
+                  <Properties><Content-Length>0</Content-Length><ResourceType>directory</ResourceType></Properties></Blob>
+              </Blobs>
+              <NextMarker>next</NextMarker>
+            </EnumerationResults>"#;
+        let (paths, marker) = parse_page(body).unwrap();
+        assert_eq!(marker.as_deref(), Some("next"));
+        assert_eq!((paths[0].path.as_str(), paths[0].deletion_id.as_str(), paths[0].size), ("data/a.csv", "132549163", 10));
+        assert_eq!(paths[0].remaining_retention_days, Some(6));
+        assert!(paths[0].deleted_on.is_some());
+        assert!(paths[1].is_directory);
+
+        let (paths, marker) = parse_page(br#"<EnumerationResults><Blobs /><NextMarker /></EnumerationResults>"#).unwrap();
+        assert!(paths.is_empty());
+        assert_eq!(marker, None);
+    }
+
+    #[test]
+    fn test_only_paths_below_the_prefix_are_listed() {
+        assert!(is_listed_below("data/a.csv", "data", false));
+        assert!(!is_listed_below("data/logs/a.log", "data", false));
+        assert!(is_listed_below("data/logs/a.log", "data", true));
+        assert!(!is_listed_below("database/a.csv", "data", true));
+        assert!(!is_listed_below("data", "data", true));
+        assert!(is_listed_below("a.csv", "", false));
+    }
+}
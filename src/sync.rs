@@ -0,0 +1,396 @@
+//! Mirroring a local directory into a remote prefix, planned up front so the changes can be reviewed first
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use azure_core::StatusCode;
+use futures::StreamExt;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::{http_status, AzureStorageError};
+use crate::logging::LogLevel;
+use crate::upload::UploadOptions;
+
+/// One side of a conflicting path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileVersion {
+    pub size: u64,
+    pub last_modified: OffsetDateTime,
+}
+
+/// A path both sides changed since the last sync
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub path: String,
+    pub local: FileVersion,
+    pub remote: FileVersion,
+}
+
+/// How a [`SyncConflict`] is settled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Overwrite the remote file with the local one
+    KeepLocal,
+    /// Keep the remote file and leave the local one as it is, the path is reported as skipped
+    KeepRemote,
+    /// Keep the remote file at its path and store the local one next to it under `renamed`
+    KeepBoth { renamed: String },
+    /// Leave both sides as they are and report the path
+    Skip,
+}
+
+/// Decides conflicts for a [`ConflictStrategy::Callback`]
+pub type ConflictCallback = Arc<dyn Fn(&SyncConflict) -> ConflictResolution + Send + Sync>;
+
+/// What a sync does when both sides changed the same path
+#[derive(Clone, Default)]
+pub enum ConflictStrategy {
+    /// The side modified last wins, the remote side on a tie since its timestamp comes from the service clock
+    #[default]
+    NewestWins,
+    RemoteWins,
+    LocalWins,
+    /// Keeps both, the local version is stored as `<stem><suffix>.<ext>` next to the remote one
+    RenameWithSuffix(String),
+    Callback(ConflictCallback),
+}
+
+impl fmt::Debug for ConflictStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictStrategy::NewestWins => f.write_str("NewestWins"),
+            ConflictStrategy::RemoteWins => f.write_str("RemoteWins"),
+            ConflictStrategy::LocalWins => f.write_str("LocalWins"),
+            ConflictStrategy::RenameWithSuffix(suffix) => f.debug_tuple("RenameWithSuffix").field(suffix).finish(),
+            ConflictStrategy::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+impl ConflictStrategy {
+    /// Resolution of `conflict` under this strategy
+    pub fn resolve(&self, conflict: &SyncConflict) -> ConflictResolution {
+        match self {
+            ConflictStrategy::NewestWins if conflict.local.last_modified > conflict.remote.last_modified => {
+                ConflictResolution::KeepLocal
+            }
+            ConflictStrategy::NewestWins => ConflictResolution::KeepRemote,
+            ConflictStrategy::RemoteWins => ConflictResolution::KeepRemote,
+            ConflictStrategy::LocalWins => ConflictResolution::KeepLocal,
+            ConflictStrategy::RenameWithSuffix(suffix) => ConflictResolution::KeepBoth {
+                renamed: suffixed_path(&conflict.path, suffix),
+            },
+            ConflictStrategy::Callback(callback) => callback(conflict),
+        }
+    }
+}
+
+/// How [`AzureStorageBackend::plan_sync`] compares the two sides
+#[derive(Clone, Debug, Default)]
+pub struct SyncOptions {
+    pub(crate) conflict: ConflictStrategy,
+    pub(crate) delete_extraneous: bool,
+}
+
+impl SyncOptions {
+    /// Settles paths that differ between the two sides, newest wins by default
+    pub fn conflict(mut self, conflict: ConflictStrategy) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// Deletes remote files that do not exist locally, off by default
+    pub fn delete_extraneous(mut self, delete_extraneous: bool) -> Self {
+        self.delete_extraneous = delete_extraneous;
+        self
+    }
+}
+
+/// A change a sync makes to the remote side, with remote paths relative to the container
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncAction {
+    /// Uploads a local file to a remote path that does not exist yet
+    Create { path: String, local_path: PathBuf, size: u64 },
+    /// Overwrites a remote file with the local one
+    Update { path: String, local_path: PathBuf, size: u64 },
+    /// Removes a remote file that has no local counterpart
+    Delete { path: String },
+}
+
+/// Everything a sync would change, in path order. Nothing is changed until it is passed to
+/// [`AzureStorageBackend::apply_sync`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    /// Paths left alone because their conflict resolved to the remote side or to skipping
+    pub skipped: Vec<String>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+impl fmt::Display for SyncPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for action in &self.actions {
+            match action {
+                SyncAction::Create { path, size, .. } => writeln!(f, "create {} ({} bytes)", path, size)?,
+                SyncAction::Update { path, size, .. } => writeln!(f, "update {} ({} bytes)", path, size)?,
+                SyncAction::Delete { path } => writeln!(f, "delete {}", path)?,
+            }
+        }
+        for path in &self.skipped {
+            writeln!(f, "skip {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+impl AzureStorageBackend {
+    /// Compares `local_dir` with `prefix` and returns the changes that make the remote side mirror it, without
+    /// changing anything. Local files missing remotely are uploaded, files that are newer locally or differ in size
+    /// are settled by the configured [`ConflictStrategy`]
+    pub async fn plan_sync(
+        &self,
+        container_name: &str,
+        local_dir: impl AsRef<Path>,
+        prefix: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncPlan, miette::Error> {
+        let local_dir = local_dir.as_ref();
+        let prefix = prefix.trim_matches('/');
+        let local = local_files(local_dir).await?;
+        let remote = self.remote_files(container_name, prefix).await?;
+        Ok(plan_actions(local_dir, prefix, &local, &remote, options))
+    }
+
+    /// Carries out `plan` in order, stopping at the first failure. Actions before it stay applied
+    pub async fn apply_sync(&self, container_name: &str, plan: &SyncPlan) -> Result<(), miette::Error> {
+        for action in &plan.actions {
+            match action {
+                SyncAction::Create { path, local_path, .. } | SyncAction::Update { path, local_path, .. } => {
+                    self.upload_from_path(container_name, path, local_path, UploadOptions::default()).await?;
+                }
+                SyncAction::Delete { path } => {
+                    self.file_system_client(container_name)
+                        .await
+                        .get_file_client(path)
+                        .delete()
+                        .await
+                        .map_err(AzureStorageError::Request)?;
+                }
+            }
+            self.log(LogLevel::Info, format_args!("Applied {:?}", action));
+        }
+        Ok(())
+    }
+
+    async fn remote_files(&self, container_name: &str, prefix: &str) -> Result<BTreeMap<String, FileVersion>, AzureStorageError> {
+        let file_system_client = self.file_system_client(container_name).await;
+        let mut list_paths = file_system_client.list_paths().recursive(true);
+        if !prefix.is_empty() {
+            list_paths = list_paths.directory(prefix.to_string());
+        }
+
+        let mut files = BTreeMap::new();
+        let mut pages = list_paths.into_stream();
+        while let Some(page) = pages.next().await {
+            let page = match page {
+                Ok(page) => page,
+                Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => break,
+                Err(error) => return Err(error.into()),
+            };
+            for path in page.paths.into_iter().filter(|path| !path.is_directory) {
+                let relative = match prefix.is_empty() {
+                    true => path.name.as_str(),
+                    false => path.name.strip_prefix(prefix).unwrap_or(&path.name).trim_start_matches('/'),
+                };
+                let version = FileVersion {
+                    size: path.content_length.max(0) as u64,
+                    last_modified: path.last_modified,
+                };
+                files.insert(relative.to_string(), version);
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Every file below `root` by its `/` separated path relative to it
+async fn local_files(root: &Path) -> Result<BTreeMap<String, FileVersion>, AzureStorageError> {
+    let local_io = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| AzureStorageError::LocalIo { path, source }
+    };
+
+    let mut files = BTreeMap::new();
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let mut entries = tokio::fs::read_dir(&directory).await.map_err(local_io(&directory))?;
+        while let Some(entry) = entries.next_entry().await.map_err(local_io(&directory))? {
+            let path = entry.path();
+            let metadata = entry.metadata().await.map_err(local_io(&path))?;
+            if metadata.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(root).expect("entries are below the root");
+            let relative: Vec<String> = relative.components().map(|component| component.as_os_str().to_string_lossy().into_owned()).collect();
+            let version = FileVersion {
+                size: metadata.len(),
+                last_modified: metadata.modified().map_err(local_io(&path))?.into(),
+            };
+            files.insert(relative.join("/"), version);
+        }
+    }
+    Ok(files)
+}
+
+fn plan_actions(
+    local_dir: &Path,
+    prefix: &str,
+    local: &BTreeMap<String, FileVersion>,
+    remote: &BTreeMap<String, FileVersion>,
+    options: &SyncOptions,
+) -> SyncPlan {
+    let remote_path = |path: &str| match prefix.is_empty() {
+        true => path.to_string(),
+        false => format!("{}/{}", prefix, path),
+    };
+    let local_path = |path: &str| path.split('/').fold(local_dir.to_path_buf(), |local_path, segment| local_path.join(segment));
+
+    let mut plan = SyncPlan::default();
+    for (path, version) in local {
+        let Some(remote_version) = remote.get(path) else {
+            plan.actions.push(SyncAction::Create { path: remote_path(path), local_path: local_path(path), size: version.size });
+            continue;
+        };
+        if version.size == remote_version.size && version.last_modified <= remote_version.last_modified {
+            continue;
+        }
+
+        let conflict = SyncConflict {
+            path: path.clone(),
+            local: version.clone(),
+            remote: remote_version.clone(),
+        };
+        match options.conflict.resolve(&conflict) {
+            ConflictResolution::KeepLocal => {
+                plan.actions.push(SyncAction::Update { path: remote_path(path), local_path: local_path(path), size: version.size })
+            }
+            ConflictResolution::KeepBoth { renamed } => {
+                plan.actions.push(SyncAction::Create { path: remote_path(&renamed), local_path: local_path(path), size: version.size })
+            }
+            ConflictResolution::KeepRemote | ConflictResolution::Skip => plan.skipped.push(remote_path(path)),
+        }
+    }
+    if options.delete_extraneous {
+        for path in remote.keys().filter(|path| !local.contains_key(*path)) {
+            plan.actions.push(SyncAction::Delete { path: remote_path(path) });
+        }
+    }
+    plan
+}
+
+/// `reports/q1.csv` with suffix `.local` becomes `reports/q1.local.csv`
+fn suffixed_path(path: &str, suffix: &str) -> String {
+    let (directory, file_name) = match path.rfind('/') {
+        Some(index) => path.split_at(index + 1),
+        None => ("", path),
+    };
+    match file_name.rfind('.') {
+        Some(index) if index > 0 => {
+            let (stem, extension) = file_name.split_at(index);
+            format!("{}{}{}{}", directory, stem, suffix, extension)
+        }
+        _ => format!("{}{}{}", directory, file_name, suffix),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::Duration;
+
+    fn conflict(local_age: i64, remote_age: i64) -> SyncConflict {
+        let now = OffsetDateTime::now_utc();
+        SyncConflict {
+            path: "reports/q1.csv".to_string(),
+            local: FileVersion { size: 10, last_modified: now - Duration::seconds(local_age) },
+            remote: FileVersion { size: 12, last_modified: now - Duration::seconds(remote_age) },
+        }
+    }
+
+    #[test]
+    fn test_newest_wins() {
+        assert_eq!(ConflictStrategy::NewestWins.resolve(&conflict(1, 60)), ConflictResolution::KeepLocal);
+        assert_eq!(ConflictStrategy::NewestWins.resolve(&conflict(60, 1)), ConflictResolution::KeepRemote);
+    }
+
+    #[test]
+    fn test_rename_and_callback() {
+        assert_eq!(
+            ConflictStrategy::RenameWithSuffix(".local".to_string()).resolve(&conflict(1, 1)),
+            ConflictResolution::KeepBoth { renamed: "reports/q1.local.csv".to_string() }
+        );
+        assert_eq!(suffixed_path("Makefile", "-conflict"), "Makefile-conflict");
+
+        let larger_wins = ConflictStrategy::Callback(Arc::new(|conflict: &SyncConflict| {
+            if conflict.local.size > conflict.remote.size { ConflictResolution::KeepLocal } else { ConflictResolution::Skip }
+        }));
+        assert_eq!(larger_wins.resolve(&conflict(1, 1)), ConflictResolution::Skip);
+    }
+
+    #[test]
+    fn test_plan_lists_every_change() {
+        let now = OffsetDateTime::now_utc();
+        let version = |size, age| FileVersion { size, last_modified: now - Duration::seconds(age) };
+        let local = BTreeMap::from([
+            ("new.csv".to_string(), version(1, 0)),
+            ("changed.csv".to_string(), version(2, 0)),
+            ("same.csv".to_string(), version(3, 60)),
+            ("reports/q1.csv".to_string(), version(4, 60)),
+        ]);
+        let remote = BTreeMap::from([
+            ("changed.csv".to_string(), version(2, 60)),
+            ("same.csv".to_string(), version(3, 0)),
+            ("reports/q1.csv".to_string(), version(5, 0)),
+            ("gone.csv".to_string(), version(6, 0)),
+        ]);
+
+        let options = SyncOptions::default().delete_extraneous(true);
+        let plan = plan_actions(Path::new("data"), "backup", &local, &remote, &options);
+        assert_eq!(
+            plan.actions,
+            vec![
+                SyncAction::Update { path: "backup/changed.csv".to_string(), local_path: PathBuf::from("data/changed.csv"), size: 2 },
+                SyncAction::Create { path: "backup/new.csv".to_string(), local_path: PathBuf::from("data/new.csv"), size: 1 },
+                SyncAction::Delete { path: "backup/gone.csv".to_string() },
+            ]
+        );
+        assert_eq!(plan.skipped, vec!["backup/reports/q1.csv".to_string()]);
+        assert_eq!(plan.to_string().lines().next(), Some("update backup/changed.csv (2 bytes)"));
+
+        let options = options.conflict(ConflictStrategy::RenameWithSuffix(".local".to_string()));
+        let plan = plan_actions(Path::new("data"), "", &local, &remote, &options);
+        assert!(plan.actions.contains(&SyncAction::Create {
+            path: "reports/q1.local.csv".to_string(),
+            local_path: PathBuf::from("data/reports/q1.csv"),
+            size: 4,
+        }));
+
+        let options = SyncOptions::default().conflict(ConflictStrategy::RemoteWins);
+        let plan = plan_actions(Path::new("data"), "", &local, &remote, &options);
+        assert_eq!(
+            plan.actions,
+            vec![SyncAction::Create { path: "new.csv".to_string(), local_path: PathBuf::from("data/new.csv"), size: 1 }]
+        );
+        assert_eq!(plan.skipped, vec!["changed.csv".to_string(), "reports/q1.csv".to_string()]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Blob index tags, key value pairs on files the service indexes so files can be found by tag across the account
+use std::collections::BTreeMap;
+
+use azure_core::headers::CONTENT_TYPE;
+use azure_core::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::rest::RestRequest;
+
+/// Index tags by key. A file has at most 10, keys and values are case sensitive
+pub type Tags = BTreeMap<String, String>;
+
+/// A file found by [`AzureStorageBackend::find_by_tags`], with the tags the query matched on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaggedFile {
+    pub container_name: String,
+    pub path: String,
+    pub tags: Tags,
+}
+
+/// The `Tags` document of Set and Get Blob Tags
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename = "Tags")]
+struct TagsDocument {
+    #[serde(rename = "TagSet", default)]
+    tag_set: TagSet,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TagSet {
+    #[serde(rename = "Tag", default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tag {
+    #[serde(rename = "Key")]
+    key: String,
+    #[serde(rename = "Value")]
+    value: String,
+}
+
+impl From<&Tags> for TagsDocument {
+    fn from(tags: &Tags) -> Self {
+        let tags = tags.iter().map(|(key, value)| Tag {
+            key: key.clone(),
+            value: value.clone(),
+        });
+        Self {
+            tag_set: TagSet { tags: tags.collect() },
+        }
+    }
+}
+
+impl From<TagsDocument> for Tags {
+    fn from(document: TagsDocument) -> Self {
+        document.tag_set.tags.into_iter().map(|tag| (tag.key, tag.value)).collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FoundBlobsPage {
+    #[serde(default)]
+    blobs: FoundBlobs,
+    #[serde(default)]
+    next_marker: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct FoundBlobs {
+    #[serde(rename = "Blob", default)]
+    blobs: Vec<FoundBlob>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FoundBlob {
+    name: String,
+    container_name: String,
+    #[serde(default)]
+    tags: TagsDocument,
+}
+
+/// The files of a page of Find Blobs by Tags and the marker of the next page
+fn parse_found(body: &[u8]) -> Result<(Vec<TaggedFile>, Option<String>), azure_core::Error> {
+    let page: FoundBlobsPage = azure_core::xml::read_xml(body)?;
+    let found = page.blobs.blobs.into_iter().map(|blob| TaggedFile {
+        container_name: blob.container_name,
+        path: blob.name,
+        tags: blob.tags.into(),
+    });
+    Ok((found.collect(), page.next_marker.filter(|marker| !marker.is_empty())))
+}
+
+impl AzureStorageBackend {
+    /// Replaces all index tags of the file at `path` with `tags`, e.g. after uploading it, so it can be found
+    /// with [`AzureStorageBackend::find_by_tags`]. Needs an account with blob index tags available, which
+    /// rejects the request otherwise
+    pub async fn set_tags(&self, container_name: &str, path: &str, tags: &Tags) -> Result<(), miette::Error> {
+        let body = azure_core::xml::to_xml(&TagsDocument::from(tags)).map_err(AzureStorageError::Request)?;
+        let request = RestRequest::blob(Method::Put, container_name, path)
+            .query("comp", "tags")
+            .headers([(CONTENT_TYPE, "application/xml".to_string())])
+            .body(body);
+        self.send_rest(request).await?;
+        Ok(())
+    }
+
+    /// The index tags of the file at `path`, empty when it has none
+    pub async fn get_tags(&self, container_name: &str, path: &str) -> Result<Tags, miette::Error> {
+        let body = self.send_rest(RestRequest::blob(Method::Get, container_name, path).query("comp", "tags")).await?.body;
+        let document: TagsDocument = azure_core::xml::read_xml(&body).map_err(AzureStorageError::Request)?;
+        Ok(document.into())
+    }
+
+    /// The files of every container whose index tags match `expression`, e.g.
+    /// `"project" = 'alpha' AND "date" >= '2025-10-01'`. The index is updated shortly after tags change, so
+    /// files tagged a moment ago may be missing
+    pub async fn find_by_tags(&self, expression: &str) -> Result<Vec<TaggedFile>, miette::Error> {
+        let mut found = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut request = RestRequest::blob(Method::Get, "", "").query("comp", "blobs").query("where", expression);
+            if let Some(marker) = marker.take() {
+                request = request.query("marker", marker);
+            }
+            let body = self.send_rest(request).await?.body;
+            let (files, next_marker) = parse_found(&body).map_err(AzureStorageError::Request)?;
+            found.extend(files);
+            match next_marker {
+                Some(next_marker) => marker = Some(next_marker),
+                None => return Ok(found),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_round_trip_through_the_tags_document() {
+        let tags = Tags::from([
+            ("date".to_string(), "2025-10-14".to_string()),
+            ("project".to_string(), "a&b".to_string()),
+        ]);
+        let xml = azure_core::xml::to_xml(&TagsDocument::from(&tags)).unwrap();
+        assert_eq!(
+            xml,
+            "<Tags><TagSet><Tag><Key>date</Key><Value>2025-10-14</Value></Tag><Tag><Key>project</Key><Value>a&amp;b</Value></Tag></TagSet></Tags>"
+        );
+        let document: TagsDocument = azure_core::xml::read_xml(xml.as_bytes()).unwrap();
+        assert_eq!(Tags::from(document), tags);
+    }
+
+    #[test]
+    fn test_found_files_carry_their_container() {
+        let body = br#"<?xml version="1.0" encoding="utf-8"?>
+            <EnumerationResults ServiceEndpoint="https://account.blob.core.windows.net/">
+              <Where>"project" = 'alpha'</Where>
+              <Blobs>
+                <Blob><Name>data/a.csv</Name><ContainerName>raw</ContainerName>
+                  <Tags><TagSet><Tag><Key>project</Key><Value>alpha</Value></Tag></TagSet></Tags></Blob>
+              </Blobs>
+              <NextMarker>next</NextMarker>
+            </EnumerationResults>"#;
+        let (found, marker) = parse_found(body).unwrap();
+        assert_eq!(marker.as_deref(), Some("next"));
+        assert_eq!((found[0].container_name.as_str(), found[0].path.as_str()), ("raw", "data/a.csv"));
+        assert_eq!(found[0].tags.get("project").map(String::as_str), Some("alpha"));
+    }
+}
@@ -0,0 +1,146 @@
+//! Following files other writers append to, such as logs, as their content grows
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_core::prelude::Range;
+use bytes::Bytes;
+use futures::Stream;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::logging::{LogLevel, LogSettings};
+use crate::sdk::datalake::*;
+
+const DEFAULT_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Where and how often a tail looks for new content
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TailOptions {
+    pub(crate) poll_interval: Duration,
+    pub(crate) offset: Option<u64>,
+    pub(crate) chunk_size: u64,
+}
+
+impl Default for TailOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            offset: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+impl TailOptions {
+    /// How long to wait for new content once everything committed was read, 1s by default
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Byte the tail starts at, e.g. `0` to read the file from the beginning. By default only content committed
+    /// after the tail started is yielded
+    pub fn from_offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Largest chunk yielded, 4 MiB by default. Content that arrived since the last poll is read in chunks of
+    /// this size without waiting in between
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+struct TailState {
+    file_client: FileClient,
+    path: String,
+    log_settings: Arc<LogSettings>,
+    chunk_size: u64,
+    /// `None` until the starting point is known
+    offset: Option<u64>,
+    ticker: Interval,
+}
+
+impl TailState {
+    /// The next range to read if the file grew past the offset
+    async fn poll(&mut self) -> Result<Option<Range>, AzureStorageError> {
+        let properties = self.file_client.get_properties().await?;
+        let size = properties.content_length.unwrap_or_default().max(0) as u64;
+        let offset = *self.offset.get_or_insert(size);
+        if size < offset {
+            self.log_settings.event(LogLevel::Info, format_args!("{} shrank to {} bytes, following it from the start", self.path, size));
+        }
+        Ok(next_range(offset, size, self.chunk_size))
+    }
+}
+
+/// The range after `offset` of a file that is `size` bytes long, from the start again when the file shrank
+/// because it was replaced or truncated
+fn next_range(offset: u64, size: u64, chunk_size: u64) -> Option<Range> {
+    let offset = match size < offset {
+        true => 0,
+        false => offset,
+    };
+    (size > offset).then(|| Range::new(offset, size.min(offset + chunk_size)))
+}
+
+impl AzureStorageBackend {
+    /// Yields the content appended to the file at `path` as other writers commit it, like `tail -f`. Checks the
+    /// size of the file every [`TailOptions::poll_interval`] and reads what was added since the last check.
+    /// Failures are yielded as errors and the tail carries on at the same offset
+    pub async fn tail(&self, container_name: &str, path: &str, options: TailOptions) -> impl Stream<Item = Result<Bytes, miette::Error>> {
+        let mut ticker = tokio::time::interval(options.poll_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let state = TailState {
+            file_client: self.file_system_client(container_name).await.get_file_client(path),
+            path: path.to_string(),
+            log_settings: Arc::clone(&self.log_settings),
+            chunk_size: options.chunk_size,
+            offset: options.offset,
+            ticker,
+        };
+
+        futures::stream::unfold(state, |mut state| async move {
+            loop {
+                let range = match state.poll().await {
+                    Ok(Some(range)) => range,
+                    Ok(None) => {
+                        state.ticker.tick().await;
+                        continue;
+                    }
+                    Err(error) => {
+                        state.ticker.tick().await;
+                        return Some((Err(error.into()), state));
+                    }
+                };
+                match state.file_client.read().range(range).await {
+                    Ok(response) => {
+                        state.offset = Some(range.start + response.data.len() as u64);
+                        return Some((Ok(response.data), state));
+                    }
+                    Err(error) => {
+                        state.ticker.tick().await;
+                        return Some((Err(AzureStorageError::Request(error).into()), state));
+                    }
+                }
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_reads_what_was_appended() {
+        assert_eq!(next_range(10, 10, 4), None);
+        assert_eq!(next_range(10, 12, 4), Some(Range::new(10, 12)));
+        assert_eq!(next_range(10, 20, 4), Some(Range::new(10, 14)));
+        assert_eq!(next_range(10, 3, 4), Some(Range::new(0, 3)));
+    }
+}
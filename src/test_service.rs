@@ -0,0 +1,561 @@
+//! An in-memory data lake answering the requests of a backend in tests, so operations can be exercised without an
+//! account, including interleavings a real service only produces by chance
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use azure_core::auth::{TokenCredential, TokenResponse};
+use azure_core::error::{Error, ErrorKind};
+use azure_core::headers::{HeaderName, Headers, LEASE_ACTION, LEASE_ID, PROPOSED_LEASE_ID};
+use azure_core::{BytesStream, ClientOptions, Context, Method, Policy, PolicyResult, Request, Response, RetryOptions, StatusCode, TransportOptions};
+use bytes::Bytes;
+use percent_encoding::percent_decode_str;
+use tokio::sync::{Notify, RwLock};
+
+use crate::backend::{AzureStorageBackend, AzureStorageBackendBuilder, ClientTokenCredential};
+use crate::context_headers::ContextHeadersPolicy;
+use crate::handle::{ClientParts, HandleOptions};
+use crate::logging::{LogLevel, LogSettings};
+use crate::sdk::datalake::*;
+use crate::sdk::rest;
+use crate::sdk::storage::StorageCredentials;
+
+const IF_MATCH: HeaderName = HeaderName::from_static("if-match");
+const IF_NONE_MATCH: HeaderName = HeaderName::from_static("if-none-match");
+const RENAME_SOURCE: HeaderName = HeaderName::from_static("x-ms-rename-source");
+const PROPERTIES: HeaderName = HeaderName::from_static("x-ms-properties");
+const COPY_SOURCE: HeaderName = HeaderName::from_static("x-ms-copy-source");
+
+#[derive(Clone, Debug, Default)]
+struct FakePath {
+    content: Vec<u8>,
+    /// Appended but not flushed yet, by position
+    appended: BTreeMap<u64, Bytes>,
+    etag: String,
+    is_directory: bool,
+    properties: Option<String>,
+    /// Id of the lease held on the path
+    lease: Option<String>,
+}
+
+/// A request held until the test lets it through
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Hold {
+    reached: Arc<Notify>,
+    released: Arc<Notify>,
+}
+
+impl Hold {
+    /// Waits until the held request arrived
+    pub(crate) async fn reached(&self) {
+        self.reached.notified().await
+    }
+
+    pub(crate) fn release(&self) {
+        self.released.notify_one()
+    }
+}
+
+type Matcher = Box<dyn Fn(&Method, &str) -> bool + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    paths: HashMap<String, FakePath>,
+    requests: Vec<String>,
+    hold: Option<(Matcher, Hold)>,
+    failures: Vec<(Matcher, StatusCode)>,
+    next_etag: u64,
+}
+
+impl State {
+    fn etag(&mut self) -> String {
+        self.next_etag += 1;
+        format!("\"0x8DB{:012X}\"", self.next_etag)
+    }
+}
+
+/// Answers requests like a hierarchical namespace account: files with etags, conditions and leases, appends committed
+/// by flushes, renames and listings. Of the blob endpoint only Copy Blob changes files, reads of a blob answer with
+/// the current content whatever snapshot or version they select, and the other blob operations are acknowledged
+/// without changing anything. Every request answers asynchronously, as over a network
+#[derive(Default)]
+pub(crate) struct FakeDataLake {
+    state: Mutex<State>,
+}
+
+impl std::fmt::Debug for FakeDataLake {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeDataLake").finish_non_exhaustive()
+    }
+}
+
+/// The fake's answer
+struct Answer {
+    status: StatusCode,
+    headers: Headers,
+    body: Bytes,
+}
+
+impl Answer {
+    fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    fn error(status: StatusCode, code: &str) -> Self {
+        let mut answer = Self::new(status);
+        answer.headers.insert("x-ms-error-code", code.to_string());
+        answer.body = Bytes::from(format!(r#"{{"error":{{"code":"{}","message":"{}"}}}}"#, code, code));
+        answer
+    }
+
+    fn with_path(mut self, path: &FakePath) -> Self {
+        self.headers.insert("etag", path.etag.clone());
+        self.headers.insert("last-modified", azure_core::date::to_rfc1123(&time::OffsetDateTime::now_utc()));
+        self
+    }
+}
+
+/// Whether the conditions of `request` hold for `path`, the answer when they do not
+fn check_conditions(request: &Request, path: Option<&FakePath>) -> Option<Answer> {
+    let etag = path.map(|path| path.etag.as_str());
+    if let Some(expected) = request.headers().get_optional_str(&IF_MATCH) {
+        if etag.is_none() || (expected != "*" && Some(expected) != etag) {
+            return Some(Answer::error(StatusCode::PreconditionFailed, "ConditionNotMet"));
+        }
+    }
+    if let Some(unexpected) = request.headers().get_optional_str(&IF_NONE_MATCH) {
+        match (unexpected, etag) {
+            ("*", Some(_)) => return Some(Answer::error(StatusCode::Conflict, "PathAlreadyExists")),
+            (unexpected, Some(etag)) if unexpected == etag => {
+                let status = if *request.method() == Method::Get { StatusCode::NotModified } else { StatusCode::PreconditionFailed };
+                return Some(Answer::error(status, "ConditionNotMet"));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether `request` sends the lease id a write to `path` needs, the answer when it does not
+fn check_lease(request: &Request, path: Option<&FakePath>) -> Option<Answer> {
+    let sent = request.headers().get_optional_str(&LEASE_ID);
+    match (path.and_then(|path| path.lease.as_deref()), sent) {
+        (Some(_), None) => Some(Answer::error(StatusCode::PreconditionFailed, "LeaseIdMissing")),
+        (Some(held), Some(sent)) if held != sent => Some(Answer::error(StatusCode::PreconditionFailed, "LeaseIdMismatchWithBlobOperation")),
+        (None, Some(_)) => Some(Answer::error(StatusCode::PreconditionFailed, "LeaseNotPresentWithBlobOperation")),
+        _ => None,
+    }
+}
+
+fn decode(path: &str) -> String {
+    percent_decode_str(path).decode_utf8_lossy().trim_start_matches('/').to_string()
+}
+
+impl FakeDataLake {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// A backend whose requests this fake answers, with the policies that shape requests but without retries
+    pub(crate) fn backend(self: &Arc<Self>) -> AzureStorageBackend {
+        let account = format!("fake{}", uuid::Uuid::new_v4().simple());
+        let mut client_options = ClientOptions::default()
+            .retry(RetryOptions::none())
+            .transport(TransportOptions::new_custom_policy(Arc::clone(self) as Arc<dyn Policy>));
+        client_options.per_retry_policies_mut().push(Arc::new(ContextHeadersPolicy));
+        let storage_credentials = StorageCredentials::anonymous();
+        let data_lake_client = DataLakeClient::builder(account.clone(), storage_credentials.clone())
+            .client_options(client_options.clone())
+            .build();
+        let client = Arc::new(RwLock::new(data_lake_client));
+        let pipeline = rest::pipeline(client_options.clone(), storage_credentials.clone());
+        AzureStorageBackend {
+            client: Arc::clone(&client),
+            token_credential: Arc::new(ClientTokenCredential::new(Arc::new(NoCredential))),
+            account_key: None,
+            credential_diagnostics: Arc::default(),
+            config: AzureStorageBackendBuilder::new(account.clone()).disable_throttle(),
+            governor: None,
+            log_settings: Arc::new(LogSettings::new(account, LogLevel::default(), false)),
+            append_positions: Arc::default(),
+            delegation_keys: Arc::default(),
+            provenance: Arc::default(),
+            pipeline: pipeline.clone(),
+            parts: Arc::new(ClientParts {
+                client,
+                pipeline,
+                storage_credentials,
+                client_options,
+            }),
+            options: HandleOptions::default(),
+        }
+    }
+
+    /// Stores `content` at `path`, `container/dir/file`, as a committed file
+    pub(crate) fn put_file(&self, path: &str, content: impl Into<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        let etag = state.etag();
+        state.paths.insert(path.to_string(), FakePath { content: content.into(), etag, ..FakePath::default() });
+    }
+
+    /// The committed content of the file at `path`
+    pub(crate) fn file(&self, path: &str) -> Option<Bytes> {
+        let state = self.state.lock().unwrap();
+        state.paths.get(path).filter(|path| !path.is_directory).map(|path| Bytes::from(path.content.clone()))
+    }
+
+    /// Every request answered so far as `METHOD /container/path?query`, with `blob:` before the path for requests to
+    /// the blob endpoint
+    pub(crate) fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub(crate) fn etag(&self, path: &str) -> Option<String> {
+        self.state.lock().unwrap().paths.get(path).map(|path| path.etag.clone())
+    }
+
+    /// The paths the fake holds, directories included, in name order
+    pub(crate) fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.state.lock().unwrap().paths.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Holds the next request for which `matches` holds with its method and `/container/path?query` until
+    /// [`Hold::release`] is called
+    pub(crate) fn hold_next(&self, matches: impl Fn(&Method, &str) -> bool + Send + Sync + 'static) -> Hold {
+        let hold = Hold::default();
+        self.state.lock().unwrap().hold = Some((Box::new(matches), hold.clone()));
+        hold
+    }
+
+    /// Answers every request for which `matches` holds with `status`
+    pub(crate) fn fail(&self, matches: impl Fn(&Method, &str) -> bool + Send + Sync + 'static, status: StatusCode) {
+        self.state.lock().unwrap().failures.push((Box::new(matches), status));
+    }
+
+    fn answer(&self, request: &Request) -> Answer {
+        let url = request.url();
+        let target = format!("{}?{}", url.path(), url.query().unwrap_or_default());
+        let mut state = self.state.lock().unwrap();
+        let blob_endpoint = url.host_str().is_some_and(|host| host.contains(".blob."));
+        state.requests.push(format!("{} {}{}", request.method(), if blob_endpoint { "blob:" } else { "" }, target));
+        if let Some((_, status)) = state.failures.iter().find(|(matches, _)| matches(request.method(), &target)) {
+            return Answer::error(*status, "InjectedFailure");
+        }
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let key = decode(url.path());
+        let is_container = !key.contains('/');
+        if blob_endpoint {
+            return blob(&mut state, request, &key, is_container);
+        }
+        match (request.method(), is_container) {
+            (&Method::Get, true) => list(&state, &key, &query),
+            (&Method::Put, true) => Answer::new(StatusCode::Created),
+            (&Method::Put, false) => match request.headers().get_optional_str(&RENAME_SOURCE) {
+                Some(source) => rename(&mut state, request, &decode(source.split('?').next().unwrap_or_default()), &key),
+                None => create(&mut state, request, &key, query.get("resource").map(String::as_str) == Some("directory")),
+            },
+            (&Method::Patch, false) => patch(&mut state, request, &key, &query),
+            (&Method::Post, false) => lease(&mut state, request, &key),
+            (&Method::Get, false) => match state.paths.get(&key) {
+                None => Answer::error(StatusCode::NotFound, "PathNotFound"),
+                Some(path) => match check_conditions(request, Some(path)) {
+                    Some(answer) => answer,
+                    None if path.content.is_empty() => Answer::error(StatusCode::RequestedRangeNotSatisfiable, "InvalidRange"),
+                    None => {
+                        let mut answer = Answer::new(StatusCode::Ok).with_path(path);
+                        answer.body = Bytes::from(path.content.clone());
+                        answer
+                    }
+                },
+            },
+            (&Method::Head, false) => match state.paths.get(&key) {
+                None => Answer::new(StatusCode::NotFound),
+                Some(path) => {
+                    let mut answer = Answer::new(StatusCode::Ok).with_path(path);
+                    answer.headers.insert("content-length", path.content.len().to_string());
+                    answer.headers.insert("x-ms-resource-type", if path.is_directory { "directory" } else { "file" });
+                    if let Some(properties) = &path.properties {
+                        answer.headers.insert(PROPERTIES, properties.clone());
+                    }
+                    answer
+                }
+            },
+            (&Method::Delete, false) => match check_conditions(request, state.paths.get(&key)).or_else(|| check_lease(request, state.paths.get(&key))) {
+                Some(answer) => answer,
+                None => match state.paths.remove(&key) {
+                    None => Answer::error(StatusCode::NotFound, "PathNotFound"),
+                    Some(_) => {
+                        state.paths.retain(|path, _| !path.starts_with(&format!("{}/", key)));
+                        Answer::new(StatusCode::Ok)
+                    }
+                },
+            },
+            _ => Answer::error(StatusCode::BadRequest, "UnsupportedOperation"),
+        }
+    }
+
+    /// The hold `request` has to wait for, taken so later requests pass
+    fn take_hold(&self, request: &Request) -> Option<Hold> {
+        let url = request.url();
+        let target = format!("{}?{}", url.path(), url.query().unwrap_or_default());
+        let mut state = self.state.lock().unwrap();
+        match &state.hold {
+            Some((matches, _)) if matches(request.method(), &target) => state.hold.take().map(|(_, hold)| hold),
+            _ => None,
+        }
+    }
+}
+
+fn create(state: &mut State, request: &Request, key: &str, is_directory: bool) -> Answer {
+    if let Some(answer) = check_conditions(request, state.paths.get(key)).or_else(|| check_lease(request, state.paths.get(key))) {
+        return answer;
+    }
+    create_parents(state, key);
+    let path = FakePath {
+        etag: state.etag(),
+        is_directory,
+        properties: request.headers().get_optional_string(&PROPERTIES),
+        // replacing a leased file keeps the lease
+        lease: state.paths.get(key).and_then(|path| path.lease.clone()),
+        ..FakePath::default()
+    };
+    let answer = Answer::new(StatusCode::Created).with_path(&path);
+    state.paths.insert(key.to_string(), path);
+    answer
+}
+
+/// Adds the directories above `key` that are missing, like the service does when creating a path
+fn create_parents(state: &mut State, key: &str) {
+    let mut parent = key;
+    while let Some((directory, _)) = parent.rsplit_once('/') {
+        if directory.contains('/') && !state.paths.contains_key(directory) {
+            let etag = state.etag();
+            state.paths.insert(directory.to_string(), FakePath { etag, is_directory: true, ..FakePath::default() });
+        }
+        parent = directory;
+    }
+}
+
+fn rename(state: &mut State, request: &Request, source: &str, destination: &str) -> Answer {
+    if let Some(answer) = check_conditions(request, state.paths.get(destination)).or_else(|| check_lease(request, state.paths.get(destination))) {
+        return answer;
+    }
+    // unlike a create, a rename does not add the directories above the destination
+    let parent = destination.rsplit_once('/').map(|(parent, _)| parent).filter(|parent| parent.contains('/'));
+    if parent.is_some_and(|parent| !state.paths.get(parent).is_some_and(|parent| parent.is_directory)) {
+        return Answer::error(StatusCode::NotFound, "RenameDestinationParentPathNotFound");
+    }
+    let Some(mut path) = state.paths.remove(source) else {
+        return Answer::error(StatusCode::NotFound, "SourcePathNotFound");
+    };
+    path.lease = state.paths.get(destination).and_then(|destination| destination.lease.clone());
+    let answer = Answer::new(StatusCode::Created).with_path(&path);
+    state.paths.insert(destination.to_string(), path);
+    answer
+}
+
+fn blob(state: &mut State, request: &Request, key: &str, is_container: bool) -> Answer {
+    if request.url().query_pairs().any(|(name, value)| name == "comp" && value == "userdelegationkey") {
+        return user_delegation_key(request);
+    }
+    if let Some(source) = request.headers().get_optional_str(&COPY_SOURCE) {
+        let source = url::Url::parse(source).map(|source| decode(source.path())).unwrap_or_default();
+        let Some(content) = state.paths.get(&source).map(|path| path.content.clone()) else {
+            return Answer::error(StatusCode::NotFound, "CannotVerifyCopySource");
+        };
+        create_parents(state, key);
+        let path = FakePath { content, etag: state.etag(), ..FakePath::default() };
+        let mut answer = Answer::new(StatusCode::Accepted).with_path(&path);
+        answer.headers.insert("x-ms-copy-id", uuid::Uuid::new_v4().to_string());
+        answer.headers.insert("x-ms-copy-status", "success");
+        state.paths.insert(key.to_string(), path);
+        return answer;
+    }
+    let is_operation = request.url().query_pairs().any(|(name, _)| name == "comp" || name == "restype");
+    let is_listing = request.url().query_pairs().any(|(name, value)| name == "comp" && value == "list");
+    match state.paths.get(key) {
+        // the fake keeps no versions, snapshots or deleted paths, so blob listings are empty
+        _ if is_container && is_listing => {
+            let mut answer = Answer::new(StatusCode::Ok);
+            answer.body = Bytes::from_static(b"<?xml version=\"1.0\" encoding=\"utf-8\"?><EnumerationResults><Blobs /><NextMarker /></EnumerationResults>");
+            answer
+        }
+        None if !is_container => Answer::error(StatusCode::NotFound, "BlobNotFound"),
+        Some(path) if *request.method() == Method::Get && !is_operation => {
+            let mut answer = Answer::new(StatusCode::Ok).with_path(path);
+            answer.headers.insert("content-range", format!("bytes 0-{}/{}", path.content.len().saturating_sub(1), path.content.len()));
+            answer.body = Bytes::from(path.content.clone());
+            answer
+        }
+        _ if *request.method() == Method::Put => Answer::new(StatusCode::Created),
+        _ => Answer::new(StatusCode::Ok),
+    }
+}
+
+/// A user delegation key valid for as long as the Get User Delegation Key request asked for
+fn user_delegation_key(request: &Request) -> Answer {
+    let key_info = match request.body() {
+        azure_core::Body::Bytes(data) => String::from_utf8_lossy(data).into_owned(),
+        azure_core::Body::SeekableStream(_) => return Answer::error(StatusCode::BadRequest, "UnsupportedBody"),
+    };
+    let element = |name: &str| {
+        let start = key_info.find(&format!("<{}>", name)).map(|start| start + name.len() + 2)?;
+        let end = key_info[start..].find('<')?;
+        Some(key_info[start..start + end].to_string())
+    };
+    let (Some(start), Some(expiry)) = (element("Start"), element("Expiry")) else {
+        return Answer::error(StatusCode::BadRequest, "InvalidXmlDocument");
+    };
+    let mut answer = Answer::new(StatusCode::Ok);
+    answer.body = Bytes::from(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><UserDelegationKey><SignedOid>oid</SignedOid><SignedTid>tid</SignedTid>\
+         <SignedStart>{}</SignedStart><SignedExpiry>{}</SignedExpiry><SignedService>b</SignedService>\
+         <SignedVersion>2020-12-06</SignedVersion><Value>a2V5</Value></UserDelegationKey>",
+        start, expiry
+    ));
+    answer
+}
+
+fn patch(state: &mut State, request: &Request, key: &str, query: &HashMap<String, String>) -> Answer {
+    let etag = state.etag();
+    let Some(path) = state.paths.get_mut(key) else {
+        return Answer::error(StatusCode::NotFound, "PathNotFound");
+    };
+    if let Some(answer) = check_conditions(request, Some(path)).or_else(|| check_lease(request, Some(path))) {
+        return answer;
+    }
+    let position = query.get("position").and_then(|position| position.parse::<u64>().ok()).unwrap_or_default();
+    match query.get("action").map(String::as_str) {
+        Some("append") => {
+            let data = match request.body() {
+                azure_core::Body::Bytes(data) => data.clone(),
+                azure_core::Body::SeekableStream(_) => return Answer::error(StatusCode::BadRequest, "UnsupportedBody"),
+            };
+            path.appended.insert(position, data);
+            Answer::new(StatusCode::Accepted)
+        }
+        Some("flush") => {
+            for (offset, data) in std::mem::take(&mut path.appended) {
+                if offset != path.content.len() as u64 {
+                    return Answer::error(StatusCode::BadRequest, "InvalidFlushPosition");
+                }
+                path.content.extend_from_slice(&data);
+            }
+            if position != path.content.len() as u64 {
+                return Answer::error(StatusCode::BadRequest, "InvalidFlushPosition");
+            }
+            path.etag = etag;
+            Answer::new(StatusCode::Ok).with_path(path)
+        }
+        Some("setProperties") => {
+            path.properties = request.headers().get_optional_string(&PROPERTIES);
+            path.etag = etag;
+            Answer::new(StatusCode::Ok).with_path(path)
+        }
+        _ => Answer::error(StatusCode::BadRequest, "UnsupportedOperation"),
+    }
+}
+
+/// The Path Lease operation
+fn lease(state: &mut State, request: &Request, key: &str) -> Answer {
+    let Some(path) = state.paths.get_mut(key) else {
+        return Answer::error(StatusCode::NotFound, "PathNotFound");
+    };
+    let sent = request.headers().get_optional_string(&LEASE_ID);
+    let mut answer = match request.headers().get_optional_str(&LEASE_ACTION) {
+        Some("acquire") => {
+            let proposed = request.headers().get_optional_string(&PROPOSED_LEASE_ID);
+            if path.lease.is_some() && path.lease != proposed {
+                return Answer::error(StatusCode::Conflict, "LeaseAlreadyPresent");
+            }
+            path.lease = Some(proposed.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()));
+            Answer::new(StatusCode::Created)
+        }
+        Some(action @ ("renew" | "release")) => {
+            if path.lease.is_none() || path.lease != sent {
+                return Answer::error(StatusCode::Conflict, "LeaseIdMismatchWithLeaseOperation");
+            }
+            if action == "release" {
+                path.lease = None;
+            }
+            Answer::new(StatusCode::Ok)
+        }
+        Some("break") => {
+            path.lease = None;
+            let mut answer = Answer::new(StatusCode::Accepted);
+            answer.headers.insert("x-ms-lease-time", "0");
+            answer
+        }
+        _ => return Answer::error(StatusCode::BadRequest, "UnsupportedOperation"),
+    };
+    if let Some(lease) = &path.lease {
+        answer.headers.insert(LEASE_ID, lease.clone());
+    }
+    answer.with_path(path)
+}
+
+fn list(state: &State, container: &str, query: &HashMap<String, String>) -> Answer {
+    let directory = query.get("directory").map(|directory| directory.trim_matches('/')).unwrap_or_default();
+    let prefix = match directory.is_empty() {
+        true => format!("{}/", container),
+        false => format!("{}/{}/", container, directory),
+    };
+    let recursive = query.get("recursive").map(String::as_str) == Some("true");
+    let mut names: Vec<&String> = state
+        .paths
+        .keys()
+        .filter(|key| key.strip_prefix(&prefix).is_some_and(|relative| recursive || !relative.contains('/')))
+        .collect();
+    names.sort();
+    let paths: Vec<serde_json::Value> = names
+        .into_iter()
+        .map(|key| {
+            let path = &state.paths[key];
+            serde_json::json!({
+                "name": key.strip_prefix(&format!("{}/", container)).unwrap_or(key),
+                "isDirectory": path.is_directory.to_string(),
+                "contentLength": path.content.len().to_string(),
+                "etag": path.etag,
+                "lastModified": azure_core::date::to_rfc1123(&time::OffsetDateTime::now_utc()),
+                "owner": "$superuser",
+                "group": "$superuser",
+                "permissions": "rw-r-----",
+            })
+        })
+        .collect();
+    let mut answer = Answer::new(StatusCode::Ok);
+    answer.body = Bytes::from(serde_json::json!({ "paths": paths }).to_string());
+    answer
+}
+
+#[async_trait::async_trait]
+impl Policy for FakeDataLake {
+    async fn send(&self, _ctx: &Context, request: &mut Request, _next: &[Arc<dyn Policy>]) -> PolicyResult {
+        tokio::task::yield_now().await;
+        if let Some(hold) = self.take_hold(request) {
+            hold.reached.notify_one();
+            hold.released.notified().await;
+        }
+        let Answer { status, mut headers, body } = self.answer(request);
+        headers.insert("x-ms-request-id", uuid::Uuid::new_v4().to_string());
+        headers.insert("x-ms-version", "2019-12-12");
+        headers.insert("date", azure_core::date::to_rfc1123(&time::OffsetDateTime::now_utc()));
+        headers.insert("server", "FakeDataLake");
+        Ok(Response::new(status, headers, Box::pin(BytesStream::new(body))))
+    }
+}
+
+/// Requests go out anonymously, no token is ever asked for
+struct NoCredential;
+
+#[async_trait::async_trait]
+impl TokenCredential for NoCredential {
+    async fn get_token(&self, _resource: &str) -> azure_core::Result<TokenResponse> {
+        Err(Error::message(ErrorKind::Credential, "the fake data lake takes no tokens"))
+    }
+}
@@ -0,0 +1,236 @@
+//! Account-wide throttling shared by every request sent through a cached client
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use azure_core::headers::{HeaderName, Headers, RETRY_AFTER};
+use azure_core::{Context, Policy, PolicyResult, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::events::{emit, BackendEvent};
+use crate::logging::{LogLevel, LogSettings};
+
+const MS_RETRY_AFTER: HeaderName = HeaderName::from_static("x-ms-retry-after-ms");
+
+/// Tuning of the throttle governor. When the service answers 429 or 503 the governor pauses every request to
+/// the account for the advertised retry-after, then spaces request starts out by a delay that grows on every
+/// further throttle and shrinks again with each success.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThrottleConfig {
+    /// Spacing used after the first throttle
+    pub initial_delay: Duration,
+    /// Upper bound for the spacing between request starts, also capping `initial_delay` when it is the smaller
+    pub max_delay: Duration,
+    /// Pause applied when a throttled response does not say how long to back off
+    pub default_pause: Duration,
+    /// Percentage of the current delay kept after each successful response, must be below 100
+    pub recovery_percent: u32,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(5),
+            default_pause: Duration::from_secs(1),
+            recovery_percent: 90,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct GovernorState {
+    delay: Duration,
+    next_start: Instant,
+    paused_until: Instant,
+}
+
+/// Shared request rate state for one account
+#[derive(Debug)]
+pub(crate) struct ThrottleGovernor {
+    account: String,
+    config: ThrottleConfig,
+    state: Mutex<GovernorState>,
+    log_settings: Arc<LogSettings>,
+}
+
+impl ThrottleGovernor {
+    pub(crate) fn new(account: impl Into<String>, config: ThrottleConfig, log_settings: Arc<LogSettings>) -> Self {
+        let now = Instant::now();
+        Self {
+            account: account.into(),
+            config,
+            log_settings,
+            state: Mutex::new(GovernorState {
+                delay: Duration::ZERO,
+                next_start: now,
+                paused_until: now,
+            }),
+        }
+    }
+
+    /// Reserves the next start slot and returns when the caller may send
+    fn reserve(&self, now: Instant) -> Instant {
+        let mut state = self.state.lock().unwrap();
+        let start = now.max(state.next_start).max(state.paused_until);
+        state.next_start = start + state.delay;
+        start
+    }
+
+    pub(crate) async fn acquire(&self) {
+        let start = self.reserve(Instant::now());
+        tokio::time::sleep_until(start).await;
+    }
+
+    pub(crate) fn throttled(&self, now: Instant, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        let pause = retry_after.unwrap_or(self.config.default_pause);
+        state.paused_until = state.paused_until.max(now + pause);
+        // not `clamp`, which panics when the configured delays are inverted
+        state.delay = (state.delay * 2).max(self.config.initial_delay).min(self.config.max_delay);
+        self.log_settings.event(
+            LogLevel::Info,
+            format_args!("Storage account throttled, pausing for {:?} then spacing requests by {:?}", pause, state.delay),
+        );
+        emit(BackendEvent::CircuitOpened {
+            account: self.account.clone(),
+            pause,
+        });
+    }
+
+    pub(crate) fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.delay.is_zero() {
+            return;
+        }
+        state.delay = state.delay * self.config.recovery_percent.min(99) / 100;
+        // once well below the first throttle step the account is treated as healthy again
+        if state.delay < self.config.initial_delay / 4 {
+            state.delay = Duration::ZERO;
+        }
+    }
+
+    pub(crate) fn current_delay(&self) -> Duration {
+        self.state.lock().unwrap().delay
+    }
+}
+
+/// How long the service asked us to back off, from `x-ms-retry-after-ms` or a `Retry-After` in seconds
+fn retry_after(headers: &Headers) -> Option<Duration> {
+    if let Some(ms) = headers.get_optional_str(&MS_RETRY_AFTER).and_then(|value| value.trim().parse().ok()) {
+        return Some(Duration::from_millis(ms));
+    }
+    headers
+        .get_optional_str(&RETRY_AFTER)
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Pipeline policy placed in front of the transport so every attempt, including SDK retries, goes through the governor
+#[derive(Debug)]
+pub(crate) struct ThrottlePolicy {
+    governor: Arc<ThrottleGovernor>,
+}
+
+impl ThrottlePolicy {
+    pub(crate) fn new(governor: Arc<ThrottleGovernor>) -> Self {
+        Self { governor }
+    }
+}
+
+#[async_trait::async_trait]
+impl Policy for ThrottlePolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        self.governor.acquire().await;
+
+        let response = next[0].send(ctx, request, &next[1..]).await?;
+        match response.status() {
+            StatusCode::TooManyRequests | StatusCode::ServiceUnavailable => {
+                self.governor.throttled(Instant::now(), retry_after(response.headers()))
+            }
+            status if status.is_success() => self.governor.succeeded(),
+            _ => {}
+        }
+        Ok(response)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn governor() -> ThrottleGovernor {
+        ThrottleGovernor::new("account", ThrottleConfig::default(), Arc::default())
+    }
+
+    #[test]
+    fn test_unthrottled_requests_start_immediately() {
+        let governor = governor();
+        let now = Instant::now();
+        assert_eq!(governor.reserve(now), now);
+        assert_eq!(governor.reserve(now), now);
+    }
+
+    #[test]
+    fn test_throttle_pauses_then_spaces_requests() {
+        let governor = governor();
+        let now = Instant::now();
+        governor.throttled(now, Some(Duration::from_secs(2)));
+
+        let first = governor.reserve(now);
+        let second = governor.reserve(now);
+        assert_eq!(first, now + Duration::from_secs(2));
+        assert_eq!(second - first, ThrottleConfig::default().initial_delay);
+    }
+
+    #[test]
+    fn test_inverted_delays_cap_at_the_maximum() {
+        let config = ThrottleConfig {
+            initial_delay: Duration::from_secs(10),
+            max_delay: Duration::from_secs(1),
+            ..ThrottleConfig::default()
+        };
+        let governor = ThrottleGovernor::new("account", config, Arc::default());
+        governor.throttled(Instant::now(), None);
+        assert_eq!(governor.current_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_delay_grows_and_recovers() {
+        let config = ThrottleConfig::default();
+        let governor = governor();
+        let now = Instant::now();
+
+        governor.throttled(now, None);
+        governor.throttled(now, None);
+        assert_eq!(governor.current_delay(), config.initial_delay * 2);
+
+        for _ in 0..100 {
+            governor.succeeded();
+        }
+        assert_eq!(governor.current_delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let config = ThrottleConfig::default();
+        let governor = governor();
+        for _ in 0..30 {
+            governor.throttled(Instant::now(), None);
+        }
+        assert_eq!(governor.current_delay(), config.max_delay);
+    }
+
+    #[test]
+    fn test_retry_after_headers() {
+        let mut headers = Headers::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, "3");
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+
+        headers.insert(MS_RETRY_AFTER, "250");
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(250)));
+    }
+}
@@ -0,0 +1,153 @@
+//! Directory trees with aggregate sizes, for rendering explorers
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use tokio::sync::Semaphore;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::sdk::datalake::*;
+
+/// Listing requests a tree walk keeps in flight at once
+pub(crate) const MAX_PARALLEL_LISTINGS: usize = 8;
+
+/// A file or directory of a [`AzureStorageBackend::list_tree`] result
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeNode {
+    /// Last segment of `path`
+    pub name: String,
+    pub path: String,
+    pub is_directory: bool,
+    /// Size of the file, or of every file below the directory including those beyond the listed depth
+    pub size: u64,
+    /// Number of files below the directory, 1 for a file
+    pub files: u64,
+    /// Directories first, then by name
+    pub children: Vec<TreeNode>,
+    /// Whether the directory has children that were not listed because of the depth limit
+    pub truncated: bool,
+}
+
+impl TreeNode {
+    fn file(path: &Path) -> Self {
+        Self {
+            name: last_segment(&path.name).to_string(),
+            path: path.name.clone(),
+            is_directory: false,
+            size: path.content_length.max(0) as u64,
+            files: 1,
+            children: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn directory(path: &str, mut children: Vec<TreeNode>) -> Self {
+        children.sort_by(|a, b| b.is_directory.cmp(&a.is_directory).then_with(|| a.name.cmp(&b.name)));
+        Self {
+            name: last_segment(path).to_string(),
+            path: path.to_string(),
+            is_directory: true,
+            size: children.iter().map(|child| child.size).sum(),
+            files: children.iter().map(|child| child.files).sum(),
+            children,
+            truncated: false,
+        }
+    }
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+struct TreeWalk {
+    file_system_client: FileSystemClient,
+    listings: Semaphore,
+}
+
+impl TreeWalk {
+    async fn list(&self, directory: &str, recursive: bool) -> Result<Vec<Path>, AzureStorageError> {
+        let _permit = self.listings.acquire().await.expect("listing semaphore is never closed");
+        let mut list_paths = self.file_system_client.list_paths().recursive(recursive);
+        if !directory.is_empty() {
+            list_paths = list_paths.directory(directory.to_string());
+        }
+
+        let mut paths = Vec::new();
+        let mut pages = list_paths.into_stream();
+        while let Some(page) = pages.next().await {
+            paths.extend(page?.paths);
+        }
+        Ok(paths)
+    }
+
+    fn node(self: Arc<Self>, directory: String, depth: usize) -> BoxFuture<'static, Result<TreeNode, AzureStorageError>> {
+        async move {
+            if depth == 0 {
+                // only the totals are needed, a single recursive listing is cheaper than walking every level
+                let paths = self.list(&directory, true).await?;
+                let mut node = TreeNode::directory(&directory, Vec::new());
+                node.size = paths.iter().filter(|path| !path.is_directory).map(|path| path.content_length.max(0) as u64).sum();
+                node.files = paths.iter().filter(|path| !path.is_directory).count() as u64;
+                node.truncated = !paths.is_empty();
+                return Ok(node);
+            }
+
+            let (directories, files): (Vec<Path>, Vec<Path>) =
+                self.list(&directory, false).await?.into_iter().partition(|path| path.is_directory);
+            let subdirectories: Vec<String> = directories.into_iter().map(|path| path.name).collect();
+            let mut children: Vec<TreeNode> = futures::stream::iter(subdirectories)
+                .map(|subdirectory| Arc::clone(&self).node(subdirectory, depth - 1))
+                .buffer_unordered(MAX_PARALLEL_LISTINGS)
+                .try_collect()
+                .await?;
+            children.extend(files.iter().map(TreeNode::file));
+            Ok(TreeNode::directory(&directory, children))
+        }
+        .boxed()
+    }
+}
+
+impl AzureStorageBackend {
+    /// The tree below `prefix` (the container root when empty) down to `depth` levels. Directories deeper than
+    /// that still count towards the sizes of their parents but are marked truncated instead of being expanded.
+    /// Directories are listed in parallel, with up to eight listing requests in flight
+    pub async fn list_tree(&self, container_name: &str, prefix: &str, depth: usize) -> Result<TreeNode, miette::Error> {
+        let walk = Arc::new(TreeWalk {
+            file_system_client: self.file_system_client(container_name).await,
+            listings: Semaphore::new(MAX_PARALLEL_LISTINGS),
+        });
+        Ok(walk.node(prefix.trim_matches('/').to_string(), depth).await?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: u64) -> TreeNode {
+        TreeNode {
+            name: last_segment(name).to_string(),
+            path: name.to_string(),
+            is_directory: false,
+            size,
+            files: 1,
+            children: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_directories_aggregate_their_children() {
+        let logs = TreeNode::directory("data/logs", vec![file("data/logs/b.log", 5), file("data/logs/a.log", 7)]);
+        let data = TreeNode::directory("data", vec![file("data/readme.md", 3), logs]);
+
+        assert_eq!(data.name, "data");
+        assert_eq!((data.size, data.files), (15, 3));
+        let names: Vec<&str> = data.children.iter().map(|child| child.name.as_str()).collect();
+        assert_eq!(names, ["logs", "readme.md"]);
+        let log_names: Vec<&str> = data.children[0].children.iter().map(|child| child.name.as_str()).collect();
+        assert_eq!(log_names, ["a.log", "b.log"]);
+    }
+}
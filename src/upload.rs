@@ -0,0 +1,689 @@
+//! Writing whole files in one call
+use std::path::{Path, PathBuf};
+
+use azure_core::headers::HeaderName;
+use azure_core::StatusCode;
+use bytes::{Bytes, BytesMut};
+use futures::stream::FuturesUnordered;
+use futures::{Stream, StreamExt, TryFutureExt, TryStreamExt};
+use md5::{Digest, Md5};
+use tokio::io::AsyncReadExt;
+
+use crate::backend::AzureStorageBackend;
+use crate::checksum::{ChecksumMode, ChecksumPipeline, ContentChecksum};
+use crate::condition::{condition_error, EtagCondition};
+use crate::context_headers::RequestHeaders;
+use crate::error::{http_status, AzureStorageError};
+use crate::integrity::{append_error, md5_base64, CONTENT_MD5, FILE_CONTENT_MD5};
+use crate::lease::{lease_context, lease_header, Lease};
+use crate::logging::LogLevel;
+use crate::metadata::{from_properties, to_properties, Metadata};
+use crate::progress::{ProgressCallback, ProgressHook};
+use crate::sdk::datalake::*;
+use crate::sync::FileVersion;
+
+const DEFAULT_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+/// Largest append the service accepts at the API version the SDK speaks, larger ones fail with 413
+pub(crate) const MAX_APPEND_SIZE: usize = 100 * 1024 * 1024;
+const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+/// Metadata key the SHA-256 of deduplicated uploads is stored under, in hex
+pub(crate) const CONTENT_HASH_KEY: &str = "content_sha256";
+
+const CONTENT_TYPE: HeaderName = HeaderName::from_static("x-ms-content-type");
+const CONTENT_ENCODING: HeaderName = HeaderName::from_static("x-ms-content-encoding");
+const CACHE_CONTROL: HeaderName = HeaderName::from_static("x-ms-cache-control");
+const CONTENT_DISPOSITION: HeaderName = HeaderName::from_static("x-ms-content-disposition");
+const ENCRYPTION_SCOPE: HeaderName = HeaderName::from_static("x-ms-encryption-scope");
+
+/// HTTP headers the service returns when the file is read, e.g. through a CDN or by a browser
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ContentHeaders {
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    cache_control: Option<String>,
+    content_disposition: Option<String>,
+}
+
+impl ContentHeaders {
+    /// The headers that are set
+    fn headers(&self) -> Vec<(HeaderName, String)> {
+        [
+            (CONTENT_TYPE, &self.content_type),
+            (CONTENT_ENCODING, &self.content_encoding),
+            (CACHE_CONTROL, &self.cache_control),
+            (CONTENT_DISPOSITION, &self.content_disposition),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.clone().map(|value| (name, value)))
+        .collect()
+    }
+}
+
+/// How an upload is carried out
+#[derive(Clone, Debug)]
+pub struct UploadOptions {
+    pub(crate) checksum: ChecksumMode,
+    pub(crate) block_size: usize,
+    pub(crate) max_concurrency: usize,
+    pub(crate) condition: Option<EtagCondition>,
+    pub(crate) metadata: Metadata,
+    pub(crate) content_headers: ContentHeaders,
+    pub(crate) content_md5: bool,
+    pub(crate) only_if_newer: bool,
+    pub(crate) dedup: bool,
+    pub(crate) progress: ProgressHook,
+    pub(crate) encryption_scope: Option<String>,
+    pub(crate) lease_id: Option<String>,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            checksum: ChecksumMode::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            condition: None,
+            metadata: Metadata::new(),
+            content_headers: ContentHeaders::default(),
+            content_md5: false,
+            only_if_newer: false,
+            dedup: false,
+            progress: ProgressHook::default(),
+            encryption_scope: None,
+            lease_id: None,
+        }
+    }
+}
+
+impl UploadOptions {
+    /// Bytes sent per append request, 8 MiB by default and at most 100 MiB, the service's limit. Content is cut
+    /// into blocks of this size whatever the chunks it arrives in, and local files are read a block at a time
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.clamp(1, MAX_APPEND_SIZE);
+        self
+    }
+
+    /// Blocks sent at the same time, 4 by default. Blocks are committed with one flush at the end, so they may
+    /// arrive in any order. At most this many blocks are buffered while waiting to be sent
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// How the checksum of the uploaded content is computed, hashed on a background worker by default
+    pub fn checksum(mut self, checksum: ChecksumMode) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Only replaces the file if `condition` holds, failing with [`AzureStorageError::ConditionNotMet`]
+    /// otherwise. The content is staged in a temporary file next to it and renamed into place under the
+    /// condition, so the file keeps its old content until then and a failed upload leaves it untouched
+    pub fn condition(mut self, condition: EtagCondition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Metadata the file is created with, so it is never visible without it
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// `Content-Type` of the file when it is read, e.g. `text/csv`. Without it the service answers
+    /// `application/octet-stream`
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_headers.content_type = Some(content_type.into());
+        self
+    }
+
+    /// `Content-Encoding` of the file when it is read, e.g. `gzip` for content uploaded compressed
+    pub fn content_encoding(mut self, content_encoding: impl Into<String>) -> Self {
+        self.content_headers.content_encoding = Some(content_encoding.into());
+        self
+    }
+
+    pub fn cache_control(mut self, cache_control: impl Into<String>) -> Self {
+        self.content_headers.cache_control = Some(cache_control.into());
+        self
+    }
+
+    /// `Content-Disposition` of the file when it is read, e.g. `attachment; filename="report.csv"`
+    pub fn content_disposition(mut self, content_disposition: impl Into<String>) -> Self {
+        self.content_headers.content_disposition = Some(content_disposition.into());
+        self
+    }
+
+    /// Sends the MD5 of every block, which the service checks before accepting it, and stores the MD5 of the
+    /// whole file with it, so downloads with [`DownloadOptions::verify_md5`](crate::DownloadOptions::verify_md5)
+    /// can check it. Off by default. A block corrupted on the way fails with [`AzureStorageError::ChecksumMismatch`]
+    pub fn content_md5(mut self, content_md5: bool) -> Self {
+        self.content_md5 = content_md5;
+        self
+    }
+
+    /// Leaves the remote file alone when it is as large as the local one and was modified after it, for
+    /// [`upload_from_path`](AzureStorageBackend::upload_from_path) in incremental publishing jobs. Off by default.
+    /// The receipt of a skipped upload describes the remote file
+    pub fn only_if_newer(mut self, only_if_newer: bool) -> Self {
+        self.only_if_newer = only_if_newer;
+        self
+    }
+
+    /// Hashes the content before sending it and leaves the remote file alone when its `content_sha256` metadata
+    /// already holds that hash, for [`upload_bytes`](AzureStorageBackend::upload_bytes) and
+    /// [`upload_from_path`](AzureStorageBackend::upload_from_path). Uploaded files get the hash stored as that
+    /// metadata. Off by default. Reading a local file twice is usually far cheaper than sending it again
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Called each time a block was accepted by the service, with the bytes sent so far. The total is known for
+    /// [`upload_bytes`](AzureStorageBackend::upload_bytes) and [`upload_from_path`](AzureStorageBackend::upload_from_path).
+    /// Blocks are sent concurrently, so the callback should return quickly, e.g. by forwarding to a channel
+    pub fn progress(mut self, callback: ProgressCallback) -> Self {
+        self.progress.callback = Some(callback);
+        self
+    }
+
+    /// Encryption scope of the account the file is encrypted under instead of the container's default, so
+    /// the data of different tenants is kept under different keys. Fails with a 409 when the container denies
+    /// overriding its default scope
+    pub fn encryption_scope(mut self, encryption_scope: impl Into<String>) -> Self {
+        self.encryption_scope = Some(encryption_scope.into());
+        self
+    }
+
+    /// Writes the file under `lease`, which every write needs while the lease is held. With a
+    /// [`condition`](Self::condition) the lease is sent with the rename into place, the staged file is not leased
+    pub fn lease(mut self, lease: &Lease) -> Self {
+        self.lease_id = Some(lease.id.clone());
+        self
+    }
+
+    /// The headers the file is created with
+    fn create_headers(&self) -> Vec<(HeaderName, String)> {
+        let mut headers = self.content_headers.headers();
+        headers.extend(self.encryption_scope.clone().map(|scope| (ENCRYPTION_SCOPE, scope)));
+        headers.extend(lease_header(self.lease_id.as_deref()));
+        headers
+    }
+}
+
+/// What an upload committed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadReceipt {
+    pub size: u64,
+    pub etag: Option<String>,
+    /// `None` when the upload ran with [`ChecksumMode::Off`] or was skipped
+    pub checksum: Option<ContentChecksum>,
+    /// Whether nothing was sent because the remote file was already current
+    pub skipped: bool,
+}
+
+impl AzureStorageBackend {
+    /// Creates `path` with `bytes` as its content, replacing any existing file. The content is sliced into blocks
+    /// without being copied, and a `Vec<u8>` turns into [`Bytes`] without a copy either
+    pub async fn upload_bytes(
+        &self,
+        container_name: &str,
+        path: &str,
+        bytes: impl Into<Bytes>,
+        mut options: UploadOptions,
+    ) -> Result<UploadReceipt, miette::Error> {
+        let bytes = bytes.into();
+        options.progress.total = Some(bytes.len() as u64);
+        if options.dedup {
+            let mut checksum = ChecksumPipeline::new(ChecksumMode::Inline);
+            checksum.feed(&bytes).await;
+            if let Some(receipt) = self.deduplicate(container_name, path, checksum.finish().await, &mut options).await? {
+                return Ok(receipt);
+            }
+        }
+        self.upload_chunks(container_name, path, futures::stream::iter([bytes]), options).await
+    }
+
+    /// Creates `path`, replacing any existing file, appends `chunks` and commits them with a single flush.
+    /// Each block is handed to the checksum pipeline before it is sent, so hashing overlaps the sends
+    pub async fn upload_chunks(
+        &self,
+        container_name: &str,
+        path: &str,
+        chunks: impl Stream<Item = Bytes>,
+        options: UploadOptions,
+    ) -> Result<UploadReceipt, miette::Error> {
+        Ok(self.upload_stream(container_name, path, chunks.map(Ok), options).await?)
+    }
+
+    /// Streams the local file at `local_path` into `path`, replacing any existing file. The file is read a
+    /// block at a time while earlier blocks are sent, it is never held in memory as a whole
+    pub async fn upload_from_path(
+        &self,
+        container_name: &str,
+        path: &str,
+        local_path: impl AsRef<Path>,
+        mut options: UploadOptions,
+    ) -> Result<UploadReceipt, miette::Error> {
+        let local_path = local_path.as_ref().to_path_buf();
+        let local_io = |source| AzureStorageError::LocalIo { path: local_path.clone(), source };
+        let file = tokio::fs::File::open(&local_path).await.map_err(local_io)?;
+        let metadata = file.metadata().await.map_err(local_io)?;
+        options.progress.total = Some(metadata.len());
+        if options.only_if_newer {
+            let local = FileVersion {
+                size: metadata.len(),
+                last_modified: metadata.modified().map_err(local_io)?.into(),
+            };
+            if let Some(receipt) = self.current_remote(container_name, path, &local).await? {
+                self.log(LogLevel::Info, format_args!("{} is already current, skipping the upload", path));
+                return Ok(receipt);
+            }
+        }
+        if options.dedup {
+            let mut checksum = ChecksumPipeline::new(ChecksumMode::Inline);
+            let hashed = tokio::fs::File::open(&local_path).await.map_err(local_io)?;
+            let chunks = read_chunks(hashed, local_path.clone(), options.block_size);
+            futures::pin_mut!(chunks);
+            while let Some(chunk) = chunks.next().await {
+                checksum.feed(&chunk?).await;
+            }
+            if let Some(receipt) = self.deduplicate(container_name, path, checksum.finish().await, &mut options).await? {
+                return Ok(receipt);
+            }
+        }
+        Ok(self.upload_stream(container_name, path, read_chunks(file, local_path, options.block_size), options).await?)
+    }
+
+    /// Writes `bytes` to a temporary file next to `path` and renames it into place, replacing any existing file.
+    /// Unlike the other uploads, readers never see `path` empty or partially written, only the old file or the
+    /// new one
+    pub async fn write_atomic(&self, container_name: &str, path: &str, bytes: impl Into<Bytes>) -> Result<UploadReceipt, miette::Error> {
+        let temporary_path = temporary_path(path);
+        let receipt = self.upload_bytes(container_name, &temporary_path, bytes, UploadOptions::default()).await?;
+        if let Err(error) = self.rename(container_name, &temporary_path, path).await {
+            let temporary_client = self.file_system_client(container_name).await.get_file_client(&temporary_path);
+            if let Err(cleanup_error) = temporary_client.delete().await {
+                self.log(LogLevel::Error, format_args!("Failed to remove temporary file {}: {}", temporary_path, cleanup_error));
+            }
+            return Err(error);
+        }
+        Ok(receipt)
+    }
+
+    /// A receipt for the remote file at `path` if it is current compared to `local`, `None` if it has to be uploaded
+    async fn current_remote(&self, container_name: &str, path: &str, local: &FileVersion) -> Result<Option<UploadReceipt>, AzureStorageError> {
+        let Some(properties) = self.remote_properties(container_name, path).await? else {
+            return Ok(None);
+        };
+        let remote = FileVersion {
+            size: properties.content_length.unwrap_or_default().max(0) as u64,
+            last_modified: properties.last_modified,
+        };
+        Ok(is_current(local, &remote).then_some(UploadReceipt {
+            size: remote.size,
+            etag: Some(properties.etag),
+            checksum: None,
+            skipped: true,
+        }))
+    }
+
+    /// A receipt for the remote file at `path` if its stored hash is `checksum`. Otherwise `None`, and the hash is
+    /// added to the metadata the upload creates the file with
+    async fn deduplicate(
+        &self,
+        container_name: &str,
+        path: &str,
+        checksum: Option<ContentChecksum>,
+        options: &mut UploadOptions,
+    ) -> Result<Option<UploadReceipt>, AzureStorageError> {
+        let checksum = checksum.expect("inline checksums are always computed");
+        let hash = checksum.to_hex();
+        if let Some(properties) = self.remote_properties(container_name, path).await? {
+            let metadata = properties.properties.as_ref().map(from_properties).unwrap_or_default();
+            if metadata.get(CONTENT_HASH_KEY) == Some(&hash) {
+                self.log(LogLevel::Info, format_args!("{} already holds content {}, skipping the upload", path, hash));
+                return Ok(Some(UploadReceipt {
+                    size: properties.content_length.unwrap_or_default().max(0) as u64,
+                    etag: Some(properties.etag),
+                    checksum: Some(checksum),
+                    skipped: true,
+                }));
+            }
+        }
+        options.metadata.insert(CONTENT_HASH_KEY.to_string(), hash);
+        Ok(None)
+    }
+
+    /// Properties of the file at `path`, `None` when it does not exist
+    async fn remote_properties(&self, container_name: &str, path: &str) -> Result<Option<HeadPathResponse>, AzureStorageError> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        match file_client.get_properties().await {
+            Ok(properties) => Ok(Some(properties)),
+            Err(error) if matches!(http_status(&error), Some((StatusCode::NotFound, _))) => Ok(None),
+            Err(error) => Err(AzureStorageError::Request(error)),
+        }
+    }
+
+    pub(crate) async fn upload_stream(
+        &self,
+        container_name: &str,
+        path: &str,
+        chunks: impl Stream<Item = Result<Bytes, AzureStorageError>>,
+        mut options: UploadOptions,
+    ) -> Result<UploadReceipt, AzureStorageError> {
+        let Some(condition) = options.condition.take() else {
+            return self.upload_in_place(container_name, path, chunks, options).await;
+        };
+        let lease_id = options.lease_id.take();
+        let temporary_path = temporary_path(path);
+        let file_system_client = self.file_system_client(container_name).await;
+        let temporary_client = file_system_client.get_file_client(&temporary_path);
+        let staged = match self.upload_in_place(container_name, &temporary_path, chunks, options).await {
+            // the rename carries the etag of the staged file over
+            Ok(receipt) => temporary_client
+                .rename(path)
+                .if_match_condition(condition.if_match_condition())
+                .context(lease_context(lease_id.as_deref()))
+                .await
+                .map(|_| receipt)
+                .map_err(|error| condition_error(path, error)),
+            Err(error) => Err(error),
+        };
+        if staged.is_err() {
+            if let Err(cleanup_error) = temporary_client.delete().await {
+                self.log(LogLevel::Error, format_args!("Failed to remove temporary file {}: {}", temporary_path, cleanup_error));
+            }
+        }
+        staged
+    }
+
+    /// Creates `path`, replacing any existing file, appends `chunks` and commits them
+    async fn upload_in_place(
+        &self,
+        container_name: &str,
+        path: &str,
+        chunks: impl Stream<Item = Result<Bytes, AzureStorageError>>,
+        options: UploadOptions,
+    ) -> Result<UploadReceipt, AzureStorageError> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        let mut create = file_client.create();
+        if !options.metadata.is_empty() {
+            create = create.properties(to_properties(&options.metadata));
+        }
+        if let Some(context) = RequestHeaders(options.create_headers()).into_context() {
+            create = create.context(context);
+        }
+        create.await.map_err(AzureStorageError::Request)?;
+
+        let mut checksum = ChecksumPipeline::new(options.checksum);
+        let mut blocks = BlockBuffer::new(options.block_size);
+        let mut appends = FuturesUnordered::new();
+        let mut progress = options.progress.start();
+        let mut content_md5 = options.content_md5.then(Md5::new);
+        let mut position = 0;
+        let mut ended = false;
+        futures::pin_mut!(chunks);
+        while !ended {
+            let ready = match chunks.next().await {
+                Some(chunk) => blocks.push(chunk?),
+                None => {
+                    ended = true;
+                    blocks.finish().into_iter().collect()
+                }
+            };
+            for block in ready {
+                while appends.len() >= options.max_concurrency {
+                    if let Some(length) = appends.try_next().await? {
+                        progress.advance(length);
+                    }
+                }
+                checksum.feed(&block).await;
+                let block_md5 = content_md5.as_mut().map(|content_md5| {
+                    content_md5.update(&block);
+                    md5_base64(&block)
+                });
+                let length = block.len() as i64;
+                let mut append = file_client.append(position, block);
+                let append_headers: Vec<_> = block_md5
+                    .iter()
+                    .map(|block_md5| (CONTENT_MD5, block_md5.clone()))
+                    .chain(lease_header(options.lease_id.as_deref()))
+                    .collect();
+                if let Some(context) = RequestHeaders(append_headers).into_context() {
+                    append = append.context(context);
+                }
+                let append = append.into_future().map_ok(move |_| length as u64);
+                appends.push(append.map_err(move |error| append_error(path, block_md5, error)));
+                position += length;
+            }
+        }
+        while let Some(length) = appends.try_next().await? {
+            progress.advance(length);
+        }
+
+        let mut flush = file_client.flush(position).close(true);
+        // sent again with the commit, which can set content headers as well
+        let mut commit_headers = options.content_headers.headers();
+        if let Some(content_md5) = content_md5 {
+            commit_headers.push((FILE_CONTENT_MD5, azure_core::base64::encode(content_md5.finalize())));
+        }
+        commit_headers.extend(lease_header(options.lease_id.as_deref()));
+        if let Some(context) = RequestHeaders(commit_headers).into_context() {
+            flush = flush.context(context);
+        }
+        let response = flush.await.map_err(AzureStorageError::Request)?;
+        Ok(UploadReceipt {
+            size: position as u64,
+            etag: response.etag,
+            checksum: checksum.finish().await,
+            skipped: false,
+        })
+    }
+}
+
+/// Whether `remote` holds the content of `local` as far as size and modification time tell, the same rule
+/// [`AzureStorageBackend::plan_sync`] applies
+fn is_current(local: &FileVersion, remote: &FileVersion) -> bool {
+    remote.size == local.size && remote.last_modified >= local.last_modified
+}
+
+/// A hidden, unique path in the directory of `path`, so the rename into place stays within the directory
+fn temporary_path(path: &str) -> String {
+    let (directory, name) = match path.rsplit_once('/') {
+        Some((directory, name)) => (format!("{}/", directory), name),
+        None => (String::new(), path),
+    };
+    format!("{}.{}.{}.tmp", directory, name, uuid::Uuid::new_v4())
+}
+
+/// Cuts content arriving in chunks of any size into blocks of exactly `block_size` bytes, except the last.
+/// Chunks at least a block long are sliced without copying; smaller ones are gathered until a block is full
+struct BlockBuffer {
+    block_size: usize,
+    pending: BytesMut,
+}
+
+impl BlockBuffer {
+    fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            pending: BytesMut::new(),
+        }
+    }
+
+    /// The blocks completed by `chunk`
+    fn push(&mut self, mut chunk: Bytes) -> Vec<Bytes> {
+        let mut blocks = Vec::new();
+        if !self.pending.is_empty() {
+            let missing = (self.block_size - self.pending.len()).min(chunk.len());
+            self.pending.extend_from_slice(&chunk.split_to(missing));
+            if self.pending.len() < self.block_size {
+                return blocks;
+            }
+            blocks.push(self.pending.split().freeze());
+        }
+        while chunk.len() >= self.block_size {
+            blocks.push(chunk.split_to(self.block_size));
+        }
+        self.pending.extend_from_slice(&chunk);
+        blocks
+    }
+
+    /// The final, possibly short, block
+    fn finish(&mut self) -> Option<Bytes> {
+        (!self.pending.is_empty()).then(|| self.pending.split().freeze())
+    }
+}
+
+/// Appends `bytes` at `position` in as many requests as the service's size limit needs, in order. The pieces
+/// are committed together by the caller's next flush. Each piece carries `lease_id` when the file is leased
+pub(crate) async fn append_split(file_client: &FileClient, position: i64, bytes: Bytes, lease_id: Option<&str>) -> azure_core::Result<()> {
+    let mut offset = position;
+    for piece in split_for_append(bytes, MAX_APPEND_SIZE) {
+        let length = piece.len() as i64;
+        file_client.append(offset, piece).context(lease_context(lease_id)).await?;
+        offset += length;
+    }
+    Ok(())
+}
+
+/// `bytes` sliced into pieces of at most `max_size` bytes without copying
+fn split_for_append(mut bytes: Bytes, max_size: usize) -> Vec<Bytes> {
+    let mut pieces = Vec::with_capacity(bytes.len().div_ceil(max_size));
+    while bytes.len() > max_size {
+        pieces.push(bytes.split_to(max_size));
+    }
+    if !bytes.is_empty() {
+        pieces.push(bytes);
+    }
+    pieces
+}
+
+/// The content of `file` in chunks of `chunk_size`, ending after the first read error
+fn read_chunks(file: tokio::fs::File, path: PathBuf, chunk_size: usize) -> impl Stream<Item = Result<Bytes, AzureStorageError>> {
+    futures::stream::unfold(Some(file), move |file| {
+        let path = path.clone();
+        async move {
+            let mut file = file?;
+            let mut chunk = BytesMut::with_capacity(chunk_size);
+            while chunk.len() < chunk_size {
+                match file.read_buf(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(source) => return Some((Err(AzureStorageError::LocalIo { path, source }), None)),
+                }
+            }
+            match chunk.is_empty() {
+                true => None,
+                false => Some((Ok(chunk.freeze()), Some(file))),
+            }
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use azure_core::Method;
+
+    use crate::test_service::FakeDataLake;
+
+    #[tokio::test]
+    async fn test_local_files_are_read_in_bounded_chunks() {
+        let path = std::env::temp_dir().join(format!("upload-{}.bin", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, vec![7u8; 1024 * 1024 + 10]).await.unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let chunks: Vec<Bytes> = read_chunks(file, path.clone(), 1024 * 1024).map(Result::unwrap).collect().await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        let lengths: Vec<usize> = chunks.iter().map(Bytes::len).collect();
+        assert_eq!(lengths, [1024 * 1024, 10]);
+    }
+
+    #[test]
+    fn test_only_older_or_resized_remote_files_are_replaced() {
+        let now = time::OffsetDateTime::now_utc();
+        let local = FileVersion { size: 10, last_modified: now };
+        assert!(is_current(&local, &FileVersion { size: 10, last_modified: now }));
+        assert!(!is_current(&local, &FileVersion { size: 10, last_modified: now - time::Duration::seconds(1) }));
+        assert!(!is_current(&local, &FileVersion { size: 11, last_modified: now + time::Duration::seconds(1) }));
+    }
+
+    #[test]
+    fn test_files_are_created_under_the_encryption_scope() {
+        let headers = UploadOptions::default().content_type("text/csv").encryption_scope("tenant-a").create_headers();
+        assert_eq!(headers, [(CONTENT_TYPE, "text/csv".to_string()), (ENCRYPTION_SCOPE, "tenant-a".to_string())]);
+        assert!(UploadOptions::default().create_headers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_conditional_uploads_only_replace_the_file_once_committed() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "old");
+        let backend = service.backend();
+        let hold = service.hold_next(|method, target| *method == Method::Patch && target.contains("action=flush"));
+
+        let condition = EtagCondition::IfMatch(service.etag("raw/data/a.csv").unwrap());
+        let options = UploadOptions::default().condition(condition);
+        let upload = tokio::spawn(async move { backend.upload_bytes("raw", "data/a.csv", "new", options).await });
+        hold.reached().await;
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"old"[..]));
+        hold.release();
+
+        let receipt = upload.await.unwrap().unwrap();
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"new"[..]));
+        assert_eq!(receipt.etag, service.etag("raw/data/a.csv"));
+        assert_eq!(service.paths(), ["raw/data", "raw/data/a.csv"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_conditional_uploads_leave_the_file_alone() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "old");
+        let backend = service.backend();
+
+        let stale = UploadOptions::default().condition(EtagCondition::IfMatch("\"0x1\"".to_string()));
+        let error = backend.upload_stream("raw", "data/a.csv", futures::stream::iter([Ok(Bytes::from("new"))]), stale).await;
+        assert!(matches!(error, Err(AzureStorageError::ConditionNotMet { .. })));
+
+        service.fail(|method, target| *method == Method::Patch && target.contains("action=append"), StatusCode::InternalServerError);
+        let current = UploadOptions::default().condition(EtagCondition::IfMatch(service.etag("raw/data/a.csv").unwrap()));
+        let error = backend.upload_stream("raw", "data/a.csv", futures::stream::iter([Ok(Bytes::from("new"))]), current).await;
+        assert!(matches!(error, Err(AzureStorageError::Request(_))));
+
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"old"[..]));
+        assert_eq!(service.paths(), ["raw/data", "raw/data/a.csv"]);
+    }
+
+    #[test]
+    fn test_temporary_paths_are_hidden_siblings() {
+        let temporary = temporary_path("out/report.csv");
+        assert!(temporary.starts_with("out/.report.csv.") && temporary.ends_with(".tmp"), "{}", temporary);
+        assert!(temporary_path("report.csv").starts_with(".report.csv."));
+        assert_ne!(temporary_path("report.csv"), temporary_path("report.csv"));
+    }
+
+    #[test]
+    fn test_chunks_are_cut_into_blocks() {
+        let mut blocks = BlockBuffer::new(4);
+        assert!(blocks.push(Bytes::from_static(b"ab")).is_empty());
+        assert_eq!(blocks.push(Bytes::from_static(b"cdefghijk")), [&b"abcd"[..], &b"efgh"[..]]);
+        assert_eq!(blocks.push(Bytes::from_static(b"lm")), [&b"ijkl"[..]]);
+        assert_eq!(blocks.finish(), Some(Bytes::from_static(b"m")));
+        assert_eq!(blocks.finish(), None);
+    }
+
+    #[test]
+    fn test_oversized_appends_are_split() {
+        let pieces = split_for_append(Bytes::from_static(b"abcdefghij"), 4);
+        assert_eq!(pieces, [&b"abcd"[..], &b"efgh"[..], &b"ij"[..]]);
+        assert_eq!(split_for_append(Bytes::from_static(b"abcd"), 4).len(), 1);
+        assert!(split_for_append(Bytes::new(), 4).is_empty());
+        assert_eq!(UploadOptions::default().block_size(usize::MAX).block_size, MAX_APPEND_SIZE);
+    }
+}
@@ -0,0 +1,118 @@
+//! Total size of directory trees, without holding their listings in memory
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::listing::{is_missing_directory, list_paths};
+use crate::sdk::datalake::*;
+use crate::tree::MAX_PARALLEL_LISTINGS;
+
+/// Totals of [`AzureStorageBackend::du`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub bytes: u64,
+    pub files: u64,
+    /// Directories below the one measured, not counting itself
+    pub directories: u64,
+}
+
+/// What a listing of one directory level adds to the totals, and the directories still to list below it
+#[derive(Debug, Default)]
+struct Level {
+    usage: DiskUsage,
+    subdirectories: Vec<String>,
+}
+
+impl Level {
+    fn tally(&mut self, paths: Vec<Path>) {
+        for path in paths {
+            match path.is_directory {
+                true => {
+                    self.usage.directories += 1;
+                    self.subdirectories.push(path.name);
+                }
+                false => {
+                    self.usage.files += 1;
+                    self.usage.bytes += path.content_length.max(0) as u64;
+                }
+            }
+        }
+    }
+}
+
+impl DiskUsage {
+    fn add(&mut self, other: DiskUsage) {
+        self.bytes += other.bytes;
+        self.files += other.files;
+        self.directories += other.directories;
+    }
+}
+
+/// Lists the direct children of `directory` page by page. A directory removed while the walk runs counts as empty
+async fn list_level(file_system_client: FileSystemClient, directory: String) -> Result<Level, AzureStorageError> {
+    let mut level = Level::default();
+    let mut pages = list_paths(&file_system_client, &directory, false).into_stream();
+    while let Some(page) = pages.next().await {
+        match page {
+            Ok(page) => level.tally(page.paths),
+            Err(error) if is_missing_directory(&error) => break,
+            Err(error) => return Err(AzureStorageError::Request(error)),
+        }
+    }
+    Ok(level)
+}
+
+impl AzureStorageBackend {
+    /// Total bytes, files and directories below `path`, the whole container when it is empty, like `du -s`.
+    /// Directories are listed one level at a time with up to eight listings in flight, and only the names of
+    /// directories still to list are kept, so trees with millions of files are counted in bounded memory
+    pub async fn du(&self, container_name: &str, path: &str) -> Result<DiskUsage, miette::Error> {
+        let file_system_client = self.file_system_client(container_name).await;
+        let mut usage = DiskUsage::default();
+        let mut pending = vec![path.trim_matches('/').to_string()];
+        let mut listings = FuturesUnordered::new();
+        loop {
+            while listings.len() < MAX_PARALLEL_LISTINGS {
+                match pending.pop() {
+                    Some(directory) => listings.push(list_level(file_system_client.clone(), directory)),
+                    None => break,
+                }
+            }
+            match listings.next().await {
+                Some(level) => {
+                    let level = level?;
+                    usage.add(level.usage);
+                    pending.extend(level.subdirectories);
+                }
+                None => return Ok(usage),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str, is_directory: bool, size: i64) -> Path {
+        let json = format!(
+            r#"{{"contentLength": "{}", "etag": "0x8D", "group": "g", "isDirectory": "{}", "lastModified": "Tue, 14 Oct 2025 12:00:00 GMT",
+                "name": "{}", "owner": "o", "permissions": "rwxr-x---"}}"#,
+            size, is_directory, name
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_levels_count_files_and_queue_directories() {
+        let mut level = Level::default();
+        level.tally(vec![path("data/a.csv", false, 10), path("data/logs", true, 0), path("data/b.csv", false, 5)]);
+
+        let mut usage = DiskUsage { bytes: 1, files: 1, directories: 0 };
+        usage.add(level.usage);
+        assert_eq!(usage, DiskUsage { bytes: 16, files: 3, directories: 1 });
+        assert_eq!(level.subdirectories, ["data/logs"]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Earlier versions of files the service keeps on accounts with blob versioning, and rolling back to them
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::copy::{CopyOptions, CopyReceipt};
+use crate::error::AzureStorageError;
+use crate::logging::LogLevel;
+
+/// A version of a file, the current one or an earlier one the service kept when the file was overwritten or
+/// deleted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathVersion {
+    /// Timestamp naming the version for [`AzureStorageBackend::restore_version`]
+    pub version_id: String,
+    pub is_current: bool,
+    pub last_modified: OffsetDateTime,
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersionsPage {
+    #[serde(default)]
+    blobs: VersionedBlobs,
+    #[serde(default)]
+    next_marker: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct VersionedBlobs {
+    #[serde(rename = "Blob", default)]
+    blobs: Vec<VersionedBlob>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct VersionedBlob {
+    name: String,
+    #[serde(default)]
+    version_id: Option<String>,
+    #[serde(default)]
+    is_current_version: bool,
+    properties: VersionedBlobProperties,
+}
+
+#[derive(Deserialize)]
+struct VersionedBlobProperties {
+    #[serde(rename = "Last-Modified", with = "azure_core::date::rfc1123")]
+    last_modified: OffsetDateTime,
+    #[serde(rename = "Content-Length", default)]
+    content_length: u64,
+}
+
+/// The versions of `path` on a page of List Blobs, which lists every blob starting with the same characters too,
+/// and the marker of the next page
+fn parse_versions(body: &[u8], path: &str) -> Result<(Vec<PathVersion>, Option<String>), azure_core::Error> {
+    let page: VersionsPage = azure_core::xml::read_xml(body)?;
+    let versions = page
+        .blobs
+        .blobs
+        .into_iter()
+        .filter(|blob| blob.name == path)
+        .filter_map(|blob| {
+            Some(PathVersion {
+                version_id: blob.version_id?,
+                is_current: blob.is_current_version,
+                last_modified: blob.properties.last_modified,
+                size: blob.properties.content_length,
+            })
+        });
+    Ok((versions.collect(), page.next_marker.filter(|marker| !marker.is_empty())))
+}
+
+impl AzureStorageBackend {
+    /// The versions of the file at `path` from oldest to newest, including the current one unless the file was
+    /// deleted. Empty on accounts without blob versioning
+    pub async fn list_versions(&self, container_name: &str, path: &str) -> Result<Vec<PathVersion>, miette::Error> {
+        let path = path.trim_matches('/');
+        let mut versions = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut query = vec![("include", "versions".to_string()), ("prefix", path.to_string())];
+            query.extend(marker.take().map(|marker| ("marker", marker)));
+            let body = self.list_blobs_page(container_name, query, None).await?;
+            let (page, next_marker) = parse_versions(&body, path).map_err(AzureStorageError::Request)?;
+            versions.extend(page);
+            match next_marker {
+                Some(next_marker) => marker = Some(next_marker),
+                None => return Ok(versions),
+            }
+        }
+    }
+
+    /// Makes the version `version_id` of the file at `path` the current one again by copying it over the file,
+    /// which keeps the replaced content as a version of its own, so a restore can be undone the same way
+    pub async fn restore_version(&self, container_name: &str, path: &str, version_id: &str) -> Result<CopyReceipt, miette::Error> {
+        let mut source_url = self.blob_url(container_name, path);
+        source_url.query_pairs_mut().append_pair("versionid", version_id);
+        let receipt = self.copy_from_url(source_url, container_name, path, CopyOptions::default()).await?;
+        self.log(LogLevel::Info, format_args!("Restored {}/{} to version {}", container_name, path, version_id));
+        Ok(receipt)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    #[tokio::test]
+    async fn test_versions_are_listed_on_the_blob_endpoint() {
+        let service = FakeDataLake::new();
+        service.put_file("raw/data/a.csv", "content");
+        assert!(service.backend().list_versions("raw", "data/a.csv").await.unwrap().is_empty());
+        assert_eq!(service.requests(), ["GET blob:/raw?restype=container&comp=list&include=versions&prefix=data%2Fa.csv"]);
+    }
+
+    #[test]
+    fn test_versions_of_other_blobs_are_skipped() {
+        let body = br#"<?xml version="1.0" encoding="utf-8"?>
+            <EnumerationResults ServiceEndpoint="https://account.blob.core.windows.net/" ContainerName="raw">
+              <Blobs>
+                <Blob><Name>data/a.csv</Name><VersionId>2025-10-14T12:00:00.0000000Z</VersionId>
+                  <Properties><Last-Modified>Tue, 14 Oct 2025 12:00:00 GMT</Last-Modified><Content-Length>10</Content-Length></Properties></Blob>
+                <Blob><Name>data/a.csv</Name><VersionId>2025-10-14T13:00:00.0000000Z</VersionId><IsCurrentVersion>true</IsCurrentVersion>
+                  <Properties><Last-Modified>Tue, 14 Oct 2025 13:00:00 GMT</Last-Modified><Content-Length>12</Content-Length></Properties></Blob>
+                <Blob><Name>data/a.csv.bak</Name><VersionId>2025-10-14T14:00:00.0000000Z</VersionId>
+                  <Properties><Last-Modified>Tue, 14 Oct 2025 14:00:00 GMT</Last-Modified><Content-Length>10</Content-Length></Properties></Blob>
+              </Blobs>
+              <NextMarker />
+            </EnumerationResults>"#;
+        let (versions, marker) = parse_versions(body, "data/a.csv").unwrap();
+        assert_eq!(marker, None);
+        assert_eq!(versions.len(), 2);
+        assert_eq!((versions[0].is_current, versions[0].size), (false, 10));
+        assert_eq!((versions[1].is_current, versions[1].size), (true, 12));
+    }
+}
@@ -0,0 +1,95 @@
+//! Following a file holding configuration so services pick up changes without a restart
+use std::time::Duration;
+
+use futures::Stream;
+use serde::de::DeserializeOwned;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::sdk::datalake::*;
+
+struct WatchState {
+    file_client: FileClient,
+    path: String,
+    etag: Option<String>,
+    ticker: Interval,
+}
+
+impl WatchState {
+    /// The new content if the file changed since it was last read
+    async fn poll<T: DeserializeOwned>(&mut self) -> Result<Option<T>, AzureStorageError> {
+        let properties = self.file_client.get_properties().await?;
+        if self.etag.as_deref() == Some(properties.etag.as_str()) {
+            return Ok(None);
+        }
+
+        let response = self.file_client.read().await?;
+        let value = parse_watched(&self.path, &response.data)?;
+        self.etag = Some(response.etag);
+        Ok(Some(value))
+    }
+}
+
+fn parse_watched<T: DeserializeOwned>(path: &str, data: &[u8]) -> Result<T, AzureStorageError> {
+    serde_json::from_slice(data).map_err(|source| AzureStorageError::InvalidWatchedFile {
+        path: path.to_string(),
+        source,
+    })
+}
+
+impl AzureStorageBackend {
+    /// Checks the etag of the JSON file at `path` every `interval` and yields its deserialized content whenever it
+    /// changed, starting with the current content. Failures, including content that does not deserialize, are
+    /// yielded as errors and the watch carries on, so a bad edit can be fixed without restarting the service
+    pub async fn watch_file<T: DeserializeOwned>(
+        &self,
+        container_name: &str,
+        path: &str,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<T, miette::Error>> {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let state = WatchState {
+            file_client: self.file_system_client(container_name).await.get_file_client(path),
+            path: path.to_string(),
+            etag: None,
+            ticker,
+        };
+
+        futures::stream::unfold(state, |mut state| async move {
+            loop {
+                state.ticker.tick().await;
+                match state.poll().await {
+                    Ok(Some(value)) => return Some((Ok(value), state)),
+                    Ok(None) => continue,
+                    Err(error) => return Some((Err(error.into()), state)),
+                }
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        batch_size: u32,
+    }
+
+    #[test]
+    fn test_watched_content_is_deserialized() {
+        let config: Config = parse_watched("config.json", br#"{"batch_size": 10}"#).unwrap();
+        assert_eq!(config, Config { batch_size: 10 });
+
+        assert!(matches!(
+            parse_watched::<Config>("config.json", b"batch_size: 10"),
+            Err(AzureStorageError::InvalidWatchedFile { path, .. }) if path == "config.json"
+        ));
+    }
+}
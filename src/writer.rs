@@ -0,0 +1,392 @@
+//! Writing files through `tokio::io::AsyncWrite` or `futures::Sink`, and what writers do with uncommitted data when dropped
+use std::fmt::Display;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
+use futures::{FutureExt, Sink};
+use tokio::io::AsyncWrite;
+
+use crate::backend::AzureStorageBackend;
+use crate::error::AzureStorageError;
+use crate::lease::{lease_context, Lease};
+use crate::logging::{LogLevel, LogSettings};
+use crate::sdk::datalake::*;
+use crate::upload::{append_split, MAX_APPEND_SIZE};
+
+const DEFAULT_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How a writer dropped before it was finished, e.g. because the task owning it was cancelled, settles the
+/// data it appended but did not commit yet. The cleanup runs on a background task of the current tokio runtime
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropBehavior {
+    /// Discard the uncommitted data and remove files the writer created but never committed to
+    #[default]
+    AbortAndCleanup,
+    /// Commit everything written so far as if the writer had been finished
+    DetachAndFinish,
+}
+
+/// Runs `task` in the background so a `Drop` impl can settle remote state. `action` describes it for the log
+pub(crate) fn spawn_on_drop<E: Display>(
+    log_settings: &Arc<LogSettings>,
+    action: String,
+    task: impl Future<Output = Result<(), E>> + Send + 'static,
+) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            log_settings.event(LogLevel::Info, format_args!("Writer dropped unfinished, will {} in the background", action));
+            let log_settings = Arc::clone(log_settings);
+            runtime.spawn(async move {
+                if let Err(error) = task.await {
+                    log_settings.event(LogLevel::Error, format_args!("Failed to {} after the writer was dropped: {}", action, error));
+                }
+            });
+        }
+        Err(_) => log_settings.event(LogLevel::Error, format_args!("Writer dropped outside of a tokio runtime, cannot {}", action)),
+    }
+}
+
+
+/// A request of a [`DataLakeFileWriter`] in flight, resolving to the committed length and whether the file was
+/// closed for flushes
+type PendingRequest = BoxFuture<'static, azure_core::Result<Option<(i64, bool)>>>;
+
+/// Writes a file through [`AsyncWrite`], or [`Sink<Bytes>`] to `forward` a stream of chunks into it. Writes are buffered and appended a block at a time, `flush` appends
+/// the buffer and commits everything written so far, and `shutdown` commits and closes the file. A writer
+/// dropped before `shutdown` settles its uncommitted data according to its [`DropBehavior`]. After a failed
+/// request every further call fails, as the remote position is unknown
+pub struct DataLakeFileWriter {
+    file_client: FileClient,
+    path: String,
+    /// Sent with every write while the file is leased
+    lease_id: Option<String>,
+    log_settings: Arc<LogSettings>,
+    block_size: usize,
+    on_drop: DropBehavior,
+    buffer: BytesMut,
+    /// A chunk sent through the `Sink` that fills a block on its own, appended as it is instead of being copied
+    /// into the buffer. Only set while the buffer is empty
+    unbuffered: Option<Bytes>,
+    /// Length of the file including appended but uncommitted data
+    appended: i64,
+    committed: i64,
+    pending: Option<PendingRequest>,
+    failed: bool,
+    closed: bool,
+}
+
+impl std::fmt::Debug for DataLakeFileWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataLakeFileWriter")
+            .field("path", &self.path)
+            .field("appended", &self.appended)
+            .field("committed", &self.committed)
+            .field("buffered", &(self.buffer.len() + self.unbuffered.as_ref().map_or(0, Bytes::len)))
+            .finish()
+    }
+}
+
+impl AzureStorageBackend {
+    /// Creates `path`, replacing any existing file, and returns a writer for its content
+    pub async fn file_writer(&self, container_name: &str, path: &str) -> Result<DataLakeFileWriter, miette::Error> {
+        self.create_writer(container_name, path, None).await
+    }
+
+    /// [`file_writer`](Self::file_writer) for the file `lease` is held on, every request of the writer carries the
+    /// lease id
+    pub async fn file_writer_under_lease(&self, lease: &Lease) -> Result<DataLakeFileWriter, miette::Error> {
+        self.create_writer(&lease.container_name, &lease.path, Some(lease.id.clone())).await
+    }
+
+    async fn create_writer(&self, container_name: &str, path: &str, lease_id: Option<String>) -> Result<DataLakeFileWriter, miette::Error> {
+        let file_client = self.file_system_client(container_name).await.get_file_client(path);
+        file_client
+            .create()
+            .context(lease_context(lease_id.as_deref()))
+            .await
+            .map_err(AzureStorageError::Request)?;
+        Ok(DataLakeFileWriter {
+            file_client,
+            path: path.to_string(),
+            lease_id,
+            log_settings: Arc::clone(&self.log_settings),
+            block_size: DEFAULT_BLOCK_SIZE,
+            on_drop: DropBehavior::default(),
+            buffer: BytesMut::new(),
+            unbuffered: None,
+            appended: 0,
+            committed: 0,
+            pending: None,
+            failed: false,
+            closed: false,
+        })
+    }
+}
+
+impl DataLakeFileWriter {
+    /// Bytes buffered before they are appended, 8 MiB by default and at most 100 MiB
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.clamp(1, MAX_APPEND_SIZE);
+        self
+    }
+
+    /// What happens to data written but not committed when the writer is dropped before `shutdown`
+    pub fn on_drop(mut self, on_drop: DropBehavior) -> Self {
+        self.on_drop = on_drop;
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Drives the request in flight, if any, to completion
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.failed {
+            return Poll::Ready(Err(io::Error::other(format!("an earlier request for {} failed", self.path))));
+        }
+        let Some(pending) = self.pending.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(pending.poll_unpin(cx));
+        self.pending = None;
+        match result {
+            Ok(Some((committed, closed))) => {
+                self.committed = committed;
+                self.closed |= closed;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                self.failed = true;
+                return Poll::Ready(Err(io::Error::other(error)));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Appends the unbuffered chunk if there is one, the buffer otherwise
+    fn start_append(&mut self) {
+        let block = self.unbuffered.take().unwrap_or_else(|| self.buffer.split().freeze());
+        let file_client = self.file_client.clone();
+        let lease_id = self.lease_id.clone();
+        let position = self.appended;
+        self.appended += block.len() as i64;
+        self.pending = Some(async move { append_split(&file_client, position, block, lease_id.as_deref()).await.map(|_| None) }.boxed());
+    }
+
+    fn start_commit(&mut self, close: bool) {
+        let flush = self.file_client.flush(self.appended).close(close).context(lease_context(self.lease_id.as_deref()));
+        let length = self.appended;
+        self.pending = Some(async move { flush.await.map(|_| Some((length, close))) }.boxed());
+    }
+
+    /// Appends the buffer and commits everything appended, closing the file if `close`
+    fn poll_commit(&mut self, cx: &mut Context<'_>, close: bool) -> Poll<io::Result<()>> {
+        ready!(self.poll_pending(cx))?;
+        if self.unbuffered.is_some() || !self.buffer.is_empty() {
+            self.start_append();
+            ready!(self.poll_pending(cx))?;
+        }
+        if self.committed < self.appended || (close && !self.closed) {
+            self.start_commit(close);
+            ready!(self.poll_pending(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for DataLakeFileWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if this.buffer.len() >= this.block_size {
+            this.start_append();
+            ready!(this.poll_pending(cx))?;
+        }
+        let accepted = buf.len().min(this.block_size - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..accepted]);
+        Poll::Ready(Ok(accepted))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_commit(cx, false)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_commit(cx, true)
+    }
+}
+
+/// Same buffering as [`AsyncWrite`], `poll_flush` commits and `poll_close` commits and closes the file
+impl Sink<Bytes> for DataLakeFileWriter {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if this.unbuffered.is_some() || this.buffer.len() >= this.block_size {
+            this.start_append();
+            ready!(this.poll_pending(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> io::Result<()> {
+        // a chunk larger than a block is appended whole, `append_split` keeps each request within the limit
+        let this = self.get_mut();
+        match this.buffer.is_empty() && item.len() >= this.block_size {
+            true => this.unbuffered = Some(item),
+            false => this.buffer.extend_from_slice(&item),
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_commit(cx, false)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_commit(cx, true)
+    }
+}
+
+impl Drop for DataLakeFileWriter {
+    fn drop(&mut self) {
+        let unsettled = self.pending.is_some() || self.unbuffered.is_some() || !self.buffer.is_empty() || self.committed < self.appended;
+        if self.closed || self.failed || !unsettled {
+            return;
+        }
+        let file_client = self.file_client.clone();
+        let lease_id = self.lease_id.clone();
+        let pending = self.pending.take();
+        match self.on_drop {
+            DropBehavior::AbortAndCleanup => {
+                let committed = self.committed;
+                spawn_on_drop(&self.log_settings, format!("discard uncommitted data of {}", self.path), async move {
+                    let committed = match pending {
+                        Some(pending) => pending.await.ok().flatten().map_or(committed, |(committed, _)| committed),
+                        None => committed,
+                    };
+                    match committed {
+                        // nothing was ever committed, the file only exists because the writer created it
+                        0 => file_client.delete().context(lease_context(lease_id.as_deref())).await.map(|_| ()),
+                        _ => file_client.flush(committed).context(lease_context(lease_id.as_deref())).await.map(|_| ()),
+                    }
+                })
+            }
+            DropBehavior::DetachAndFinish => {
+                let block = self.unbuffered.take().unwrap_or_else(|| self.buffer.split().freeze());
+                let position = self.appended;
+                spawn_on_drop(&self.log_settings, format!("commit data written to {}", self.path), async move {
+                    if let Some(pending) = pending {
+                        pending.await?;
+                    }
+                    let length = position + block.len() as i64;
+                    append_split(&file_client, position, block, lease_id.as_deref()).await?;
+                    file_client
+                        .flush(length)
+                        .close(true)
+                        .context(lease_context(lease_id.as_deref()))
+                        .await
+                        .map(|_| ())
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_service::FakeDataLake;
+
+    #[tokio::test]
+    async fn test_drop_task_runs_in_background() {
+        let (done, finished) = tokio::sync::oneshot::channel();
+        spawn_on_drop(&Arc::default(), "signal".to_string(), async move { done.send(()).map_err(|_| "receiver dropped") });
+        finished.await.unwrap();
+    }
+
+    #[test]
+    fn test_drop_outside_runtime_does_not_panic() {
+        spawn_on_drop(&Arc::default(), "fail".to_string(), async { Err("never polled") });
+    }
+
+    fn writer(block_size: usize) -> DataLakeFileWriter {
+        let file_client = DataLakeClient::new("account", crate::sdk::storage::StorageCredentials::anonymous())
+            .file_system_client("container")
+            .get_file_client("file.bin");
+        DataLakeFileWriter {
+            file_client,
+            path: "file.bin".to_string(),
+            lease_id: None,
+            log_settings: Arc::default(),
+            block_size,
+            on_drop: DropBehavior::default(),
+            buffer: BytesMut::new(),
+            unbuffered: None,
+            appended: 0,
+            committed: 0,
+            pending: None,
+            failed: false,
+            closed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_buffered_up_to_a_block() {
+        use tokio::io::AsyncWriteExt;
+
+        let mut writer = writer(4);
+        assert_eq!(writer.write(b"abc").await.unwrap(), 3);
+        assert_eq!(writer.write(b"defg").await.unwrap(), 1);
+        assert_eq!(&writer.buffer[..], b"abcd");
+        assert!(writer.pending.is_none());
+        // keep the drop from reaching out to the service
+        writer.closed = true;
+    }
+
+    #[tokio::test]
+    async fn test_sink_buffers_chunks() {
+        use futures::SinkExt;
+
+        let mut writer = writer(8);
+        writer.feed(Bytes::from_static(b"abc")).await.unwrap();
+        writer.feed(Bytes::from_static(b"defg")).await.unwrap();
+        assert_eq!(&writer.buffer[..], b"abcdefg");
+        assert!(writer.pending.is_none());
+        writer.closed = true;
+
+        // a chunk filling a block is appended as it is, not copied
+        let mut unbuffered = self::writer(4);
+        let block = Bytes::from_static(b"abcdefgh");
+        unbuffered.feed(block.clone()).await.unwrap();
+        assert_eq!(unbuffered.unbuffered.as_ref().map(|chunk| chunk.as_ptr()), Some(block.as_ptr()));
+        assert!(unbuffered.buffer.is_empty());
+        unbuffered.closed = true;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_the_file_once() {
+        use tokio::io::AsyncWriteExt;
+
+        // every request of the fake is pending when first polled
+        let service = FakeDataLake::new();
+        let mut writer = service.backend().file_writer("raw", "data/a.csv").await.unwrap();
+        writer.write_all(b"abc").await.unwrap();
+        // a close still pending when first polled must not be started again on the next poll
+        let shutdown = tokio::time::timeout(std::time::Duration::from_secs(10), writer.shutdown());
+        shutdown.await.expect("shutdown finishes").unwrap();
+        writer.shutdown().await.unwrap();
+
+        assert_eq!(service.file("raw/data/a.csv").as_deref(), Some(&b"abc"[..]));
+        let flushes: Vec<String> = service.requests().into_iter().filter(|request| request.contains("action=flush")).collect();
+        assert_eq!(flushes, ["PATCH /raw/data/a.csv?action=flush&close=true&position=3"]);
+    }
+}
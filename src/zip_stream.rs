@@ -0,0 +1,361 @@
+//! Streaming directory trees out as zip archives, without staging their files anywhere
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+use azure_core::prelude::Range;
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::Crc;
+use futures::{Stream, StreamExt, TryStreamExt};
+use time::OffsetDateTime;
+
+use crate::backend::AzureStorageBackend;
+use crate::download::{chunk_ranges, read_chunk, DEFAULT_CHUNK_SIZE};
+use crate::error::AzureStorageError;
+use crate::listing::{list_entries, PathEntry};
+use crate::sdk::datalake::*;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const END: u32 = 0x06054b50;
+/// Sizes and CRC follow the content in a data descriptor, names are UTF-8
+const FLAGS: u16 = 0x0808;
+/// Version needed to extract plain entries, and entries with zip64 fields
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Field values that defer to the zip64 extra field or record
+const MAX_U16: u64 = 0xffff;
+const MAX_U32: u64 = 0xffff_ffff;
+
+/// An entry written, as the central directory lists it
+struct CentralEntry {
+    name: String,
+    size: u64,
+    crc: u32,
+    offset: u64,
+    time: u16,
+    date: u16,
+}
+
+impl CentralEntry {
+    fn zip64_size(&self) -> bool {
+        self.size >= MAX_U32
+    }
+
+    fn zip64_offset(&self) -> bool {
+        self.offset >= MAX_U32
+    }
+}
+
+/// Encodes a zip archive of stored (uncompressed) entries as their content passes through, with zip64 fields
+/// wherever sizes or offsets do not fit the classic format. Entry sizes must be known before their content
+struct ZipEncoder {
+    written: u64,
+    entries: Vec<CentralEntry>,
+    crc: Crc,
+}
+
+impl ZipEncoder {
+    fn new() -> Self {
+        Self {
+            written: 0,
+            entries: Vec::new(),
+            crc: Crc::new(),
+        }
+    }
+
+    fn emit(&mut self, bytes: BytesMut) -> Bytes {
+        self.written += bytes.len() as u64;
+        bytes.freeze()
+    }
+
+    /// The local header of an entry of `size` bytes, whose content must follow before the next entry starts
+    fn start_entry(&mut self, name: &str, size: u64, modified: OffsetDateTime) -> Bytes {
+        let (time, date) = dos_time(modified);
+        let entry = CentralEntry {
+            name: name.to_string(),
+            size,
+            crc: 0,
+            offset: self.written,
+            time,
+            date,
+        };
+        self.crc.reset();
+
+        let mut header = BytesMut::with_capacity(30 + name.len() + 20);
+        header.put_u32_le(LOCAL_HEADER);
+        header.put_u16_le(if entry.zip64_size() { VERSION_ZIP64 } else { VERSION });
+        header.put_u16_le(FLAGS);
+        header.put_u16_le(0);
+        header.put_u16_le(time);
+        header.put_u16_le(date);
+        header.put_u32_le(0);
+        match entry.zip64_size() {
+            true => {
+                header.put_u32_le(MAX_U32 as u32);
+                header.put_u32_le(MAX_U32 as u32);
+            }
+            false => {
+                header.put_u32_le(size as u32);
+                header.put_u32_le(size as u32);
+            }
+        }
+        header.put_u16_le(name.len() as u16);
+        header.put_u16_le(if entry.zip64_size() { 20 } else { 0 });
+        header.put_slice(name.as_bytes());
+        if entry.zip64_size() {
+            header.put_u16_le(0x0001);
+            header.put_u16_le(16);
+            header.put_u64_le(size);
+            header.put_u64_le(size);
+        }
+        self.entries.push(entry);
+        self.emit(header)
+    }
+
+    /// Content of the entry started last, passed through unchanged
+    fn content(&mut self, chunk: Bytes) -> Bytes {
+        self.crc.update(&chunk);
+        self.written += chunk.len() as u64;
+        chunk
+    }
+
+    /// The data descriptor closing the entry started last
+    fn finish_entry(&mut self) -> Bytes {
+        let crc = self.crc.sum();
+        let entry = self.entries.last_mut().expect("an entry was started");
+        entry.crc = crc;
+        let zip64 = entry.zip64_size();
+        let size = entry.size;
+
+        let mut descriptor = BytesMut::with_capacity(24);
+        descriptor.put_u32_le(DATA_DESCRIPTOR);
+        descriptor.put_u32_le(crc);
+        match zip64 {
+            true => {
+                descriptor.put_u64_le(size);
+                descriptor.put_u64_le(size);
+            }
+            false => {
+                descriptor.put_u32_le(size as u32);
+                descriptor.put_u32_le(size as u32);
+            }
+        }
+        self.emit(descriptor)
+    }
+
+    /// The central directory and end records, the last bytes of the archive
+    fn finish(&mut self) -> Bytes {
+        let directory_offset = self.written;
+        let mut directory = BytesMut::new();
+        for entry in &self.entries {
+            let mut extra = BytesMut::new();
+            if entry.zip64_size() {
+                extra.put_u64_le(entry.size);
+                extra.put_u64_le(entry.size);
+            }
+            if entry.zip64_offset() {
+                extra.put_u64_le(entry.offset);
+            }
+            let zip64 = !extra.is_empty();
+            let version = if zip64 { VERSION_ZIP64 } else { VERSION };
+
+            directory.put_u32_le(CENTRAL_HEADER);
+            directory.put_u16_le(version);
+            directory.put_u16_le(version);
+            directory.put_u16_le(FLAGS);
+            directory.put_u16_le(0);
+            directory.put_u16_le(entry.time);
+            directory.put_u16_le(entry.date);
+            directory.put_u32_le(entry.crc);
+            let size = entry.size.min(MAX_U32) as u32;
+            directory.put_u32_le(size);
+            directory.put_u32_le(size);
+            directory.put_u16_le(entry.name.len() as u16);
+            directory.put_u16_le(if zip64 { extra.len() as u16 + 4 } else { 0 });
+            directory.put_u16_le(0);
+            directory.put_u16_le(0);
+            directory.put_u16_le(0);
+            directory.put_u32_le(0);
+            directory.put_u32_le(entry.offset.min(MAX_U32) as u32);
+            directory.put_slice(entry.name.as_bytes());
+            if zip64 {
+                directory.put_u16_le(0x0001);
+                directory.put_u16_le(extra.len() as u16);
+                directory.put_slice(&extra);
+            }
+        }
+        let directory_size = directory.len() as u64;
+        let count = self.entries.len() as u64;
+
+        let mut end = directory;
+        if count >= MAX_U16 || directory_size >= MAX_U32 || directory_offset >= MAX_U32 {
+            let zip64_end_offset = directory_offset + directory_size;
+            end.put_u32_le(ZIP64_END);
+            end.put_u64_le(44);
+            end.put_u16_le(VERSION_ZIP64);
+            end.put_u16_le(VERSION_ZIP64);
+            end.put_u32_le(0);
+            end.put_u32_le(0);
+            end.put_u64_le(count);
+            end.put_u64_le(count);
+            end.put_u64_le(directory_size);
+            end.put_u64_le(directory_offset);
+            end.put_u32_le(ZIP64_LOCATOR);
+            end.put_u32_le(0);
+            end.put_u64_le(zip64_end_offset);
+            end.put_u32_le(1);
+        }
+        end.put_u32_le(END);
+        end.put_u16_le(0);
+        end.put_u16_le(0);
+        end.put_u16_le(count.min(MAX_U16) as u16);
+        end.put_u16_le(count.min(MAX_U16) as u16);
+        end.put_u32_le(directory_size.min(MAX_U32) as u32);
+        end.put_u32_le(directory_offset.min(MAX_U32) as u32);
+        end.put_u16_le(0);
+        self.emit(end)
+    }
+}
+
+/// MS-DOS time and date of `time` in UTC, the only timestamps the basic format has. Times before 1980 cannot be
+/// represented and are written as its start
+fn dos_time(time: OffsetDateTime) -> (u16, u16) {
+    let time = time.to_offset(time::UtcOffset::UTC);
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    let dos_date = (((time.year() - 1980) as u16) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    (dos_time, dos_date)
+}
+
+/// Name of `path` within an archive of `prefix`
+fn entry_name<'a>(prefix: &str, path: &'a str) -> &'a str {
+    match prefix.is_empty() {
+        true => path,
+        false => path.strip_prefix(prefix).map_or(path, |name| name.trim_start_matches('/')),
+    }
+}
+
+struct ZipStream {
+    file_system_client: FileSystemClient,
+    prefix: String,
+    entries: Pin<Box<dyn Stream<Item = Result<PathEntry, AzureStorageError>> + Send>>,
+    encoder: ZipEncoder,
+    /// The file whose content is being streamed, with the ranges still to read
+    current: Option<(PathEntry, VecDeque<Range>)>,
+    listed: bool,
+}
+
+impl ZipStream {
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, AzureStorageError> {
+        loop {
+            if let Some((entry, ranges)) = &mut self.current {
+                let chunk = match ranges.pop_front() {
+                    Some(range) => {
+                        let file_client = self.file_system_client.get_file_client(&entry.path);
+                        let chunk = read_chunk(&file_client, range, &entry.etag).await?;
+                        self.encoder.content(chunk)
+                    }
+                    None => {
+                        self.current = None;
+                        self.encoder.finish_entry()
+                    }
+                };
+                return Ok(Some(chunk));
+            }
+            if self.listed {
+                return Ok(None);
+            }
+            match self.entries.next().await {
+                Some(entry) => {
+                    let entry = entry?;
+                    if entry.is_directory {
+                        continue;
+                    }
+                    let header = self.encoder.start_entry(entry_name(&self.prefix, &entry.path), entry.size, entry.last_modified);
+                    let ranges = chunk_ranges(entry.size, DEFAULT_CHUNK_SIZE).collect();
+                    self.current = Some((entry, ranges));
+                    return Ok(Some(header));
+                }
+                None => {
+                    self.listed = true;
+                    return Ok(Some(self.encoder.finish()));
+                }
+            }
+        }
+    }
+}
+
+impl AzureStorageBackend {
+    /// Every file below `prefix` as a zip archive, named relative to `prefix`, produced as the stream is
+    /// consumed so a service can offer a folder as one download without staging it. Files are stored
+    /// uncompressed and each is read at the version listed, so a file changed while the archive streams fails it.
+    /// The stream ends after an error, leaving the archive truncated
+    pub async fn download_zip(&self, container_name: &str, prefix: &str) -> impl Stream<Item = Result<Bytes, miette::Error>> {
+        let file_system_client = self.file_system_client(container_name).await;
+        let state = ZipStream {
+            entries: list_entries(&file_system_client, prefix, true).boxed(),
+            file_system_client,
+            prefix: prefix.trim_matches('/').to_string(),
+            encoder: ZipEncoder::new(),
+            current: None,
+            listed: false,
+        };
+
+        futures::stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            match state.next_chunk().await {
+                Ok(chunk) => chunk.map(|chunk| (Ok(chunk), Some(state))),
+                Err(error) => Some((Err(error), None)),
+            }
+        })
+        .map_err(miette::Error::from)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_archives_end_with_a_directory_of_their_entries() {
+        let mut encoder = ZipEncoder::new();
+        let mut archive = BytesMut::new();
+        let modified = OffsetDateTime::UNIX_EPOCH + time::Duration::days(20_000);
+        for (name, content) in [("a.txt", &b"hello"[..]), ("logs/b.txt", &b""[..])] {
+            archive.put(encoder.start_entry(name, content.len() as u64, modified));
+            archive.put(encoder.content(Bytes::copy_from_slice(content)));
+            archive.put(encoder.finish_entry());
+        }
+        archive.put(encoder.finish());
+
+        assert_eq!(u32_at(&archive, 0), LOCAL_HEADER);
+        assert_eq!(&archive[30..35], b"a.txt");
+        assert_eq!(&archive[35..40], b"hello");
+        assert_eq!((u32_at(&archive, 40), u32_at(&archive, 44)), (DATA_DESCRIPTOR, 0x3610a686));
+
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(&archive, end), END);
+        assert_eq!(u16::from_le_bytes([archive[end + 10], archive[end + 11]]), 2);
+        let directory_offset = u32_at(&archive, end + 16) as usize;
+        assert_eq!(u32_at(&archive, directory_offset), CENTRAL_HEADER);
+        assert_eq!(directory_offset + u32_at(&archive, end + 12) as usize, end);
+        assert_eq!(encoder.written, archive.len() as u64);
+    }
+
+    #[test]
+    fn test_entries_are_named_relative_to_the_prefix() {
+        assert_eq!(entry_name("data/export", "data/export/a/b.csv"), "a/b.csv");
+        assert_eq!(entry_name("", "data/a.csv"), "data/a.csv");
+        assert_eq!(dos_time(OffsetDateTime::UNIX_EPOCH), (0, 0x21));
+    }
+}